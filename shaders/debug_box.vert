@@ -0,0 +1,38 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject
+{
+    mat4 mat;
+    vec4 sun_light;
+}
+ubo;
+
+// Per instance, see `DebugBox` in debug_renderer.rs.
+layout(location = 0) in mat4 transform;
+layout(location = 4) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+
+// The 12 edges of the unit cube, as a line list.
+const vec3 EDGES[24] = vec3[](
+    vec3(0., 0., 0.), vec3(1., 0., 0.),
+    vec3(0., 1., 0.), vec3(1., 1., 0.),
+    vec3(0., 0., 1.), vec3(1., 0., 1.),
+    vec3(0., 1., 1.), vec3(1., 1., 1.),
+    vec3(0., 0., 0.), vec3(0., 1., 0.),
+    vec3(1., 0., 0.), vec3(1., 1., 0.),
+    vec3(0., 0., 1.), vec3(0., 1., 1.),
+    vec3(1., 0., 1.), vec3(1., 1., 1.),
+    vec3(0., 0., 0.), vec3(0., 0., 1.),
+    vec3(1., 0., 0.), vec3(1., 0., 1.),
+    vec3(0., 1., 0.), vec3(0., 1., 1.),
+    vec3(1., 1., 0.), vec3(1., 1., 1.)
+);
+
+void main()
+{
+    vec4 pos = transform * vec4(EDGES[gl_VertexIndex], 1.0);
+    // The transform can be projective, like the inverse view projection of a frustum.
+    gl_Position = ubo.mat * vec4(pos.xyz / pos.w, 1.0);
+    fragColor = color;
+}
@@ -0,0 +1,36 @@
+#version 450
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// `shader.vert` for the `DebugVertex` of vertex.rs, with the `debug_vertex` feature.
+
+layout(binding = 0) uniform UniformBufferObject
+{
+    mat4 mat;
+    vec4 sun_light;
+}
+ubo;
+
+layout(push_constant) uniform PushConstants
+{
+    i64vec3 model;
+}
+pcs;
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec3 color;
+layout(location = 3) in vec2 light;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) flat out vec3 normal;
+layout(location = 2) flat out float blockLight;
+layout(location = 3) flat out float skyLight;
+
+void main()
+{
+    gl_Position = ubo.mat * vec4(pcs.model * 32 + pos, 1.0);
+    fragColor = color;
+    normal = inNormal;
+    blockLight = light.x;
+    skyLight = light.y;
+}
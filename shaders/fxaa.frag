@@ -0,0 +1,67 @@
+#version 450
+
+// FXAA as described by Timothy Lottes, without the edge search of the quality presets.
+
+const float SPAN_MAX = 8.0;
+const float REDUCE_MUL = 1.0 / 8.0;
+const float REDUCE_MIN = 1.0 / 128.0;
+
+layout(location = 0) in vec2 uv;
+
+layout(location = 0) out vec4 out_color;
+
+// Already tonemapped.
+layout(binding = 0) uniform sampler2D scene;
+
+layout(push_constant) uniform PushConstants
+{
+    vec2 inverse_screen_size;
+};
+
+float luma(vec3 color)
+{
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main()
+{
+    vec3 rgb_nw = texture(scene, uv + vec2(-1.0, -1.0) * inverse_screen_size).rgb;
+    vec3 rgb_ne = texture(scene, uv + vec2(1.0, -1.0) * inverse_screen_size).rgb;
+    vec3 rgb_sw = texture(scene, uv + vec2(-1.0, 1.0) * inverse_screen_size).rgb;
+    vec3 rgb_se = texture(scene, uv + vec2(1.0, 1.0) * inverse_screen_size).rgb;
+    vec3 rgb_m = texture(scene, uv).rgb;
+
+    float luma_nw = luma(rgb_nw);
+    float luma_ne = luma(rgb_ne);
+    float luma_sw = luma(rgb_sw);
+    float luma_se = luma(rgb_se);
+    float luma_m = luma(rgb_m);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // Blur along the edge, perpendicular to the luma gradient.
+    vec2 dir = vec2(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * (0.25 * REDUCE_MUL), REDUCE_MIN);
+    float inverse_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * inverse_dir_min, vec2(-SPAN_MAX), vec2(SPAN_MAX)) * inverse_screen_size;
+
+    vec3 rgb_a = 0.5 * (
+        texture(scene, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        texture(scene, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (
+        texture(scene, uv + dir * -0.5).rgb +
+        texture(scene, uv + dir * 0.5).rgb);
+    float luma_b = luma(rgb_b);
+
+    // The wide blur went past the edge, keep the narrow one.
+    if (luma_b < luma_min || luma_b > luma_max)
+    {
+        out_color = vec4(rgb_a, 1.0);
+    }
+    else
+    {
+        out_color = vec4(rgb_b, 1.0);
+    }
+}
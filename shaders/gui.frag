@@ -3,11 +3,21 @@
 layout(location = 0) in vec4 fragColor;
 layout(location = 1) in vec2 uv;
 
-layout(set = 1, binding = 0) uniform sampler2D texSampler;
+layout(set = 1, binding = 0) uniform texture2D tex;
+// Shared by all the textures: linear then nearest.
+layout(set = 1, binding = 1) uniform sampler samplers[2];
+
+layout(push_constant) uniform PushConstants {
+    uint samplerIndex;
+};
 
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = fragColor * texture(texSampler, uv);
-}
\ No newline at end of file
+    // Constant indices, a dynamic one needs the shaderSampledImageArrayDynamicIndexing feature.
+    vec4 texel = samplerIndex == 0u
+        ? texture(sampler2D(tex, samplers[0]), uv)
+        : texture(sampler2D(tex, samplers[1]), uv);
+    outColor = fragColor * texel;
+}
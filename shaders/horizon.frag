@@ -0,0 +1,34 @@
+#version 450
+
+const float CHUNK_SIZE = 32.;
+
+// See `PushConstants` in horizon.rs.
+layout(push_constant) uniform PushConstants
+{
+    vec4 outer;
+    vec4 color_height;
+    // xy: player chunk x and z, zw: render distance on x and z, -1 if no chunk is loaded at the ground height
+    ivec4 hole;
+    // The column dx, dz chunks from the player is loaded if dx² * x + dz² * y <= z
+    uvec4 hole_factors;
+}
+pcs;
+
+layout(location = 0) in vec3 fragColor;
+layout(location = 1) in vec2 worldXZ;
+
+layout(location = 0) out vec4 outColor;
+
+void main()
+{
+    // The terrain is drawn in the loaded columns, like `RenderDistance::contains` at the ground height.
+    ivec2 offset = abs(ivec2(floor(worldXZ / CHUNK_SIZE)) - pcs.hole.xy);
+    if (all(lessThanEqual(offset, pcs.hole.zw)))
+    {
+        uvec2 squared = uvec2(offset * offset);
+        if (squared.x * pcs.hole_factors.x + squared.y * pcs.hole_factors.y <= pcs.hole_factors.z)
+            discard;
+    }
+
+    outColor = vec4(fragColor, 1.0);
+}
@@ -0,0 +1,43 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject
+{
+    mat4 mat;
+    // xyz: direction towards the sun scaled by its intensity, w: ambient light
+    vec4 sun_light;
+    // 0: every face is lit as if it faced the sun, 1: full directional shading
+    float shading;
+}
+ubo;
+
+// See `PushConstants` in horizon.rs.
+layout(push_constant) uniform PushConstants
+{
+    // min x, min z, max x, max z
+    vec4 outer;
+    // xyz: color, w: height
+    vec4 color_height;
+    // Used by horizon.frag.
+    ivec4 hole;
+    uvec4 hole_factors;
+}
+pcs;
+
+layout(location = 0) out vec3 fragColor;
+layout(location = 1) out vec2 worldXZ;
+
+// Indices of the x and z coordinates of the corners of a rectangle, in triangle strip order.
+const ivec2 CORNERS[4] = ivec2[](ivec2(0, 1), ivec2(2, 1), ivec2(0, 3), ivec2(2, 3));
+
+void main()
+{
+    ivec2 corner = CORNERS[gl_VertexIndex];
+    worldXZ = vec2(pcs.outer[corner.x], pcs.outer[corner.y]);
+    gl_Position = ubo.mat * vec4(worldXZ.x, pcs.color_height.w, worldXZ.y, 1.0);
+
+    // Lit like the top faces of the terrain in the sky light.
+    float ambient = ubo.sun_light.w;
+    float facing = max(ubo.sun_light.y, 0.);
+    float diffuse = mix(length(ubo.sun_light.xyz), facing, ubo.shading);
+    fragColor = pcs.color_height.rgb * (ambient + (1. - ambient) * diffuse);
+}
@@ -0,0 +1,33 @@
+#version 450
+
+layout(binding = 0) uniform UniformBufferObject
+{
+    mat4 mat;
+    vec4 sun_light;
+}
+ubo;
+
+// Right and up vectors of the camera, the quads face it.
+layout(push_constant) uniform PushConstants
+{
+    vec4 right;
+    vec4 up;
+}
+pcs;
+
+// Per instance, see `ParticleInstance` in particles.rs.
+layout(location = 0) in vec4 posSize;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 fragColor;
+
+// The corners of the quad, as a triangle strip.
+const vec2 CORNERS[4] = vec2[](vec2(-.5, -.5), vec2(.5, -.5), vec2(-.5, .5), vec2(.5, .5));
+
+void main()
+{
+    vec2 corner = CORNERS[gl_VertexIndex] * posSize.w;
+    vec3 pos = posSize.xyz + pcs.right.xyz * corner.x + pcs.up.xyz * corner.y;
+    gl_Position = ubo.mat * vec4(pos, 1.0);
+    fragColor = color;
+}
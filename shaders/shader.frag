@@ -1,10 +1,29 @@
 #version 450
 
+layout(binding = 0) uniform UniformBufferObject
+{
+    mat4 mat;
+    // xyz: direction towards the sun scaled by its intensity, w: ambient light
+    vec4 sun_light;
+    // 0: every face is lit as if it faced the sun, 1: full directional shading
+    float shading;
+}
+ubo;
+
 layout(location = 0) in vec3 fragColor;
+layout(location = 1) flat in vec3 normal;
+layout(location = 2) flat in float blockLight;
+layout(location = 3) flat in float skyLight;
 
 layout(location = 0) out vec4 outColor;
 
 void main() {
-    outColor = vec4(fragColor, 1.0);
+    float ambient = ubo.sun_light.w;
+    float facing = max(dot(normal, ubo.sun_light.xyz), 0.);
+    float diffuse = mix(length(ubo.sun_light.xyz), facing, ubo.shading);
+    // The sun only reaches the faces in the sky light.
+    float light = (ambient + (1. - ambient) * diffuse) * skyLight;
+    // Torches light the faces in the dark, without adding to the sun.
+    light = max(light, blockLight);
+    outColor = vec4(fragColor * light, 1.0);
 }
-
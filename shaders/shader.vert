@@ -4,6 +4,7 @@
 layout(binding = 0) uniform UniformBufferObject
 {
     mat4 mat;
+    vec4 sun_light;
 }
 ubo;
 
@@ -16,11 +17,28 @@ pcs;
 layout(location = 0) in uint data;
 
 layout(location = 0) out vec3 fragColor;
+layout(location = 1) flat out vec3 normal;
+layout(location = 2) flat out float blockLight;
+layout(location = 3) flat out float skyLight;
+
+// Indexed by the face direction packed in the vertex, must match `ADDENDS` in chunk_mesh.rs.
+const vec3 NORMALS[6] = vec3[](
+    vec3(1., 0., 0.),
+    vec3(-1., 0., 0.),
+    vec3(0., 1., 0.),
+    vec3(0., -1., 0.),
+    vec3(0., 0., 1.),
+    vec3(0., 0., -1.)
+);
 
 void main()
 {
+    // Fields of `Vertex` in vertex.rs, see `VERTEX_FORMAT_VERSION`.
     ivec3 pos = ivec3(data & 63, (data >> 6) & 63, (data >> 12) & 63);
-    uint face_light = 2 * ((data >> 18) & 3) + 4;
+    uint face = (data >> 20) & 7;
     gl_Position = ubo.mat * vec4(pcs.model * 32 + pos, 1.0);
-    fragColor = vec3(1., 1., 1.) * (float(face_light) / 10.0);
-}
\ No newline at end of file
+    fragColor = vec3(1., 1., 1.);
+    normal = NORMALS[face];
+    blockLight = float((data >> 23) & 15) / 15.;
+    skyLight = float((data >> 27) & 15) / 15.;
+}
@@ -0,0 +1,14 @@
+#version 450
+
+layout(push_constant) uniform PushConstants
+{
+    // After the chunk position of the vertex shader, see `WireframeOverlay::COLOR_OFFSET`.
+    layout(offset = 32) vec4 color;
+}
+pcs;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = pcs.color;
+}
@@ -1,24 +1,33 @@
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
-use log::warn;
+use log::{info, warn};
 use winit::{
-    event::{
-        DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
-    },
+    dpi::PhysicalSize,
+    event::{DeviceEvent, Event, MouseButton, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
 };
 
 use crate::{
     debug,
     events::{self, MainLoopEvent},
-    gui::GuiContext,
+    gui::{self, GuiContext},
     inputs::Inputs,
-    options::AppOptions,
+    options::{AppOptions, OPTIONS},
     render::{Renderer, Window},
-    world::World,
+    replay::{Input, Recorder, Replay},
+    utils::FixedTimestep,
+    world::{Aabb, BlockId, RenderDistance, World},
 };
 
+/// Max distance of the blocks broken or placed, in blocks.
+const REACH: f32 = 6.;
+/// The late world ticks beyond are dropped, so a long frame doesn't make the next ones longer to catch up.
+const MAX_WORLD_TICKS_PER_FRAME: u32 = 4;
+
 #[derive(Debug)]
 pub struct App {
     game_focused: bool,
@@ -26,30 +35,62 @@ pub struct App {
     world: World,
     renderer: Renderer,
     inputs: Inputs,
+    /// Index in `BlockId::PLACEABLE` of the block placed with the right button.
+    selected_block: usize,
 
     last_frame_time: Instant,
+    world_ticks: FixedTimestep,
+    /// Set by `Resized` events, the swapchain is recreated once in the next `MainEventsCleared`
+    /// so a burst of resizes only rebuilds it once.
+    needs_recreate: bool,
+    /// Latest size received in a `Resized` event.
+    window_size: PhysicalSize<u32>,
 
     gui: GuiContext,
+    recorder: Option<Recorder>,
+    /// Live inputs are ignored while it has frames left.
+    replay: Option<Replay>,
 }
 
 impl App {
     pub fn new(window: Window, event_loop: &EventLoop<MainLoopEvent>) -> Result<Self> {
         events::init_proxy(event_loop);
 
+        let replay = Replay::from_env().context("Replay loading failed")?;
+        if let Some(replay) = &replay {
+            OPTIONS.write().expect("Lock poisoned").generation.seed = Some(replay.seed);
+        }
         let chunks = World::create_chunks();
+        let seed = chunks.read().expect("Lock poisoned").seed();
+        let recorder = Recorder::from_env(seed).context("Recorder creation failed")?;
         let renderer =
             Renderer::new(&window, Arc::clone(&chunks)).context("Renderer creation failed")?;
         let world =
             World::new(chunks, Arc::clone(&renderer.regions)).context("World creation failed")?;
+        // The event loop isn't running yet and the window is hidden until the spawn area is ready.
+        world
+            .pregenerate_spawn(
+                renderer.camera_pos().chunk(),
+                AppOptions::get().spawn_pregeneration,
+            )
+            .context("Spawn area pregeneration failed")?;
+        window.set_visible(true);
         let inputs = Inputs::new();
+        let window_size = window.inner_size();
         let mut s = Self {
             game_focused: true,
             window,
             renderer,
             world,
             inputs,
+            selected_block: 0,
             last_frame_time: Instant::now(),
+            world_ticks: FixedTimestep::new(),
+            needs_recreate: false,
+            window_size,
             gui: GuiContext::new(event_loop),
+            recorder,
+            replay,
         };
         s.set_game_focused(true);
         Ok(s)
@@ -58,7 +99,7 @@ impl App {
     pub fn tick_event(&mut self, event: Event<MainLoopEvent>) -> Result<Option<ControlFlow>> {
         #[cfg(feature = "bench")]
         {
-            use std::{sync::LazyLock, time::Duration};
+            use std::sync::LazyLock;
             static START: LazyLock<Instant> = LazyLock::new(Instant::now);
             if START.elapsed() > Duration::from_secs(60) && !matches!(event, Event::LoopDestroyed) {
                 return Ok(Some(ControlFlow::Exit));
@@ -66,80 +107,105 @@ impl App {
         }
         let control_flow = match event {
             Event::WindowEvent { event, .. } => {
-                let propagate = self.gui.on_event(&event);
-                if !propagate {
+                // During a replay, the GUI and the game don't see the live inputs.
+                let live = self.replay.is_none();
+                if live && !self.gui.on_event(&event) {
                     return Ok(None);
                 }
                 match event {
                     WindowEvent::CloseRequested => Some(ControlFlow::Exit),
-                    WindowEvent::Resized(_) => {
-                        self.renderer
-                            .recreate_swapchain(&self.window)
-                            .context("Swapchain recreation failed")?;
+                    WindowEvent::Resized(size) => {
+                        self.needs_recreate = true;
+                        self.window_size = size;
                         None
                     }
-                    WindowEvent::KeyboardInput { input, .. } => {
-                        let KeyboardInput {
-                            state,
-                            virtual_keycode,
-                            scancode,
-                            ..
-                        } = input;
-                        let key = if let Some(keycode) = virtual_keycode {
-                            keycode
-                        } else {
-                            warn!("Unknown key: {}", scancode);
-                            return Ok(None);
-                        };
-                        match state {
-                            ElementState::Pressed => {
-                                if key == VirtualKeyCode::Escape {
-                                    self.set_game_focused(false);
-                                }
-                                debug::key_pressed(key);
-                                self.inputs.key_pressed(key)
-                            }
-                            ElementState::Released => self.inputs.key_released(key),
+                    event => {
+                        if live && let Some(input) = Input::from_window_event(&event) {
+                            self.input(input)?;
                         }
                         None
                     }
-                    WindowEvent::MouseInput { state, button, .. } => {
-                        if state == ElementState::Pressed && button == MouseButton::Left {
-                            self.set_game_focused(true);
-                        }
-                        None
-                    }
-                    WindowEvent::Focused(focused) => {
-                        self.set_game_focused(focused);
-                        None
-                    }
-                    _ => None,
                 }
             }
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
             } => {
-                if self.game_focused {
-                    self.inputs.mouse_moved(delta);
+                if self.replay.is_none() {
+                    self.input(Input::MouseMotion(delta))?;
                 }
                 None
             }
             Event::MainEventsCleared => {
                 let now = Instant::now();
-                let elasped = now - self.last_frame_time;
+                let mut elasped = now - self.last_frame_time;
                 self.last_frame_time = now;
 
-                if AppOptions::get().tick_world {
-                    self.world
-                        .tick(self.renderer.camera_pos())
-                        .context("World ticking failed")?;
+                if let Some(replay) = &mut self.replay {
+                    match replay.next_frame() {
+                        Some(frame) => {
+                            for input in frame.inputs {
+                                self.input(input)?;
+                            }
+                            elasped = frame.elapsed;
+                        }
+                        None => {
+                            info!("Replay finished, back to the live inputs");
+                            self.replay = None;
+                        }
+                    }
+                }
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.frame(elasped)?;
                 }
 
+                let (paused, tick_world, tick_rate) = {
+                    let options = AppOptions::get();
+                    (options.paused, options.tick_world, options.world_tick_rate)
+                };
+                self.world.set_paused(paused);
+                let ticks = self.world_ticks.advance(
+                    elasped,
+                    Duration::from_secs(1) / tick_rate.max(1),
+                    MAX_WORLD_TICKS_PER_FRAME,
+                );
+                if tick_world && !paused {
+                    for _ in 0..ticks {
+                        self.world
+                            .tick(self.renderer.camera_pos())
+                            .context("World ticking failed")?;
+                    }
+                }
+                self.world.end_frame();
+
+                // A minimized window has a zero size and no swapchain can be created for it.
+                // The recreation stays pending until the window is restored.
+                if self.needs_recreate {
+                    if self.window_size.width == 0 || self.window_size.height == 0 {
+                        return Ok(None);
+                    }
+                    self.renderer
+                        .recreate_swapchain(&self.window)
+                        .context("Swapchain recreation failed")?;
+                    self.needs_recreate = false;
+                }
+
+                if self.game_focused {
+                    self.window.recenter_cursor();
+                }
+                gui::DATA.write().expect("Lock poisoned").chunk_state =
+                    self.world.chunk_state(self.renderer.camera_pos().chunk());
                 let gui_data = self.gui.render(&self.window);
 
                 self.renderer
-                    .render(elasped, &self.window, &self.inputs, &gui_data.0, gui_data.1)
+                    .render(
+                        elasped,
+                        &self.window,
+                        &self.inputs,
+                        &gui_data.0,
+                        self.gui.pixels_per_point(),
+                        gui_data.1,
+                    )
                     .context("Rendering failed")?;
                 None
             }
@@ -150,6 +216,39 @@ impl App {
                         .context("Pipeline recreation failed")?;
                     None
                 }
+                MainLoopEvent::RegenerateChunk => {
+                    self.world
+                        .regenerate_chunk(self.renderer.camera_pos().chunk())
+                        .context("Chunk regeneration failed")?;
+                    None
+                }
+                MainLoopEvent::RenderDistanceChanged => {
+                    self.world
+                        .set_render_distance(RenderDistance::from_options());
+                    None
+                }
+                MainLoopEvent::ProjectionChanged => {
+                    self.renderer.rebuild_proj();
+                    None
+                }
+                MainLoopEvent::DumpChunk(neighbours) => {
+                    // A diagnostic failing to write its file isn't worth stopping the app.
+                    let pos = self.renderer.camera_pos().chunk();
+                    if let Err(e) = self.world.dump_chunk(pos, neighbours) {
+                        warn!("Chunk dump failed: {:?}", e);
+                    }
+                    None
+                }
+                MainLoopEvent::Teleport(pos) => {
+                    self.renderer.teleport(pos);
+                    // While paused, the first tick after resuming loads around the new position.
+                    if !AppOptions::get().paused {
+                        self.world
+                            .recenter(self.renderer.camera_pos())
+                            .context("World recentering failed")?;
+                    }
+                    None
+                }
             },
             Event::LoopDestroyed => {
                 #[cfg(feature = "bench")]
@@ -161,14 +260,87 @@ impl App {
         Ok(control_flow)
     }
 
+    /// Handle a live or replayed input, recording it if enabled.
+    fn input(&mut self, input: Input) -> Result<()> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.input(input)?;
+        }
+        match input {
+            Input::KeyPressed(key) => {
+                if key == VirtualKeyCode::Escape {
+                    self.set_game_focused(false);
+                }
+                if key == VirtualKeyCode::F11 {
+                    let mode = AppOptions::get().fullscreen_mode;
+                    self.window.toggle_fullscreen(mode);
+                    // Not every platform sends a resize when the fullscreen changes.
+                    self.needs_recreate = true;
+                    self.window_size = self.window.inner_size();
+                }
+                debug::key_pressed(key);
+                self.inputs.key_pressed(key)
+            }
+            Input::KeyReleased(key) => self.inputs.key_released(key),
+            Input::MousePressed(button) => {
+                if self.game_focused {
+                    self.block_action(button);
+                } else if button == MouseButton::Left {
+                    self.set_game_focused(true);
+                }
+            }
+            Input::Scroll(y) => {
+                if self.game_focused {
+                    self.scroll_selected_block(y);
+                }
+            }
+            Input::Focused(focused) => self.set_game_focused(focused),
+            Input::MouseMotion(delta) => {
+                if self.game_focused {
+                    self.inputs.mouse_moved(delta);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Break the targeted block with the left button or place the selected one against it with the right button.
+    fn block_action(&mut self, button: MouseButton) {
+        let Some(hit) = self.world.raycast(self.renderer.camera_pos(), REACH) else {
+            return;
+        };
+        match button {
+            MouseButton::Left => {
+                let block = self.world.get_block(hit.block);
+                if self.world.set_block(hit.block, BlockId::Air)
+                    && let Some(block) = block
+                {
+                    self.renderer.block_broken(hit.block, block);
+                }
+            }
+            MouseButton::Right => {
+                let player = self.renderer.player_bounding_box();
+                if !Aabb::from_block(hit.adjacent).intersects(&player) {
+                    let block = BlockId::PLACEABLE[self.selected_block];
+                    self.world.set_block(hit.adjacent, block);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn scroll_selected_block(&mut self, y: f64) {
+        let count = BlockId::PLACEABLE.len();
+        if y > 0. {
+            self.selected_block = (self.selected_block + 1) % count;
+        } else if y < 0. {
+            self.selected_block = (self.selected_block + count - 1) % count;
+        }
+        gui::DATA.write().expect("Lock poisoned").selected_block =
+            BlockId::PLACEABLE[self.selected_block];
+    }
+
     fn set_game_focused(&mut self, focused: bool) {
         self.game_focused = focused;
-        if focused {
-            self.window.grab_cursor();
-            self.window.set_cursor_visible(false);
-        } else {
-            self.window.release_cursor();
-            self.window.set_cursor_visible(true);
-        }
+        self.window.capture_cursor(focused);
     }
 }
@@ -1,17 +1,28 @@
 use std::{
-    fs::{self, OpenOptions},
-    sync::{atomic::Ordering, Mutex},
-    time::Instant,
+    env,
+    fs::{self, File, OpenOptions},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 
-use crate::gui;
+use crate::{
+    gui,
+    world::{self, EntityPos},
+};
+
+/// Environment variable holding the path of the camera path file.
+const CAMERA_PATH_VAR: &str = "BENCH_CAMERA_PATH";
 
 #[derive(Debug)]
 struct DataFrame {
     time: Instant,
     fps: f32,
+    stutters: usize,
 
     pub created_chunks_total: usize,
     pub generated_chunks_total: usize,
@@ -25,6 +36,9 @@ struct DataFrame {
 
     pub loaded_chunks: usize,
     pub loaded_regions: usize,
+
+    pub draw_calls: usize,
+    pub triangles: usize,
 }
 
 impl From<&gui::Data> for DataFrame {
@@ -32,6 +46,7 @@ impl From<&gui::Data> for DataFrame {
         Self {
             time: Instant::now(),
             fps: data.fps_calculator.fps(),
+            stutters: data.fps_calculator.stutters,
 
             created_chunks_total: data.created_chunks_total.load(Ordering::Relaxed),
             generated_chunks_total: data.generated_chunks_total.load(Ordering::Relaxed),
@@ -45,11 +60,92 @@ impl From<&gui::Data> for DataFrame {
 
             loaded_chunks: data.loaded_chunks.load(Ordering::Relaxed),
             loaded_regions: data.loaded_regions.load(Ordering::Relaxed),
+
+            draw_calls: data.draw_calls.load(Ordering::Relaxed),
+            triangles: data.triangles.load(Ordering::Relaxed),
         }
     }
 }
 
 static DATA: Mutex<Vec<DataFrame>> = Mutex::new(Vec::new());
+/// Duration of each chunk meshing, recorded by the meshing threads.
+pub static MESH_TIMES: MeshTimes = MeshTimes::new();
+
+/// Bucket `i` holds the durations in `[2^(i-1); 2^i[` µs, the bucket 0 the ones below 1 µs.
+/// The last one also holds the longer ones.
+const MESH_TIME_BUCKETS: usize = 24;
+
+/// Lock-free histogram of durations, by power of two of microseconds.
+#[derive(Debug)]
+pub struct MeshTimes {
+    buckets: [AtomicUsize; MESH_TIME_BUCKETS],
+    count: AtomicUsize,
+    total_nanos: AtomicU64,
+    min_nanos: AtomicU64,
+}
+
+impl MeshTimes {
+    const fn new() -> Self {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            buckets: [ZERO; MESH_TIME_BUCKETS],
+            count: AtomicUsize::new(0),
+            total_nanos: AtomicU64::new(0),
+            min_nanos: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    pub fn record(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(MESH_TIME_BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = duration.as_nanos() as u64;
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.min_nanos.fetch_min(nanos, Ordering::Relaxed);
+    }
+
+    /// Exclusive upper bound of the bucket `i`.
+    #[inline]
+    fn bucket_max(i: usize) -> Duration {
+        Duration::from_micros(1 << i)
+    }
+
+    fn min(&self) -> Option<Duration> {
+        let nanos = self.min_nanos.load(Ordering::Relaxed);
+        (nanos != u64::MAX).then(|| Duration::from_nanos(nanos))
+    }
+
+    fn average(&self) -> Option<Duration> {
+        let count = self.count.load(Ordering::Relaxed);
+        (count != 0)
+            .then(|| Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed) / count as u64))
+    }
+
+    /// Upper bound of the bucket holding the `percent` percentile.
+    fn percentile_max(&self, percent: usize) -> Option<Duration> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+        let rank = (count * percent).div_ceil(100).max(1);
+        let mut seen = 0;
+        self.buckets.iter().enumerate().find_map(|(i, bucket)| {
+            seen += bucket.load(Ordering::Relaxed);
+            (seen >= rank).then(|| Self::bucket_max(i))
+        })
+    }
+
+    /// The upper bound and count of the non-empty buckets.
+    fn distribution(&self) -> impl Iterator<Item = (Duration, usize)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| (Self::bucket_max(i), bucket.load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count != 0)
+    }
+}
 
 pub fn append(gui_data: &gui::Data) {
     let frame = gui_data.into();
@@ -60,7 +156,10 @@ pub fn end() {
     let data = DATA.lock().expect("Mutex poisoned");
     print_infos_fps(&data);
     print_infos_chunks(&data);
+    print_infos_spawn(&data);
+    print_infos_mesh_times(&MESH_TIMES);
     emit_csv(&data).expect("Csv emit failed");
+    emit_mesh_times_csv(&MESH_TIMES).expect("Csv emit failed");
 }
 
 fn print_infos_fps(data: &[DataFrame]) {
@@ -80,6 +179,14 @@ fn print_infos_fps(data: &[DataFrame]) {
 fn print_infos_chunks(data: &[DataFrame]) {
     let last = data.last().expect("Data is empty");
 
+    println!(
+        "Workers: {}",
+        if world::chunks::shared_workers() {
+            "shared"
+        } else {
+            "dedicated"
+        }
+    );
     println!("Total created chunks: {}", last.created_chunks_total);
     println!("Total generated chunks: {}", last.generated_chunks_total);
     println!("Total meshed chunks: {}", last.meshed_chunks_total);
@@ -98,11 +205,45 @@ fn print_infos_chunks(data: &[DataFrame]) {
     );
 }
 
-fn emit_csv(data: &[DataFrame]) -> Result<()> {
+/// How long the spawn takes: until the chunks around the start are all generated and meshed for the first time.
+fn print_infos_spawn(data: &[DataFrame]) {
+    let first = data.first().expect("Data is empty");
+    let Some(spawned) = data.iter().find(|frame| {
+        frame.meshed_chunks_total > 0
+            && frame.waiting_for_generate_chunks == 0
+            && frame.waiting_for_mesh_chunks == 0
+    }) else {
+        println!("Spawn not finished");
+        return;
+    };
+    let duration = spawned.time - first.time;
+    println!("Spawn time: {:?}", duration);
+    println!(
+        "Spawn meshing rate: {}/s",
+        spawned.meshed_chunks_total as f32 / duration.as_secs_f32()
+    );
+}
+
+fn print_infos_mesh_times(times: &MeshTimes) {
+    let (Some(min), Some(average), Some(p99)) =
+        (times.min(), times.average(), times.percentile_max(99))
+    else {
+        println!("No chunk meshed");
+        return;
+    };
+    println!("Mesh time min/avg/p99: {:?}/{:?}/<{:?}", min, average, p99);
+    println!("Mesh time distribution:");
+    for (max, count) in times.distribution() {
+        println!("  <{:?}: {}", max, count);
+    }
+}
+
+/// `name` is appended to the date of the file.
+fn create_result_file(name: &str) -> Result<File> {
     let dir = "bench_results";
     fs::create_dir_all(dir)?;
     let path = format!(
-        "{dir}/{}_{}.csv",
+        "{dir}/{}_{}{name}.csv",
         chrono::Local::now().format("%F-%H-%M-%S"),
         if cfg!(debug_assertions) {
             "debug"
@@ -111,11 +252,28 @@ fn emit_csv(data: &[DataFrame]) -> Result<()> {
         }
     );
     let file = OpenOptions::new().create_new(true).write(true).open(path)?;
+    Ok(file)
+}
+
+fn emit_mesh_times_csv(times: &MeshTimes) -> Result<()> {
+    let file = create_result_file("_mesh_times")?;
+    let mut writer = csv::Writer::from_writer(&file);
+    writer.write_record(["max_us", "count"])?;
+    for (max, count) in times.distribution() {
+        writer.serialize((max.as_micros() as u64, count))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn emit_csv(data: &[DataFrame]) -> Result<()> {
+    let file = create_result_file("")?;
     let mut writer = csv::Writer::from_writer(&file);
 
     writer.write_record([
         "time",
         "fps",
+        "stutters",
         "created_chunks_total",
         "generated_chunks_total",
         "meshed_chunks_total",
@@ -126,10 +284,13 @@ fn emit_csv(data: &[DataFrame]) -> Result<()> {
         "waiting_for_mesh_chunks",
         "loaded_chunks",
         "loaded_regions",
+        "draw_calls",
+        "triangles",
     ])?;
     for DataFrame {
         time,
         fps,
+        stutters,
         created_chunks_total,
         generated_chunks_total,
         meshed_chunks_total,
@@ -140,12 +301,15 @@ fn emit_csv(data: &[DataFrame]) -> Result<()> {
         waiting_for_mesh_chunks,
         loaded_chunks,
         loaded_regions,
+        draw_calls,
+        triangles,
     } in data
     {
         let time = time.duration_since(data[0].time).as_secs_f32();
         writer.serialize((
             time,
             fps,
+            stutters,
             created_chunks_total,
             generated_chunks_total,
             meshed_chunks_total,
@@ -156,8 +320,143 @@ fn emit_csv(data: &[DataFrame]) -> Result<()> {
             waiting_for_mesh_chunks,
             loaded_chunks,
             loaded_regions,
+            draw_calls,
+            triangles,
         ))?;
     }
     writer.flush()?;
     Ok(())
 }
+
+/// Keyframes the camera goes through during the bench, linearly interpolated.
+///
+/// The file has one keyframe per line: `time x y z pitch yaw`, with the time in seconds from the start.
+/// Empty lines and lines starting with `#` are ignored. The yaw isn't wrapped: going from 350 to 10
+/// turns back by 340 degrees, use 370 instead.
+#[derive(Debug)]
+pub struct CameraPath {
+    keyframes: Vec<(Duration, EntityPos)>,
+}
+
+impl CameraPath {
+    /// Load the file at the path in `BENCH_CAMERA_PATH`. Return `None` if it isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = env::var_os(CAMERA_PATH_VAR) else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+        Self::parse(&content).map(Some)
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut keyframes: Vec<(Duration, EntityPos)> = Vec::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(str::parse::<f32>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("Invalid number at line {}", i + 1))?;
+            let &[time, x, y, z, pitch, yaw] = values.as_slice() else {
+                bail!(
+                    "Expected 6 values at line {}, found {}",
+                    i + 1,
+                    values.len()
+                );
+            };
+            let time = Duration::try_from_secs_f32(time)
+                .with_context(|| format!("Invalid time at line {}", i + 1))?;
+            if let Some(&(last, _)) = keyframes.last() && time < last {
+                bail!("Keyframes aren't sorted by time at line {}", i + 1);
+            }
+            keyframes.push((time, EntityPos::new(x, y, z, pitch, yaw)));
+        }
+        if keyframes.is_empty() {
+            bail!("No keyframe");
+        }
+        Ok(Self { keyframes })
+    }
+
+    /// The camera stays on the first keyframe before its time and on the last one after it.
+    pub fn pos_at(&self, time: Duration) -> EntityPos {
+        let next = self.keyframes.partition_point(|&(t, _)| t <= time);
+        if next == 0 {
+            return self.keyframes[0].1;
+        }
+        if next == self.keyframes.len() {
+            return self.keyframes[next - 1].1;
+        }
+        let (start_time, start) = self.keyframes[next - 1];
+        let (end_time, end) = self.keyframes[next];
+        let t = (time - start_time).as_secs_f32() / (end_time - start_time).as_secs_f32();
+        EntityPos {
+            pos: start.pos + (end.pos - start.pos) * t,
+            look: start.look + (end.look - start.look) * t,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_path_interpolation() {
+        let path = CameraPath::parse(
+            "# time x y z pitch yaw
+            1 0 100 0 0 0
+
+            3 100 200 -50 -20 90
+            4 100 200 -50 -20 90",
+        )
+        .expect("Valid path");
+
+        assert_eq!(path.pos_at(Duration::ZERO), path.keyframes[0].1);
+        assert_eq!(
+            path.pos_at(Duration::from_secs(2)),
+            EntityPos::new(50., 150., -25., -10., 45.)
+        );
+        assert_eq!(
+            path.pos_at(Duration::from_secs(10)),
+            EntityPos::new(100., 200., -50., -20., 90.)
+        );
+    }
+
+    #[test]
+    fn mesh_times_histogram() {
+        let times = MeshTimes::new();
+        assert_eq!(times.percentile_max(99), None);
+
+        for _ in 0..98 {
+            times.record(Duration::from_micros(100));
+        }
+        times.record(Duration::from_nanos(500));
+        times.record(Duration::from_millis(5));
+
+        assert_eq!(times.min(), Some(Duration::from_nanos(500)));
+        assert_eq!(times.average(), Some(Duration::from_nanos(148_005)));
+        assert_eq!(times.percentile_max(50), Some(Duration::from_micros(128)));
+        assert_eq!(times.percentile_max(99), Some(Duration::from_micros(128)));
+        assert_eq!(times.percentile_max(100), Some(Duration::from_micros(8192)));
+        assert_eq!(
+            times.distribution().collect::<Vec<_>>(),
+            [
+                (Duration::from_micros(1), 1),
+                (Duration::from_micros(128), 98),
+                (Duration::from_micros(8192), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn camera_path_invalid() {
+        assert!(CameraPath::parse("").is_err());
+        assert!(CameraPath::parse("0 1 2 3 4").is_err());
+        assert!(CameraPath::parse("0 1 2 3 4 five").is_err());
+        assert!(CameraPath::parse("2 0 0 0 0 0\n1 0 0 0 0 0").is_err());
+    }
+}
@@ -1,9 +1,11 @@
+#[cfg(feature = "hot_shaders")]
+use log::warn;
 use vulkanalia::vk;
 use winit::event::VirtualKeyCode;
 
 use crate::{
     events::{self, MainLoopEvent},
-    options::OPTIONS,
+    options::{AntiAliasing, OPTIONS},
 };
 
 pub fn key_pressed(key: VirtualKeyCode) {
@@ -22,6 +24,40 @@ pub fn key_pressed(key: VirtualKeyCode) {
             }
             Some(MainLoopEvent::RecreatePipeline)
         }
+        VirtualKeyCode::F3 => {
+            let mut options = OPTIONS.write().expect("Lock poisoned");
+            options.walk_mode = !options.walk_mode;
+            None
+        }
+        VirtualKeyCode::F4 => Some(MainLoopEvent::RegenerateChunk),
+        VirtualKeyCode::F5 => {
+            let mut options = OPTIONS.write().expect("Lock poisoned");
+            options.anti_aliasing = match options.anti_aliasing {
+                AntiAliasing::None => AntiAliasing::Fxaa,
+                AntiAliasing::Fxaa => AntiAliasing::None,
+            };
+            Some(MainLoopEvent::RecreatePipeline)
+        }
+        VirtualKeyCode::F6 => {
+            let mut options = OPTIONS.write().expect("Lock poisoned");
+            options.paused = !options.paused;
+            None
+        }
+        VirtualKeyCode::F7 => {
+            let mut options = OPTIONS.write().expect("Lock poisoned");
+            options.debug_camera = !options.debug_camera;
+            None
+        }
+        // The old pipelines are kept if a shader doesn't compile.
+        #[cfg(feature = "hot_shaders")]
+        VirtualKeyCode::F8 => match crate::render::hot_shaders::reload() {
+            Ok(()) => Some(MainLoopEvent::RecreatePipeline),
+            Err(e) => {
+                warn!("Shaders reload failed: {:?}", e);
+                None
+            }
+        },
+        VirtualKeyCode::F9 => Some(MainLoopEvent::DumpChunk(false)),
         _ => None,
     };
     if let Some(event) = event_to_send {
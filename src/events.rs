@@ -1,3 +1,4 @@
+use nalgebra_glm::Vec3;
 use winit::event_loop::{EventLoop, EventLoopProxy};
 
 use crate::utils::DerefOnceCell;
@@ -26,4 +27,14 @@ pub fn send_event(event: MainLoopEvent) {
 #[derive(Debug)]
 pub enum MainLoopEvent {
     RecreatePipeline,
+    /// Generate and mesh again the chunk the player is in.
+    RegenerateChunk,
+    /// Apply `AppOptions::render_distance` to the loaded chunks.
+    RenderDistanceChanged,
+    /// Rebuild the cameras projection, after `AppOptions::fov_axis` changed.
+    ProjectionChanged,
+    /// Move the player to these world coordinates and load the chunks around them at once.
+    Teleport(Vec3),
+    /// Write the blocks of the chunk the player is in to a file, with its neighbours if `true`.
+    DumpChunk(bool),
 }
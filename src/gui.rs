@@ -1,22 +1,35 @@
 use std::{
     fmt::Debug,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         RwLock,
     },
     time::{Duration, Instant},
 };
 
 use egui::{ClippedPrimitive, TexturesDelta, Ui};
+use nalgebra_glm::Vec3;
 use winit::{event::WindowEvent, event_loop::EventLoopWindowTarget, window::Window};
 
-use crate::world::EntityPos;
+use crate::{
+    events::{self, MainLoopEvent},
+    minimap::Minimap,
+    options::{AppOptions, FovAxis, OPTIONS},
+    render::{HeapInfo, MAX_PARTICLES},
+    world::{chunk::ChunkState, BlockId, EntityPos, RenderDistance},
+};
 
 pub type Vertex = egui::epaint::Vertex;
 
+/// Farthest coordinate the teleport form accepts on each axis. `f32` still has a sub-block precision there.
+const TELEPORT_LIMIT: f32 = 1_000_000.;
+
 pub struct GuiContext {
     ctx: egui::Context,
     state: egui_winit::State,
+    minimap: Minimap,
+    /// Count of chunks around the spawn while their loading is shown. `None` once they are ready or it's closed.
+    world_loading: Option<usize>,
 }
 
 impl Debug for GuiContext {
@@ -31,7 +44,18 @@ impl GuiContext {
     pub fn new<T>(event_loop: &EventLoopWindowTarget<T>) -> Self {
         let ctx = egui::Context::default();
         let state = egui_winit::State::new(event_loop);
-        Self { ctx, state }
+        Self {
+            ctx,
+            state,
+            minimap: Minimap::new(),
+            world_loading: Some(RenderDistance::from_options().chunks_count()),
+        }
+    }
+
+    /// Of the primitives of the last frame.
+    #[inline]
+    pub fn pixels_per_point(&self) -> f32 {
+        self.ctx.pixels_per_point()
     }
 
     /// Return `true` if the event should be propagated.
@@ -43,13 +67,39 @@ impl GuiContext {
     pub fn render(&mut self, window: &Window) -> (Vec<ClippedPrimitive>, TexturesDelta) {
         let mut data = DATA.write().expect("Lock poisoned");
         data.fps_calculator.tick();
+        let camera_pos = data.camera_pos;
+        self.minimap.update(&self.ctx, camera_pos.chunk().flat());
 
+        set_scale(
+            &self.ctx,
+            egui_winit::native_pixels_per_point(window),
+            AppOptions::get().gui_scale,
+        );
+        let mut world_loading = self.world_loading;
         let output = self.ctx.run(self.state.take_egui_input(window), |ctx| {
             egui::Window::new("Debug")
                 .resizable(false)
                 .movable(false)
                 .show(ctx, |ui| self.ui(ui, &mut data));
+            self.minimap.show(ctx, camera_pos);
+            if let Some(expected) = world_loading {
+                let (done, ready) = data.spawn_progress(expected);
+                let mut open = !ready;
+                egui::Window::new("Generating world")
+                    .anchor(egui::Align2::CENTER_CENTER, [0., 0.])
+                    .resizable(false)
+                    .collapsible(false)
+                    .open(&mut open)
+                    .show(ctx, |ui| {
+                        ui.label(format!("Generating world… {}/{} chunks", done, expected));
+                        ui.add(egui::ProgressBar::new(done as f32 / expected as f32));
+                    });
+                if !open {
+                    world_loading = None;
+                }
+            }
         });
+        self.world_loading = world_loading;
 
         #[cfg(feature = "bench")]
         crate::bench::append(&data);
@@ -66,10 +116,20 @@ impl GuiContext {
             "Frame time: {:.2?}",
             data.fps_calculator.frame_time
         ));
+        ui.label(format!("Stutters: {}", data.fps_calculator.stutters));
         ui.label(format!("Position: {}", data.camera_pos));
         let chunk_pos = data.camera_pos.chunk();
-        ui.label(format!("Chunk: {}", chunk_pos));
+        ui.label(format!("Chunk: {} ({:?})", chunk_pos, data.chunk_state));
         ui.label(format!("Region: {}", chunk_pos.region()));
+        teleport_form(ui);
+        ui.horizontal(|ui| {
+            if ui.button("Dump chunk (F9)").clicked() {
+                events::send_event(MainLoopEvent::DumpChunk(false));
+            }
+            if ui.button("Dump with neighbours").clicked() {
+                events::send_event(MainLoopEvent::DumpChunk(true));
+            }
+        });
         ui.label(format!(
             "Chunks created/generated/meshed: {}/{}/{}",
             data.created_chunks_total.load(Ordering::Relaxed),
@@ -81,17 +141,244 @@ impl GuiContext {
             data.waiting_for_generate_chunks.load(Ordering::Relaxed),
             data.waiting_for_mesh_chunks.load(Ordering::Relaxed)
         ));
+        if data.loading_throttled.load(Ordering::Relaxed) {
+            ui.label("Chunks loading throttled");
+        }
         ui.label(format!(
             "Loaded chunks/regions: {}/{}",
             data.loaded_chunks.load(Ordering::Relaxed),
             data.loaded_regions.load(Ordering::Relaxed)
         ));
+        ui.label(format!(
+            "Draw calls/triangles: {}/{}",
+            data.draw_calls.load(Ordering::Relaxed),
+            data.triangles.load(Ordering::Relaxed)
+        ));
+        let hits = data.height_maps_cache_hits.load(Ordering::Relaxed);
+        let misses = data.height_maps_cache_misses.load(Ordering::Relaxed);
+        ui.label(format!(
+            "Height maps cache size/hit rate: {}/{:.1}%",
+            data.height_maps_cache_size.load(Ordering::Relaxed),
+            hits as f32 / (hits + misses).max(1) as f32 * 100.
+        ));
+        for (memory_type, fragmentation) in data.memory_fragmentation.iter().enumerate() {
+            if let Some(fragmentation) = fragmentation {
+                ui.label(format!(
+                    "Memory type {} fragmentation: {:.1}%",
+                    memory_type,
+                    fragmentation * 100.
+                ));
+            }
+        }
+        for (i, heap) in data.memory_heaps.iter().enumerate() {
+            let kind = match (heap.device_local, heap.host_visible) {
+                (true, true) => "device local, host visible",
+                (true, false) => "device local",
+                (false, true) => "host visible",
+                (false, false) => "host",
+            };
+            let size = heap.size >> 20;
+            ui.label(match heap.budget {
+                Some(budget) => format!(
+                    "Heap {} ({}): {}/{} MiB used, {} MiB total",
+                    i,
+                    kind,
+                    budget.usage >> 20,
+                    budget.budget >> 20,
+                    size
+                ),
+                None => format!("Heap {} ({}): {} MiB", i, kind, size),
+            });
+        }
+
+        ui.separator();
+        let mut options = OPTIONS.write().expect("Lock poisoned");
+        ui.label(format!("Selected block: {:?}", data.selected_block));
+        ui.label(format!("Time of day: {:.2}", data.time_of_day));
+        ui.checkbox(&mut options.paused, "Pause world (F6)");
+        ui.add(
+            egui::Slider::new(&mut options.world_tick_rate, 1..=120).text("World ticks per second"),
+        );
+        ui.checkbox(&mut options.debug_camera, "Debug camera (F7)");
+        let render_distance = &mut options.render_distance;
+        let mut render_distance_changed = false;
+        for (radius, axis) in [
+            (&mut render_distance.x, "X"),
+            (&mut render_distance.y, "Y"),
+            (&mut render_distance.z, "Z"),
+        ] {
+            render_distance_changed |= ui
+                .add(egui::Slider::new(radius, 0..=32).text(format!("{axis} render distance")))
+                .changed();
+        }
+        if render_distance_changed {
+            events::send_event(MainLoopEvent::RenderDistanceChanged);
+        }
+        let horizontal = options.render_distance.horizontal();
+        ui.add(
+            egui::Slider::new(&mut options.look_ahead, 0..=horizontal).text("Look ahead chunks"),
+        );
+        ui.add(egui::Slider::new(&mut options.terrain_shading, 0.0..=1.0).text("Terrain shading"));
+        ui.checkbox(&mut options.day_cycle_paused, "Pause day cycle");
+        ui.add(
+            egui::Slider::new(&mut options.day_cycle_speed, 0.0..=100.0)
+                .logarithmic(true)
+                .text("Day cycle speed"),
+        );
+        ui.checkbox(&mut options.minimap.enabled, "Minimap");
+        ui.add_enabled_ui(options.minimap.enabled, |ui| {
+            ui.add(egui::Slider::new(&mut options.minimap.radius, 1..=16).text("Minimap radius"));
+            ui.checkbox(&mut options.minimap.rotate, "Rotate minimap");
+        });
+        let simplification = &mut options.mesh_simplification;
+        let mut simplify = simplification.distance.is_some();
+        ui.checkbox(&mut simplify, "Simplify distant meshes");
+        ui.add_enabled_ui(simplify, |ui| {
+            let mut distance = simplification.distance.unwrap_or(8);
+            ui.add(egui::Slider::new(&mut distance, 1..=32).text("Simplification distance"));
+            simplification.distance = simplify.then_some(distance);
+            ui.add(
+                egui::Slider::new(&mut simplification.light_tolerance, 1..=15)
+                    .text("Simplification light tolerance"),
+            );
+        });
+        ui.checkbox(
+            &mut options.wait_for_neighbours,
+            "Mesh chunks after their neighbours",
+        );
+        ui.checkbox(&mut options.particles.enabled, "Block break particles");
+        ui.add_enabled_ui(options.particles.enabled, |ui| {
+            ui.add(
+                egui::Slider::new(&mut options.particles.per_break, 1..=MAX_PARTICLES)
+                    .logarithmic(true)
+                    .text("Particles per break"),
+            );
+            ui.add(
+                egui::Slider::new(&mut options.particles.lifetime, 0.1..=5.0)
+                    .text("Particles lifetime (s)"),
+            );
+        });
+        ui.checkbox(&mut options.horizon.enabled, "Horizon ground");
+        ui.add_enabled_ui(options.horizon.enabled, |ui| {
+            ui.horizontal(|ui| {
+                ui.color_edit_button_rgb(&mut options.horizon.color);
+                ui.label("Ground color");
+            });
+        });
+        let overlay = &mut options.wireframe_overlay;
+        let mut overlay_changed = ui
+            .checkbox(&mut overlay.enabled, "Wireframe overlay")
+            .changed();
+        ui.add_enabled_ui(overlay.enabled, |ui| {
+            ui.horizontal(|ui| {
+                overlay_changed |= ui.color_edit_button_rgb(&mut overlay.color).changed();
+                ui.label("Wireframe color");
+            });
+            overlay_changed |= ui
+                .add(
+                    egui::Slider::new(&mut overlay.depth_bias_constant, -16.0..=0.0)
+                        .text("Wireframe depth bias"),
+                )
+                .changed();
+            overlay_changed |= ui
+                .add(
+                    egui::Slider::new(&mut overlay.depth_bias_slope, -8.0..=0.0)
+                        .text("Wireframe slope bias"),
+                )
+                .changed();
+        });
+        if overlay_changed {
+            events::send_event(MainLoopEvent::RecreatePipeline);
+        }
+        if ui
+            .checkbox(&mut options.depth_buffer, "Depth buffer")
+            .changed()
+        {
+            events::send_event(MainLoopEvent::RecreatePipeline);
+        }
+        ui.horizontal(|ui| {
+            ui.label("FOV axis");
+            let vertical = ui.radio_value(&mut options.fov_axis, FovAxis::Vertical, "Vertical");
+            let horizontal =
+                ui.radio_value(&mut options.fov_axis, FovAxis::Horizontal, "Horizontal");
+            if vertical.changed() || horizontal.changed() {
+                events::send_event(MainLoopEvent::ProjectionChanged);
+            }
+        });
+        ui.checkbox(&mut options.raw_mouse_input, "Raw mouse input");
+        ui.add_enabled(
+            !options.raw_mouse_input,
+            egui::Slider::new(&mut options.mouse_smoothing, 0.0..=0.95).text("Mouse smoothing"),
+        );
+        ui.add(
+            egui::Slider::new(&mut options.fly_speed.sprint, 1.0..=20.0)
+                .text("Sprint speed (Ctrl)"),
+        );
+        ui.add(egui::Slider::new(&mut options.fly_speed.slow, 0.05..=1.0).text("Slow speed (Alt)"));
+        ui.add(
+            egui::Slider::new(&mut options.gui_scale, 0.5..=3.0)
+                .step_by(0.25)
+                .text("GUI scale"),
+        );
+
+        #[cfg(feature = "debug_boxes")]
+        {
+            ui.separator();
+            let boxes = &mut options.debug_boxes;
+            ui.checkbox(&mut boxes.chunks, "Chunk borders");
+            ui.checkbox(&mut boxes.regions, "Region borders");
+            ui.checkbox(&mut boxes.frustum, "Freeze frustum");
+        }
+    }
+}
+
+/// X, Y and Z fields and a button sending `MainLoopEvent::Teleport`. The fields are kept in the egui memory.
+fn teleport_form(ui: &mut Ui) {
+    let id = ui.id().with("teleport");
+    let mut fields = ui
+        .data_mut(|data| data.get_temp::<[String; 3]>(id))
+        .unwrap_or_default();
+    ui.horizontal(|ui| {
+        for (field, axis) in fields.iter_mut().zip(["X", "Y", "Z"]) {
+            ui.add(
+                egui::TextEdit::singleline(field)
+                    .hint_text(axis)
+                    .desired_width(60.),
+            );
+        }
+        let target = parse_teleport(&fields);
+        if ui
+            .add_enabled(target.is_some(), egui::Button::new("Teleport"))
+            .clicked()
+            && let Some(target) = target
+        {
+            events::send_event(MainLoopEvent::Teleport(target));
+        }
+    });
+    ui.data_mut(|data| data.insert_temp(id, fields));
+}
+
+/// The coordinates of the teleport form clamped to [`TELEPORT_LIMIT`], `None` if one isn't a finite number.
+fn parse_teleport(fields: &[String; 3]) -> Option<Vec3> {
+    let mut pos = Vec3::zeros();
+    for (coord, field) in pos.iter_mut().zip(fields) {
+        let value = field
+            .trim()
+            .parse::<f32>()
+            .ok()
+            .filter(|value| value.is_finite())?;
+        *coord = value.clamp(-TELEPORT_LIMIT, TELEPORT_LIMIT);
     }
+    Some(pos)
 }
 
 #[derive(Debug)]
 pub struct Data {
     pub camera_pos: EntityPos,
+    /// Of the chunk of `camera_pos`.
+    pub chunk_state: ChunkState,
+    pub time_of_day: f32,
+    pub selected_block: BlockId,
     pub fps_calculator: FpsCalculator,
 
     pub created_chunks_total: AtomicUsize,
@@ -106,12 +393,30 @@ pub struct Data {
 
     pub loaded_chunks: AtomicUsize,
     pub loaded_regions: AtomicUsize,
+    /// `true` if the last world tick stopped loading chunks because of the generation backlog.
+    pub loading_throttled: AtomicBool,
+
+    pub height_maps_cache_hits: AtomicUsize,
+    pub height_maps_cache_misses: AtomicUsize,
+    pub height_maps_cache_size: AtomicUsize,
+
+    /// Terrain and GUI draws of the last frame.
+    pub draw_calls: AtomicUsize,
+    pub triangles: AtomicUsize,
+
+    /// Of each allocator pool, indexed by memory type. `None` if the pool has no free memory.
+    pub memory_fragmentation: Vec<Option<f32>>,
+    /// Refreshed every `heaps::REFRESH_INTERVAL`.
+    pub memory_heaps: Vec<HeapInfo>,
 }
 
 impl Data {
     const fn new() -> Self {
         Self {
             camera_pos: EntityPos::new(0., 0., 0., 0., 0.),
+            chunk_state: ChunkState::Unloaded,
+            time_of_day: 0.,
+            selected_block: BlockId::PLACEABLE[0],
             fps_calculator: FpsCalculator::new(),
 
             created_chunks_total: AtomicUsize::new(0),
@@ -126,9 +431,31 @@ impl Data {
 
             loaded_chunks: AtomicUsize::new(0),
             loaded_regions: AtomicUsize::new(0),
+            loading_throttled: AtomicBool::new(false),
+
+            height_maps_cache_hits: AtomicUsize::new(0),
+            height_maps_cache_misses: AtomicUsize::new(0),
+            height_maps_cache_size: AtomicUsize::new(0),
+
+            draw_calls: AtomicUsize::new(0),
+            triangles: AtomicUsize::new(0),
+
+            memory_fragmentation: Vec::new(),
+            memory_heaps: Vec::new(),
         }
     }
 
+    /// Count of the `expected` chunks around the spawn done generating, and `true` once they are all meshed too.
+    fn spawn_progress(&self, expected: usize) -> (usize, bool) {
+        let done = self
+            .created_chunks_total
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.waiting_for_generate_chunks.load(Ordering::Relaxed))
+            .min(expected);
+        let ready = done == expected && self.waiting_for_mesh_chunks.load(Ordering::Relaxed) == 0;
+        (done, ready)
+    }
+
     fn reset(&mut self) {
         self.created_chunks.store(0, Ordering::Relaxed);
         self.generated_chunks.store(0, Ordering::Relaxed);
@@ -138,11 +465,23 @@ impl Data {
 
 pub static DATA: RwLock<Data> = RwLock::new(Data::new());
 
+/// Applied by the next `Context::run`, egui-winit then follows it instead of the scale factor of the window.
+fn set_scale(ctx: &egui::Context, native_pixels_per_point: f32, scale: f32) {
+    ctx.set_pixels_per_point(native_pixels_per_point * scale);
+}
+
+/// A frame taking more than this times the average frame time is a stutter.
+const STUTTER_FACTOR: u32 = 2;
+
 #[derive(Debug, Clone)]
 pub struct FpsCalculator {
+    /// Averaged over a few frames.
     pub frame_time: Duration,
     pub start_time: Option<Instant>,
     pub frame_count: u32,
+    last_frame_time: Option<Instant>,
+    /// Count of frames longer than `STUTTER_FACTOR` times the average frame time since the start.
+    pub stutters: usize,
 }
 
 impl FpsCalculator {
@@ -151,16 +490,31 @@ impl FpsCalculator {
             frame_time: Duration::new(0, 0),
             start_time: None,
             frame_count: 0,
+            last_frame_time: None,
+            stutters: 0,
         }
     }
 
+    #[inline]
     fn tick(&mut self) {
+        self.tick_at(Instant::now())
+    }
+
+    fn tick_at(&mut self, now: Instant) {
         const FRAMES_COUNT: u32 = 5;
-        let start_time = *self.start_time.get_or_insert_with(Instant::now);
+
+        if let Some(last_frame_time) = self.last_frame_time
+            && self.frame_time != Duration::ZERO
+            && now - last_frame_time > self.frame_time * STUTTER_FACTOR
+        {
+            self.stutters += 1;
+        }
+        self.last_frame_time = Some(now);
+
+        let start_time = *self.start_time.get_or_insert(now);
 
         self.frame_count += 1;
         if self.frame_count == FRAMES_COUNT {
-            let now = Instant::now();
             let elapsed = now - start_time;
             self.frame_time = elapsed.div_f32(FRAMES_COUNT as f32);
             self.start_time = Some(now);
@@ -176,3 +530,79 @@ impl FpsCalculator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_progress() {
+        let data = Data::new();
+        assert_eq!(data.spawn_progress(10), (0, false));
+        data.created_chunks_total.store(12, Ordering::Relaxed);
+        data.waiting_for_generate_chunks.store(4, Ordering::Relaxed);
+        assert_eq!(data.spawn_progress(10), (8, false));
+        data.waiting_for_generate_chunks.store(0, Ordering::Relaxed);
+        data.waiting_for_mesh_chunks.store(3, Ordering::Relaxed);
+        assert_eq!(data.spawn_progress(10), (10, false));
+        data.waiting_for_mesh_chunks.store(0, Ordering::Relaxed);
+        assert_eq!(data.spawn_progress(10), (10, true));
+    }
+
+    #[test]
+    fn gui_scale() {
+        let ctx = egui::Context::default();
+        set_scale(&ctx, 2., 1.5);
+        let _ = ctx.run(egui::RawInput::default(), |_| {});
+        assert_eq!(ctx.pixels_per_point(), 3.);
+
+        set_scale(&ctx, 1., 0.5);
+        let _ = ctx.run(egui::RawInput::default(), |_| {});
+        assert_eq!(ctx.pixels_per_point(), 0.5);
+    }
+
+    #[test]
+    fn teleport_fields() {
+        let fields = |x: &str, y: &str, z: &str| [x.to_owned(), y.to_owned(), z.to_owned()];
+        assert_eq!(
+            parse_teleport(&fields("12.5", " -64 ", "3e2")),
+            Some(Vec3::new(12.5, -64., 300.))
+        );
+        assert_eq!(
+            parse_teleport(&fields("1e9", "0", "-1e12")),
+            Some(Vec3::new(TELEPORT_LIMIT, 0., -TELEPORT_LIMIT))
+        );
+        assert_eq!(parse_teleport(&fields("", "0", "0")), None);
+        assert_eq!(parse_teleport(&fields("1", "up", "0")), None);
+        assert_eq!(parse_teleport(&fields("1", "0", "NaN")), None);
+        assert_eq!(parse_teleport(&fields("inf", "0", "0")), None);
+    }
+
+    #[test]
+    fn stutters_detection() {
+        let mut fps_calculator = FpsCalculator::new();
+        let mut now = Instant::now();
+        let mut frames = |fps_calculator: &mut FpsCalculator, count: usize, millis: u64| {
+            for _ in 0..count {
+                now += Duration::from_millis(millis);
+                fps_calculator.tick_at(now);
+            }
+        };
+
+        frames(&mut fps_calculator, 20, 10);
+        assert_eq!(fps_calculator.stutters, 0);
+        frames(&mut fps_calculator, 1, 50);
+        assert_eq!(fps_calculator.stutters, 1);
+        // The spike raises the average for a while but doesn't flag the normal frames.
+        frames(&mut fps_calculator, 20, 10);
+        assert_eq!(fps_calculator.stutters, 1);
+        frames(&mut fps_calculator, 1, 25);
+        assert_eq!(fps_calculator.stutters, 2);
+        frames(&mut fps_calculator, 20, 10);
+        frames(&mut fps_calculator, 1, 15);
+        assert_eq!(fps_calculator.stutters, 2);
+
+        // The average isn't affected by the stutters detection.
+        assert!((fps_calculator.fps() - 100.).abs() < 1.);
+    }
+}
@@ -3,6 +3,7 @@ use std::{
     fmt::Debug,
     ptr,
     sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
 };
 
 use winit::event::VirtualKeyCode;
@@ -107,3 +108,68 @@ impl Inputs {
         self.keys.contains(&key)
     }
 }
+
+/// Exponential smoothing of the mouse movement.
+///
+/// The speed of the mouse is smoothed rather than the deltas so the result doesn't depend on the frame rate.
+#[derive(Debug, Default)]
+pub struct MouseSmoothing {
+    /// In pixels/s.
+    velocity: (f64, f64),
+}
+
+impl MouseSmoothing {
+    /// Return the smoothed delta of a frame lasting `elapsed`.
+    /// `factor` is the part of the previous speed kept after 1/60 s, 0 gives the raw delta.
+    pub fn apply(&mut self, delta: (f64, f64), elapsed: Duration, factor: f32) -> (f64, f64) {
+        let dt = elapsed.as_secs_f64();
+        if factor <= 0. || dt <= 0. {
+            self.velocity = (0., 0.);
+            return delta;
+        }
+        let kept = (factor.min(0.99) as f64).powf(dt * 60.);
+        self.velocity.0 = self.velocity.0 * kept + delta.0 / dt * (1. - kept);
+        self.velocity.1 = self.velocity.1 * kept + delta.1 / dt * (1. - kept);
+        (self.velocity.0 * dt, self.velocity.1 * dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mouse_smoothing_response() {
+        let frame = Duration::from_secs_f64(1. / 60.);
+        let mut smoothing = MouseSmoothing::default();
+        assert_eq!(smoothing.apply((3., -2.), frame, 0.), (3., -2.));
+
+        // A constant movement is reached progressively.
+        let mut previous = 0.;
+        for _ in 0..60 {
+            let (x, _) = smoothing.apply((10., 0.), frame, 0.5);
+            assert!(x >= previous && x <= 10.);
+            previous = x;
+        }
+        assert!((previous - 10.).abs() < 1e-6);
+
+        // Then decays once the mouse stops.
+        let (x, _) = smoothing.apply((0., 0.), frame, 0.5);
+        assert!((x - 5.).abs() < 1e-6);
+
+        // The same movement gives the same result whatever the frame rate.
+        let moved = |fps: u32| {
+            let mut smoothing = MouseSmoothing::default();
+            let elapsed = Duration::from_secs_f64(1. / fps as f64);
+            let mut total = 0.;
+            for i in 0..fps * 2 {
+                let delta = if i < fps / 4 { 240. / fps as f64 } else { 0. };
+                total += smoothing.apply((delta, 0.), elapsed, 0.8).0;
+            }
+            total
+        };
+        let (slow, fast) = (moved(30), moved(240));
+        assert!((slow - 60.).abs() < 0.1, "{slow}");
+        assert!((fast - 60.).abs() < 0.1, "{fast}");
+    }
+}
@@ -13,9 +13,6 @@
     unused_features
 )]
 #![allow(incomplete_features)]
-#![feature(new_uninit)]
-#![feature(maybe_uninit_write_slice)]
-#![feature(maybe_uninit_slice)]
 #![feature(adt_const_params)]
 #![feature(maybe_uninit_uninit_array)]
 #![feature(maybe_uninit_array_assume_init)]
@@ -30,6 +27,10 @@
 
 extern crate test;
 
+// The compute shader writes packed vertices.
+#[cfg(all(feature = "compute_meshing", feature = "debug_vertex"))]
+compile_error!("The `compute_meshing` and `debug_vertex` features can't be enabled together");
+
 mod app;
 #[cfg(feature = "bench")]
 mod bench;
@@ -37,8 +38,10 @@ mod debug;
 mod events;
 mod gui;
 mod inputs;
+mod minimap;
 mod options;
 mod render;
+mod replay;
 mod utils;
 mod world;
 
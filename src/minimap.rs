@@ -0,0 +1,209 @@
+use std::{
+    array,
+    collections::{hash_map::Entry, HashMap},
+    f32::consts::FRAC_PI_2,
+    time::{Duration, Instant},
+};
+
+use egui::{Color32, ColorImage, TextureHandle, TextureOptions};
+
+use crate::{
+    options::{AppOptions, GenerationOptions},
+    world::{cached_height_map, EntityPos, FlatChunkPos, HeightMap, CHUNK_SIZE},
+};
+
+/// Pixels per side of a chunk column, sampling one block out of `CHUNK_SIZE / COLUMN_PIXELS`.
+const COLUMN_PIXELS: usize = 8;
+/// The columns missing from the height maps cache are looked up again at most this often.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(250);
+/// Displayed size of a texture pixel.
+const PIXEL_SCALE: f32 = 2.;
+const BACKGROUND: Color32 = Color32::from_rgb(20, 20, 20);
+const LOW_COLOR: [f32; 3] = [40., 110., 50.];
+const HIGH_COLOR: [f32; 3] = [240., 240., 240.];
+
+type ColumnPixels = [Color32; COLUMN_PIXELS * COLUMN_PIXELS];
+
+/// Top-down view of the surface height around the player, from the generator height maps.
+///
+/// Keeping it cheap: each column is converted to pixels once, when its height map is first found in
+/// the cache, and the texture is only uploaded when a column is added or the player changes column.
+pub struct Minimap {
+    texture: Option<TextureHandle>,
+    /// Pixels of the columns in range of `center` whose height map was found.
+    columns: HashMap<FlatChunkPos, ColumnPixels>,
+    center: Option<FlatChunkPos>,
+    /// In chunks around `center`.
+    radius: usize,
+    last_update: Option<Instant>,
+}
+
+impl Minimap {
+    pub fn new() -> Self {
+        Self {
+            texture: None,
+            columns: HashMap::new(),
+            center: None,
+            radius: 0,
+            last_update: None,
+        }
+    }
+
+    pub fn update(&mut self, ctx: &egui::Context, player_chunk: FlatChunkPos) {
+        let options = AppOptions::get().minimap;
+        if !options.enabled {
+            self.texture = None;
+            self.columns.clear();
+            self.center = None;
+            return;
+        }
+
+        let now = Instant::now();
+        let moved = self.center != Some(player_chunk) || self.radius != options.radius;
+        if !moved
+            && self
+                .last_update
+                .is_some_and(|last| now - last < UPDATE_INTERVAL)
+        {
+            return;
+        }
+        self.last_update = Some(now);
+        self.center = Some(player_chunk);
+        self.radius = options.radius;
+
+        let radius = options.radius as i64;
+        self.columns.retain(|pos, _| {
+            (pos.x() - player_chunk.x()).abs() <= radius
+                && (pos.z() - player_chunk.z()).abs() <= radius
+        });
+        let generation = AppOptions::get().generation;
+        let mut changed = moved;
+        for x in -radius..=radius {
+            for z in -radius..=radius {
+                let pos = FlatChunkPos::new(player_chunk.x() + x, player_chunk.z() + z);
+                if let Entry::Vacant(entry) = self.columns.entry(pos)
+                    && let Some(map) = cached_height_map(pos)
+                {
+                    entry.insert(column_pixels(&map, &generation));
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            let image = self.image(player_chunk, options.radius);
+            match &mut self.texture {
+                Some(texture) => texture.set(image, TextureOptions::NEAREST),
+                None => {
+                    self.texture = Some(ctx.load_texture("minimap", image, TextureOptions::NEAREST))
+                }
+            }
+        }
+    }
+
+    /// The columns around `center`, x to the right and z to the bottom.
+    fn image(&self, center: FlatChunkPos, radius: usize) -> ColorImage {
+        let side = (2 * radius + 1) * COLUMN_PIXELS;
+        let mut image = ColorImage::new([side, side], BACKGROUND);
+        for (pos, pixels) in &self.columns {
+            let left = (pos.x() - center.x() + radius as i64) as usize * COLUMN_PIXELS;
+            let top = (pos.z() - center.z() + radius as i64) as usize * COLUMN_PIXELS;
+            for (i, color) in pixels.iter().enumerate() {
+                let (x, z) = (i / COLUMN_PIXELS, i % COLUMN_PIXELS);
+                image.pixels[(top + z) * side + left + x] = *color;
+            }
+        }
+        image
+    }
+
+    /// Draw the map in the top right corner, with a marker for the player.
+    pub fn show(&self, ctx: &egui::Context, player: EntityPos) {
+        let Some(texture) = &self.texture else {
+            return;
+        };
+        let rotate = AppOptions::get().minimap.rotate;
+        egui::Window::new("Minimap")
+            .anchor(egui::Align2::RIGHT_TOP, [-10., 10.])
+            .resizable(false)
+            .movable(false)
+            .title_bar(false)
+            .show(ctx, |ui| {
+                let size = texture.size_vec2() * PIXEL_SCALE;
+                // The player in the texture, in [0; 1].
+                let side = (2 * self.radius + 1) as f32;
+                let in_chunk = |v: f32| v.rem_euclid(CHUNK_SIZE as f32) / CHUNK_SIZE as f32;
+                let origin = egui::vec2(
+                    (self.radius as f32 + in_chunk(player.x)) / side,
+                    (self.radius as f32 + in_chunk(player.z)) / side,
+                );
+
+                let yaw = player.yaw().to_radians();
+                let mut image = egui::Image::new(texture, size);
+                // Clockwise angle bringing the look direction up.
+                if rotate {
+                    image = image.rotate(-FRAC_PI_2 - yaw, origin);
+                }
+                let rect = ui.add(image).rect;
+
+                let marker = rect.min + origin * size;
+                let heading = if rotate { -FRAC_PI_2 } else { yaw };
+                let painter = ui.painter();
+                painter.circle_filled(marker, 3., Color32::RED);
+                painter.line_segment(
+                    [
+                        marker,
+                        marker + egui::vec2(heading.cos(), heading.sin()) * 10.,
+                    ],
+                    (2., Color32::RED),
+                );
+            });
+    }
+}
+
+/// Indexed by `x * COLUMN_PIXELS + z`, like the height maps.
+fn column_pixels(map: &HeightMap, options: &GenerationOptions) -> ColumnPixels {
+    const STEP: usize = CHUNK_SIZE / COLUMN_PIXELS;
+    array::from_fn(|i| {
+        let (x, z) = (i / COLUMN_PIXELS * STEP, i % COLUMN_PIXELS * STEP);
+        height_color(map[x * CHUNK_SIZE + z], options)
+    })
+}
+
+fn height_color(height: i64, options: &GenerationOptions) -> Color32 {
+    let t = ((height - options.base_height) as f32 / options.amplitude.max(1) as f32).clamp(0., 1.);
+    let [r, g, b] = array::from_fn(|i| (LOW_COLOR[i] + (HIGH_COLOR[i] - LOW_COLOR[i]) * t) as u8);
+    Color32::from_rgb(r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimap_image_layout() {
+        let options = GenerationOptions::new();
+        let low = height_color(options.base_height, &options);
+        let high = height_color(options.base_height + options.amplitude, &options);
+        assert_eq!(low, Color32::from_rgb(40, 110, 50));
+        assert_eq!(high, Color32::from_rgb(240, 240, 240));
+        assert_eq!(height_color(i64::MIN / 2, &options), low);
+
+        // The highest blocks of the map are at x = 4 and z = 8, the pixel (1, 2) of the column.
+        let mut map = [options.base_height; CHUNK_SIZE * CHUNK_SIZE];
+        map[4 * CHUNK_SIZE + 8] = options.base_height + options.amplitude;
+        let mut minimap = Minimap::new();
+        let center = FlatChunkPos::new(10, -3);
+        minimap
+            .columns
+            .insert(FlatChunkPos::new(11, -4), column_pixels(&map, &options));
+
+        let image = minimap.image(center, 1);
+        assert_eq!(image.size, [3 * COLUMN_PIXELS, 3 * COLUMN_PIXELS]);
+        let pixel = |x: usize, y: usize| image.pixels[y * image.size[0] + x];
+        let (left, top) = (2 * COLUMN_PIXELS, 0);
+        assert_eq!(pixel(left + 1, top + 2), high);
+        assert_eq!(pixel(left, top), low);
+        assert_eq!(pixel(left - 1, top), BACKGROUND);
+        assert_eq!(pixel(COLUMN_PIXELS, COLUMN_PIXELS), BACKGROUND);
+    }
+}
@@ -1,20 +1,185 @@
-use std::{ops::Deref, sync::RwLock};
+use std::{ops::Deref, sync::RwLock, time::Duration};
 
 use vulkanalia::vk;
 
+use crate::{
+    render::MAX_FRAMES_IN_FLIGHT,
+    world::{EntityPos, RenderDistance},
+};
+
 pub static OPTIONS: RwLock<AppOptions> = RwLock::new(AppOptions::new());
 
 #[derive(Debug)]
 pub struct AppOptions {
     pub polygon_mode: vk::PolygonMode,
+    /// `true` allocates the depth attachment and enables the depth test, `false` renders the scene in draw order.
+    /// Read when the pipeline is recreated.
+    pub depth_buffer: bool,
+    /// Read when the pipeline is recreated.
+    pub anti_aliasing: AntiAliasing,
+    /// Used by F11 and `start_fullscreen`.
+    pub fullscreen_mode: FullscreenMode,
+    /// Start fullscreen on the monitor at this index, the primary one if there is no such monitor.
+    /// Read at startup.
+    pub start_fullscreen: Option<usize>,
+    /// Axis along which the field of view is kept when the aspect ratio changes. Applied when the swapchain is
+    /// recreated and when `MainLoopEvent::ProjectionChanged` is sent.
+    pub fov_axis: FovAxis,
     pub tick_world: bool,
+    /// World ticks per second, whatever the frame rate. At least 1, a frame runs one tick at most.
+    pub world_tick_rate: u32,
+    /// Like disabling `tick_world` but also parks the generator and meshing threads, for a static scene.
+    pub paused: bool,
+    /// Radii of the ellipsoid of chunks loaded around the player, in chunks. Read at startup and when
+    /// `MainLoopEvent::RenderDistanceChanged` is sent.
+    pub render_distance: RenderDistance,
+    /// Chunks are discarded this many chunks beyond the render distance, on each axis,
+    /// so moving back and forth across a chunk boundary doesn't reload them.
+    pub discard_margin: usize,
+    /// No new chunk is loaded while this many chunks are waiting for generation.
+    pub max_generation_backlog: usize,
+    /// Above this count, the chunks beyond the render distance are discarded from the farthest.
+    /// The ones within it are always kept.
+    pub max_loaded_chunks: usize,
+    /// Read at startup: the blocks of this many unloaded chunks are kept, with their edits, and reused if the
+    /// chunks are loaded again instead of generating them. Each one takes at least 32 KB. 0 disables it.
+    pub unloaded_chunks_cache_capacity: u64,
+    /// Chunks are also loaded around a point this many chunks ahead of the player, horizontally.
+    /// 0 disables it. Clamped to the smallest horizontal render distance.
+    pub look_ahead: usize,
+    /// Position and look of the camera at startup. `None` spawns high above the origin.
+    pub spawn: Option<EntityPos>,
+    /// Read at startup: the chunks up to this many chunks around the spawn, within the render distance, are
+    /// generated and meshed before the window is shown. 0 disables it, the first frames are then empty.
+    /// Clamped to `world::MAX_SPAWN_PREGENERATION`.
+    pub spawn_pregeneration: usize,
+    /// Walk with gravity and collisions instead of flying.
+    pub walk_mode: bool,
+    pub fly_speed: FlySpeedModifiers,
+    /// Render from a detached camera moved by the inputs, to see what the main camera culls.
+    pub debug_camera: bool,
+    /// Part of the mouse speed kept after 1/60 s, in `[0; 1[`. 0 disables the smoothing.
+    pub mouse_smoothing: f32,
+    /// Use the mouse movement 1:1, whatever `mouse_smoothing` is.
+    pub raw_mouse_input: bool,
+    /// Multiplier of the scale factor of the window for the GUI.
+    pub gui_scale: f32,
+    /// Count of frames the CPU can record ahead of the GPU, in `1..=MAX_FRAMES_IN_FLIGHT`.
+    /// Clamped to the swapchain images count.
+    pub frames_in_flight: usize,
+    /// How long a frame waits for the GPU before being skipped with a warning. `None` waits forever.
+    pub gpu_timeout: Option<Duration>,
+    /// Between two removals of the regions without loaded chunks, freeing their command buffers.
+    pub region_cleanup_interval: Duration,
+    /// Strength of the directional shading of the terrain, from 0 (every face lit as if it faced the sun) to 1.
+    pub terrain_shading: f32,
+    /// Multiplier of the day/night cycle speed.
+    pub day_cycle_speed: f32,
+    pub day_cycle_paused: bool,
+    pub generation: GenerationOptions,
+    /// Read at startup and clamped to `1..=available_parallelism`.
+    pub generator_threads: usize,
+    /// Read at startup and clamped to `1..=available_parallelism`. Each thread uses its own transfer queue.
+    pub meshing_threads: usize,
+    /// Read at startup, overridden by the `VOXELS_SHARED_WORKERS` environment variable (`0` or `1`).
+    /// The generator threads aren't started and the meshing threads also generate the chunks, taking whichever
+    /// work is waiting.
+    pub shared_workers: bool,
+    /// Capacity of the chunks vertex buffers. Unless it's exact, the replaced buffers are kept to be reused
+    /// by the next meshes fitting in them instead of being destroyed.
+    pub vertex_buffer_rounding: BufferRounding,
+    pub minimap: MinimapOptions,
+    pub particles: ParticlesOptions,
+    pub horizon: HorizonOptions,
+    /// Read when the pipeline is recreated.
+    pub wireframe_overlay: WireframeOverlayOptions,
+    /// Read when a chunk is meshed, the meshes already uploaded aren't simplified again.
+    pub mesh_simplification: MeshSimplification,
+    /// Don't mesh a chunk, and so don't show it, until all its loaded neighbours are generated. Without it a chunk
+    /// meshed before a neighbour shows the faces at their border until it's remeshed.
+    pub wait_for_neighbours: bool,
+    /// Read at startup, overridden by the `VOXELS_ALLOCATOR` environment variable.
+    pub allocator: AllocatorKind,
+    #[cfg(feature = "debug_boxes")]
+    pub debug_boxes: DebugBoxes,
 }
 
 impl AppOptions {
     pub const fn new() -> Self {
         Self {
             polygon_mode: vk::PolygonMode::FILL,
+            depth_buffer: true,
+            anti_aliasing: AntiAliasing::None,
+            fullscreen_mode: FullscreenMode::Borderless,
+            start_fullscreen: None,
+            fov_axis: FovAxis::Vertical,
             tick_world: true,
+            world_tick_rate: 20,
+            paused: false,
+            render_distance: RenderDistance { x: 10, y: 4, z: 10 },
+            discard_margin: 2,
+            max_generation_backlog: 1024,
+            max_loaded_chunks: 10_000,
+            unloaded_chunks_cache_capacity: 1024,
+            look_ahead: 3,
+            spawn: None,
+            spawn_pregeneration: 0,
+            walk_mode: false,
+            fly_speed: FlySpeedModifiers {
+                sprint: 5.,
+                slow: 0.2,
+            },
+            debug_camera: false,
+            mouse_smoothing: 0.,
+            raw_mouse_input: false,
+            gui_scale: 1.,
+            frames_in_flight: 2,
+            gpu_timeout: Some(Duration::from_secs(5)),
+            region_cleanup_interval: Duration::from_secs(5),
+            terrain_shading: 1.,
+            day_cycle_speed: 1.,
+            day_cycle_paused: false,
+            generation: GenerationOptions::new(),
+            generator_threads: 2,
+            meshing_threads: 10,
+            shared_workers: false,
+            vertex_buffer_rounding: BufferRounding::Exact,
+            allocator: if cfg!(feature = "dumb_allocator") {
+                AllocatorKind::Dumb
+            } else {
+                AllocatorKind::Pooled
+            },
+            minimap: MinimapOptions {
+                enabled: false,
+                radius: 8,
+                rotate: false,
+            },
+            particles: ParticlesOptions {
+                enabled: true,
+                per_break: 24,
+                lifetime: 0.8,
+            },
+            horizon: HorizonOptions {
+                enabled: false,
+                color: [0.3, 0.45, 0.25],
+            },
+            wireframe_overlay: WireframeOverlayOptions {
+                enabled: false,
+                color: [1., 0.3, 0.1],
+                depth_bias_constant: -1.,
+                depth_bias_slope: -1.,
+            },
+            mesh_simplification: MeshSimplification {
+                distance: None,
+                light_tolerance: 2,
+            },
+            wait_for_neighbours: false,
+            #[cfg(feature = "debug_boxes")]
+            debug_boxes: DebugBoxes {
+                chunks: false,
+                regions: false,
+                frustum: false,
+            },
         }
     }
 
@@ -23,3 +188,177 @@ impl AppOptions {
         OPTIONS.read().expect("Lock poisoned")
     }
 }
+
+/// Top-down view of the surface height around the player.
+#[derive(Debug, Clone, Copy)]
+pub struct MinimapOptions {
+    pub enabled: bool,
+    /// In chunks around the player's one.
+    pub radius: usize,
+    /// Rotate the map so the look direction points up.
+    pub rotate: bool,
+}
+
+/// Multipliers of the flying speed while a modifier key is held. Both apply if both keys are held.
+#[derive(Debug, Clone, Copy)]
+pub struct FlySpeedModifiers {
+    /// With Ctrl.
+    pub sprint: f32,
+    /// With Alt.
+    pub slow: f32,
+}
+
+/// Quads thrown out of the broken blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct ParticlesOptions {
+    pub enabled: bool,
+    /// Count spawned by each break.
+    pub per_break: usize,
+    /// Of the longest living ones, in seconds. The others live from half of it.
+    pub lifetime: f32,
+}
+
+/// A flat ground from the edge of the render distance to the horizon, at the average surface height.
+#[derive(Debug, Clone, Copy)]
+pub struct HorizonOptions {
+    pub enabled: bool,
+    pub color: [f32; 3],
+}
+
+/// The terrain drawn again in lines over its faces, unlike `polygon_mode` which hides the faces.
+///
+/// The lines are rasterized from the same triangles as the faces but their depth is interpolated differently, so
+/// they fight with the faces without a depth bias pulling them towards the camera. The error is about one depth
+/// unit on the faces seen head-on, covered by the constant part, and grows with the depth slope on the grazing
+/// ones, covered by the slope part. Too much of it shows the lines of the faces just behind the visible ones,
+/// like the far side of a one block thick wall.
+#[derive(Debug, Clone, Copy)]
+pub struct WireframeOverlayOptions {
+    pub enabled: bool,
+    pub color: [f32; 3],
+    /// In the smallest depth difference the depth buffer can hold, negative towards the camera.
+    pub depth_bias_constant: f32,
+    /// Scales the depth slope of the faces, negative towards the camera.
+    pub depth_bias_slope: f32,
+}
+
+/// Fewer quads for the distant chunks: their faces are merged even when their lights differ slightly, the
+/// merged quad taking the lights of its first face. Not applied by the `compute_meshing` feature.
+///
+/// Only the attributes of the faces are simplified, never their geometry: a simplified mesh covers exactly the
+/// same faces as the detailed one. A simplified chunk next to a detailed one so has no crack or T-junction
+/// along their border that two detailed chunks wouldn't have, only a light step of at most `light_tolerance`
+/// levels. The chunk keeps the level it was meshed at until it's meshed again, e.g. after an edit.
+#[derive(Debug, Clone, Copy)]
+pub struct MeshSimplification {
+    /// The chunks at least this many chunks away from the player's one, on any axis, are simplified.
+    /// `None` disables it.
+    pub distance: Option<usize>,
+    /// Maximum difference of block and sky light of the merged faces, in light levels.
+    pub light_tolerance: u8,
+}
+
+/// Categories of boxes drawn by the debug renderer.
+#[cfg(feature = "debug_boxes")]
+#[derive(Debug, Clone, Copy)]
+pub struct DebugBoxes {
+    /// Borders of the loaded chunks.
+    pub chunks: bool,
+    /// Borders of the regions having a command buffer.
+    pub regions: bool,
+    /// Frustum of the camera when it's enabled, so it can be seen from elsewhere.
+    pub frustum: bool,
+}
+
+/// There is no MSAA mode, the scene render pass and its pipelines use a single sample.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    /// Post-process pass on the tonemapped scene, the GUI is drawn after it.
+    Fxaa,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocatorKind {
+    /// Sub-allocates from big chunks of device memory.
+    Pooled,
+    /// One device memory per allocation, for debugging.
+    Dumb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// A window covering the monitor, at its current resolution.
+    Borderless,
+    /// Takes over the monitor with its largest video mode. Falls back to borderless if there is none.
+    Exclusive,
+}
+
+/// The field of view is 60° along this axis, the other one follows the aspect ratio.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FovAxis {
+    /// Wider screens see more on the sides.
+    Vertical,
+    /// Wider screens see less above and below, for ultrawide monitors.
+    Horizontal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferRounding {
+    Exact,
+    PowerOfTwo,
+    /// To a multiple of this count of elements.
+    Stride(usize),
+}
+
+impl BufferRounding {
+    /// Capacity for `count` elements.
+    pub fn round(self, count: usize) -> usize {
+        match self {
+            Self::Exact => count,
+            Self::PowerOfTwo => count.next_power_of_two(),
+            Self::Stride(stride) => count.next_multiple_of(stride.max(1)),
+        }
+    }
+}
+
+/// Parameters of the terrain generator, read when the generator threads start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GenerationOptions {
+    /// `None` picks one from the current time, or 0 with the `bench` feature. Read when the chunks are created.
+    pub seed: Option<u32>,
+    pub mode: WorldGenMode,
+    /// Height of the lowest possible surface, in blocks.
+    pub base_height: i64,
+    /// Height difference between the lowest and the highest possible surface, in blocks.
+    pub amplitude: i64,
+    /// Maximum count of height maps cached, shared by all the generator threads. Each one takes 8 KB.
+    pub height_maps_cache_capacity: u64,
+    /// Blocks below this height, in blocks, are bedrock whatever the mode. `None` disables the floor.
+    pub bedrock_height: Option<i64>,
+}
+
+impl GenerationOptions {
+    pub const fn new() -> Self {
+        Self {
+            seed: None,
+            mode: WorldGenMode::Noise,
+            base_height: 50,
+            amplitude: 100,
+            height_maps_cache_capacity: 4096,
+            bedrock_height: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldGenMode {
+    /// Terrain from the height noise, shaped by `base_height` and `amplitude`.
+    Noise,
+    /// Solid up to a constant height, in blocks.
+    Flat { height: i64 },
+    /// One block out of four in every chunk, the worst case of the mesher.
+    Checkerboard,
+    /// Only air.
+    Empty,
+}
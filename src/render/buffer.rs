@@ -1,9 +1,14 @@
-use anyhow::{anyhow, Context, Result};
+use std::{mem::size_of_val, slice};
+
+use anyhow::{anyhow, bail, Context, Result};
 use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
 use crate::render::memory::allocator;
 
-use super::{devices::DEVICE, memory::Allocation};
+use super::{
+    devices::DEVICE,
+    memory::{Allocation, MemoryProperties},
+};
 
 #[derive(Debug)]
 pub struct Buffer {
@@ -15,7 +20,7 @@ impl Buffer {
     pub fn new(
         size: usize,
         usage: vk::BufferUsageFlags,
-        alloc_properties: vk::MemoryPropertyFlags,
+        alloc_properties: MemoryProperties,
         mapped: bool,
         alignment: usize,
     ) -> Result<Self> {
@@ -42,6 +47,12 @@ impl Buffer {
         Ok(Self { buffer, alloc })
     }
 
+    /// Return `true` if it has been created with mapped as true.
+    #[inline]
+    pub fn is_mapped(&self) -> bool {
+        self.alloc.is_mapped()
+    }
+
     #[inline]
     pub fn data(&mut self) -> Result<&mut [u8]> {
         self.alloc
@@ -49,11 +60,28 @@ impl Buffer {
             .ok_or_else(|| anyhow!("Buffer has not been created with mapped as true"))
     }
 
+    /// Copy `data` at the start of the mapped buffer and flush it.
+    pub fn write<T: Copy>(&mut self, data: &[T]) -> Result<()> {
+        let size = size_of_val(data);
+        if size > self.size() {
+            bail!("{}B don't fit in the buffer ({}B)", size, self.size());
+        }
+        let bytes = unsafe { slice::from_raw_parts(data.as_ptr() as *const u8, size) };
+        self.data()?[..size].copy_from_slice(bytes);
+        self.flush().context("Buffer flush failed")
+    }
+
     #[inline(always)]
     pub fn flush(&self) -> Result<()> {
         self.alloc.flush()
     }
 
+    #[cfg(feature = "compute_meshing")]
+    #[inline(always)]
+    pub fn invalidate(&self) -> Result<()> {
+        self.alloc.invalidate()
+    }
+
     #[inline]
     pub fn size(&self) -> usize {
         self.alloc.size()
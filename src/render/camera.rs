@@ -1,43 +1,90 @@
-use std::time::Duration;
+use std::{sync::RwLock, time::Duration};
 
-use glm::{TVec3, Vec3};
+use glm::{Vec3, Vec4};
 use nalgebra_glm as glm;
 use nalgebra_glm::Mat4;
 use vulkanalia::vk;
 
 use crate::gui;
-use crate::inputs::Inputs;
-use crate::world::EntityPos;
+use crate::inputs::{Inputs, MouseSmoothing};
+use crate::options::{AppOptions, FlySpeedModifiers, FovAxis};
+use crate::world::{chunks::Chunks, collision, Aabb, EntityPos};
 
 const SENSITIVITY: f32 = 0.05;
 const SPEED: f32 = 100.;
+const WALK_SPEED: f32 = 5.;
+/// In blocks/s².
+const GRAVITY: f32 = 30.;
+const JUMP_SPEED: f32 = 9.;
+const MAX_FALL_SPEED: f32 = 60.;
+const PLAYER_WIDTH: f32 = 0.6;
+const PLAYER_HEIGHT: f32 = 1.8;
+/// Height of the eyes (the camera position) above the feet.
+const EYE_HEIGHT: f32 = 1.6;
+/// Along `AppOptions::fov_axis`.
 const FOV: f32 = 60.;
 const NEAR: f32 = 0.1;
 const FAR: f32 = 100000.;
+/// Used when `AppOptions::spawn` isn't set.
+pub const DEFAULT_SPAWN: EntityPos = EntityPos::new(0., 300., 0., -30., 0.);
 
 #[derive(Debug)]
 #[repr(C)]
 pub struct UniformBufferObject {
     mat: Mat4,
+    /// `xyz` is the direction towards the sun scaled by its intensity and `w` the ambient light.
+    sun_light: Vec4,
+    /// Strength of the directional shading, see `AppOptions::terrain_shading`.
+    shading: f32,
+    /// The std140 block size is rounded up to 16 bytes.
+    _padding: [f32; 3],
 }
 
 #[derive(Debug)]
 pub struct Camera {
     pub pos: EntityPos,
     proj: Mat4,
+    /// Only used in walk mode.
+    velocity: Vec3,
+    on_ground: bool,
+    #[cfg(not(feature = "bench"))]
+    mouse_smoothing: MouseSmoothing,
+    /// Replaces the default straight line of the bench if set.
+    #[cfg(feature = "bench")]
+    path: Option<crate::bench::CameraPath>,
 }
 
 impl Camera {
-    pub fn new(swapchain_extent: vk::Extent2D) -> Self {
+    pub fn new(swapchain_extent: vk::Extent2D, spawn: EntityPos) -> Self {
         Self {
-            pos: EntityPos::new(0., 300., 0., -30., 0.),
-            proj: Self::create_proj(swapchain_extent),
+            pos: spawn,
+            proj: Self::create_proj(swapchain_extent, AppOptions::get().fov_axis),
+            velocity: Vec3::zeros(),
+            on_ground: false,
+            #[cfg(not(feature = "bench"))]
+            mouse_smoothing: MouseSmoothing::default(),
+            #[cfg(feature = "bench")]
+            // A malformed path falls back to the default one instead of stopping the bench.
+            path: crate::bench::CameraPath::from_env().unwrap_or_else(|e| {
+                log::warn!("Camera path ignored: {:?}", e);
+                None
+            }),
         }
     }
 
     #[cfg(not(feature = "bench"))]
-    pub fn tick(&mut self, inputs: &Inputs, elapsed: Duration) {
-        let mouse_delta = inputs.fetch_mouse_delta();
+    pub fn tick(&mut self, inputs: &Inputs, elapsed: Duration, chunks: &RwLock<Chunks>) {
+        let smoothing = {
+            let options = AppOptions::get();
+            if options.raw_mouse_input {
+                0.
+            } else {
+                options.mouse_smoothing
+            }
+        };
+        let mouse_delta =
+            self.mouse_smoothing
+                .apply(inputs.fetch_mouse_delta(), elapsed, smoothing);
 
         let mut yaw = self.pos.yaw() + mouse_delta.0 as f32 * SENSITIVITY;
         let mut pitch = self.pos.pitch() - mouse_delta.1 as f32 * SENSITIVITY;
@@ -50,9 +97,28 @@ impl Camera {
 
         let dir = Vec3::new(yaw.to_radians().cos(), 0., yaw.to_radians().sin()).normalize();
         let right = dir.cross(&Vec3::y()).normalize();
+
+        if AppOptions::get().walk_mode {
+            self.walk(inputs, elapsed, chunks, dir, right);
+        } else {
+            self.fly(inputs, elapsed, dir, right);
+        }
+
+        self.pos.look.x = pitch;
+        self.pos.look.y = yaw;
+
+        gui::DATA.write().expect("Lock poisoned").camera_pos = self.pos;
+    }
+
+    #[cfg(not(feature = "bench"))]
+    fn fly(&mut self, inputs: &Inputs, elapsed: Duration, dir: Vec3, right: Vec3) {
         let up = Vec3::y();
 
-        let speed = SPEED * elapsed.as_secs_f32();
+        let sprint = inputs.is_key_pressed(winit::event::VirtualKeyCode::LControl)
+            || inputs.is_key_pressed(winit::event::VirtualKeyCode::RControl);
+        let slow = inputs.is_key_pressed(winit::event::VirtualKeyCode::LAlt)
+            || inputs.is_key_pressed(winit::event::VirtualKeyCode::RAlt);
+        let speed = fly_speed(sprint, slow, AppOptions::get().fly_speed) * elapsed.as_secs_f32();
 
         let pos: &mut Vec3 = &mut self.pos;
 
@@ -75,54 +141,219 @@ impl Camera {
             *pos -= up * speed;
         }
 
-        self.pos.look.x = pitch;
-        self.pos.look.y = yaw;
+        self.velocity = Vec3::zeros();
+        self.on_ground = false;
+    }
+
+    /// Move with gravity, colliding with the solid blocks.
+    ///
+    /// Unloaded and not yet generated chunks have no collision so the player falls through them
+    /// until they are generated. The fall speed is capped to give the generator a chance to catch up.
+    #[cfg(not(feature = "bench"))]
+    fn walk(
+        &mut self,
+        inputs: &Inputs,
+        elapsed: Duration,
+        chunks: &RwLock<Chunks>,
+        dir: Vec3,
+        right: Vec3,
+    ) {
+        let dt = elapsed.as_secs_f32();
+
+        let mut wish = Vec3::zeros();
+        if inputs.is_key_pressed(winit::event::VirtualKeyCode::Z) {
+            wish += dir;
+        }
+        if inputs.is_key_pressed(winit::event::VirtualKeyCode::S) {
+            wish -= dir;
+        }
+        if inputs.is_key_pressed(winit::event::VirtualKeyCode::Q) {
+            wish -= right;
+        }
+        if inputs.is_key_pressed(winit::event::VirtualKeyCode::D) {
+            wish += right;
+        }
+        if wish != Vec3::zeros() {
+            wish = wish.normalize() * WALK_SPEED;
+        }
+        self.velocity.x = wish.x;
+        self.velocity.z = wish.z;
+
+        if self.on_ground && inputs.is_key_pressed(winit::event::VirtualKeyCode::Space) {
+            self.velocity.y = JUMP_SPEED;
+        }
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-MAX_FALL_SPEED);
+
+        let motion = self.velocity * dt;
+        let applied = collision::move_aabb(
+            &chunks.read().expect("Lock poisoned"),
+            self.bounding_box(),
+            motion,
+        );
+        *self.pos += applied;
 
+        self.on_ground = motion.y < 0. && applied.y > motion.y;
+        // Stop the velocity on the axes that hit a block.
+        for axis in 0..3 {
+            if applied[axis] != motion[axis] {
+                self.velocity[axis] = 0.;
+            }
+        }
+    }
+
+    /// Move to `pos` keeping the look direction, without momentum.
+    pub fn teleport(&mut self, pos: Vec3) {
+        *self.pos = pos;
+        self.velocity = Vec3::zeros();
+        self.on_ground = false;
         gui::DATA.write().expect("Lock poisoned").camera_pos = self.pos;
     }
 
+    /// The box of the player's body, the camera being at the eyes.
+    pub fn bounding_box(&self) -> Aabb {
+        let half_width = PLAYER_WIDTH / 2.;
+        Aabb::new(
+            *self.pos - Vec3::new(half_width, EYE_HEIGHT, half_width),
+            *self.pos + Vec3::new(half_width, PLAYER_HEIGHT - EYE_HEIGHT, half_width),
+        )
+    }
+
     #[cfg(feature = "bench")]
-    pub fn tick(&mut self, _inputs: &Inputs, elapsed: Duration) {
+    pub fn tick(&mut self, _inputs: &Inputs, elapsed: Duration, _chunks: &RwLock<Chunks>) {
         use std::{sync::LazyLock, time::Instant};
 
         static START: LazyLock<Instant> = LazyLock::new(Instant::now);
         let elapsed_total = START.elapsed();
 
-        self.pos.x += elapsed_total.as_secs_f32() * 30. * elapsed.as_secs_f32();
+        match &self.path {
+            Some(path) => self.pos = path.pos_at(elapsed_total),
+            None => self.pos.x += elapsed_total.as_secs_f32() * 30. * elapsed.as_secs_f32(),
+        }
 
         gui::DATA.write().expect("Lock poisoned").camera_pos = self.pos;
     }
 
-    pub fn ubo(&self) -> UniformBufferObject {
-        let mut front = TVec3::default();
-        front.x = self.pos.yaw().to_radians().cos() * self.pos.pitch().to_radians().cos();
-        front.y = self.pos.pitch().to_radians().sin();
-        front.z = self.pos.yaw().to_radians().sin() * self.pos.pitch().to_radians().cos();
-        let rotation = front.normalize();
+    pub fn ubo(&self, sun_light: Vec4, shading: f32) -> UniformBufferObject {
+        UniformBufferObject {
+            mat: self.view_proj(),
+            sun_light,
+            shading,
+            _padding: [0.; 3],
+        }
+    }
+
+    pub fn view_proj(&self) -> Mat4 {
+        let rotation = self.pos.look_dir();
         let view = glm::look_at(
             &self.pos,
             &(*self.pos + rotation),
             &glm::vec3(0.0, 1.0, 0.0),
         );
-
-        UniformBufferObject {
-            mat: self.proj * view,
-        }
+        self.proj * view
     }
 
     #[inline]
     pub fn rebuild_proj(&mut self, swapchain_extent: vk::Extent2D) {
-        self.proj = Self::create_proj(swapchain_extent);
+        self.proj = Self::create_proj(swapchain_extent, AppOptions::get().fov_axis);
     }
 
-    fn create_proj(swapchain_extent: vk::Extent2D) -> Mat4 {
-        let mut proj = glm::perspective_rh_zo(
-            swapchain_extent.width as f32 / swapchain_extent.height as f32,
-            FOV.to_radians(),
-            NEAR,
-            FAR,
-        );
+    fn create_proj(swapchain_extent: vk::Extent2D, fov_axis: FovAxis) -> Mat4 {
+        let aspect = swapchain_extent.width as f32 / swapchain_extent.height as f32;
+        let fov_y = match fov_axis {
+            FovAxis::Vertical => FOV.to_radians(),
+            FovAxis::Horizontal => 2. * ((FOV.to_radians() / 2.).tan() / aspect).atan(),
+        };
+        let mut proj = glm::perspective_rh_zo(aspect, fov_y, NEAR, FAR);
         proj[(1, 1)] *= -1.0;
         proj
     }
 }
+
+/// In blocks/s.
+#[cfg_attr(feature = "bench", allow(dead_code))]
+fn fly_speed(sprint: bool, slow: bool, modifiers: FlySpeedModifiers) -> f32 {
+    let mut speed = SPEED;
+    if sprint {
+        speed *= modifiers.sprint;
+    }
+    if slow {
+        speed *= modifiers.slow;
+    }
+    speed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_spawn() {
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        let spawn = EntityPos::new(12., 70.5, -40., 10., 90.);
+        let camera = Camera::new(extent, spawn);
+        assert!(camera.pos == spawn);
+        assert_eq!(camera.pos.yaw(), 90.);
+    }
+
+    #[test]
+    fn fly_speed_modifiers() {
+        let modifiers = FlySpeedModifiers {
+            sprint: 4.,
+            slow: 0.25,
+        };
+        assert_eq!(fly_speed(false, false, modifiers), SPEED);
+        assert_eq!(fly_speed(true, false, modifiers), 4. * SPEED);
+        assert_eq!(fly_speed(false, true, modifiers), SPEED / 4.);
+        assert_eq!(fly_speed(true, true, modifiers), SPEED);
+    }
+
+    #[test]
+    fn teleport_keeps_look() {
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        let mut camera = Camera::new(extent, EntityPos::new(0., 0., 0., 20., 45.));
+        camera.velocity = Vec3::new(1., -10., 0.);
+        let target = Vec3::new(1000.5, -64., -2000.);
+        camera.teleport(target);
+        assert_eq!(*camera.pos, target);
+        assert_eq!((camera.pos.pitch(), camera.pos.yaw()), (20., 45.));
+        assert_eq!(camera.velocity, Vec3::zeros());
+        assert!(gui::DATA.read().expect("Lock poisoned").camera_pos == camera.pos);
+    }
+
+    #[test]
+    fn fov_axis_projection() {
+        let extent = vk::Extent2D {
+            width: 1600,
+            height: 800,
+        };
+        let focal = 1. / (FOV.to_radians() / 2.).tan();
+
+        // The y scale is negative because the y axis is flipped for Vulkan.
+        let vertical = Camera::create_proj(extent, FovAxis::Vertical);
+        assert!((vertical[(1, 1)] + focal).abs() < 1e-5);
+        assert!((vertical[(0, 0)] - focal / 2.).abs() < 1e-5);
+
+        let horizontal = Camera::create_proj(extent, FovAxis::Horizontal);
+        assert!((horizontal[(0, 0)] - focal).abs() < 1e-5);
+        assert!((horizontal[(1, 1)] + focal * 2.).abs() < 1e-5);
+
+        // Both modes agree on a square extent.
+        let square = vk::Extent2D {
+            width: 800,
+            height: 800,
+        };
+        assert!(
+            (Camera::create_proj(square, FovAxis::Vertical)
+                - Camera::create_proj(square, FovAxis::Horizontal))
+            .abs()
+            .max()
+                < 1e-5
+        );
+    }
+}
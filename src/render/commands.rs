@@ -44,6 +44,13 @@ impl CommandPool {
         Ok(buffers)
     }
 
+    /// The buffers must not be used by the GPU anymore.
+    pub fn free_buffers(&mut self, buffers: impl IntoIterator<Item = CommandBuffer>) {
+        for buffer in buffers {
+            buffer.free(self.pool);
+        }
+    }
+
     #[inline]
     pub fn reset(&mut self) -> Result<()> {
         unsafe {
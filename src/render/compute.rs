@@ -0,0 +1,128 @@
+use std::slice;
+
+use anyhow::{Context, Result};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder, PipelineCache};
+
+use super::{
+    descriptors::{DescriptorPool, DescriptorSet, DescriptorSetLayout},
+    devices::DEVICE,
+    Buffer,
+};
+
+/// A compute pipeline with a single descriptor set made of storage buffers.
+#[derive(Debug)]
+pub struct ComputePipeline {
+    pipeline: vk::Pipeline,
+    layout: vk::PipelineLayout,
+    descriptor_set: DescriptorSet,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_layout: DescriptorSetLayout,
+}
+
+impl ComputePipeline {
+    /// The storage buffers are bound from 0 to `storage_buffers_count`. `shader` is destroyed once the pipeline is created.
+    pub fn new(shader: vk::ShaderModule, storage_buffers_count: usize) -> Result<Self> {
+        let bindings = (0..storage_buffers_count)
+            .map(|i| {
+                vk::DescriptorSetLayoutBinding::builder()
+                    .binding(i as u32)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::COMPUTE)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let descriptor_layout = DescriptorSetLayout::from_bindings(&bindings)
+            .context("Descriptor set layout creation failed")?;
+        let pool_sizes = &[vk::DescriptorPoolSize::builder()
+            .type_(vk::DescriptorType::STORAGE_BUFFER)
+            .descriptor_count(storage_buffers_count as u32)
+            .build()];
+        let mut descriptor_pool = DescriptorPool::from_sizes(1, pool_sizes)?;
+        let descriptor_set = descriptor_pool
+            .alloc_set(&descriptor_layout)
+            .context("Descriptor set allocation failed")?;
+
+        let layouts = &[*descriptor_layout];
+        let layout_info = vk::PipelineLayoutCreateInfo::builder().set_layouts(layouts);
+        let layout = unsafe {
+            DEVICE
+                .create_pipeline_layout(&layout_info, None)
+                .context("Pipeline layout creation failed")?
+        };
+
+        let stage = vk::PipelineShaderStageCreateInfo::builder()
+            .stage(vk::ShaderStageFlags::COMPUTE)
+            .module(shader)
+            .name(b"main\0");
+        let info = vk::ComputePipelineCreateInfo::builder()
+            .stage(stage)
+            .layout(layout);
+        let pipeline =
+            unsafe { DEVICE.create_compute_pipelines(PipelineCache::null(), &[info], None) }
+                .context("Pipeline creation failed")?
+                .0;
+
+        unsafe { DEVICE.destroy_shader_module(shader, None) };
+
+        Ok(Self {
+            pipeline,
+            layout,
+            descriptor_set,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_layout: descriptor_layout,
+        })
+    }
+
+    /// Bind `buffers` to the storage buffers, in the bindings order.
+    /// The pipeline must not be used by a command buffer in flight.
+    pub fn set_buffers(&mut self, buffers: &[&Buffer]) {
+        let infos = buffers
+            .iter()
+            .map(|buffer| {
+                vk::DescriptorBufferInfo::builder()
+                    .buffer(buffer.buffer)
+                    .offset(0)
+                    .range(vk::WHOLE_SIZE as u64)
+                    .build()
+            })
+            .collect::<Vec<_>>();
+        let writes = infos
+            .iter()
+            .enumerate()
+            .map(|(i, info)| {
+                vk::WriteDescriptorSet::builder()
+                    .dst_set(*self.descriptor_set)
+                    .dst_binding(i as u32)
+                    .dst_array_element(0)
+                    .descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+                    .buffer_info(slice::from_ref(info))
+            })
+            .collect::<Vec<_>>();
+        self.descriptor_set.update(&writes);
+    }
+
+    pub fn cmd_dispatch(&self, command_buff: vk::CommandBuffer, group_count: (u32, u32, u32)) {
+        unsafe {
+            DEVICE.cmd_bind_pipeline(command_buff, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+            DEVICE.cmd_bind_descriptor_sets(
+                command_buff,
+                vk::PipelineBindPoint::COMPUTE,
+                self.layout,
+                0,
+                &[*self.descriptor_set],
+                &[],
+            );
+            DEVICE.cmd_dispatch(command_buff, group_count.0, group_count.1, group_count.2);
+        }
+    }
+}
+
+impl Drop for ComputePipeline {
+    fn drop(&mut self) {
+        unsafe {
+            DEVICE.destroy_pipeline(self.pipeline, None);
+            DEVICE.destroy_pipeline_layout(self.layout, None);
+        }
+    }
+}
@@ -1,11 +1,70 @@
-use std::ffi::c_char;
+use std::{env, ffi::c_char, sync::OnceLock};
 
+use log::{info, warn};
 use vulkanalia::vk::{Extension, KHR_SHADER_NON_SEMANTIC_INFO_EXTENSION, KHR_SWAPCHAIN_EXTENSION};
 
-pub const VALIDATION_ENABLED: bool = cfg!(debug_assertions);
+/// Overrides the default of the validation layers, enabled in debug builds, with `0` or `1`.
+const VALIDATION_VAR: &str = "VK_VALIDATION";
+
 pub const VALIDATION_LAYERS: &[*const c_char] = &[b"VK_LAYER_KHRONOS_validation\0".as_ptr().cast()];
 
 pub const DEVICE_REQUIRED_EXTENSIONS: &[Extension] = &[
     KHR_SWAPCHAIN_EXTENSION,
     KHR_SHADER_NON_SEMANTIC_INFO_EXTENSION,
 ];
+
+/// Whether the validation layers and the debug messenger are enabled, from the build and the environment.
+/// Read once so the instance and the device agree.
+pub fn validation_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| {
+        let enabled = validation_from_env(env::var(VALIDATION_VAR).ok().as_deref());
+        info!(
+            "Validation layers {}",
+            if enabled { "enabled" } else { "disabled" }
+        );
+        enabled
+    })
+}
+
+/// The layers to enable on the instance and the device.
+pub fn layers() -> &'static [*const c_char] {
+    layers_for(validation_enabled())
+}
+
+fn validation_from_env(value: Option<&str>) -> bool {
+    match value {
+        Some("1") => true,
+        Some("0") => false,
+        Some(value) => {
+            warn!("Unknown {VALIDATION_VAR} value: {value:?}");
+            cfg!(debug_assertions)
+        }
+        None => cfg!(debug_assertions),
+    }
+}
+
+fn layers_for(validation: bool) -> &'static [*const c_char] {
+    if validation {
+        VALIDATION_LAYERS
+    } else {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation_override() {
+        assert_eq!(
+            layers_for(validation_from_env(Some("1"))),
+            VALIDATION_LAYERS
+        );
+        assert!(layers_for(validation_from_env(Some("0"))).is_empty());
+        let default = layers_for(cfg!(debug_assertions));
+        assert_eq!(layers_for(validation_from_env(None)), default);
+        assert_eq!(layers_for(validation_from_env(Some("yes"))), default);
+    }
+}
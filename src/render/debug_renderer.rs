@@ -0,0 +1,334 @@
+use std::{
+    marker::Unsize,
+    mem::{align_of, size_of},
+    slice,
+    sync::RwLock,
+};
+
+use anyhow::{Context, Result};
+use memoffset::offset_of;
+use nalgebra_glm::{self as glm, Mat4, Vec3, Vec4};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::{
+    options::AppOptions,
+    shader_module,
+    world::{chunks::Chunks, Aabb, ChunkPos, RenderDistance},
+};
+
+use super::{
+    descriptors::DescriptorSetLayout,
+    devices::DEVICE,
+    memory::MemoryProperties,
+    pipeline::{self, Pipeline, PipelineCreationOptions},
+    regions::RegionsManager,
+    render_pass::RenderPass,
+    swapchain::Swapchain,
+    vertex::VertexDescriptor,
+    Buffer, CommandBuffer, CommandPool, QUEUES,
+};
+
+/// In instances.
+const DEFAULT_INSTANCE_BUFFER_SIZE: usize = 1024;
+const CHUNK_COLOR: [f32; 4] = [1., 1., 0., 1.];
+const REGION_COLOR: [f32; 4] = [1., 0., 0., 1.];
+const FRUSTUM_COLOR: [f32; 4] = [0., 1., 0., 1.];
+
+/// The unit cube transformed by `transform`, drawn as lines of a uniform color.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct DebugBox {
+    transform: Mat4,
+    color: Vec4,
+}
+
+impl DebugBox {
+    pub fn aabb(aabb: &Aabb, color: [f32; 4]) -> Self {
+        let transform = glm::scale(&glm::translation(&aabb.min), &(aabb.max - aabb.min));
+        Self {
+            transform,
+            color: color.into(),
+        }
+    }
+
+    /// The volume seen through `view_proj`.
+    pub fn frustum(view_proj: &Mat4, color: [f32; 4]) -> Self {
+        // From the unit cube to the Vulkan clip space, then back to the world.
+        let to_clip = glm::scale(
+            &glm::translation(&Vec3::new(-1., -1., 0.)),
+            &Vec3::new(2., 2., 1.),
+        );
+        let inverse = view_proj.try_inverse().unwrap_or_else(Mat4::identity);
+        Self {
+            transform: inverse * to_clip,
+            color: color.into(),
+        }
+    }
+}
+
+/// Boxes are instances of the same binding, the cube itself is in `debug_box.vert`.
+impl VertexDescriptor for DebugBox {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    fn attribute_descriptions() -> impl Unsize<[vk::VertexInputAttributeDescription]> {
+        let column = |i: usize| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(i as u32)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset((offset_of!(Self, transform) + i * size_of::<Vec4>()) as u32)
+                .build()
+        };
+        [
+            column(0),
+            column(1),
+            column(2),
+            column(3),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(4)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(Self, color) as u32)
+                .build(),
+        ]
+    }
+}
+
+/// Draws the boxes of the categories enabled in `AppOptions::debug_boxes` in one instanced draw,
+/// in the scene render pass after the terrain.
+#[derive(Debug)]
+pub struct DebugRenderer {
+    pipeline: Pipeline,
+    /// One per swapchain image, rewritten each frame.
+    instance_buffers: Vec<Buffer>,
+    command_buffers: Vec<CommandBuffer>,
+    command_pool: CommandPool,
+    /// View projection of the camera when the frustum category was enabled.
+    frozen_frustum: Option<Mat4>,
+}
+
+impl DebugRenderer {
+    pub fn new(
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<Self> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        let pipeline = Pipeline::new::<DebugBox>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline creation failed")?;
+        let instance_buffers = (0..swapchain.images.len())
+            .map(|_| Self::create_instance_buff(DEFAULT_INSTANCE_BUFFER_SIZE))
+            .collect::<Result<Vec<_>>>()
+            .context("Instance buffers creation failed")?;
+        let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)
+            .context("Command pool creation failed")?;
+        let command_buffers = command_pool
+            .alloc_buffers(swapchain.images.len(), true)
+            .context("Command buffers allocation failed")?;
+        Ok(Self {
+            pipeline,
+            instance_buffers,
+            command_buffers,
+            command_pool,
+            frozen_frustum: None,
+        })
+    }
+
+    fn pipeline_options(uniforms_layout: &DescriptorSetLayout) -> Result<PipelineCreationOptions> {
+        Ok(PipelineCreationOptions {
+            shaders: vec![
+                (
+                    shader_module!("debug_box.vert")?,
+                    vk::ShaderStageFlags::VERTEX,
+                ),
+                (
+                    shader_module!("debug_box.frag")?,
+                    vk::ShaderStageFlags::FRAGMENT,
+                ),
+            ],
+            topology: vk::PrimitiveTopology::LINE_LIST,
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            descriptors_layouts: vec![uniforms_layout],
+            push_constant_ranges: Vec::new(),
+            blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .build(),
+            dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(),
+        })
+    }
+
+    #[inline]
+    fn create_instance_buff(instances: usize) -> Result<Buffer> {
+        Buffer::new(
+            instances * size_of::<DebugBox>(),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            MemoryProperties::required(
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ),
+            true,
+            align_of::<DebugBox>(),
+        )
+    }
+
+    #[inline]
+    pub fn recreate(
+        &mut self,
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<()> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        self.pipeline
+            .recreate::<DebugBox>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline recreation failed")?;
+        self.resize(swapchain)
+    }
+
+    /// Update the resources depending on the swapchain images count.
+    fn resize(&mut self, swapchain: &Swapchain) -> Result<()> {
+        let images_count = swapchain.images.len();
+        if images_count != self.instance_buffers.len() {
+            self.instance_buffers = (0..images_count)
+                .map(|_| Self::create_instance_buff(DEFAULT_INSTANCE_BUFFER_SIZE))
+                .collect::<Result<Vec<_>>>()
+                .context("Instance buffers creation failed")?;
+            self.command_pool
+                .realloc_buffers(&mut self.command_buffers, images_count, true)
+                .context("Command buffers reallocation failed")?;
+        }
+        Ok(())
+    }
+
+    /// Boxes of the enabled categories. `center` is the camera chunk and `view_proj` its view
+    /// projection. The chunks and regions kept beyond the render distance aren't drawn.
+    pub fn collect_boxes(
+        &mut self,
+        chunks: &RwLock<Chunks>,
+        regions: &RegionsManager,
+        center: ChunkPos,
+        view_proj: &Mat4,
+    ) -> Vec<DebugBox> {
+        let categories = AppOptions::get().debug_boxes;
+        let render_distance = RenderDistance::from_options();
+        let mut boxes = Vec::new();
+        if categories.chunks {
+            let chunks = chunks.read().expect("Lock poisoned");
+            boxes.extend(
+                chunks
+                    .iter()
+                    .filter(|(&pos, _)| render_distance.contains(center, pos, 0))
+                    .map(|(&pos, _)| DebugBox::aabb(&Aabb::from_chunk(pos), CHUNK_COLOR)),
+            );
+        }
+        if categories.regions {
+            boxes.extend(
+                regions
+                    .inner()
+                    .keys()
+                    .filter(|&&pos| render_distance.contains_region(center, pos))
+                    .map(|&pos| DebugBox::aabb(&Aabb::from_region(pos), REGION_COLOR)),
+            );
+        }
+        if categories.frustum {
+            let frustum = self.frozen_frustum.get_or_insert(*view_proj);
+            boxes.push(DebugBox::frustum(frustum, FRUSTUM_COLOR));
+        } else {
+            self.frozen_frustum = None;
+        }
+        boxes
+    }
+
+    /// Return `None` if there is nothing to draw.
+    pub fn render(
+        &mut self,
+        image_index: usize,
+        boxes: &[DebugBox],
+        uniforms_set: vk::DescriptorSet,
+        inheritance_info: &vk::CommandBufferInheritanceInfo,
+        extent: vk::Extent2D,
+    ) -> Result<Option<vk::CommandBuffer>> {
+        if boxes.is_empty() {
+            return Ok(None);
+        }
+
+        // The buffer of this image isn't used by the GPU anymore (its fence has been waited) and
+        // is entirely rewritten so it can be replaced without copying its content.
+        let instance_buff = &mut self.instance_buffers[image_index];
+        if boxes.len() * size_of::<DebugBox>() > instance_buff.size() {
+            *instance_buff = Self::create_instance_buff(boxes.len().next_power_of_two())
+                .context("Instance buffer creation failed")?;
+        }
+        let data = unsafe {
+            slice::from_raw_parts(
+                boxes.as_ptr() as *const u8,
+                boxes.len() * size_of::<DebugBox>(),
+            )
+        };
+        instance_buff.data()?[..data.len()].copy_from_slice(data);
+
+        let command_buff = &mut self.command_buffers[image_index];
+        command_buff.begin_secondary(inheritance_info)?;
+        unsafe {
+            DEVICE.cmd_bind_pipeline(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            DEVICE.cmd_bind_descriptor_sets(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[uniforms_set],
+                &[],
+            );
+            pipeline::set_viewport(**command_buff, extent);
+            pipeline::set_full_scissor(**command_buff, extent);
+            DEVICE.cmd_bind_vertex_buffers(**command_buff, 0, &[instance_buff.buffer], &[0]);
+            DEVICE.cmd_draw(**command_buff, 24, boxes.len() as u32, 0, 0);
+        }
+        command_buff.end()?;
+
+        Ok(Some(**command_buff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transform_point(m: &Mat4, p: Vec3) -> Vec3 {
+        let h = m * Vec4::new(p.x, p.y, p.z, 1.);
+        h.xyz() / h.w
+    }
+
+    #[test]
+    fn boxes_corners() {
+        let aabb = Aabb::new(Vec3::new(-2., 0., 5.), Vec3::new(3., 1., 7.));
+        let transform = DebugBox::aabb(&aabb, CHUNK_COLOR).transform;
+        assert_eq!(transform_point(&transform, Vec3::zeros()), aabb.min);
+        assert_eq!(transform_point(&transform, Vec3::repeat(1.)), aabb.max);
+
+        let view_proj = glm::perspective_rh_zo(1.5, 1., 0.1, 100.)
+            * glm::look_at(&Vec3::new(1., 2., 3.), &Vec3::zeros(), &Vec3::y());
+        let transform = DebugBox::frustum(&view_proj, FRUSTUM_COLOR).transform;
+        for (corner, clip) in [
+            (Vec3::zeros(), Vec3::new(-1., -1., 0.)),
+            (Vec3::repeat(1.), Vec3::repeat(1.)),
+        ] {
+            let projected = transform_point(&view_proj, transform_point(&transform, corner));
+            assert!((projected - clip).norm() < 1e-3, "{projected} != {clip}");
+        }
+    }
+}
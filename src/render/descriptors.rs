@@ -12,7 +12,13 @@ pub struct DescriptorSetLayout {
 
 impl DescriptorSetLayout {
     pub fn new(binding: &impl vk::Cast<Target = vk::DescriptorSetLayoutBinding>) -> Result<Self> {
-        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(slice::from_ref(binding));
+        Self::from_bindings(slice::from_ref(binding))
+    }
+
+    pub fn from_bindings(
+        bindings: &[impl vk::Cast<Target = vk::DescriptorSetLayoutBinding>],
+    ) -> Result<Self> {
+        let info = vk::DescriptorSetLayoutCreateInfo::builder().bindings(bindings);
         let layout = unsafe { DEVICE.create_descriptor_set_layout(&info, None) }
             .context("Layout creation failed")?;
 
@@ -43,8 +49,35 @@ impl DescriptorPool {
         let pool_size = vk::DescriptorPoolSize::builder()
             .descriptor_count(max_sets as u32)
             .type_(descriptors_type);
-        let pool_sizes = &[pool_size];
+        Self::from_sizes(max_sets, &[pool_size])
+    }
+
+    /// A pool whose sets can be given back one by one with [`DescriptorPool::free_set`].
+    pub fn freeable(
+        max_sets: usize,
+        pool_sizes: &[impl vk::Cast<Target = vk::DescriptorPoolSize>],
+    ) -> Result<Self> {
+        Self::with_flags(
+            max_sets,
+            pool_sizes,
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+        )
+    }
+
+    pub fn from_sizes(
+        max_sets: usize,
+        pool_sizes: &[impl vk::Cast<Target = vk::DescriptorPoolSize>],
+    ) -> Result<Self> {
+        Self::with_flags(max_sets, pool_sizes, vk::DescriptorPoolCreateFlags::empty())
+    }
+
+    fn with_flags(
+        max_sets: usize,
+        pool_sizes: &[impl vk::Cast<Target = vk::DescriptorPoolSize>],
+        flags: vk::DescriptorPoolCreateFlags,
+    ) -> Result<Self> {
         let info = vk::DescriptorPoolCreateInfo::builder()
+            .flags(flags)
             .pool_sizes(pool_sizes)
             .max_sets(max_sets as u32);
 
@@ -73,6 +106,13 @@ impl DescriptorPool {
         let set = sets.into_iter().next().expect("Should contain one set");
         Ok(set)
     }
+
+    /// The pool must have been created with [`DescriptorPool::freeable`] and `set` must not be used
+    /// by a command buffer in flight.
+    pub fn free_set(&mut self, set: &DescriptorSet) -> Result<()> {
+        unsafe { DEVICE.free_descriptor_sets(self.inner, &[set.inner]) }
+            .context("Descriptor set free failed")
+    }
 }
 
 impl Drop for DescriptorPool {
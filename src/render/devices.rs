@@ -8,12 +8,12 @@ use vulkanalia::vk::{
 };
 
 use crate::{
-    render::{config::VALIDATION_LAYERS, instance::INSTANCE, swapchain::SwapchainSupport},
+    render::{instance::INSTANCE, swapchain::SwapchainSupport},
     utils::DerefOnceLock,
 };
 
 use super::{
-    config::{DEVICE_REQUIRED_EXTENSIONS, VALIDATION_ENABLED},
+    config::{self, DEVICE_REQUIRED_EXTENSIONS},
     queues::{get_queue_families, QueuesManager, QUEUES},
     Queue,
 };
@@ -141,6 +141,8 @@ pub struct Device {
     pub device: vulkanalia::Device,
     pub graphics_queue: Queue,
     pub properties: vk::PhysicalDeviceProperties,
+    /// `VK_EXT_memory_budget` is enabled.
+    pub memory_budget: bool,
 }
 
 impl Deref for Device {
@@ -172,16 +174,21 @@ impl Device {
 
         let properties = unsafe { INSTANCE.get_physical_device_properties(physical_device) };
 
-        let extensions = DEVICE_REQUIRED_EXTENSIONS
+        let mut extensions = DEVICE_REQUIRED_EXTENSIONS
             .iter()
             .map(|ext| ext.name.as_ptr())
             .collect::<Vec<_>>();
-
-        let layers = if VALIDATION_ENABLED {
-            VALIDATION_LAYERS
-        } else {
-            &[]
-        };
+        let available_extensions =
+            unsafe { INSTANCE.enumerate_device_extension_properties(physical_device, None) }
+                .context("Enumerating device extensions failed")?;
+        // Queried through `VK_KHR_get_physical_device_properties2`.
+        let memory_budget = INSTANCE.properties2
+            && available_extensions
+                .iter()
+                .any(|ext| ext.extension_name == vk::EXT_MEMORY_BUDGET_EXTENSION.name);
+        if memory_budget {
+            extensions.push(vk::EXT_MEMORY_BUDGET_EXTENSION.name.as_ptr());
+        }
 
         let features = vk::PhysicalDeviceFeatures::builder()
             .shader_int64(true)
@@ -189,7 +196,7 @@ impl Device {
             .sampler_anisotropy(true);
         let create_info = DeviceCreateInfo::builder()
             .queue_create_infos(&queue_create_infos)
-            .enabled_layer_names(layers)
+            .enabled_layer_names(config::layers())
             .enabled_extension_names(&extensions)
             .enabled_features(&features);
 
@@ -206,6 +213,7 @@ impl Device {
             device,
             graphics_queue,
             properties,
+            memory_budget,
         })
     }
 }
@@ -0,0 +1,101 @@
+use std::{error::Error, fmt::Display};
+
+use anyhow::anyhow;
+use vulkanalia::vk;
+
+/// Vulkan outcomes a caller can recover from.
+///
+/// Converted into `anyhow::Error` like any other error and found back with [`RenderError::find`],
+/// even through added contexts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderError {
+    /// The swapchain doesn't match the surface anymore and has to be recreated.
+    SwapchainOutOfDate,
+    OutOfHostMemory,
+    OutOfDeviceMemory,
+    DeviceLost,
+}
+
+impl RenderError {
+    /// Return the recoverable error matching `code` or `None` if it isn't one.
+    pub fn from_vk(code: vk::ErrorCode) -> Option<Self> {
+        match code {
+            vk::ErrorCode::OUT_OF_DATE_KHR => Some(Self::SwapchainOutOfDate),
+            vk::ErrorCode::OUT_OF_HOST_MEMORY => Some(Self::OutOfHostMemory),
+            vk::ErrorCode::OUT_OF_DEVICE_MEMORY => Some(Self::OutOfDeviceMemory),
+            vk::ErrorCode::DEVICE_LOST => Some(Self::DeviceLost),
+            _ => None,
+        }
+    }
+
+    /// Return the first `RenderError` in the chain of `error`.
+    pub fn find(error: &anyhow::Error) -> Option<Self> {
+        error
+            .chain()
+            .find_map(|e| e.downcast_ref::<Self>())
+            .copied()
+    }
+
+    #[inline]
+    pub fn is_out_of_memory(self) -> bool {
+        matches!(self, Self::OutOfHostMemory | Self::OutOfDeviceMemory)
+    }
+}
+
+impl Display for RenderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::SwapchainOutOfDate => "Swapchain out of date",
+            Self::OutOfHostMemory => "Out of host memory",
+            Self::OutOfDeviceMemory => "Out of device memory",
+            Self::DeviceLost => "Device lost",
+        };
+        f.write_str(message)
+    }
+}
+
+impl Error for RenderError {}
+
+/// Convert a Vulkan error code into an `anyhow::Error`, as a [`RenderError`] if it is recoverable.
+pub fn vk_error(code: vk::ErrorCode) -> anyhow::Error {
+    match RenderError::from_vk(code) {
+        Some(error) => error.into(),
+        None => anyhow!(code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn find_through_contexts() {
+        let error = Err::<(), _>(vk_error(vk::ErrorCode::OUT_OF_DEVICE_MEMORY))
+            .context("Alloc failed")
+            .context("Buffer creation failed")
+            .expect_err("Should be an error");
+
+        let found = RenderError::find(&error);
+        assert_eq!(found, Some(RenderError::OutOfDeviceMemory));
+        assert!(found.map_or(false, RenderError::is_out_of_memory));
+        assert_eq!(error.to_string(), "Buffer creation failed");
+        assert_eq!(
+            format!("{:#}", error),
+            "Buffer creation failed: Alloc failed: Out of device memory"
+        );
+    }
+
+    #[test]
+    fn unrecoverable_codes() {
+        let error = vk_error(vk::ErrorCode::INITIALIZATION_FAILED).context("Init failed");
+        assert_eq!(RenderError::find(&error), None);
+        assert!(format!("{:#}", error).starts_with("Init failed: "));
+
+        assert_eq!(
+            RenderError::find(&vk_error(vk::ErrorCode::OUT_OF_DATE_KHR)),
+            Some(RenderError::SwapchainOutOfDate)
+        );
+    }
+}
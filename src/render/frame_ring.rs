@@ -0,0 +1,178 @@
+//! Transient memory written by the CPU for a single frame, like the GUI meshes.
+
+use std::mem;
+
+use anyhow::{Context, Result};
+use vulkanalia::vk;
+
+use super::{memory::MemoryProperties, Buffer};
+
+/// Alignment of the start of the buffers, the largest one an allocation can ask for.
+const MAX_ALIGNMENT: usize = 256;
+
+/// Bump allocator with one persistently mapped, host coherent buffer per frame slot (e.g. per swapchain image).
+///
+/// [`FrameRing::begin_frame`] resets a slot, so it must only be called once the GPU is done with the previous
+/// frame using it. An allocation not fitting in the rest of the buffer replaces it with one at least twice as
+/// big and starts again at its beginning. The outgrown buffer is kept until the slot is reset since the
+/// previous allocations of the frame still point to it, and the next frames use the bigger one directly.
+#[derive(Debug)]
+pub struct FrameRing {
+    usage: vk::BufferUsageFlags,
+    /// Of the buffers of the new slots.
+    capacity: usize,
+    slots: Vec<Slot>,
+    current: usize,
+}
+
+#[derive(Debug)]
+struct Slot {
+    buffer: Buffer,
+    /// Of `buffer`, its memory may be bigger.
+    size: usize,
+    /// Where the next allocation starts.
+    offset: usize,
+    /// Outgrown during the current frame.
+    retired: Vec<Buffer>,
+}
+
+/// Memory of the current frame, valid until its slot is reset.
+#[derive(Debug)]
+pub struct FrameAlloc<'a> {
+    pub buffer: vk::Buffer,
+    /// In `buffer`.
+    pub offset: usize,
+    pub data: &'a mut [u8],
+}
+
+impl FrameRing {
+    pub fn new(slots: usize, capacity: usize, usage: vk::BufferUsageFlags) -> Result<Self> {
+        let mut ring = Self {
+            usage,
+            capacity,
+            slots: Vec::with_capacity(slots),
+            current: 0,
+        };
+        ring.resize(slots)?;
+        Ok(ring)
+    }
+
+    /// Change the count of slots. None of them may be used by the GPU.
+    pub fn resize(&mut self, slots: usize) -> Result<()> {
+        self.slots.truncate(slots);
+        while self.slots.len() < slots {
+            self.slots.push(Slot {
+                buffer: self.create_buffer(self.capacity)?,
+                size: self.capacity,
+                offset: 0,
+                retired: Vec::new(),
+            });
+        }
+        self.current = 0;
+        Ok(())
+    }
+
+    /// Allocate from `slot` until the next call. The GPU must be done with the previous frame of this slot.
+    pub fn begin_frame(&mut self, slot: usize) {
+        self.current = slot;
+        let slot = &mut self.slots[slot];
+        slot.offset = 0;
+        slot.retired.clear();
+    }
+
+    /// `alignment` must be a power of two up to [`MAX_ALIGNMENT`].
+    pub fn alloc(&mut self, size: usize, alignment: usize) -> Result<FrameAlloc<'_>> {
+        debug_assert!(alignment.is_power_of_two() && alignment <= MAX_ALIGNMENT);
+        let mut offset = self.slots[self.current].offset.next_multiple_of(alignment);
+        if offset + size > self.slots[self.current].size {
+            let capacity = (self.slots[self.current].size * 2)
+                .max(size)
+                .next_power_of_two();
+            let buffer = self
+                .create_buffer(capacity)
+                .context("Frame ring growth failed")?;
+            let slot = &mut self.slots[self.current];
+            slot.retired.push(mem::replace(&mut slot.buffer, buffer));
+            slot.size = capacity;
+            offset = 0;
+        }
+
+        let slot = &mut self.slots[self.current];
+        slot.offset = offset + size;
+        let buffer = slot.buffer.buffer;
+        let data = &mut slot.buffer.data()?[offset..offset + size];
+        Ok(FrameAlloc {
+            buffer,
+            offset,
+            data,
+        })
+    }
+
+    fn create_buffer(&self, size: usize) -> Result<Buffer> {
+        Buffer::new(
+            size,
+            self.usage,
+            MemoryProperties::required(
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            ),
+            true,
+            MAX_ALIGNMENT,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring() -> FrameRing {
+        FrameRing::new(2, 1024, vk::BufferUsageFlags::VERTEX_BUFFER).expect("Creation failed")
+    }
+
+    #[test]
+    fn bump_allocation() {
+        let mut ring = ring();
+        ring.begin_frame(0);
+        let first = ring.alloc(10, 4).expect("Alloc failed");
+        assert_eq!((first.offset, first.data.len()), (0, 10));
+        first.data.fill(1);
+        let buffer = first.buffer;
+
+        let aligned = ring.alloc(8, 16).expect("Alloc failed");
+        assert_eq!((aligned.offset, aligned.buffer), (16, buffer));
+        let unaligned = ring.alloc(3, 1).expect("Alloc failed");
+        assert_eq!((unaligned.offset, unaligned.buffer), (24, buffer));
+
+        // Too big for the rest of the buffer.
+        let grown = ring.alloc(1020, 4).expect("Alloc failed");
+        assert_eq!(grown.offset, 0);
+        assert_ne!(grown.buffer, buffer);
+        assert_eq!(ring.slots[0].retired.len(), 1);
+        assert_eq!(ring.slots[0].size, 2048);
+    }
+
+    #[test]
+    fn frame_reset() {
+        let mut ring = ring();
+        ring.begin_frame(0);
+        let buffer = ring.alloc(100, 4).expect("Alloc failed").buffer;
+
+        // The other slot is independent.
+        ring.begin_frame(1);
+        let other = ring.alloc(100, 4).expect("Alloc failed");
+        assert_eq!(other.offset, 0);
+        assert_ne!(other.buffer, buffer);
+
+        ring.begin_frame(0);
+        let reset = ring.alloc(100, 4).expect("Alloc failed");
+        assert_eq!((reset.offset, reset.buffer), (0, buffer));
+
+        // The outgrown buffer is released with the reset, the bigger one stays.
+        ring.alloc(2000, 4).expect("Alloc failed");
+        ring.begin_frame(0);
+        assert!(ring.slots[0].retired.is_empty());
+        let grown = ring.alloc(2000, 4).expect("Alloc failed");
+        assert_eq!(grown.offset, 0);
+        assert_ne!(grown.buffer, buffer);
+    }
+}
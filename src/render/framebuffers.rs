@@ -5,7 +5,7 @@ use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
 use crate::utils::drop_then_new;
 
-use super::{depth::DepthBuffer, devices::DEVICE, render_pass::RenderPass, swapchain::Swapchain};
+use super::{depth::DepthBuffer, devices::DEVICE, render_pass::RenderPass};
 
 #[derive(Debug)]
 pub struct Framebuffers {
@@ -13,21 +13,25 @@ pub struct Framebuffers {
 }
 
 impl Framebuffers {
+    /// One framebuffer per view of `color_views`, each with the depth buffer if any.
     pub fn new(
-        swapchain: &Swapchain,
+        color_views: &[vk::ImageView],
+        extent: vk::Extent2D,
         render_pass: &RenderPass,
-        depth_buffer: &DepthBuffer,
+        depth_buffer: Option<&DepthBuffer>,
     ) -> Result<Self> {
-        let framebuffers = swapchain
-            .image_views
+        let framebuffers = color_views
             .iter()
-            .map(|i| {
-                let attachments = &[*i, depth_buffer.view()];
+            .map(|&view| {
+                let mut attachments = vec![view];
+                if let Some(depth_buffer) = depth_buffer {
+                    attachments.push(depth_buffer.view());
+                }
                 let create_info = vk::FramebufferCreateInfo::builder()
                     .render_pass(**render_pass)
-                    .attachments(attachments)
-                    .width(swapchain.extent.width)
-                    .height(swapchain.extent.height)
+                    .attachments(&attachments)
+                    .width(extent.width)
+                    .height(extent.height)
                     .layers(1);
 
                 unsafe { DEVICE.create_framebuffer(&create_info, None) }
@@ -46,11 +50,14 @@ impl Framebuffers {
     #[inline]
     pub fn recreate(
         &mut self,
-        swapchain: &Swapchain,
+        color_views: &[vk::ImageView],
+        extent: vk::Extent2D,
         render_pass: &RenderPass,
-        depth_buffer: &DepthBuffer,
+        depth_buffer: Option<&DepthBuffer>,
     ) -> Result<()> {
-        drop_then_new(self, || Self::new(swapchain, render_pass, depth_buffer))
+        drop_then_new(self, || {
+            Self::new(color_views, extent, render_pass, depth_buffer)
+        })
     }
 }
 
@@ -1,7 +1,9 @@
 use std::{
+    array,
     collections::HashMap,
     marker::Unsize,
     mem::{align_of, size_of},
+    vec,
 };
 
 use anyhow::{Context, Result};
@@ -13,25 +15,30 @@ use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 use crate::{
     gui,
     render::{
-        texture::{Texture, TextureCreationOptions},
+        texture::{Sampler, SamplerOptions, Texture},
         StagingBuffer, DEVICE,
     },
     shader_module,
 };
 
 use super::{
-    descriptors::{DescriptorPool, DescriptorSetLayout},
-    pipeline::{Pipeline, PipelineCreationOptions},
+    descriptors::{DescriptorPool, DescriptorSet, DescriptorSetLayout},
+    frame_ring::FrameRing,
+    pipeline::{self, Pipeline, PipelineCreationOptions},
+    regions::DrawStats,
     render_pass::RenderPass,
     swapchain::Swapchain,
     uniform::Uniforms,
     vertex::VertexDescriptor,
-    Buffer, CommandBuffer, CommandPool, QUEUES,
+    Buffer, CommandBuffer, CommandPool, MAX_FRAMES_IN_FLIGHT, QUEUES,
 };
 
-const DEFAULT_INDEX_BUFFER_SIZE: usize = 2048;
-const DEFAULT_VERTEX_BUFFER_SIZE: usize = 4000;
-const MAX_TEXTURES: usize = 4;
+/// Initial size of the meshes buffer of each swapchain image.
+const DEFAULT_MESH_BUFFER_SIZE: usize = 8192;
+/// Textures of the first descriptor pool, each new pool is twice as big as the previous one.
+const FIRST_POOL_TEXTURES: usize = 4;
+/// Filters of the samplers shared by all the textures, the fragment shader picks one with a push constant.
+const SAMPLER_FILTERS: [vk::Filter; 2] = [vk::Filter::LINEAR, vk::Filter::NEAREST];
 
 impl VertexDescriptor for gui::Vertex {
     fn binding_description() -> vk::VertexInputBindingDescription {
@@ -69,18 +76,21 @@ impl VertexDescriptor for gui::Vertex {
 #[derive(Debug)]
 pub struct GuiRenderer {
     pipeline: Pipeline,
-    vertex_buffers: Vec<Buffer>,
-    index_buffers: Vec<Buffer>,
+    /// One slot per swapchain image, holding the vertices of the frame followed by its indices.
+    meshes: FrameRing,
 
     uniforms: Uniforms<Vec2>,
     textures_command_buff: CommandBuffer,
 
-    descriptor_pool: DescriptorPool,
-    descriptor_layout: DescriptorSetLayout,
-    textures: HashMap<egui::TextureId, Texture>,
+    texture_descriptors: TextureDescriptors,
+    textures: HashMap<egui::TextureId, GuiTexture>,
+    /// Replaced or freed textures which may still be used by the frames in flight.
+    retired_textures: DeferredRelease<GuiTexture>,
 
     command_pool: CommandPool,
     command_buffers: Vec<CommandBuffer>,
+
+    extent: vk::Extent2D,
 }
 
 impl GuiRenderer {
@@ -91,24 +101,20 @@ impl GuiRenderer {
     ) -> Result<Self> {
         let uniforms = Uniforms::new(swapchain.images.len()).context("Uniforms creation failed")?;
 
-        let pool = DescriptorPool::new(MAX_TEXTURES, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-            .context("Descriptor pool creation failed")?;
-        let layout = DescriptorSetLayout::new(&Texture::binding(0))
-            .context("Descriptor set layout creation failed")?;
+        let texture_descriptors =
+            TextureDescriptors::new().context("Texture descriptors creation failed")?;
 
-        let pipeline_options = Self::pipeline_options(&[&uniforms.layout, &layout])?;
+        let pipeline_options =
+            Self::pipeline_options(&[&uniforms.layout, &texture_descriptors.layout])?;
         let pipeline = Pipeline::new::<gui::Vertex>(swapchain, render_pass, &pipeline_options)
             .context("Pipeline creation failed")?;
 
-        let vertex_buffers: Vec<_> = (0..swapchain.image_views.len())
-            .map(|_| Self::create_vertex_buff())
-            .collect::<Result<Vec<_>>>()
-            .context("Vertex buffers creation failed")?;
-
-        let index_buffers: Vec<_> = (0..swapchain.image_views.len())
-            .map(|_| Self::create_index_buff())
-            .collect::<Result<Vec<_>>>()
-            .context("Vertex buffers creation failed")?;
+        let meshes = FrameRing::new(
+            swapchain.image_views.len(),
+            DEFAULT_MESH_BUFFER_SIZE,
+            vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::INDEX_BUFFER,
+        )
+        .context("Mesh buffers creation failed")?;
 
         let textures_command_buff = textures_cmd_pool
             .alloc_buffers(1, false)
@@ -123,22 +129,21 @@ impl GuiRenderer {
             .alloc_buffers(swapchain.images.len(), true)
             .context("Command buffers allocation failed")?;
 
-        let mut s = Self {
+        Ok(Self {
             pipeline,
-            vertex_buffers,
-            index_buffers,
+            meshes,
             uniforms,
             textures_command_buff,
 
-            descriptor_pool: pool,
-            descriptor_layout: layout,
+            texture_descriptors,
             textures: HashMap::new(),
+            retired_textures: DeferredRelease::new(),
 
             command_pool,
             command_buffers,
-        };
-        s.fill_uniforms(swapchain);
-        Ok(s)
+
+            extent: swapchain.extent,
+        })
     }
 
     fn pipeline_options<'a>(
@@ -151,10 +156,16 @@ impl GuiRenderer {
                 (shader_module!("gui.vert")?, vk::ShaderStageFlags::VERTEX),
                 (shader_module!("gui.frag")?, vk::ShaderStageFlags::FRAGMENT),
             ],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             cull_mode: vk::CullModeFlags::NONE,
             polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
             descriptors_layouts: vec,
-            push_constant_ranges: Vec::new(),
+            push_constant_ranges: vec![vk::PushConstantRange::builder()
+                .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                .offset(0)
+                .size(size_of::<u32>() as u32)
+                .build()],
             blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
                 .blend_enable(true)
                 .src_color_blend_factor(vk::BlendFactor::ONE)
@@ -162,96 +173,75 @@ impl GuiRenderer {
                 .color_write_mask(vk::ColorComponentFlags::all())
                 .build(),
             dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
-                .dynamic_states(&[vk::DynamicState::SCISSOR])
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
                 .build(),
         })
     }
 
+    /// Return the offset of the indices and the size needed in a mesh buffer.
     #[inline]
-    fn create_vertex_buff() -> Result<Buffer> {
-        Buffer::new(
-            DEFAULT_VERTEX_BUFFER_SIZE,
-            vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            true,
-            align_of::<gui::Vertex>(),
+    fn mesh_buff_layout(vert_count: usize, index_count: usize) -> (usize, usize) {
+        let indices_offset =
+            (vert_count * size_of::<gui::Vertex>()).next_multiple_of(size_of::<u32>());
+        (
+            indices_offset,
+            indices_offset + index_count * size_of::<u32>(),
         )
     }
 
-    #[inline]
-    fn create_index_buff() -> Result<Buffer> {
-        Buffer::new(
-            DEFAULT_INDEX_BUFFER_SIZE,
-            vk::BufferUsageFlags::INDEX_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            true,
-            align_of::<u32>(),
-        )
-    }
-
-    fn resize_buff<T>(
-        buffer: &mut Buffer,
-        min_len: usize,
-        usage: vk::BufferUsageFlags,
-    ) -> Result<()> {
-        let min_size = min_len * size_of::<T>();
-        let mut new_size = buffer.size() * 2;
-        while new_size < min_size {
-            new_size *= 2;
+    /// egui can give clip rects partially or entirely out of the screen, which isn't a valid scissor.
+    /// The clip rects are in points, the scissor in pixels.
+    fn clip_rect_scissor(
+        clip_rect: &egui::Rect,
+        pixels_per_point: f32,
+        extent: vk::Extent2D,
+    ) -> vk::Rect2D {
+        let (width, height) = (extent.width as f32, extent.height as f32);
+        let (min, max) = (
+            clip_rect.min.to_vec2() * pixels_per_point,
+            clip_rect.max.to_vec2() * pixels_per_point,
+        );
+        let min_x = min.x.round().clamp(0., width);
+        let min_y = min.y.round().clamp(0., height);
+        let max_x = max.x.round().clamp(min_x, width);
+        let max_y = max.y.round().clamp(min_y, height);
+        vk::Rect2D {
+            offset: vk::Offset2D {
+                x: min_x as i32,
+                y: min_y as i32,
+            },
+            extent: vk::Extent2D {
+                width: (max_x - min_x) as u32,
+                height: (max_y - min_y) as u32,
+            },
         }
-        let mut new_buff = Buffer::new(
-            new_size,
-            usage,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            true,
-            align_of::<T>(),
-        )
-        .context("Buffer creation failed")?;
-        let new_data = new_buff.data().expect("Buffer should be mapped");
-        let old_data = buffer.data().expect("Buffer should be mapped");
-        new_data[..old_data.len()].copy_from_slice(old_data);
-
-        *buffer = new_buff;
-
-        Ok(())
     }
 
-    unsafe fn get_buff_data<T>(buffer: &mut Buffer) -> &mut [T] {
-        let buff = buffer.data().expect("Buffer should be mapped");
-        let (a, data, b) = unsafe { buff.align_to_mut::<T>() };
+    unsafe fn get_buff_data<T>(data: &mut [u8]) -> &mut [T] {
+        let (a, data, b) = unsafe { data.align_to_mut::<T>() };
         assert_eq!(a.len(), 0);
         assert_eq!(b.len(), 0);
         data
     }
 
-    fn fill_uniforms(&mut self, swapchain: &Swapchain) {
-        let data = Vec2::new(
-            swapchain.extent.width as f32,
-            swapchain.extent.height as f32,
-        );
-        for uniform in &mut self.uniforms {
-            uniform.write(data);
-        }
-    }
-
     #[inline]
     pub fn recreate(&mut self, swapchain: &Swapchain, render_pass: &RenderPass) -> Result<()> {
-        self.fill_uniforms(swapchain);
         let pipeline_options =
-            Self::pipeline_options(&[&self.uniforms.layout, &self.descriptor_layout])?;
+            Self::pipeline_options(&[&self.uniforms.layout, &self.texture_descriptors.layout])?;
         self.pipeline
             .recreate::<gui::Vertex>(swapchain, render_pass, &pipeline_options)
             .context("Pipeline recreation failed")?;
-        if swapchain.image_views.len() != self.vertex_buffers.len() {
-            self.vertex_buffers = (0..swapchain.image_views.len())
-                .map(|_| Self::create_vertex_buff())
-                .collect::<Result<Vec<_>>>()
-                .context("Vertex buffers creation failed")?;
+        self.resize(swapchain)
+    }
 
-            self.index_buffers = (0..swapchain.image_views.len())
-                .map(|_| Self::create_index_buff())
-                .collect::<Result<Vec<_>>>()
-                .context("Vertex buffers creation failed")?;
+    /// Update the resources depending on the swapchain extent or images count.
+    /// The pipeline uses a dynamic viewport so it doesn't need to be recreated.
+    pub fn resize(&mut self, swapchain: &Swapchain) -> Result<()> {
+        self.extent = swapchain.extent;
+        if swapchain.image_views.len() != self.command_buffers.len() {
+            self.meshes
+                .resize(swapchain.image_views.len())
+                .context("Mesh buffers creation failed")?;
 
             self.command_pool
                 .realloc_buffers(&mut self.command_buffers, swapchain.image_views.len(), true)
@@ -260,7 +250,7 @@ impl GuiRenderer {
         Ok(())
     }
 
-    pub fn load_textures(&mut self, textures_delta: egui::TexturesDelta) -> Result<()> {
+    pub fn load_textures(&mut self, textures_delta: &egui::TexturesDelta) -> Result<()> {
         for (id, delta) in &textures_delta.set {
             self.load_texture(*id, delta)
                 .with_context(|| format!("Failed to load texture {:?}", id))?;
@@ -268,6 +258,16 @@ impl GuiRenderer {
         Ok(())
     }
 
+    /// Destroy the retired textures the GPU is done with. Must be called once per frame, after the
+    /// fence of the frame has been waited.
+    fn destroy_retired_textures(&mut self) -> Result<()> {
+        for texture in self.retired_textures.tick() {
+            self.texture_descriptors
+                .free(&texture.texture.descriptor_set, texture.pool_index)?;
+        }
+        Ok(())
+    }
+
     pub fn load_texture(
         &mut self,
         id: egui::TextureId,
@@ -302,30 +302,23 @@ impl GuiRenderer {
 
         data.copy_from_slice(&pixels);
 
-        let texture_options = TextureCreationOptions {
-            format: vk::Format::R8G8B8A8_UNORM,
-            address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
-            anisotropy: false,
-            ..Default::default()
+        let size = vk::Extent3D {
+            width: delta.image.width() as u32,
+            height: delta.image.height() as u32,
+            depth: 1,
         };
-        let descriptor_set = self
-            .descriptor_pool
-            .alloc_set(&self.descriptor_layout)
-            .context("Descriptor set alloc failed")?;
-        let texture = Texture::new(
-            &mut self.textures_command_buff,
-            &staging_buff,
-            vk::Extent3D {
-                width: delta.image.width() as u32,
-                height: delta.image.height() as u32,
-                depth: 1,
-            },
-            0,
-            descriptor_set,
-            &texture_options,
-        )
-        .context("Texture creation failed")?;
-        self.textures.insert(id, texture);
+        let texture = self
+            .texture_descriptors
+            .create_texture(
+                &mut self.textures_command_buff,
+                &staging_buff,
+                size,
+                delta.options.magnification,
+            )
+            .context("Texture creation failed")?;
+        if let Some(previous) = self.textures.insert(id, texture) {
+            self.retired_textures.push(previous);
+        }
 
         Ok(())
     }
@@ -334,10 +327,13 @@ impl GuiRenderer {
         &mut self,
         image_index: usize,
         primitives: &[egui::ClippedPrimitive],
+        pixels_per_point: f32,
         textures_delta: egui::TexturesDelta,
         inheritance_info: &vk::CommandBufferInheritanceInfo,
-    ) -> Result<vk::CommandBuffer> {
-        self.load_textures(textures_delta)
+    ) -> Result<(vk::CommandBuffer, DrawStats)> {
+        self.destroy_retired_textures()
+            .context("Retired textures destruction failed")?;
+        self.load_textures(&textures_delta)
             .context("Textures loading failed")?;
 
         let mut vert_count = 0;
@@ -351,21 +347,20 @@ impl GuiRenderer {
             index_count += mesh.indices.len();
         }
 
-        let vertex_buff = &mut self.vertex_buffers[image_index];
-        let index_buff = &mut self.index_buffers[image_index];
-
-        if vert_count * size_of::<gui::Vertex>() > vertex_buff.size() {
-            Self::resize_buff::<gui::Vertex>(
-                vertex_buff,
-                vert_count,
-                vk::BufferUsageFlags::VERTEX_BUFFER,
+        // The meshes and the uniform of this image aren't used by the GPU anymore, its fence has been waited.
+        self.meshes.begin_frame(image_index);
+        // The vertices are in points.
+        self.uniforms[image_index].write(
+            Vec2::new(self.extent.width as f32, self.extent.height as f32) / pixels_per_point,
+        );
+        let (indices_offset, needed_size) = Self::mesh_buff_layout(vert_count, index_count);
+        let mesh = self
+            .meshes
+            .alloc(
+                needed_size,
+                align_of::<gui::Vertex>().max(align_of::<u32>()),
             )
-            .context("Buffer resize failed")?;
-        }
-        if index_count * size_of::<u32>() > index_buff.size() {
-            Self::resize_buff::<u32>(index_buff, index_count, vk::BufferUsageFlags::INDEX_BUFFER)
-                .context("Buffer resize failed")?;
-        }
+            .context("Mesh allocation failed")?;
 
         let command_buff = &mut self.command_buffers[image_index];
         command_buff.begin_secondary(inheritance_info)?;
@@ -384,12 +379,29 @@ impl GuiRenderer {
                 &[],
             );
         }
+        pipeline::set_viewport(**command_buff, self.extent);
 
-        let vertex_buffer = vertex_buff.buffer;
-        let index_buffer = index_buff.buffer;
+        // An empty frame has no valid offset to bind.
+        if index_count > 0 {
+            unsafe {
+                DEVICE.cmd_bind_vertex_buffers(
+                    **command_buff,
+                    0,
+                    &[mesh.buffer],
+                    &[mesh.offset as u64],
+                );
+                DEVICE.cmd_bind_index_buffer(
+                    **command_buff,
+                    mesh.buffer,
+                    (mesh.offset + indices_offset) as u64,
+                    vk::IndexType::UINT32,
+                );
+            }
+        }
 
-        let vertex_data = unsafe { Self::get_buff_data(vertex_buff) };
-        let index_data = unsafe { Self::get_buff_data(index_buff) };
+        let (vertex_data, index_data) = mesh.data.split_at_mut(indices_offset);
+        let vertex_data = unsafe { Self::get_buff_data::<gui::Vertex>(vertex_data) };
+        let index_data = unsafe { Self::get_buff_data::<u32>(index_data) };
 
         let mut vert_i = 0;
         let mut index_i = 0;
@@ -419,26 +431,17 @@ impl GuiRenderer {
                     vk::PipelineBindPoint::GRAPHICS,
                     self.pipeline.layout,
                     1,
-                    &[*texture.descriptor_set],
+                    &[*texture.texture.descriptor_set],
                     &[],
                 );
-                DEVICE.cmd_bind_vertex_buffers(**command_buff, 0, &[vertex_buffer], &[0]);
-                DEVICE.cmd_bind_index_buffer(
+                DEVICE.cmd_push_constants(
                     **command_buff,
-                    index_buffer,
+                    self.pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
                     0,
-                    vk::IndexType::UINT32,
+                    &texture.sampler_index.to_ne_bytes(),
                 );
-                let scissor = vk::Rect2D {
-                    offset: vk::Offset2D {
-                        x: clip_rect.min.x as i32,
-                        y: clip_rect.min.y as i32,
-                    },
-                    extent: vk::Extent2D {
-                        width: clip_rect.width() as u32,
-                        height: clip_rect.height() as u32,
-                    },
-                };
+                let scissor = Self::clip_rect_scissor(clip_rect, pixels_per_point, self.extent);
                 DEVICE.cmd_set_scissor(**command_buff, 0, &[scissor]);
                 DEVICE.cmd_draw_indexed(
                     **command_buff,
@@ -455,6 +458,306 @@ impl GuiRenderer {
         }
         command_buff.end()?;
 
-        Ok(**command_buff)
+        // egui frees the textures once the frame using them for the last time is painted.
+        for id in &textures_delta.free {
+            if let Some(texture) = self.textures.remove(id) {
+                self.retired_textures.push(texture);
+            }
+        }
+
+        let draw_stats = DrawStats {
+            draw_calls: primitives.len(),
+            triangles: index_count / 3,
+        };
+        Ok((**command_buff, draw_stats))
+    }
+}
+
+#[derive(Debug)]
+struct GuiTexture {
+    texture: Texture,
+    /// Of the descriptor set of the texture in `TextureDescriptors::pools`.
+    pool_index: usize,
+    /// Of its filter in [`SAMPLER_FILTERS`].
+    sampler_index: u32,
+}
+
+/// The descriptor sets of the GUI textures. egui may create any number of textures so a new pool
+/// is added when the previous ones are full.
+///
+/// The textures only write their image view, the samplers are immutable ones of the layout.
+#[derive(Debug)]
+struct TextureDescriptors {
+    layout: DescriptorSetLayout,
+    /// Each pool with its count of allocated sets.
+    pools: Vec<(DescriptorPool, usize)>,
+    /// One per filter of [`SAMPLER_FILTERS`]. Declared last to be destroyed after the layout and the sets.
+    samplers: [Sampler; 2],
+}
+
+impl TextureDescriptors {
+    fn new() -> Result<Self> {
+        let sampler = |filter| {
+            Sampler::new(&SamplerOptions {
+                filter,
+                address_mode: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                anisotropy: 1.0,
+                lod_bias: 0.0,
+                mipmaps: false,
+            })
+        };
+        let samplers = [sampler(SAMPLER_FILTERS[0])?, sampler(SAMPLER_FILTERS[1])?];
+        let handles = [*samplers[0], *samplers[1]];
+        let layout = DescriptorSetLayout::from_bindings(&[
+            Texture::image_binding(0),
+            Sampler::immutable_binding(1, &handles).build(),
+        ])
+        .context("Descriptor set layout creation failed")?;
+        Ok(Self {
+            layout,
+            pools: Vec::new(),
+            samplers,
+        })
+    }
+
+    /// Create a texture from the pixels of `buff`, magnified with `filter`.
+    fn create_texture(
+        &mut self,
+        command_buff: &mut CommandBuffer,
+        buff: &Buffer,
+        size: vk::Extent3D,
+        filter: egui::TextureFilter,
+    ) -> Result<GuiTexture> {
+        let filter = match filter {
+            egui::TextureFilter::Nearest => vk::Filter::NEAREST,
+            egui::TextureFilter::Linear => vk::Filter::LINEAR,
+        };
+        let sampler_index = SAMPLER_FILTERS
+            .iter()
+            .position(|&sampler_filter| sampler_filter == filter)
+            .expect("No sampler for the filter") as u32;
+
+        let (descriptor_set, pool_index) = self.alloc().context("Descriptor set alloc failed")?;
+        let texture = Texture::new(
+            command_buff,
+            buff,
+            size,
+            vk::Format::R8G8B8A8_UNORM,
+            0,
+            descriptor_set,
+        )?;
+        Ok(GuiTexture {
+            texture,
+            pool_index,
+            sampler_index,
+        })
+    }
+
+    #[inline]
+    fn pool_capacity(pool_index: usize) -> usize {
+        FIRST_POOL_TEXTURES << pool_index
+    }
+
+    /// Return the set and the index of its pool.
+    fn alloc(&mut self) -> Result<(DescriptorSet, usize)> {
+        let free_pool = self
+            .pools
+            .iter()
+            .enumerate()
+            .position(|(i, (_, count))| *count < Self::pool_capacity(i));
+        let pool_index = match free_pool {
+            Some(pool_index) => pool_index,
+            None => {
+                let capacity = Self::pool_capacity(self.pools.len());
+                let pool_sizes = [
+                    vk::DescriptorPoolSize::builder()
+                        .type_(vk::DescriptorType::SAMPLED_IMAGE)
+                        .descriptor_count(capacity as u32),
+                    vk::DescriptorPoolSize::builder()
+                        .type_(vk::DescriptorType::SAMPLER)
+                        .descriptor_count((capacity * self.samplers.len()) as u32),
+                ];
+                let pool = DescriptorPool::freeable(capacity, &pool_sizes)
+                    .context("Descriptor pool creation failed")?;
+                self.pools.push((pool, 0));
+                self.pools.len() - 1
+            }
+        };
+        let (pool, count) = &mut self.pools[pool_index];
+        let set = pool.alloc_set(&self.layout)?;
+        *count += 1;
+        Ok((set, pool_index))
+    }
+
+    fn free(&mut self, set: &DescriptorSet, pool_index: usize) -> Result<()> {
+        let (pool, count) = &mut self.pools[pool_index];
+        pool.free_set(set)?;
+        *count -= 1;
+        Ok(())
+    }
+}
+
+/// Keeps the items alive for `MAX_FRAMES_IN_FLIGHT` frames, like `WaitingForDeleteBuffers`.
+#[derive(Debug)]
+struct DeferredRelease<T> {
+    items: [Vec<T>; MAX_FRAMES_IN_FLIGHT],
+    index: usize,
+}
+
+impl<T> DeferredRelease<T> {
+    fn new() -> Self {
+        Self {
+            items: array::from_fn(|_| Vec::new()),
+            index: 0,
+        }
+    }
+
+    #[inline]
+    fn push(&mut self, item: T) {
+        self.items[self.index].push(item);
+    }
+
+    /// Move to the next frame and return the items pushed `MAX_FRAMES_IN_FLIGHT` frames ago.
+    #[inline]
+    fn tick(&mut self) -> vec::Drain<'_, T> {
+        self.index = (self.index + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.items[self.index].drain(..)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mesh_buff_layout() {
+        assert_eq!(GuiRenderer::mesh_buff_layout(0, 0), (0, 0));
+        let (indices_offset, size) = GuiRenderer::mesh_buff_layout(3, 6);
+        assert_eq!(indices_offset, 3 * size_of::<gui::Vertex>());
+        assert_eq!(indices_offset % align_of::<u32>(), 0);
+        assert_eq!(size, indices_offset + 6 * size_of::<u32>());
+    }
+
+    #[test]
+    fn clip_rect_clamped() {
+        let extent = vk::Extent2D {
+            width: 800,
+            height: 600,
+        };
+        let scaled_scissor = |min: (f32, f32), max: (f32, f32), pixels_per_point| {
+            let rect = egui::Rect::from_min_max(min.into(), max.into());
+            let scissor = GuiRenderer::clip_rect_scissor(&rect, pixels_per_point, extent);
+            (
+                (scissor.offset.x, scissor.offset.y),
+                (scissor.extent.width, scissor.extent.height),
+            )
+        };
+        let scissor = |min, max| scaled_scissor(min, max, 1.);
+
+        assert_eq!(scissor((10., 20.), (110., 70.)), ((10, 20), (100, 50)));
+        assert_eq!(scissor((-50., -10.), (100., 1000.)), ((0, 0), (100, 600)));
+        assert_eq!(
+            scissor((700., 500.), (f32::INFINITY, 900.)),
+            ((700, 500), (100, 100))
+        );
+        // Entirely out of the screen.
+        assert_eq!(scissor((900., 0.), (1000., 10.)), ((800, 0), (0, 10)));
+        assert_eq!(scissor((-100., -100.), (-10., -10.)), ((0, 0), (0, 0)));
+
+        // Scaled to pixels before being clamped.
+        assert_eq!(
+            scaled_scissor((10., 20.), (110., 70.), 2.),
+            ((20, 40), (200, 100))
+        );
+        assert_eq!(
+            scaled_scissor((300., 0.), (500., 10.), 2.),
+            ((600, 0), (200, 20))
+        );
+        assert_eq!(
+            scaled_scissor((10.2, 0.), (20.6, 10.), 1.5),
+            ((15, 0), (16, 15))
+        );
+    }
+
+    #[test]
+    fn texture_descriptors_grow() {
+        let mut descriptors = TextureDescriptors::new().expect("Creation failed");
+        let sets = (0..3 * FIRST_POOL_TEXTURES)
+            .map(|_| descriptors.alloc().expect("Alloc failed"))
+            .collect::<Vec<_>>();
+        assert_eq!(descriptors.pools.len(), 2);
+        assert!(sets[..FIRST_POOL_TEXTURES]
+            .iter()
+            .all(|(_, pool)| *pool == 0));
+        assert!(sets[FIRST_POOL_TEXTURES..]
+            .iter()
+            .all(|(_, pool)| *pool == 1));
+
+        // A freed set is reused before adding a pool.
+        let (set, pool_index) = &sets[1];
+        descriptors.free(set, *pool_index).expect("Free failed");
+        let (_, pool_index) = descriptors.alloc().expect("Alloc failed");
+        assert_eq!(pool_index, 0);
+        assert_eq!(descriptors.pools.len(), 2);
+
+        descriptors.alloc().expect("Alloc failed");
+        assert_eq!(descriptors.pools.len(), 3);
+    }
+
+    #[test]
+    fn textures_share_samplers() {
+        let mut descriptors = TextureDescriptors::new().expect("Creation failed");
+        let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)
+            .expect("Command pool creation failed");
+        let mut command_buff = command_pool
+            .alloc_buffers(1, false)
+            .expect("Command buffer allocation failed")
+            .into_iter()
+            .next()
+            .expect("Should contain one buffer");
+        let mut staging_buff =
+            StagingBuffer::new(2 * 2 * 4, 1).expect("Staging buffer creation failed");
+        unsafe { staging_buff.data::<u8>() }.fill(255);
+        let size = vk::Extent3D {
+            width: 2,
+            height: 2,
+            depth: 1,
+        };
+
+        let filters = [
+            egui::TextureFilter::Linear,
+            egui::TextureFilter::Nearest,
+            egui::TextureFilter::Linear,
+        ];
+        let textures = filters.map(|filter| {
+            descriptors
+                .create_texture(&mut command_buff, &staging_buff, size, filter)
+                .expect("Texture creation failed")
+        });
+        // The same filter gives the same sampler options, so the same sampler of the layout.
+        let [first, nearest, second] = &textures;
+        assert_eq!(first.sampler_index, second.sampler_index);
+        assert_ne!(first.sampler_index, nearest.sampler_index);
+        assert_eq!(
+            SAMPLER_FILTERS[first.sampler_index as usize],
+            vk::Filter::LINEAR
+        );
+        assert_eq!(
+            SAMPLER_FILTERS[nearest.sampler_index as usize],
+            vk::Filter::NEAREST
+        );
+    }
+
+    #[test]
+    fn textures_released_after_frames_in_flight() {
+        let mut retired = DeferredRelease::new();
+        retired.push("set");
+        retired.push("freed");
+        for _ in 1..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(retired.tick().count(), 0);
+            retired.push("later");
+        }
+        assert_eq!(retired.tick().collect::<Vec<_>>(), ["set", "freed"]);
+        assert_eq!(retired.tick().collect::<Vec<_>>(), ["later"]);
     }
 }
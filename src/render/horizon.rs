@@ -0,0 +1,373 @@
+//! A flat ground around the loaded area, so the world ends on a horizon instead of the sky color.
+//!
+//! It's a rectangle in `horizon.vert` at the average surface height, extending [`EXTENT`] blocks past the render
+//! distance around the player chunk. `horizon.frag` cuts out the columns of the chunks loaded at that height, the
+//! slice of the render distance ellipsoid, so the terrain is never under it. It's drawn before the terrain, which
+//! covers it at the edge of the loaded area, so it doesn't need the depth buffer. Seen from below, it would cover
+//! the sky like a ceiling so it isn't drawn then.
+
+use std::{mem::size_of, slice};
+
+use anyhow::{Context, Result};
+use nalgebra_glm::{Vec3, Vec4};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::{
+    options::{AppOptions, GenerationOptions, WorldGenMode},
+    shader_module,
+    world::{ChunkPos, RenderDistance, CHUNK_SIZE},
+};
+
+use super::{
+    descriptors::DescriptorSetLayout,
+    devices::DEVICE,
+    pipeline::{self, Pipeline, PipelineCreationOptions},
+    post_process::NoVertex,
+    render_pass::RenderPass,
+    swapchain::Swapchain,
+    CommandBuffer, CommandPool, QUEUES,
+};
+
+/// How far the ground goes past the loaded area, in blocks. Within the far plane of the camera.
+const EXTENT: f32 = 20_000.;
+
+/// Read by `horizon.vert` and `horizon.frag`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+struct PushConstants {
+    /// Min x, min z, max x and max z of the ground, in blocks.
+    outer: Vec4,
+    /// `xyz` is the color and `w` the height.
+    color_height: Vec4,
+    /// Player chunk x and z, then the render distance on x and z. The radii are -1 if no chunk is loaded at the
+    /// ground height.
+    hole: [i32; 4],
+    /// The column `dx`, `dz` chunks from the player is loaded if `dx² * x + dz² * y <= z`, see [`hole_factors`].
+    hole_factors: [u32; 4],
+}
+
+impl PushConstants {
+    /// Return `None` if the ground shouldn't be drawn from `eye`.
+    fn new(
+        player_chunk: ChunkPos,
+        render_distance: RenderDistance,
+        generation: &GenerationOptions,
+        color: [f32; 3],
+        eye: Vec3,
+    ) -> Option<Self> {
+        let height = average_surface_height(generation)?;
+        if eye.y < height {
+            return None;
+        }
+        let size = CHUNK_SIZE as i64;
+        let (rx, rz) = (render_distance.x as i64, render_distance.z as i64);
+        let loaded = Vec4::new(
+            ((player_chunk.x() - rx) * size) as f32,
+            ((player_chunk.z() - rz) * size) as f32,
+            ((player_chunk.x() + rx + 1) * size) as f32,
+            ((player_chunk.z() + rz + 1) * size) as f32,
+        );
+        let outer = loaded + Vec4::new(-EXTENT, -EXTENT, EXTENT, EXTENT);
+        let dy = ((height / CHUNK_SIZE as f32).floor() as i64).abs_diff(player_chunk.y());
+        let (hole, hole_factors) = match hole_factors(render_distance, dy) {
+            Some([x, y, z]) => (
+                [
+                    player_chunk.x() as i32,
+                    player_chunk.z() as i32,
+                    rx as i32,
+                    rz as i32,
+                ],
+                [x, y, z, 0],
+            ),
+            None => ([0, 0, -1, -1], [0; 4]),
+        };
+        let [r, g, b] = color;
+        Some(Self {
+            outer,
+            color_height: Vec4::new(r, g, b, height),
+            hole,
+            hole_factors,
+        })
+    }
+}
+
+/// The factors of the columns loaded at `dy` chunks from the player, like [`RenderDistance::contains`] with `dy`
+/// moved to the right: `dx² ry² rz² + dz² rx² ry² <= rx² rz² (ry² - dy²)`, divided by their gcd to fit the `uint`s
+/// of the shader up to a render distance of 32. Past that they are all 0 so the hole is the whole square. `None`
+/// if no chunk is loaded at `dy`.
+fn hole_factors(render_distance: RenderDistance, dy: u64) -> Option<[u32; 3]> {
+    if dy > render_distance.y as u64 {
+        return None;
+    }
+    let [rx, ry, rz] = [render_distance.x, render_distance.y, render_distance.z]
+        .map(|radius| (radius.max(1) as u128).pow(2));
+    let factors = [ry * rz, rx * ry, rx * rz * (ry - (dy as u128).pow(2))];
+    let divisor = factors.into_iter().fold(0, gcd);
+    let [x, y, z] = factors.map(|factor| factor / divisor.max(1));
+    // The offsets are at most the radii in the shader.
+    let max = (render_distance.x as u128).pow(2) * x + (render_distance.z as u128).pow(2) * y;
+    if max > u32::MAX as u128 {
+        return Some([0; 3]);
+    }
+    Some([x as u32, y as u32, z as u32])
+}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// Middle of the possible surface heights, in blocks. `None` if the terrain has no surface.
+fn average_surface_height(options: &GenerationOptions) -> Option<f32> {
+    match options.mode {
+        WorldGenMode::Noise => Some(options.base_height as f32 + options.amplitude as f32 / 2.),
+        WorldGenMode::Flat { height } => Some(height as f32),
+        WorldGenMode::Checkerboard | WorldGenMode::Empty => None,
+    }
+}
+
+/// Draws the ground in the scene render pass after the terrain.
+#[derive(Debug)]
+pub struct HorizonRenderer {
+    pipeline: Pipeline,
+    command_buffers: Vec<CommandBuffer>,
+    command_pool: CommandPool,
+}
+
+impl HorizonRenderer {
+    pub fn new(
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<Self> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        let pipeline = Pipeline::new::<NoVertex>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline creation failed")?;
+        let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)
+            .context("Command pool creation failed")?;
+        let command_buffers = command_pool
+            .alloc_buffers(swapchain.images.len(), true)
+            .context("Command buffers allocation failed")?;
+        Ok(Self {
+            pipeline,
+            command_buffers,
+            command_pool,
+        })
+    }
+
+    fn pipeline_options(uniforms_layout: &DescriptorSetLayout) -> Result<PipelineCreationOptions> {
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<PushConstants>() as u32)
+            .build();
+        Ok(PipelineCreationOptions {
+            shaders: vec![
+                (
+                    shader_module!("horizon.vert")?,
+                    vk::ShaderStageFlags::VERTEX,
+                ),
+                (
+                    shader_module!("horizon.frag")?,
+                    vk::ShaderStageFlags::FRAGMENT,
+                ),
+            ],
+            topology: vk::PrimitiveTopology::TRIANGLE_STRIP,
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            descriptors_layouts: vec![uniforms_layout],
+            push_constant_ranges: vec![push_constant_range],
+            blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .build(),
+            dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(),
+        })
+    }
+
+    #[inline]
+    pub fn recreate(
+        &mut self,
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<()> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        self.pipeline
+            .recreate::<NoVertex>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline recreation failed")?;
+        let images_count = swapchain.images.len();
+        if images_count != self.command_buffers.len() {
+            self.command_pool
+                .realloc_buffers(&mut self.command_buffers, images_count, true)
+                .context("Command buffers reallocation failed")?;
+        }
+        Ok(())
+    }
+
+    /// `eye` is the position of the camera drawing the frame, the render distance is around `player_chunk`.
+    /// Return `None` if there is nothing to draw.
+    pub fn render(
+        &mut self,
+        image_index: usize,
+        player_chunk: ChunkPos,
+        eye: Vec3,
+        uniforms_set: vk::DescriptorSet,
+        inheritance_info: &vk::CommandBufferInheritanceInfo,
+        extent: vk::Extent2D,
+    ) -> Result<Option<vk::CommandBuffer>> {
+        let push_constants = {
+            let options = AppOptions::get();
+            if !options.horizon.enabled {
+                return Ok(None);
+            }
+            PushConstants::new(
+                player_chunk,
+                options.render_distance,
+                &options.generation,
+                options.horizon.color,
+                eye,
+            )
+        };
+        let Some(push_constants) = push_constants else {
+            return Ok(None);
+        };
+
+        let command_buff = &mut self.command_buffers[image_index];
+        command_buff.begin_secondary(inheritance_info)?;
+        unsafe {
+            DEVICE.cmd_bind_pipeline(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            DEVICE.cmd_bind_descriptor_sets(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[uniforms_set],
+                &[],
+            );
+            DEVICE.cmd_push_constants(
+                **command_buff,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                0,
+                slice::from_raw_parts(
+                    &push_constants as *const PushConstants as *const u8,
+                    size_of::<PushConstants>(),
+                ),
+            );
+            pipeline::set_viewport(**command_buff, extent);
+            pipeline::set_full_scissor(**command_buff, extent);
+            DEVICE.cmd_draw(**command_buff, 4, 1, 0, 0);
+        }
+        command_buff.end()?;
+
+        Ok(Some(**command_buff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ground_around_render_distance() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 3 };
+        let generation = GenerationOptions {
+            mode: WorldGenMode::Flat { height: 40 },
+            ..GenerationOptions::new()
+        };
+        let color = [0.1, 0.2, 0.3];
+        let push_constants = PushConstants::new(
+            ChunkPos::new(-1, 0, 4),
+            render_distance,
+            &generation,
+            color,
+            Vec3::new(0., 41., 0.),
+        )
+        .expect("Camera below the ground");
+        // Chunks -3..=1 on x and 1..=7 on z.
+        assert_eq!(
+            push_constants.outer,
+            Vec4::new(-96. - EXTENT, 32. - EXTENT, 64. + EXTENT, 256. + EXTENT)
+        );
+        assert_eq!(push_constants.color_height, Vec4::new(0.1, 0.2, 0.3, 40.));
+        // The ground is in the chunk above the player, at the top of the ellipsoid: only the player column.
+        assert_eq!(push_constants.hole, [-1, 4, 2, 3]);
+        assert_eq!(push_constants.hole_factors, [9, 4, 0, 0]);
+        let push_constants = PushConstants::new(
+            ChunkPos::new(-1, 3, 4),
+            render_distance,
+            &generation,
+            color,
+            Vec3::new(0., 41., 0.),
+        )
+        .expect("Camera below the ground");
+        assert_eq!(push_constants.hole, [0, 0, -1, -1]);
+
+        // Not seen from below nor without surface.
+        let eye = Vec3::new(0., 39., 0.);
+        assert!(PushConstants::new(
+            ChunkPos::new(0, 0, 0),
+            render_distance,
+            &generation,
+            color,
+            eye
+        )
+        .is_none());
+        let generation = GenerationOptions {
+            mode: WorldGenMode::Empty,
+            ..generation
+        };
+        let eye = Vec3::new(0., 1000., 0.);
+        assert!(PushConstants::new(
+            ChunkPos::new(0, 0, 0),
+            render_distance,
+            &generation,
+            color,
+            eye
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn hole_is_ellipsoid_slice() {
+        let render_distance = RenderDistance { x: 5, y: 3, z: 4 };
+        let center = ChunkPos::new(0, 0, 0);
+        for dy in 0..=3 {
+            let [x, y, z] = hole_factors(render_distance, dy).expect("Nothing loaded");
+            for dx in -6..=6_i64 {
+                for dz in -6..=6_i64 {
+                    let in_radii = dx.unsigned_abs() <= 5 && dz.unsigned_abs() <= 4;
+                    let in_hole = in_radii && (dx * dx) as u32 * x + (dz * dz) as u32 * y <= z;
+                    let pos = ChunkPos::new(dx, dy as i64, dz);
+                    assert_eq!(in_hole, render_distance.contains(center, pos, 0));
+                }
+            }
+        }
+        assert_eq!(hole_factors(render_distance, 4), None);
+
+        // The largest render distance of the options window still fits.
+        let render_distance = RenderDistance {
+            x: 32,
+            y: 31,
+            z: 30,
+        };
+        assert_ne!(hole_factors(render_distance, 1), Some([0; 3]));
+        // Uneven radii larger than that don't simplify enough.
+        let render_distance = RenderDistance {
+            x: 64,
+            y: 63,
+            z: 62,
+        };
+        assert_eq!(hole_factors(render_distance, 1), Some([0; 3]));
+    }
+}
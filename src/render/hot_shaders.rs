@@ -0,0 +1,80 @@
+//! Runtime recompilation of the shaders.
+//!
+//! `shader_module!` embeds the SPIR-V compiled by the build script. With the `hot_shaders` feature it first
+//! looks for the version compiled by the last [`reload`], so the pipelines created after a reload by
+//! `Renderer::recreate_pipeline` use the current sources. The compute meshing shader is only loaded at startup.
+
+use std::{collections::HashMap, ffi::OsStr, fs, path::Path, sync::RwLock};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use shaderc::{CompileOptions, Compiler, ShaderKind};
+
+const SHADERS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+
+/// SPIR-V words by file name.
+static COMPILED: RwLock<Option<HashMap<String, Vec<u32>>>> = RwLock::new(None);
+
+/// The SPIR-V of `file` compiled by the last successful reload.
+pub fn compiled(file: &str) -> Option<Vec<u32>> {
+    COMPILED
+        .read()
+        .expect("Lock poisoned")
+        .as_ref()?
+        .get(file)
+        .cloned()
+}
+
+/// Compile all the shaders of the source directory. Nothing is replaced if one of them fails.
+pub fn reload() -> Result<()> {
+    let compiler = Compiler::new().ok_or_else(|| anyhow!("Shader compiler creation failed"))?;
+    let mut options =
+        CompileOptions::new().ok_or_else(|| anyhow!("Compile options creation failed"))?;
+    options.set_generate_debug_info();
+
+    let mut compiled = HashMap::new();
+    for entry in fs::read_dir(SHADERS_DIR).context("Failed to read the shaders directory")? {
+        let path = entry
+            .context("Failed to read the shaders directory")?
+            .path();
+        let name = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or_else(|| anyhow!("Invalid shader file name: {}", path.display()))?
+            .to_owned();
+        let source = fs::read_to_string(&path).with_context(|| format!("Failed to read {name}"))?;
+        let artifact = compiler
+            .compile_into_spirv(&source, shader_kind(&path)?, &name, "main", Some(&options))
+            .with_context(|| format!("{name} compilation failed"))?;
+        compiled.insert(name, artifact.as_binary().to_vec());
+    }
+
+    info!("{} shaders recompiled", compiled.len());
+    *COMPILED.write().expect("Lock poisoned") = Some(compiled);
+    Ok(())
+}
+
+/// From the extension, as glslc does.
+fn shader_kind(path: &Path) -> Result<ShaderKind> {
+    Ok(match path.extension().and_then(OsStr::to_str) {
+        Some("vert") => ShaderKind::Vertex,
+        Some("frag") => ShaderKind::Fragment,
+        Some("comp") => ShaderKind::Compute,
+        _ => bail!("Unknown shader stage of {}", path.display()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_sources() {
+        reload().expect("Shaders reload failed");
+        for file in ["shader.vert", "shader.frag", "mesh.comp"] {
+            let words = compiled(file).expect("Shader not compiled");
+            assert_eq!(words.first(), Some(&0x0723_0203), "{file} isn't SPIR-V");
+        }
+        assert!(compiled("missing.frag").is_none());
+    }
+}
@@ -3,7 +3,11 @@ use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
 use crate::render::memory::allocator;
 
-use super::{devices::DEVICE, memory::Allocation, Buffer, CommandBuffer, Queue};
+use super::{
+    devices::DEVICE,
+    memory::{Allocation, MemoryProperties},
+    Buffer, CommandBuffer, Queue,
+};
 
 pub fn create_image_view(
     image: vk::Image,
@@ -71,7 +75,11 @@ impl Image {
         let requirements = unsafe { DEVICE.get_image_memory_requirements(image) };
 
         let alloc = allocator()
-            .alloc(vk::MemoryPropertyFlags::DEVICE_LOCAL, requirements, false)
+            .alloc(
+                MemoryProperties::required(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+                requirements,
+                false,
+            )
             .context("Alloc failed")?;
 
         unsafe { DEVICE.bind_image_memory(image, alloc.memory(), alloc.offset() as u64) }
@@ -15,15 +15,14 @@ use vulkanalia::{
 };
 use winit::window::Window;
 
-use crate::{
-    render::config::{VALIDATION_ENABLED, VALIDATION_LAYERS},
-    utils::DerefOnceLock,
-};
+use crate::{render::config, utils::DerefOnceLock};
 
 #[derive(Debug)]
 pub struct Instance {
     instance: vulkanalia::Instance,
     debug_messenger: Option<DebugUtilsMessengerEXT>,
+    /// `VK_KHR_get_physical_device_properties2` is enabled.
+    pub properties2: bool,
 }
 
 impl Deref for Instance {
@@ -75,18 +74,24 @@ impl Instance {
             .application_name(b"Vulkan Voxels 2\0")
             .application_version(app_version);
 
-        let layers = if VALIDATION_ENABLED {
-            VALIDATION_LAYERS
-        } else {
-            &[]
-        };
+        let validation = config::validation_enabled();
         let mut extensions = vulkanalia::window::get_required_instance_extensions(window)
             .iter()
             .map(|&ext| ext.as_ptr())
             .collect::<Vec<_>>();
-        if VALIDATION_ENABLED {
+        if validation {
             extensions.push(vk::EXT_DEBUG_UTILS_EXTENSION.name.as_ptr())
         }
+        // Optional, needed to query the memory budget.
+        let available_extensions = unsafe { entry.enumerate_instance_extension_properties(None) }
+            .context("Enumerating instance extensions failed")?;
+        let properties2_extension = vk::KHR_GET_PHYSICAL_DEVICE_PROPERTIES2_EXTENSION.name;
+        let properties2 = available_extensions
+            .iter()
+            .any(|ext| ext.extension_name == properties2_extension);
+        if properties2 {
+            extensions.push(properties2_extension.as_ptr());
+        }
 
         let mut debug_messenger_create_info = DebugUtilsMessengerCreateInfoEXT::builder()
             .message_severity(
@@ -96,16 +101,19 @@ impl Instance {
             .message_type(vk::DebugUtilsMessageTypeFlagsEXT::all())
             .user_callback(Some(debug_callback));
 
-        let instance_create_info = InstanceCreateInfo::builder()
+        let mut instance_create_info = InstanceCreateInfo::builder()
             .application_info(&app_info)
-            .enabled_layer_names(layers)
-            .enabled_extension_names(&extensions)
-            .push_next(&mut debug_messenger_create_info);
+            .enabled_layer_names(config::layers())
+            .enabled_extension_names(&extensions);
+        // Also reports the instance creation and destruction, only valid with the debug utils extension.
+        if validation {
+            instance_create_info = instance_create_info.push_next(&mut debug_messenger_create_info);
+        }
 
         let instance = unsafe { entry.create_instance(&instance_create_info, None) }
             .context("Vulkan instance creation failed")?;
 
-        let debug_messenger = if VALIDATION_ENABLED {
+        let debug_messenger = if validation {
             match unsafe {
                 instance.create_debug_utils_messenger_ext(&debug_messenger_create_info, None)
             } {
@@ -122,6 +130,7 @@ impl Instance {
         Ok(Self {
             instance,
             debug_messenger,
+            properties2,
         })
     }
 }
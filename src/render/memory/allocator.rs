@@ -9,14 +9,17 @@ use std::{
 
 use anyhow::{bail, Context, Result};
 use log::trace;
-use vulkanalia::vk::{self, DeviceV1_0, HasBuilder, InstanceV1_0};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
-use crate::render::{devices::DEVICE, instance::INSTANCE, memory::get_memory_type_index};
-
-use super::allocator;
+use crate::render::{
+    devices::DEVICE,
+    error::vk_error,
+    memory::{get_memory_type_index, has_memory_type, MemoryProperties},
+};
 
 const MIN_CHUNK_SIZE: usize = 1024 * 1024 * 32;
 
+/// Sub-allocates from chunks of device memory, one pool of chunks per memory type.
 #[derive(Debug)]
 pub struct Allocator {
     device_memory_properties: vk::PhysicalDeviceMemoryProperties,
@@ -24,9 +27,7 @@ pub struct Allocator {
 }
 
 impl Allocator {
-    pub fn new(physical_device: vk::PhysicalDevice) -> Self {
-        let device_memory_properties =
-            unsafe { INSTANCE.get_physical_device_memory_properties(physical_device) };
+    pub fn new(device_memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
         let pools = {
             let mut vec = Vec::with_capacity(device_memory_properties.memory_type_count as usize);
             for (i, _) in device_memory_properties
@@ -47,7 +48,7 @@ impl Allocator {
 
     pub fn alloc(
         &self,
-        properties: vk::MemoryPropertyFlags,
+        properties: MemoryProperties,
         requirements: vk::MemoryRequirements,
         mapped: bool,
     ) -> Result<Allocation> {
@@ -63,8 +64,29 @@ impl Allocator {
         .context("Alloc failed")
     }
 
+    #[cfg(test)]
     #[inline]
-    fn free(&self, alloc: &Allocation) {
+    pub fn memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
+        self.device_memory_properties
+    }
+
+    /// Return `true` if a memory type has all the `properties`.
+    #[inline]
+    pub fn has_memory_type(&self, properties: vk::MemoryPropertyFlags) -> bool {
+        has_memory_type(self.device_memory_properties, properties)
+    }
+
+    /// Write the fragmentation of each pool in `fragmentation`, indexed by memory type.
+    /// `None` if the pool has no free memory.
+    pub fn update_gui_data(&self, fragmentation: &mut Vec<Option<f32>>) {
+        fragmentation.resize(self.pools.len(), None);
+        for (pool, fragmentation) in self.pools.iter().zip(fragmentation.iter_mut()) {
+            *fragmentation = pool.free_space().fragmentation();
+        }
+    }
+
+    #[inline]
+    pub fn free(&self, alloc: &Allocation) {
         trace!(target: "allocator", "Free {}B", alloc.size);
 
         let pool = &self.pools[alloc.memory_type_index as usize];
@@ -114,10 +136,11 @@ impl Pool {
                         let info = vk::MemoryAllocateInfo::builder()
                             .allocation_size(size as u64)
                             .memory_type_index(self.memory_type_index);
-                        let mem = unsafe { DEVICE.allocate_memory(&info, None) }?;
+                        let mem =
+                            unsafe { DEVICE.allocate_memory(&info, None) }.map_err(vk_error)?;
                         (size, mem)
                     }
-                    Err(e) => bail!(e),
+                    Err(e) => return Err(vk_error(e)),
                 }
             };
             Chunk::new(0, allocated_size, self.memory_type_index, memory, mapped)?
@@ -140,6 +163,37 @@ impl Pool {
             .expect("Invalid chunk id in allocation when freeing");
         chunks[index].free(alloc);
     }
+
+    fn free_space(&self) -> FreeSpace {
+        let chunks = self.chunks.read().expect("Lock poisoned");
+        let mut free_space = FreeSpace::default();
+        for chunk in chunks.iter() {
+            free_space.add(&chunk.blocks.lock().expect("Mutex poisoned"));
+        }
+        free_space
+    }
+}
+
+/// Free blocks of a pool. Adjacent free blocks aren't merged so they count as separate blocks,
+/// like in [`Chunk::try_alloc`].
+#[derive(Debug, Clone, Copy, Default)]
+struct FreeSpace {
+    total: usize,
+    largest_block: usize,
+}
+
+impl FreeSpace {
+    fn add(&mut self, blocks: &[Block]) {
+        for block in blocks.iter().filter(|block| block.is_free) {
+            self.total += block.size;
+            self.largest_block = self.largest_block.max(block.size);
+        }
+    }
+
+    /// `0` if all the free memory is in one block, close to `1` if it's split in many small ones.
+    fn fragmentation(self) -> Option<f32> {
+        (self.total > 0).then(|| 1. - self.largest_block as f32 / self.total as f32)
+    }
 }
 
 #[derive(Debug)]
@@ -291,6 +345,7 @@ impl Block {
     }
 }
 
+/// Freed by [`Allocator::free`].
 #[derive(Debug)]
 pub struct Allocation {
     memory_type_index: u32,
@@ -318,6 +373,11 @@ impl Allocation {
         self.offset
     }
 
+    #[inline(always)]
+    pub fn is_mapped(&self) -> bool {
+        !self.ptr.is_null()
+    }
+
     #[inline(always)]
     pub fn data(&mut self) -> Option<&mut [u8]> {
         if !self.ptr.is_null() {
@@ -343,11 +403,24 @@ impl Allocation {
         };
         Ok(())
     }
-}
 
-impl Drop for Allocation {
-    fn drop(&mut self) {
-        allocator().free(self)
+    /// Make the device writes visible to the host.
+    #[cfg(feature = "compute_meshing")]
+    #[inline]
+    pub fn invalidate(&self) -> Result<()> {
+        if self.ptr.is_null() {
+            bail!("A non-mapped allocation couldn't be invalidated");
+        }
+        let memory_ranges = &[vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(self.offset as u64)
+            .size(self.size as u64)];
+        unsafe {
+            DEVICE
+                .invalidate_mapped_memory_ranges(memory_ranges)
+                .context("Allocation invalidation failed")?;
+        };
+        Ok(())
     }
 }
 
@@ -370,4 +443,28 @@ mod tests {
             assert_eq!(block.aligned_size(alignment), result);
         }
     }
+
+    #[test]
+    fn free_space_fragmentation() {
+        let block = |offset, size, is_free| Block {
+            offset,
+            size,
+            is_free,
+        };
+        let mut free_space = FreeSpace::default();
+        assert_eq!(free_space.fragmentation(), None);
+
+        free_space.add(&[block(0, 100, false), block(100, 400, true)]);
+        assert_eq!(free_space.fragmentation(), Some(0.));
+
+        // Adjacent free blocks stay separate.
+        free_space.add(&[
+            block(0, 200, true),
+            block(200, 200, true),
+            block(400, 1000, false),
+        ]);
+        assert_eq!(free_space.total, 800);
+        assert_eq!(free_space.largest_block, 400);
+        assert_eq!(free_space.fragmentation(), Some(0.5));
+    }
 }
@@ -2,21 +2,21 @@ use std::{ptr, slice};
 
 use anyhow::{bail, Context, Result};
 use log::trace;
-use vulkanalia::vk::{self, DeviceV1_0, HasBuilder, InstanceV1_0};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
-use crate::render::{instance::INSTANCE, memory::get_memory_type_index, DEVICE};
-
-use super::allocator;
+use crate::render::{
+    memory::{get_memory_type_index, has_memory_type, MemoryProperties},
+    vk_error, DEVICE,
+};
 
+/// One device memory per allocation, to rule out the pooled allocator when debugging.
 #[derive(Debug)]
 pub struct Allocator {
     device_memory_properties: vk::PhysicalDeviceMemoryProperties,
 }
 
 impl Allocator {
-    pub fn new(physical_device: vk::PhysicalDevice) -> Self {
-        let device_memory_properties =
-            unsafe { INSTANCE.get_physical_device_memory_properties(physical_device) };
+    pub fn new(device_memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
         Self {
             device_memory_properties,
         }
@@ -24,7 +24,7 @@ impl Allocator {
 
     pub fn alloc(
         &self,
-        properties: vk::MemoryPropertyFlags,
+        properties: MemoryProperties,
         requirements: vk::MemoryRequirements,
         mapped: bool,
     ) -> Result<Allocation> {
@@ -34,8 +34,9 @@ impl Allocator {
         let info = vk::MemoryAllocateInfo::builder()
             .allocation_size(requirements.size)
             .memory_type_index(memory_type_index);
-        let memory =
-            unsafe { DEVICE.allocate_memory(&info, None) }.context("Failed to allocated")?;
+        let memory = unsafe { DEVICE.allocate_memory(&info, None) }
+            .map_err(vk_error)
+            .context("Failed to allocated")?;
 
         let ptr = if mapped {
             unsafe {
@@ -59,13 +60,32 @@ impl Allocator {
         Ok(alloc)
     }
 
+    #[cfg(test)]
+    #[inline]
+    pub fn memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
+        self.device_memory_properties
+    }
+
+    /// Return `true` if a memory type has all the `properties`.
+    #[inline]
+    pub fn has_memory_type(&self, properties: vk::MemoryPropertyFlags) -> bool {
+        has_memory_type(self.device_memory_properties, properties)
+    }
+
+    /// Each allocation has its own memory so there is no fragmentation to report.
     #[inline]
-    fn free(&self, alloc: &Allocation) {
+    pub fn update_gui_data(&self, fragmentation: &mut Vec<Option<f32>>) {
+        fragmentation.clear();
+    }
+
+    #[inline]
+    pub fn free(&self, alloc: &Allocation) {
         trace!(target: "allocator", "Free {}B", alloc.size);
         unsafe { DEVICE.free_memory(alloc.memory, None) }
     }
 }
 
+/// Freed by [`Allocator::free`].
 #[derive(Debug)]
 pub struct Allocation {
     memory: vk::DeviceMemory,
@@ -90,6 +110,11 @@ impl Allocation {
         0
     }
 
+    #[inline(always)]
+    pub fn is_mapped(&self) -> bool {
+        !self.ptr.is_null()
+    }
+
     #[inline(always)]
     pub fn data(&mut self) -> Option<&mut [u8]> {
         if !self.ptr.is_null() {
@@ -115,10 +140,23 @@ impl Allocation {
         };
         Ok(())
     }
-}
 
-impl Drop for Allocation {
-    fn drop(&mut self) {
-        allocator().free(self)
+    /// Make the device writes visible to the host.
+    #[cfg(feature = "compute_meshing")]
+    #[inline]
+    pub fn invalidate(&self) -> Result<()> {
+        if self.ptr.is_null() {
+            bail!("A non-mapped allocation couldn't be invalidated");
+        }
+        let memory_ranges = &[vk::MappedMemoryRange::builder()
+            .memory(self.memory)
+            .offset(0)
+            .size(self.size as u64)];
+        unsafe {
+            DEVICE
+                .invalidate_mapped_memory_ranges(memory_ranges)
+                .context("Allocation invalidation failed")?;
+        };
+        Ok(())
     }
 }
@@ -0,0 +1,138 @@
+//! The memory heaps as seen by the driver, with the memory used by the other apps unlike the allocator stats.
+
+use std::time::Duration;
+
+use vulkanalia::vk::{self, InstanceV1_0, KhrGetPhysicalDeviceProperties2Extension};
+
+use crate::render::{instance::INSTANCE, DEVICE};
+
+/// Between two queries, they are too slow for every frame.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapInfo {
+    /// In bytes.
+    pub size: u64,
+    pub device_local: bool,
+    /// One of its memory types can be mapped.
+    pub host_visible: bool,
+    /// `None` without `VK_EXT_memory_budget`.
+    pub budget: Option<HeapBudget>,
+}
+
+/// In bytes, for this process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapBudget {
+    pub usage: u64,
+    /// How much it can use before allocations may fail or degrade, lowered by the other apps using the heap.
+    pub budget: u64,
+}
+
+/// Query the heaps of the device, with their budget if the device supports it.
+pub fn query(physical_device: vk::PhysicalDevice) -> Vec<HeapInfo> {
+    if !DEVICE.memory_budget {
+        let properties = unsafe { INSTANCE.get_physical_device_memory_properties(physical_device) };
+        return heaps_info(&properties, None);
+    }
+    let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+    let mut properties = vk::PhysicalDeviceMemoryProperties2::builder().push_next(&mut budget);
+    unsafe {
+        INSTANCE.get_physical_device_memory_properties2_khr(physical_device, &mut properties)
+    };
+    let memory_properties = properties.memory_properties;
+    heaps_info(&memory_properties, Some(&budget))
+}
+
+fn heaps_info(
+    properties: &vk::PhysicalDeviceMemoryProperties,
+    budget: Option<&vk::PhysicalDeviceMemoryBudgetPropertiesEXT>,
+) -> Vec<HeapInfo> {
+    let memory_types = &properties.memory_types[..properties.memory_type_count as usize];
+    properties.memory_heaps[..properties.memory_heap_count as usize]
+        .iter()
+        .enumerate()
+        .map(|(i, heap)| HeapInfo {
+            size: heap.size,
+            device_local: heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL),
+            host_visible: memory_types.iter().any(|memory_type| {
+                memory_type.heap_index as usize == i
+                    && memory_type
+                        .property_flags
+                        .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            }),
+            budget: budget.map(|budget| HeapBudget {
+                usage: budget.heap_usage[i],
+                budget: budget.heap_budget[i],
+            }),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthetic_heaps() {
+        const GIB: u64 = 1 << 30;
+        let mut properties = vk::PhysicalDeviceMemoryProperties {
+            memory_heap_count: 2,
+            memory_type_count: 3,
+            ..Default::default()
+        };
+        properties.memory_heaps[0] = vk::MemoryHeap {
+            size: 8 * GIB,
+            flags: vk::MemoryHeapFlags::DEVICE_LOCAL,
+        };
+        properties.memory_heaps[1] = vk::MemoryHeap {
+            size: 16 * GIB,
+            flags: vk::MemoryHeapFlags::empty(),
+        };
+        properties.memory_types[0] = vk::MemoryType {
+            property_flags: vk::MemoryPropertyFlags::DEVICE_LOCAL,
+            heap_index: 0,
+        };
+        properties.memory_types[1] = vk::MemoryType {
+            property_flags: vk::MemoryPropertyFlags::HOST_VISIBLE
+                | vk::MemoryPropertyFlags::HOST_COHERENT,
+            heap_index: 1,
+        };
+        // Past `memory_type_count`, ignored.
+        properties.memory_types[2] = vk::MemoryType {
+            property_flags: vk::MemoryPropertyFlags::HOST_VISIBLE,
+            heap_index: 0,
+        };
+
+        let heaps = heaps_info(&properties, None);
+        assert_eq!(
+            heaps,
+            [
+                HeapInfo {
+                    size: 8 * GIB,
+                    device_local: true,
+                    host_visible: false,
+                    budget: None,
+                },
+                HeapInfo {
+                    size: 16 * GIB,
+                    device_local: false,
+                    host_visible: true,
+                    budget: None,
+                },
+            ]
+        );
+
+        let mut budget = vk::PhysicalDeviceMemoryBudgetPropertiesEXT::default();
+        budget.heap_usage[..2].copy_from_slice(&[GIB, 0]);
+        budget.heap_budget[..2].copy_from_slice(&[7 * GIB, 12 * GIB]);
+        let heaps = heaps_info(&properties, Some(&budget));
+        assert_eq!(
+            heaps[0].budget,
+            Some(HeapBudget {
+                usage: GIB,
+                budget: 7 * GIB,
+            })
+        );
+        assert_eq!(heaps[1].budget.map(|budget| budget.budget), Some(12 * GIB));
+    }
+}
@@ -1,41 +1,343 @@
-#[cfg(not(feature = "dumb_allocator"))]
 mod allocator;
-#[cfg(not(feature = "dumb_allocator"))]
-pub use allocator::*;
-#[cfg(feature = "dumb_allocator")]
 mod dumb_allocator;
-#[cfg(feature = "dumb_allocator")]
-pub use dumb_allocator::*;
+pub mod heaps;
 
 use anyhow::{anyhow, Result};
-use vulkanalia::vk;
+use log::{info, warn};
+use vulkanalia::vk::{self, InstanceV1_0};
 
-use std::sync::OnceLock;
+#[cfg(debug_assertions)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(all(test, debug_assertions))]
+use std::{cell::RefCell, sync::atomic::AtomicIsize, sync::Arc};
+use std::{env, sync::OnceLock};
+
+use crate::options::{AllocatorKind, AppOptions};
+
+use super::{instance::INSTANCE, DEVICE};
+
+/// Overrides `AppOptions::allocator` with `pooled` or `dumb`.
+const ALLOCATOR_VAR: &str = "VOXELS_ALLOCATOR";
 
 static ALLOCATOR: OnceLock<Allocator> = OnceLock::new();
 
+/// Count of the allocations not freed yet, to catch the ones outliving their owner. Only tracked in debug builds.
+#[cfg(debug_assertions)]
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(all(test, debug_assertions))]
+thread_local! {
+    /// Balance of the innermost [`assert_no_leaks`] running on this thread.
+    static CURRENT_SCOPE: RefCell<Option<Arc<AtomicIsize>>> = const { RefCell::new(None) };
+}
+
+/// The [`assert_no_leaks`] call an allocation was made in. The tests share the allocator and run in parallel, and
+/// an allocation may be freed by another thread, so it is carried by the allocation. Empty outside the tests.
+#[derive(Debug)]
+pub struct LeakScope(#[cfg(all(test, debug_assertions))] Option<Arc<AtomicIsize>>);
+
+impl LeakScope {
+    #[cfg(all(test, debug_assertions))]
+    fn enter() -> Self {
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let balance = CURRENT_SCOPE.with(|scope| scope.borrow().clone());
+        if let Some(balance) = &balance {
+            balance.fetch_add(1, Ordering::Relaxed);
+        }
+        Self(balance)
+    }
+
+    #[cfg(not(all(test, debug_assertions)))]
+    fn enter() -> Self {
+        #[cfg(debug_assertions)]
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        Self()
+    }
+}
+
+impl Drop for LeakScope {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        LIVE_ALLOCATIONS.fetch_sub(1, Ordering::Relaxed);
+        #[cfg(all(test, debug_assertions))]
+        if let Some(balance) = &self.0 {
+            balance.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(all(test, debug_assertions))]
+pub fn live_allocations() -> usize {
+    LIVE_ALLOCATIONS.load(Ordering::Relaxed)
+}
+
+/// Run `f` and panic if the allocations it made on this thread aren't all freed, by any thread. The ones it returns
+/// count as leaked. Does nothing in release builds.
+#[cfg(test)]
+pub fn assert_no_leaks<T>(f: impl FnOnce() -> T) -> T {
+    #[cfg(debug_assertions)]
+    let balance = Arc::new(AtomicIsize::new(0));
+    #[cfg(debug_assertions)]
+    let outer = CURRENT_SCOPE.with(|scope| scope.replace(Some(Arc::clone(&balance))));
+    let result = f();
+    #[cfg(debug_assertions)]
+    {
+        CURRENT_SCOPE.with(|scope| scope.replace(outer));
+        let leaked = balance.load(Ordering::Relaxed);
+        assert_eq!(leaked, 0, "{} allocations leaked", leaked);
+    }
+    result
+}
+
 #[inline(always)]
 pub fn allocator() -> &'static Allocator {
     ALLOCATOR.get().expect("Allocator not initialized")
 }
 
-#[inline(always)]
 pub fn init_allocator(physical_device: vk::PhysicalDevice) {
-    ALLOCATOR.get_or_init(|| Allocator::new(physical_device));
+    ALLOCATOR.get_or_init(|| {
+        let kind = match env::var(ALLOCATOR_VAR).as_deref() {
+            Ok("pooled") => AllocatorKind::Pooled,
+            Ok("dumb") => AllocatorKind::Dumb,
+            Ok(value) => {
+                warn!("Unknown {ALLOCATOR_VAR} value: {value:?}");
+                AppOptions::get().allocator
+            }
+            Err(_) => AppOptions::get().allocator,
+        };
+        info!("Using the {kind:?} allocator");
+        let memory_properties =
+            unsafe { INSTANCE.get_physical_device_memory_properties(physical_device) };
+        Allocator::new(kind, memory_properties)
+    });
 }
 
-fn get_memory_type_index(
+/// The allocator backend, chosen at startup.
+#[derive(Debug)]
+pub enum Allocator {
+    Pooled(allocator::Allocator),
+    Dumb(dumb_allocator::Allocator),
+}
+
+impl Allocator {
+    pub fn new(kind: AllocatorKind, memory_properties: vk::PhysicalDeviceMemoryProperties) -> Self {
+        match kind {
+            AllocatorKind::Pooled => Self::Pooled(allocator::Allocator::new(memory_properties)),
+            AllocatorKind::Dumb => Self::Dumb(dumb_allocator::Allocator::new(memory_properties)),
+        }
+    }
+
+    #[cfg(test)]
+    #[inline]
+    pub fn memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
+        match self {
+            Self::Pooled(allocator) => allocator.memory_properties(),
+            Self::Dumb(allocator) => allocator.memory_properties(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn kind(&self) -> AllocatorKind {
+        match self {
+            Self::Pooled(_) => AllocatorKind::Pooled,
+            Self::Dumb(_) => AllocatorKind::Dumb,
+        }
+    }
+
+    #[inline]
+    pub fn alloc(
+        &'static self,
+        properties: MemoryProperties,
+        requirements: vk::MemoryRequirements,
+        mapped: bool,
+    ) -> Result<Allocation> {
+        let alloc = match self {
+            Self::Pooled(allocator) => Allocation::Pooled(
+                allocator.alloc(properties, requirements, mapped)?,
+                allocator,
+                LeakScope::enter(),
+            ),
+            Self::Dumb(allocator) => Allocation::Dumb(
+                allocator.alloc(properties, requirements, mapped)?,
+                allocator,
+                LeakScope::enter(),
+            ),
+        };
+        Ok(alloc)
+    }
+
+    /// Return `true` if a memory type has all the `properties`.
+    #[inline]
+    pub fn has_memory_type(&self, properties: vk::MemoryPropertyFlags) -> bool {
+        match self {
+            Self::Pooled(allocator) => allocator.has_memory_type(properties),
+            Self::Dumb(allocator) => allocator.has_memory_type(properties),
+        }
+    }
+
+    /// Write the fragmentation of each pool in `fragmentation`, indexed by memory type.
+    /// `None` if the pool has no free memory.
+    pub fn update_gui_data(&self, fragmentation: &mut Vec<Option<f32>>) {
+        match self {
+            Self::Pooled(allocator) => allocator.update_gui_data(fragmentation),
+            Self::Dumb(allocator) => allocator.update_gui_data(fragmentation),
+        }
+    }
+}
+
+/// Freed on drop by the allocator it comes from.
+#[derive(Debug)]
+pub enum Allocation {
+    Pooled(
+        allocator::Allocation,
+        &'static allocator::Allocator,
+        LeakScope,
+    ),
+    Dumb(
+        dumb_allocator::Allocation,
+        &'static dumb_allocator::Allocator,
+        LeakScope,
+    ),
+}
+
+impl Allocation {
+    #[inline]
+    pub fn memory(&self) -> vk::DeviceMemory {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.memory(),
+            Self::Dumb(alloc, ..) => alloc.memory(),
+        }
+    }
+
+    #[inline]
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.size(),
+            Self::Dumb(alloc, ..) => alloc.size(),
+        }
+    }
+
+    #[inline]
+    pub fn offset(&self) -> usize {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.offset(),
+            Self::Dumb(alloc, ..) => alloc.offset(),
+        }
+    }
+
+    #[inline]
+    pub fn is_mapped(&self) -> bool {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.is_mapped(),
+            Self::Dumb(alloc, ..) => alloc.is_mapped(),
+        }
+    }
+
+    #[inline]
+    pub fn data(&mut self) -> Option<&mut [u8]> {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.data(),
+            Self::Dumb(alloc, ..) => alloc.data(),
+        }
+    }
+
+    #[inline]
+    pub fn flush(&self) -> Result<()> {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.flush(),
+            Self::Dumb(alloc, ..) => alloc.flush(),
+        }
+    }
+
+    #[cfg(feature = "compute_meshing")]
+    #[inline]
+    pub fn invalidate(&self) -> Result<()> {
+        match self {
+            Self::Pooled(alloc, ..) => alloc.invalidate(),
+            Self::Dumb(alloc, ..) => alloc.invalidate(),
+        }
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        match self {
+            Self::Pooled(alloc, allocator, _) => allocator.free(alloc),
+            Self::Dumb(alloc, allocator, _) => allocator.free(alloc),
+        }
+    }
+}
+
+/// Return `true` if the device is an integrated GPU sharing its memory with the host.
+/// Device local memory can then be written directly instead of going through a staging buffer.
+#[cfg_attr(feature = "compute_meshing", allow(dead_code))]
+pub fn unified_memory() -> bool {
+    DEVICE.properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU
+        && allocator().has_memory_type(
+            vk::MemoryPropertyFlags::DEVICE_LOCAL | vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )
+}
+
+fn has_memory_type(
     memory: vk::PhysicalDeviceMemoryProperties,
     properties: vk::MemoryPropertyFlags,
+) -> bool {
+    memory
+        .memory_types
+        .iter()
+        .take(memory.memory_type_count as usize)
+        .any(|memory_type| memory_type.property_flags.contains(properties))
+}
+
+/// Memory properties of an allocation. A memory type with all the `preferred` ones is used if there is one,
+/// else one with only the `required` ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryProperties {
+    pub required: vk::MemoryPropertyFlags,
+    /// Contains the `required` ones.
+    pub preferred: vk::MemoryPropertyFlags,
+}
+
+impl MemoryProperties {
+    #[inline]
+    pub const fn required(properties: vk::MemoryPropertyFlags) -> Self {
+        Self {
+            required: properties,
+            preferred: properties,
+        }
+    }
+
+    /// Prefer a memory type also having `properties`.
+    #[inline]
+    pub fn prefer(self, properties: vk::MemoryPropertyFlags) -> Self {
+        Self {
+            preferred: self.preferred | properties,
+            ..self
+        }
+    }
+}
+
+fn get_memory_type_index(
+    memory: vk::PhysicalDeviceMemoryProperties,
+    properties: MemoryProperties,
     requirements: vk::MemoryRequirements,
 ) -> Result<u32> {
-    (0..memory.memory_type_count)
-        .find(|i| {
+    let find = |flags: vk::MemoryPropertyFlags| {
+        (0..memory.memory_type_count).find(|i| {
             let suitable = (requirements.memory_type_bits & (1 << i)) != 0;
             let memory_type = memory.memory_types[*i as usize];
-            suitable && memory_type.property_flags.contains(properties)
+            suitable && memory_type.property_flags.contains(flags)
+        })
+    };
+    find(properties.preferred)
+        .or_else(|| find(properties.required))
+        .ok_or_else(|| {
+            anyhow!(
+                "Failed to find a memory type with {:?} (allowed types: {:#b})",
+                properties.required,
+                requirements.memory_type_bits
+            )
         })
-        .ok_or_else(|| anyhow!("Failed to find suitable memory type."))
 }
 
 #[cfg(test)]
@@ -43,6 +345,7 @@ mod tests {
     use std::{
         collections::hash_map::DefaultHasher,
         hash::{Hash, Hasher},
+        panic, thread,
     };
 
     use super::*;
@@ -67,7 +370,8 @@ mod tests {
             memory_type_bits: u32::MAX, // this should accept all memory types
         };
 
-        let mut alloc = allocator().alloc(properties, requirements, mapped)?;
+        let mut alloc =
+            allocator().alloc(MemoryProperties::required(properties), requirements, mapped)?;
 
         assert_eq!(alloc.size(), size);
         assert_eq!(
@@ -94,49 +398,198 @@ mod tests {
 
     #[test]
     fn simple_allocs() -> Result<()> {
-        let mut allocations = Vec::new();
+        assert_no_leaks(|| {
+            let mut allocations = Vec::new();
 
-        for &mem in MEMS {
-            for &size in SIZES {
-                for &alignment in ALIGNMENTS {
-                    let alloc = test_alloc(size, alignment, mem, false)?;
-                    allocations.push(alloc);
+            for &mem in MEMS {
+                for &size in SIZES {
+                    for &alignment in ALIGNMENTS {
+                        let alloc = test_alloc(size, alignment, mem, false)?;
+                        allocations.push(alloc);
+                    }
                 }
             }
-        }
 
-        Ok(())
+            Ok(())
+        })
     }
 
     #[test]
     fn mapped_allocs() -> Result<()> {
-        let mut allocations = Vec::new();
+        assert_no_leaks(|| {
+            let mut allocations = Vec::new();
 
-        for &size in SIZES {
-            for &alignment in ALIGNMENTS {
-                let mut alloc =
-                    test_alloc(size, alignment, vk::MemoryPropertyFlags::HOST_VISIBLE, true)?;
-                let data = alloc.data().unwrap();
+            for &size in SIZES {
+                for &alignment in ALIGNMENTS {
+                    let mut alloc =
+                        test_alloc(size, alignment, vk::MemoryPropertyFlags::HOST_VISIBLE, true)?;
+                    let data = alloc.data().unwrap();
+
+                    let mut hasher = DefaultHasher::new();
+                    (size, alignment).hash(&mut hasher);
+                    let id = hasher.finish();
 
-                let mut hasher = DefaultHasher::new();
-                (size, alignment).hash(&mut hasher);
-                let id = hasher.finish();
+                    for val in data {
+                        *val = id as u8;
+                    }
 
-                for val in data {
-                    *val = id as u8;
+                    allocations.push((id, alloc));
                 }
+            }
 
-                allocations.push((id, alloc));
+            for (id, mut alloc) in allocations {
+                let data = alloc.data().unwrap();
+                for &mut val in data {
+                    assert_eq!(val, id as u8);
+                }
             }
-        }
 
-        for (id, mut alloc) in allocations {
-            let data = alloc.data().unwrap();
-            for &mut val in data {
-                assert_eq!(val, id as u8);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn runtime_backends() -> Result<()> {
+        assert_no_leaks(|| {
+            let memory_properties = allocator().memory_properties();
+            for kind in [AllocatorKind::Pooled, AllocatorKind::Dumb] {
+                let allocator: &'static Allocator =
+                    Box::leak(Box::new(Allocator::new(kind, memory_properties)));
+                assert_eq!(allocator.kind(), kind);
+
+                let requirements = vk::MemoryRequirements {
+                    size: 256,
+                    alignment: 16,
+                    memory_type_bits: u32::MAX,
+                };
+                let properties = MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE);
+                let mut alloc = allocator.alloc(properties, requirements, true)?;
+                assert_eq!(alloc.size(), 256);
+                alloc.data().unwrap().fill(42);
+                alloc.flush()?;
+            }
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn unaligned_free_block() -> Result<()> {
+        assert_no_leaks(|| {
+            // A fresh pool so the first allocation starts at offset 0 and nothing else allocates in between.
+            let allocator: &'static Allocator = Box::leak(Box::new(Allocator::new(
+                AllocatorKind::Pooled,
+                allocator().memory_properties(),
+            )));
+            let properties = MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE);
+            let alloc = |size: usize, alignment: usize| {
+                let requirements = vk::MemoryRequirements {
+                    size: size as u64,
+                    alignment: alignment as u64,
+                    memory_type_bits: u32::MAX,
+                };
+                allocator.alloc(properties, requirements, false)
+            };
+
+            // Leave the free block at offset 1023.
+            let mut allocations = Vec::new();
+            let mut end = 0;
+            for size in [3, 7, 13, 1000] {
+                let allocation = alloc(size, 1)?;
+                end = end.max(allocation.offset() + allocation.size());
+                allocations.push(allocation);
+            }
+            assert_eq!(end, 1023);
+
+            for alignment in [256, 4096] {
+                let aligned = alloc(100, alignment)?;
+                assert_eq!(
+                    aligned.offset() % alignment,
+                    0,
+                    "Allocation alignment mismatch (alloc offset: {}, alignment: {})",
+                    aligned.offset(),
+                    alignment
+                );
+                assert!(aligned.offset() >= end, "Overlapping allocations");
+                end = aligned.offset() + aligned.size();
+                allocations.push(aligned);
             }
+
+            // The padding before the first aligned allocation is still free.
+            let padding = alloc(1, 1)?;
+            assert_eq!(padding.offset(), 1023);
+            Ok(())
+        })
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn leak_detected() {
+        let leaked = panic::catch_unwind(|| {
+            assert_no_leaks(|| {
+                let alloc = test_alloc(64, 16, vk::MemoryPropertyFlags::HOST_VISIBLE, false);
+                assert!(live_allocations() >= 1);
+                alloc
+            })
+        });
+        assert!(leaked.is_err());
+    }
+
+    #[test]
+    fn freed_by_another_thread() -> Result<()> {
+        assert_no_leaks(|| {
+            let alloc = test_alloc(64, 16, vk::MemoryPropertyFlags::HOST_VISIBLE, false)?;
+            thread::spawn(move || drop(alloc))
+                .join()
+                .expect("Thread panicked");
+            Ok(())
+        })
+    }
+
+    fn memory_types(types: &[vk::MemoryPropertyFlags]) -> vk::PhysicalDeviceMemoryProperties {
+        let mut memory = vk::PhysicalDeviceMemoryProperties::default();
+        memory.memory_type_count = types.len() as u32;
+        for (memory_type, &flags) in memory.memory_types.iter_mut().zip(types) {
+            memory_type.property_flags = flags;
         }
+        memory
+    }
+
+    #[test]
+    fn memory_type_fallback() {
+        use vk::MemoryPropertyFlags as F;
+
+        let requirements = |memory_type_bits| vk::MemoryRequirements {
+            size: 64,
+            alignment: 4,
+            memory_type_bits,
+        };
+        let mapped = MemoryProperties::required(F::HOST_VISIBLE).prefer(F::DEVICE_LOCAL);
+
+        // Discrete GPU: no device local memory is host visible.
+        let discrete = memory_types(&[F::DEVICE_LOCAL, F::HOST_VISIBLE | F::HOST_COHERENT]);
+        assert_eq!(
+            get_memory_type_index(discrete, mapped, requirements(u32::MAX)).unwrap(),
+            1
+        );
+
+        let unified = memory_types(&[
+            F::DEVICE_LOCAL,
+            F::HOST_VISIBLE,
+            F::DEVICE_LOCAL | F::HOST_VISIBLE,
+        ]);
+        assert_eq!(
+            get_memory_type_index(unified, mapped, requirements(u32::MAX)).unwrap(),
+            2
+        );
+        // The ideal type isn't allowed by the resource.
+        assert_eq!(
+            get_memory_type_index(unified, mapped, requirements(0b011)).unwrap(),
+            1
+        );
 
-        Ok(())
+        // The required properties are never relaxed.
+        assert!(get_memory_type_index(discrete, mapped, requirements(0b001)).is_err());
+        let coherent = MemoryProperties::required(F::HOST_VISIBLE | F::HOST_COHERENT);
+        assert!(get_memory_type_index(unified, coherent, requirements(u32::MAX)).is_err());
     }
 }
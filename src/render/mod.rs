@@ -1,20 +1,35 @@
 mod buffer;
 mod camera;
 mod commands;
+#[cfg(feature = "compute_meshing")]
+mod compute;
 mod config;
+#[cfg(feature = "debug_boxes")]
+mod debug_renderer;
 mod depth;
 mod descriptors;
 mod devices;
+mod error;
+mod frame_ring;
 mod framebuffers;
 mod gui_renderer;
+mod horizon;
+#[cfg(feature = "hot_shaders")]
+pub mod hot_shaders;
 mod image;
 mod instance;
 mod memory;
+mod particles;
 mod pipeline;
+mod post_process;
 mod queues;
+// Only tested until a readback uses it.
+#[cfg(test)]
+mod readback;
 mod regions;
 mod render_pass;
 mod renderer;
+mod sky;
 mod staging;
 mod surface;
 mod swapchain;
@@ -26,11 +41,20 @@ mod window;
 
 pub use buffer::Buffer;
 pub use commands::{CommandBuffer, CommandPool};
+#[cfg(feature = "compute_meshing")]
+pub use compute::ComputePipeline;
 pub use devices::DEVICE;
+pub use error::{vk_error, RenderError};
+pub use memory::{heaps::HeapInfo, unified_memory, MemoryProperties};
+pub use particles::MAX_PARTICLES;
+pub use pipeline::{create_shader_module, shader_words, AlignedBytes};
 pub use queues::{Queue, QueueInfo, QUEUES};
 pub use regions::{RegionCmdBuff, RegionsManager};
 pub use renderer::{Renderer, MAX_FRAMES_IN_FLIGHT};
 pub use staging::StagingBuffer;
 pub use sync::*;
+#[cfg(feature = "debug_vertex")]
+pub use vertex::DebugVertex as Vertex;
+#[cfg(not(feature = "debug_vertex"))]
 pub use vertex::Vertex;
 pub use window::Window;
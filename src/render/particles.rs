@@ -0,0 +1,375 @@
+//! Short-lived quads thrown out of the broken blocks.
+//!
+//! The particles are simulated on the CPU: each frame [`Particles::tick`] applies the gravity to them and
+//! removes the expired ones, then their instances are written to a per-frame ring and drawn as camera facing
+//! quads in one instanced draw, fading out with their age. At most [`MAX_PARTICLES`] are alive, the oldest are
+//! dropped first when a break spawns more.
+
+use std::{
+    collections::VecDeque,
+    marker::Unsize,
+    mem::{align_of, size_of},
+    slice,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use memoffset::offset_of;
+use nalgebra_glm::{Vec3, Vec4};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::{
+    options::AppOptions,
+    shader_module,
+    world::{Aabb, BlockId},
+};
+
+use super::{
+    descriptors::DescriptorSetLayout,
+    devices::DEVICE,
+    frame_ring::FrameRing,
+    pipeline::{self, Pipeline, PipelineCreationOptions},
+    render_pass::RenderPass,
+    swapchain::Swapchain,
+    vertex::VertexDescriptor,
+    CommandBuffer, CommandPool, QUEUES,
+};
+
+/// Alive at the same time, whatever the count spawned by each break.
+pub const MAX_PARTICLES: usize = 1024;
+/// In blocks per second squared.
+const GRAVITY: f32 = 20.;
+/// Side of the quads, in blocks.
+const SIZE: f32 = 0.15;
+
+#[derive(Debug, Clone, Copy)]
+struct Particle {
+    pos: Vec3,
+    velocity: Vec3,
+    /// In seconds.
+    age: f32,
+    lifetime: f32,
+    color: [f32; 3],
+}
+
+/// A particle quad, centered on `pos`. The alpha of `color` fades it out.
+/// `pos` and `size` are read together as the first attribute.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ParticleInstance {
+    pos: Vec3,
+    size: f32,
+    color: Vec4,
+}
+
+/// Particles are instances of the same binding, the quad itself is in `particle.vert`.
+impl VertexDescriptor for ParticleInstance {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::INSTANCE)
+            .build()
+    }
+
+    fn attribute_descriptions() -> impl Unsize<[vk::VertexInputAttributeDescription]> {
+        [
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(0)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(Self, pos) as u32)
+                .build(),
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(1)
+                .format(vk::Format::R32G32B32A32_SFLOAT)
+                .offset(offset_of!(Self, color) as u32)
+                .build(),
+        ]
+    }
+}
+
+/// The alive particles, oldest first.
+#[derive(Debug)]
+pub struct Particles {
+    particles: VecDeque<Particle>,
+    /// Xorshift state of the spawn directions.
+    rng: u32,
+}
+
+impl Particles {
+    pub const fn new() -> Self {
+        Self {
+            particles: VecDeque::new(),
+            rng: 0x9e37_79b9,
+        }
+    }
+
+    /// Throw `AppOptions::particles.per_break` particles out of the block in `aabb`, if they are enabled.
+    pub fn spawn_break(&mut self, aabb: &Aabb, block: BlockId) {
+        let options = AppOptions::get().particles;
+        if !options.enabled {
+            return;
+        }
+        let center = (aabb.min + aabb.max) / 2.;
+        for _ in 0..options.per_break {
+            let offset = Vec3::new(self.random(), self.random(), self.random()) - Vec3::repeat(0.5);
+            self.particles.push_back(Particle {
+                pos: center + offset * 0.8,
+                velocity: Vec3::new(offset.x * 4., 2. + self.random() * 3., offset.z * 4.),
+                age: 0.,
+                lifetime: options.lifetime * (0.5 + self.random() * 0.5),
+                color: block_color(block),
+            });
+        }
+        let excess = self.particles.len().saturating_sub(MAX_PARTICLES);
+        self.particles.drain(..excess);
+    }
+
+    /// Move the particles and remove the expired ones.
+    pub fn tick(&mut self, elapsed: Duration) {
+        if !AppOptions::get().particles.enabled {
+            self.particles.clear();
+            return;
+        }
+        let dt = elapsed.as_secs_f32();
+        self.particles.retain_mut(|particle| {
+            particle.age += dt;
+            particle.velocity.y -= GRAVITY * dt;
+            particle.pos += particle.velocity * dt;
+            particle.age < particle.lifetime
+        });
+    }
+
+    pub fn instances(&self) -> Vec<ParticleInstance> {
+        self.particles
+            .iter()
+            .map(|particle| {
+                let [r, g, b] = particle.color;
+                ParticleInstance {
+                    pos: particle.pos,
+                    size: SIZE,
+                    color: Vec4::new(r, g, b, 1. - particle.age / particle.lifetime),
+                }
+            })
+            .collect()
+    }
+
+    /// In `0..1`.
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+/// The terrain is untextured, the particles are tinted by the kind of block.
+fn block_color(block: BlockId) -> [f32; 3] {
+    match block {
+        BlockId::Air | BlockId::Block => [0.8, 0.8, 0.8],
+        BlockId::Bedrock => [0.25, 0.25, 0.25],
+        BlockId::Torch => [1., 0.7, 0.3],
+    }
+}
+
+/// Draws the particles in the scene render pass after the terrain.
+#[derive(Debug)]
+pub struct ParticleRenderer {
+    pipeline: Pipeline,
+    instances: FrameRing,
+    command_buffers: Vec<CommandBuffer>,
+    command_pool: CommandPool,
+}
+
+impl ParticleRenderer {
+    pub fn new(
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<Self> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        let pipeline = Pipeline::new::<ParticleInstance>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline creation failed")?;
+        let instances = FrameRing::new(
+            swapchain.images.len(),
+            MAX_PARTICLES * size_of::<ParticleInstance>(),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )
+        .context("Instances ring creation failed")?;
+        let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)
+            .context("Command pool creation failed")?;
+        let command_buffers = command_pool
+            .alloc_buffers(swapchain.images.len(), true)
+            .context("Command buffers allocation failed")?;
+        Ok(Self {
+            pipeline,
+            instances,
+            command_buffers,
+            command_pool,
+        })
+    }
+
+    fn pipeline_options(uniforms_layout: &DescriptorSetLayout) -> Result<PipelineCreationOptions> {
+        // The right and up vectors of the camera.
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<[Vec4; 2]>() as u32)
+            .build();
+        Ok(PipelineCreationOptions {
+            shaders: vec![
+                (
+                    shader_module!("particle.vert")?,
+                    vk::ShaderStageFlags::VERTEX,
+                ),
+                (
+                    shader_module!("particle.frag")?,
+                    vk::ShaderStageFlags::FRAGMENT,
+                ),
+            ],
+            topology: vk::PrimitiveTopology::TRIANGLE_STRIP,
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            descriptors_layouts: vec![uniforms_layout],
+            push_constant_ranges: vec![push_constant_range],
+            blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(true)
+                .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
+                .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .build(),
+            dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(),
+        })
+    }
+
+    #[inline]
+    pub fn recreate(
+        &mut self,
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        uniforms_layout: &DescriptorSetLayout,
+    ) -> Result<()> {
+        let pipeline_options = Self::pipeline_options(uniforms_layout)?;
+        self.pipeline
+            .recreate::<ParticleInstance>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline recreation failed")?;
+        let images_count = swapchain.images.len();
+        if images_count != self.command_buffers.len() {
+            self.instances
+                .resize(images_count)
+                .context("Instances ring resizing failed")?;
+            self.command_pool
+                .realloc_buffers(&mut self.command_buffers, images_count, true)
+                .context("Command buffers reallocation failed")?;
+        }
+        Ok(())
+    }
+
+    /// `look` is the direction the camera looks at. Return `None` if there is nothing to draw.
+    pub fn render(
+        &mut self,
+        image_index: usize,
+        instances: &[ParticleInstance],
+        look: Vec3,
+        uniforms_set: vk::DescriptorSet,
+        inheritance_info: &vk::CommandBufferInheritanceInfo,
+        extent: vk::Extent2D,
+    ) -> Result<Option<vk::CommandBuffer>> {
+        if instances.is_empty() {
+            return Ok(None);
+        }
+
+        let data = unsafe {
+            slice::from_raw_parts(
+                instances.as_ptr() as *const u8,
+                instances.len() * size_of::<ParticleInstance>(),
+            )
+        };
+        self.instances.begin_frame(image_index);
+        let alloc = self
+            .instances
+            .alloc(data.len(), align_of::<ParticleInstance>())?;
+        alloc.data.copy_from_slice(data);
+
+        let right = look.cross(&Vec3::y()).normalize();
+        let up = right.cross(&look);
+        let basis = [right.push(0.), up.push(0.)];
+
+        let command_buff = &mut self.command_buffers[image_index];
+        command_buff.begin_secondary(inheritance_info)?;
+        unsafe {
+            DEVICE.cmd_bind_pipeline(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            DEVICE.cmd_bind_descriptor_sets(
+                **command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[uniforms_set],
+                &[],
+            );
+            DEVICE.cmd_push_constants(
+                **command_buff,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                slice::from_raw_parts(basis.as_ptr() as *const u8, size_of::<[Vec4; 2]>()),
+            );
+            pipeline::set_viewport(**command_buff, extent);
+            pipeline::set_full_scissor(**command_buff, extent);
+            DEVICE.cmd_bind_vertex_buffers(
+                **command_buff,
+                0,
+                &[alloc.buffer],
+                &[alloc.offset as u64],
+            );
+            DEVICE.cmd_draw(**command_buff, 4, instances.len() as u32, 0, 0);
+        }
+        command_buff.end()?;
+
+        Ok(Some(**command_buff))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particles_fall_and_expire() {
+        let mut particles = Particles::new();
+        let aabb = Aabb::new(Vec3::new(4., 10., -3.), Vec3::new(5., 11., -2.));
+        particles.spawn_break(&aabb, BlockId::Block);
+        let per_break = AppOptions::get().particles.per_break;
+        assert_eq!(particles.particles.len(), per_break);
+        assert!(particles
+            .particles
+            .iter()
+            .all(|particle| (particle.pos - Vec3::new(4.5, 10.5, -2.5)).amax() <= 0.4));
+
+        // The capacity is kept by dropping the oldest ones.
+        for _ in 0..MAX_PARTICLES {
+            particles.spawn_break(&aabb, BlockId::Torch);
+        }
+        assert_eq!(particles.particles.len(), MAX_PARTICLES);
+        assert!(particles
+            .instances()
+            .iter()
+            .all(|instance| instance.color.xyz() == Vec3::new(1., 0.7, 0.3)
+                && instance.color.w == 1.));
+
+        particles.tick(Duration::from_millis(50));
+        let instances = particles.instances();
+        assert!(instances.iter().all(|instance| instance.color.w < 1.));
+        particles.tick(Duration::from_secs(1000));
+        assert!(particles.instances().is_empty());
+    }
+}
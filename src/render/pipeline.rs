@@ -1,6 +1,10 @@
-use std::mem::size_of_val;
+use std::{
+    borrow::Cow,
+    mem::{size_of, size_of_val},
+};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use log::debug;
 use vulkanalia::vk::{self, DeviceV1_0, Handle, HasBuilder, PipelineCache, ShaderModuleCreateInfo};
 
 use crate::utils::drop_then_new;
@@ -10,30 +14,52 @@ use super::{
     swapchain::Swapchain, vertex::VertexDescriptor,
 };
 
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+/// Gives the alignment of `A` to `bytes`, used to embed SPIR-V already aligned to words.
+#[repr(C)]
+pub struct AlignedBytes<A, B: ?Sized> {
+    pub _align: [A; 0],
+    pub bytes: B,
+}
+
 #[macro_export]
 macro_rules! shader_module {
-    ($file: expr) => {
-        unsafe {
-            $crate::utils::with_convert(
-                include_bytes!(concat!(env!("OUT_DIR"), "/", $file)),
-                |bytes| $crate::render::pipeline::create_shader_module(bytes),
-            )
+    ($file: expr) => {{
+        static SPIRV: &$crate::render::AlignedBytes<u32, [u8]> = &$crate::render::AlignedBytes {
+            _align: [],
+            bytes: *include_bytes!(concat!(env!("OUT_DIR"), "/", $file)),
+        };
+        $crate::render::shader_words($file, &SPIRV.bytes)
+            .and_then(|words| $crate::render::create_shader_module(&words))
             .context(concat!("Shader module for ", $file, " failed"))
-        }
-    };
+    }};
 }
 
 #[derive(Debug)]
 pub struct PipelineCreationOptions<'a> {
     pub shaders: Vec<(vk::ShaderModule, vk::ShaderStageFlags)>,
+    pub topology: vk::PrimitiveTopology,
     pub cull_mode: vk::CullModeFlags,
     pub polygon_mode: vk::PolygonMode,
+    /// `None` disables the depth bias.
+    pub depth_bias: Option<DepthBias>,
     pub descriptors_layouts: Vec<&'a DescriptorSetLayout>,
     pub push_constant_ranges: Vec<vk::PushConstantRange>,
     pub blend_attachment: vk::PipelineColorBlendAttachmentState,
     pub dynamic_state: vk::PipelineDynamicStateCreateInfo,
 }
 
+/// Added to the depth of the fragments, see `VkPipelineRasterizationStateCreateInfo`. Negative factors move them
+/// towards the camera.
+#[derive(Debug, Clone, Copy)]
+pub struct DepthBias {
+    /// In the smallest depth difference the depth buffer can hold.
+    pub constant_factor: f32,
+    /// Scales the depth slope of the polygon, growing at grazing angles.
+    pub slope_factor: f32,
+}
+
 #[derive(Debug)]
 pub struct Pipeline {
     pub pipeline: vk::Pipeline,
@@ -63,7 +89,7 @@ impl Pipeline {
             .vertex_binding_descriptions(binding_descriptions)
             .vertex_attribute_descriptions(&attribute_descriptions);
         let input_assembly_state = vk::PipelineInputAssemblyStateCreateInfo::builder()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(options.topology)
             .primitive_restart_enable(false);
         let viewport = vk::Viewport::builder()
             .x(0.0)
@@ -80,6 +106,10 @@ impl Pipeline {
         let viewport_state = vk::PipelineViewportStateCreateInfo::builder()
             .viewports(viewports)
             .scissors(scissors);
+        let depth_bias = options.depth_bias.unwrap_or(DepthBias {
+            constant_factor: 0.,
+            slope_factor: 0.,
+        });
         let rasterization_state = vk::PipelineRasterizationStateCreateInfo::builder()
             .depth_clamp_enable(false)
             .rasterizer_discard_enable(false)
@@ -87,7 +117,10 @@ impl Pipeline {
             .line_width(1.0)
             .cull_mode(options.cull_mode)
             .front_face(vk::FrontFace::CLOCKWISE)
-            .depth_bias_enable(false);
+            .depth_bias_enable(options.depth_bias.is_some())
+            .depth_bias_constant_factor(depth_bias.constant_factor)
+            .depth_bias_slope_factor(depth_bias.slope_factor)
+            .depth_bias_clamp(0.);
         let multisample_state = vk::PipelineMultisampleStateCreateInfo::builder()
             .sample_shading_enable(false)
             .rasterization_samples(vk::SampleCountFlags::_1);
@@ -102,8 +135,8 @@ impl Pipeline {
             .pass_op(vk::StencilOp::KEEP)
             .compare_op(vk::CompareOp::ALWAYS);
         let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::builder()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
+            .depth_test_enable(render_pass.has_depth())
+            .depth_write_enable(render_pass.has_depth())
             .depth_compare_op(vk::CompareOp::LESS_OR_EQUAL)
             .depth_bounds_test_enable(false)
             .stencil_test_enable(false)
@@ -172,6 +205,28 @@ impl Drop for Pipeline {
     }
 }
 
+/// Set a viewport covering the whole `extent`. Only valid for pipelines created with a dynamic viewport.
+#[inline]
+pub fn set_viewport(command_buff: vk::CommandBuffer, extent: vk::Extent2D) {
+    let viewport = vk::Viewport::builder()
+        .x(0.0)
+        .y(0.0)
+        .width(extent.width as f32)
+        .height(extent.height as f32)
+        .min_depth(0.0)
+        .max_depth(1.0);
+    unsafe { DEVICE.cmd_set_viewport(command_buff, 0, &[viewport]) };
+}
+
+/// Set a scissor covering the whole `extent`. Only valid for pipelines created with a dynamic scissor.
+#[inline]
+pub fn set_full_scissor(command_buff: vk::CommandBuffer, extent: vk::Extent2D) {
+    let scissor = vk::Rect2D::builder()
+        .offset(vk::Offset2D { x: 0, y: 0 })
+        .extent(extent);
+    unsafe { DEVICE.cmd_set_scissor(command_buff, 0, &[scissor]) };
+}
+
 pub fn create_shader_module(bytes: &[u32]) -> Result<vk::ShaderModule> {
     let info = ShaderModuleCreateInfo::builder()
         .code(bytes)
@@ -180,3 +235,80 @@ pub fn create_shader_module(bytes: &[u32]) -> Result<vk::ShaderModule> {
         .context("Shader module creation failed")?;
     Ok(module)
 }
+
+/// The SPIR-V of the shader `file`, embedded as `bytes`. With the `hot_shaders` feature, the version
+/// recompiled at runtime is used instead if there is one.
+#[cfg_attr(not(feature = "hot_shaders"), allow(unused_variables))]
+pub fn shader_words<'a>(file: &str, bytes: &'a [u8]) -> Result<Cow<'a, [u32]>> {
+    #[cfg(feature = "hot_shaders")]
+    if let Some(words) = super::hot_shaders::compiled(file) {
+        return Ok(Cow::Owned(words));
+    }
+    spirv_words(bytes)
+}
+
+/// Reinterpret SPIR-V bytes as words, checking the size and the magic number.
+/// `bytes` is only copied if it isn't aligned to 4 bytes, which `shader_module!` prevents.
+pub fn spirv_words(bytes: &[u8]) -> Result<Cow<[u32]>> {
+    if bytes.len() % size_of::<u32>() != 0 {
+        bail!("SPIR-V size ({} bytes) isn't a multiple of 4", bytes.len());
+    }
+    let (prefix, aligned, suffix) = unsafe { bytes.align_to::<u32>() };
+    let words = if prefix.is_empty() && suffix.is_empty() {
+        Cow::Borrowed(aligned)
+    } else {
+        debug!("Misaligned SPIR-V, {} bytes copied", bytes.len());
+        Cow::Owned(
+            bytes
+                .chunks_exact(size_of::<u32>())
+                .map(|word| u32::from_ne_bytes(word.try_into().expect("Chunks of 4 bytes")))
+                .collect(),
+        )
+    };
+    if words.first() != Some(&SPIRV_MAGIC) {
+        bail!("Invalid SPIR-V magic number");
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use super::*;
+
+    const WORDS: [u32; 4] = [SPIRV_MAGIC, 0x0001_0000, 0, 42];
+
+    fn words_bytes() -> Vec<u8> {
+        WORDS.iter().flat_map(|word| word.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn spirv_misaligned_is_copied() {
+        let bytes = words_bytes();
+        let mut buff = vec![0; bytes.len() + 1];
+        let start = usize::from(buff.as_ptr() as usize % 4 == 0);
+        buff[start..start + bytes.len()].copy_from_slice(&bytes);
+
+        let words = spirv_words(&buff[start..start + bytes.len()]).expect("Valid SPIR-V");
+        assert!(matches!(words, Cow::Owned(_)));
+        assert_eq!(*words, WORDS);
+    }
+
+    #[test]
+    fn spirv_aligned_is_borrowed() {
+        let bytes =
+            unsafe { slice::from_raw_parts(WORDS.as_ptr() as *const u8, size_of_val(&WORDS)) };
+        let words = spirv_words(bytes).expect("Valid SPIR-V");
+        assert!(matches!(words, Cow::Borrowed(_)));
+        assert_eq!(*words, WORDS);
+    }
+
+    #[test]
+    fn spirv_invalid() {
+        let bytes = words_bytes();
+        assert!(spirv_words(&bytes[..bytes.len() - 1]).is_err());
+        assert!(spirv_words(&bytes[4..]).is_err());
+        assert!(spirv_words(&[]).is_err());
+    }
+}
@@ -0,0 +1,464 @@
+use std::{marker::Unsize, mem::size_of_val, slice};
+
+use anyhow::{Context, Result};
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::{
+    options::{AntiAliasing, AppOptions},
+    shader_module,
+    utils::drop_then_new,
+};
+
+use super::{
+    commands::{CommandBuffer, CommandPool},
+    descriptors::{DescriptorPool, DescriptorSet, DescriptorSetLayout},
+    devices::DEVICE,
+    framebuffers::Framebuffers,
+    image::Image,
+    pipeline::{self, Pipeline, PipelineCreationOptions},
+    queues::QUEUES,
+    render_pass::{RenderPass, RenderPassCreationOptions},
+    swapchain::Swapchain,
+    texture::Texture,
+    vertex::VertexDescriptor,
+};
+
+/// Format of the images the scene is rendered to, so the lighting isn't clamped before the tonemapping.
+pub const SCENE_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+/// For the pipelines generating their vertices in the shader, like the fullscreen triangle of `fullscreen.vert`,
+/// so no vertex buffer is bound.
+pub(super) struct NoVertex;
+
+impl VertexDescriptor for NoVertex {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(0)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    fn attribute_descriptions() -> impl Unsize<[vk::VertexInputAttributeDescription]> {
+        [] as [vk::VertexInputAttributeDescription; 0]
+    }
+}
+
+/// Passes between the scene render pass and the presentation.
+///
+/// The scene is rendered in HDR to `scene_targets`. The composite render pass then writes the swapchain images
+/// with a fullscreen triangle, followed by the GUI:
+/// - without anti-aliasing, the composite triangle tonemaps the scene.
+/// - with FXAA, the `tonemap` pass first writes the tonemapped scene to LDR targets which the composite triangle
+///   filters, so FXAA works on the final colors.
+///
+/// The GUI is drawn after the triangle so it's neither tonemapped nor filtered.
+#[derive(Debug)]
+pub struct PostProcess {
+    composite: FullscreenPipeline,
+    composite_framebuffers: Framebuffers,
+    composite_render_pass: RenderPass,
+    /// Only draw the composite triangle, so they are recorded once per resize instead of each frame.
+    composite_command_buffers: Vec<CommandBuffer>,
+    _command_pool: CommandPool,
+    tonemap: Option<TonemapPass>,
+    scene_targets: Targets,
+    extent: vk::Extent2D,
+}
+
+impl PostProcess {
+    pub fn new(swapchain: &Swapchain) -> Result<Self> {
+        let anti_aliasing = AppOptions::get().anti_aliasing;
+        let scene_targets =
+            Targets::new(swapchain, SCENE_FORMAT).context("Scene targets creation failed")?;
+        let tonemap = match anti_aliasing {
+            AntiAliasing::None => None,
+            AntiAliasing::Fxaa => Some(
+                TonemapPass::new(swapchain, &scene_targets)
+                    .context("Tonemap pass creation failed")?,
+            ),
+        };
+
+        let render_pass_options = RenderPassCreationOptions::default(swapchain).without_clear();
+        let composite_render_pass =
+            RenderPass::new(&render_pass_options).context("Render pass creation failed")?;
+        let (shader, inputs) = match &tonemap {
+            Some(tonemap) => (shader_module!("fxaa.frag")?, &tonemap.targets.views),
+            None => (shader_module!("tonemap.frag")?, &scene_targets.views),
+        };
+        let mut composite = FullscreenPipeline::new(shader, swapchain, &composite_render_pass)
+            .context("Composite pipeline creation failed")?;
+        composite.set_inputs(inputs);
+        let composite_framebuffers = Framebuffers::new(
+            &swapchain.image_views,
+            swapchain.extent,
+            &composite_render_pass,
+            None,
+        )
+        .context("Framebuffers creation failed")?;
+
+        let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)
+            .context("Command pool creation failed")?;
+        let composite_command_buffers = command_pool
+            .alloc_buffers(swapchain.images.len(), true)
+            .context("Command buffers allocation failed")?;
+
+        let mut s = Self {
+            composite,
+            composite_framebuffers,
+            composite_render_pass,
+            composite_command_buffers,
+            _command_pool: command_pool,
+            tonemap,
+            scene_targets,
+            extent: swapchain.extent,
+        };
+        s.record_composite()?;
+        Ok(s)
+    }
+
+    #[inline]
+    pub fn recreate(&mut self, swapchain: &Swapchain) -> Result<()> {
+        drop_then_new(self, || Self::new(swapchain))
+    }
+
+    /// Recreate the targets with the new extent. The images count and format must not have changed.
+    pub fn resize(&mut self, swapchain: &Swapchain) -> Result<()> {
+        debug_assert_eq!(self.scene_targets.views.len(), swapchain.images.len());
+        self.scene_targets
+            .recreate(swapchain, SCENE_FORMAT)
+            .context("Scene targets recreation failed")?;
+        let composite_inputs = match &mut self.tonemap {
+            Some(tonemap) => {
+                tonemap
+                    .resize(swapchain, &self.scene_targets)
+                    .context("Tonemap pass resize failed")?;
+                &tonemap.targets.views
+            }
+            None => &self.scene_targets.views,
+        };
+        self.composite.set_inputs(composite_inputs);
+        self.composite_framebuffers
+            .recreate(
+                &swapchain.image_views,
+                swapchain.extent,
+                &self.composite_render_pass,
+                None,
+            )
+            .context("Framebuffers recreation failed")?;
+        self.extent = swapchain.extent;
+        self.record_composite()
+    }
+
+    fn record_composite(&mut self) -> Result<()> {
+        for image_index in 0..self.composite_command_buffers.len() {
+            let inheritance_info = self.inheritance_info(image_index);
+            let buff = &mut self.composite_command_buffers[image_index];
+            buff.begin_secondary(&inheritance_info)?;
+            self.composite.cmd_draw(**buff, image_index, self.extent);
+            buff.end()?;
+        }
+        Ok(())
+    }
+
+    /// Views the scene must be rendered to, indexed by swapchain image.
+    #[inline(always)]
+    pub fn scene_views(&self) -> &[vk::ImageView] {
+        &self.scene_targets.views
+    }
+
+    /// The render pass the GUI is drawn in.
+    #[inline(always)]
+    pub fn composite_render_pass(&self) -> &RenderPass {
+        &self.composite_render_pass
+    }
+
+    /// Record the tonemap pass if any and begin the composite render pass with the triangle drawn.
+    /// Its content must be secondary command buffers inheriting [`PostProcess::inheritance_info`].
+    pub fn cmd_begin_composite(&self, command_buff: vk::CommandBuffer, image_index: usize) {
+        if let Some(tonemap) = &self.tonemap {
+            tonemap.cmd_draw(command_buff, image_index, self.extent);
+        }
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(self.extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*self.composite_render_pass)
+            .framebuffer(self.composite_framebuffers[image_index])
+            .render_area(render_area);
+        unsafe {
+            DEVICE.cmd_begin_render_pass(
+                command_buff,
+                &info,
+                vk::SubpassContents::SECONDARY_COMMAND_BUFFERS,
+            );
+            DEVICE.cmd_execute_commands(
+                command_buff,
+                &[*self.composite_command_buffers[image_index]],
+            );
+        }
+    }
+
+    pub fn inheritance_info(&self, image_index: usize) -> vk::CommandBufferInheritanceInfo {
+        vk::CommandBufferInheritanceInfo::builder()
+            .render_pass(*self.composite_render_pass)
+            .subpass(0)
+            .framebuffer(self.composite_framebuffers[image_index])
+            .build()
+    }
+}
+
+/// Tonemaps the scene to LDR targets, only used when they are filtered afterwards.
+#[derive(Debug)]
+struct TonemapPass {
+    pipeline: FullscreenPipeline,
+    framebuffers: Framebuffers,
+    render_pass: RenderPass,
+    targets: Targets,
+}
+
+impl TonemapPass {
+    fn new(swapchain: &Swapchain, scene_targets: &Targets) -> Result<Self> {
+        let render_pass_options = RenderPassCreationOptions::default(swapchain)
+            .without_clear()
+            .sampled();
+        let render_pass =
+            RenderPass::new(&render_pass_options).context("Render pass creation failed")?;
+        let mut pipeline =
+            FullscreenPipeline::new(shader_module!("tonemap.frag")?, swapchain, &render_pass)
+                .context("Pipeline creation failed")?;
+        pipeline.set_inputs(&scene_targets.views);
+        let targets =
+            Targets::new(swapchain, swapchain.format.format).context("Targets creation failed")?;
+        let framebuffers = Framebuffers::new(&targets.views, swapchain.extent, &render_pass, None)
+            .context("Framebuffers creation failed")?;
+        Ok(Self {
+            pipeline,
+            framebuffers,
+            render_pass,
+            targets,
+        })
+    }
+
+    fn resize(&mut self, swapchain: &Swapchain, scene_targets: &Targets) -> Result<()> {
+        self.targets
+            .recreate(swapchain, swapchain.format.format)
+            .context("Targets recreation failed")?;
+        self.framebuffers
+            .recreate(
+                &self.targets.views,
+                swapchain.extent,
+                &self.render_pass,
+                None,
+            )
+            .context("Framebuffers recreation failed")?;
+        self.pipeline.set_inputs(&scene_targets.views);
+        Ok(())
+    }
+
+    fn cmd_draw(&self, command_buff: vk::CommandBuffer, image_index: usize, extent: vk::Extent2D) {
+        let render_area = vk::Rect2D::builder()
+            .offset(vk::Offset2D::default())
+            .extent(extent);
+        let info = vk::RenderPassBeginInfo::builder()
+            .render_pass(*self.render_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(render_area);
+        unsafe {
+            DEVICE.cmd_begin_render_pass(command_buff, &info, vk::SubpassContents::INLINE);
+            self.pipeline.cmd_draw(command_buff, image_index, extent);
+            DEVICE.cmd_end_render_pass(command_buff);
+        }
+    }
+}
+
+/// Color images sampled by a following pass, one per swapchain image like the command buffers writing them.
+#[derive(Debug)]
+struct Targets {
+    views: Vec<vk::ImageView>,
+    _images: Vec<Image>,
+}
+
+impl Targets {
+    fn new(swapchain: &Swapchain, format: vk::Format) -> Result<Self> {
+        let size = vk::Extent3D {
+            width: swapchain.extent.width,
+            height: swapchain.extent.height,
+            depth: 1,
+        };
+        let images = (0..swapchain.images.len())
+            .map(|_| {
+                Image::new(
+                    size,
+                    format,
+                    vk::ImageTiling::OPTIMAL,
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+                    vk::ImageAspectFlags::COLOR,
+                )
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("Offscreen images creation failed")?;
+        Ok(Self {
+            views: images.iter().map(|image| image.view).collect(),
+            _images: images,
+        })
+    }
+
+    /// The old images are destroyed before creating the new ones to not hold both in memory.
+    #[inline]
+    fn recreate(&mut self, swapchain: &Swapchain, format: vk::Format) -> Result<()> {
+        drop_then_new(self, || Self::new(swapchain, format))
+    }
+}
+
+/// Pipeline drawing a fullscreen triangle which samples one input image per swapchain image.
+/// The fragment shader receives the inverse of the screen size as push constant.
+#[derive(Debug)]
+struct FullscreenPipeline {
+    pipeline: Pipeline,
+    descriptor_sets: Vec<DescriptorSet>,
+    _descriptor_pool: DescriptorPool,
+    _descriptor_layout: DescriptorSetLayout,
+    sampler: vk::Sampler,
+}
+
+impl FullscreenPipeline {
+    fn new(
+        fragment_shader: vk::ShaderModule,
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+    ) -> Result<Self> {
+        let images_count = swapchain.images.len();
+        let descriptor_layout = DescriptorSetLayout::new(&Texture::binding(0))
+            .context("Descriptor set layout creation failed")?;
+        let mut descriptor_pool =
+            DescriptorPool::new(images_count, vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .context("Descriptor pool creation failed")?;
+        let descriptor_sets = descriptor_pool
+            .alloc_sets(images_count, &descriptor_layout)
+            .context("Descriptor sets allocation failed")?;
+
+        let pipeline_options = Self::pipeline_options(fragment_shader, &descriptor_layout)?;
+        let pipeline = Pipeline::new::<NoVertex>(swapchain, render_pass, &pipeline_options)
+            .context("Pipeline creation failed")?;
+
+        let info = vk::SamplerCreateInfo::builder()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .anisotropy_enable(false)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+            .min_lod(0.0)
+            .max_lod(0.0);
+        let sampler =
+            unsafe { DEVICE.create_sampler(&info, None) }.context("Sampler creation failed")?;
+
+        Ok(Self {
+            pipeline,
+            descriptor_sets,
+            _descriptor_pool: descriptor_pool,
+            _descriptor_layout: descriptor_layout,
+            sampler,
+        })
+    }
+
+    fn pipeline_options(
+        fragment_shader: vk::ShaderModule,
+        layout: &DescriptorSetLayout,
+    ) -> Result<PipelineCreationOptions> {
+        let push_constant_range = vk::PushConstantRange::builder()
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of_val(&[0_f32; 2]) as u32)
+            .build();
+        Ok(PipelineCreationOptions {
+            shaders: vec![
+                (
+                    shader_module!("fullscreen.vert")?,
+                    vk::ShaderStageFlags::VERTEX,
+                ),
+                (fragment_shader, vk::ShaderStageFlags::FRAGMENT),
+            ],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            cull_mode: vk::CullModeFlags::NONE,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            descriptors_layouts: vec![layout],
+            push_constant_ranges: vec![push_constant_range],
+            blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
+                .blend_enable(false)
+                .color_write_mask(vk::ColorComponentFlags::all())
+                .build(),
+            dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(),
+        })
+    }
+
+    /// The inputs must not be read by a command buffer in flight.
+    fn set_inputs(&mut self, views: &[vk::ImageView]) {
+        debug_assert_eq!(views.len(), self.descriptor_sets.len());
+        for (set, &view) in self.descriptor_sets.iter_mut().zip(views) {
+            let info = vk::DescriptorImageInfo::builder()
+                .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .image_view(view)
+                .sampler(self.sampler);
+            let write = vk::WriteDescriptorSet::builder()
+                .dst_set(**set)
+                .dst_binding(0)
+                .dst_array_element(0)
+                .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+                .image_info(slice::from_ref(&info));
+            set.update(&[write]);
+        }
+    }
+
+    /// Draw the triangle sampling the input `image_index`. A render pass must have begun.
+    fn cmd_draw(&self, command_buff: vk::CommandBuffer, image_index: usize, extent: vk::Extent2D) {
+        let inverse_screen_size = [1. / extent.width as f32, 1. / extent.height as f32];
+        let push_constants = unsafe {
+            slice::from_raw_parts(
+                inverse_screen_size.as_ptr() as *const u8,
+                size_of_val(&inverse_screen_size),
+            )
+        };
+
+        unsafe {
+            DEVICE.cmd_bind_pipeline(
+                command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.pipeline,
+            );
+            pipeline::set_viewport(command_buff, extent);
+            pipeline::set_full_scissor(command_buff, extent);
+            DEVICE.cmd_bind_descriptor_sets(
+                command_buff,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline.layout,
+                0,
+                &[*self.descriptor_sets[image_index]],
+                &[],
+            );
+            DEVICE.cmd_push_constants(
+                command_buff,
+                self.pipeline.layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constants,
+            );
+            DEVICE.cmd_draw(command_buff, 3, 1, 0, 0);
+        }
+    }
+}
+
+impl Drop for FullscreenPipeline {
+    fn drop(&mut self) {
+        unsafe { DEVICE.destroy_sampler(self.sampler, None) };
+    }
+}
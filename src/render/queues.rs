@@ -18,6 +18,8 @@ pub fn get_queue_families(device: vk::PhysicalDevice) -> Vec<QueueFamilyProperti
 pub struct QueueInfo {
     pub family: u32,
     pub index: u32,
+    /// Of the family.
+    pub flags: vk::QueueFlags,
 }
 
 #[derive(Debug)]
@@ -25,6 +27,8 @@ pub struct Queue {
     inner: vk::Queue,
     pub family: u32,
     pub index: u32,
+    /// Of the family.
+    pub flags: vk::QueueFlags,
 }
 
 impl Queue {
@@ -34,6 +38,7 @@ impl Queue {
             inner,
             family: info.family,
             index: info.index,
+            flags: info.flags,
         }
     }
 }
@@ -54,7 +59,6 @@ struct QueueFamilyInfo {
 }
 
 const GRAPHICS_COUNT: usize = 1;
-const TRANSFER_COUNT: usize = world::meshing::THREADS_COUNT;
 
 pub static QUEUES: DerefOnceLock<QueuesManager, "Queues manager not initialized"> =
     DerefOnceLock::new();
@@ -84,9 +88,11 @@ impl QueuesManager {
         surface: vk::SurfaceKHR,
     ) -> Result<(Self, (Vec<f32>, Vec<vk::DeviceQueueCreateInfo>))> {
         let families = get_queue_families(physical_device);
+        // One for each meshing thread.
+        let meshing_count = world::meshing::threads_count();
         let mut selected_families = vec![];
         let mut found_graphics = 0;
-        let mut found_transfer = 0;
+        let mut found_meshing = 0;
 
         for (i, family) in families.iter().enumerate() {
             let mut count = family.queue_count as usize;
@@ -95,9 +101,9 @@ impl QueuesManager {
                 found_graphics += found_count;
                 count -= found_count;
             }
-            if family.queue_flags.contains(vk::QueueFlags::TRANSFER) {
-                let found_count = (TRANSFER_COUNT - found_transfer).min(count);
-                found_transfer += found_count;
+            if family.queue_flags.contains(world::meshing::QUEUE_FLAGS) {
+                let found_count = (meshing_count - found_meshing).min(count);
+                found_meshing += found_count;
                 count -= found_count;
             }
 
@@ -132,7 +138,7 @@ impl QueuesManager {
                 offset: 0,
             })
             .collect::<Vec<_>>();
-        let first_graphics_family = families
+        let (first_graphics_family, graphics_flags) = families
             .iter()
             .find(|&queue| {
                 queue.flags.contains(vk::QueueFlags::GRAPHICS)
@@ -146,11 +152,12 @@ impl QueuesManager {
                             .unwrap_or(false)
                     }
             })
-            .context("No graphics queue family")?
-            .index;
+            .map(|queue| (queue.index, queue.flags))
+            .context("No graphics queue family")?;
         let graphics = QueueInfo {
             family: first_graphics_family,
             index: 0,
+            flags: graphics_flags,
         };
         families[first_graphics_family as usize].offset = 1;
 
@@ -178,6 +185,7 @@ impl QueuesManager {
             .with_context(|| format!("No unused {:?} queue found", family_type))?;
         let family_info = &mut families[i];
         let index = family_info.offset;
+        let flags = family_info.flags;
         family_info.offset += 1;
         drop(families);
 
@@ -187,6 +195,7 @@ impl QueuesManager {
             inner: queue,
             family: i as u32,
             index,
+            flags,
         })
     }
 }
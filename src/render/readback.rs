@@ -0,0 +1,80 @@
+//! Reading the results of the GPU work of a frame, like queries or copies to a host visible buffer, without
+//! waiting for the GPU.
+
+use std::array;
+
+use super::MAX_FRAMES_IN_FLIGHT;
+
+/// Holds what each frame will produce (a query range, a buffer...) until the GPU is certainly done with it.
+///
+/// [`DeferredReadback::tick`] must be called once per frame, after waiting the fence of the frame and before
+/// pushing. The fence of a frame is waited at most `MAX_FRAMES_IN_FLIGHT` frames later, so what was pushed this
+/// many ticks ago can be read without blocking. The results are thus `MAX_FRAMES_IN_FLIGHT` frames late,
+/// whatever the frames in flight count is.
+#[derive(Debug)]
+pub struct DeferredReadback<T> {
+    pending: [Option<T>; MAX_FRAMES_IN_FLIGHT],
+    index: usize,
+}
+
+impl<T> DeferredReadback<T> {
+    pub fn new() -> Self {
+        Self {
+            pending: array::from_fn(|_| None),
+            index: 0,
+        }
+    }
+
+    /// Set what the current frame will produce. Return the one pushed before during this frame, if any.
+    #[inline]
+    pub fn push(&mut self, pending: T) -> Option<T> {
+        self.pending[self.index].replace(pending)
+    }
+
+    /// Move to the next frame and return what was pushed `MAX_FRAMES_IN_FLIGHT` frames ago, ready to be read.
+    #[inline]
+    pub fn tick(&mut self) -> Option<T> {
+        self.index = (self.index + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.pending[self.index].take()
+    }
+
+    /// Drop everything pending, once the queue is idle and the results aren't wanted anymore.
+    pub fn clear(&mut self) {
+        self.pending = array::from_fn(|_| None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deferral() {
+        let mut readback = DeferredReadback::new();
+        // The first frames have nothing to read.
+        for frame in 0..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(readback.tick(), None);
+            assert_eq!(readback.push(frame), None);
+        }
+        for frame in MAX_FRAMES_IN_FLIGHT..4 * MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(readback.tick(), Some(frame - MAX_FRAMES_IN_FLIGHT));
+            readback.push(frame);
+        }
+
+        // The frames pushing nothing give nothing to read later.
+        for frame in 4 * MAX_FRAMES_IN_FLIGHT..5 * MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(readback.tick(), Some(frame - MAX_FRAMES_IN_FLIGHT));
+        }
+        assert_eq!(readback.tick(), None);
+        assert_eq!(readback.push(100), None);
+        assert_eq!(readback.push(101), Some(100));
+        for _ in 1..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(readback.tick(), None);
+        }
+        assert_eq!(readback.tick(), Some(101));
+
+        readback.push(102);
+        readback.clear();
+        assert!((0..MAX_FRAMES_IN_FLIGHT).all(|_| readback.tick().is_none()));
+    }
+}
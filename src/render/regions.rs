@@ -1,8 +1,8 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     fmt::Debug,
-    mem::size_of,
-    ops::DerefMut,
+    mem,
+    ops::{AddAssign, DerefMut},
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc, Mutex, RwLock,
@@ -12,17 +12,49 @@ use std::{
 use anyhow::{Context, Result};
 use vulkanalia::vk::{self, DeviceV1_0};
 
-use crate::render::{CommandBuffer, Vertex, DEVICE};
+use crate::render::{CommandBuffer, DEVICE};
 
-use crate::world::{chunks::Chunks, ChunkPos, RegionPos, REGION_SIZE};
+use crate::world::{chunk::Chunk, chunks::Chunks, ChunkPos, RegionPos, REGION_SIZE};
 
-use super::{pipeline::Pipeline, CommandPool, QUEUES};
+use super::{
+    pipeline::{self, Pipeline},
+    CommandPool, QUEUES,
+};
+
+/// What a command buffer draws.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: usize,
+    pub triangles: usize,
+}
+
+impl AddAssign for DrawStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.draw_calls += rhs.draw_calls;
+        self.triangles += rhs.triangles;
+    }
+}
+
+/// Pipeline drawing the chunks again in lines over their faces, see `WireframeOverlayOptions`.
+#[derive(Debug)]
+pub struct WireframeOverlay {
+    pub pipeline: Pipeline,
+    /// Pushed to the fragment shader, after the chunk position pushed to the vertex one.
+    pub color: [f32; 4],
+}
+
+impl WireframeOverlay {
+    /// Of `color` in the push constants, the chunk position is 24 bytes and a `vec4` is aligned to 16.
+    pub const COLOR_OFFSET: u32 = 32;
+}
 
 #[derive(Debug)]
 pub struct RegionCmdBuff {
     pub pos: RegionPos,
     buffers: Vec<CommandBuffer>,
     dirty_buffs: Vec<bool>,
+    /// Stats of each buffer when it was recorded.
+    draw_stats: Vec<DrawStats>,
     chunks: Arc<RwLock<Chunks>>,
 
     min_pos: ChunkPos, // included
@@ -46,6 +78,7 @@ impl RegionCmdBuff {
             pos,
             buffers,
             dirty_buffs: vec![true; buffs_count],
+            draw_stats: vec![DrawStats::default(); buffs_count],
             chunks,
 
             min_pos,
@@ -58,47 +91,50 @@ impl RegionCmdBuff {
         &mut self,
         index: usize,
         pipeline: &Pipeline,
+        overlay: Option<&WireframeOverlay>,
         descriptor_set: vk::DescriptorSet,
         inheritance_info: &vk::CommandBufferInheritanceInfo,
+        extent: vk::Extent2D,
     ) -> Result<bool> {
         let buff = &mut self.buffers[index];
         buff.reset()?;
         buff.begin_secondary(inheritance_info)?;
 
-        unsafe {
-            DEVICE.cmd_bind_pipeline(**buff, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline);
-            DEVICE.cmd_bind_descriptor_sets(
-                **buff,
-                vk::PipelineBindPoint::GRAPHICS,
-                pipeline.layout,
-                0,
-                &[descriptor_set],
-                &[],
-            );
-        }
-        let mut is_empty = true;
+        pipeline::set_viewport(**buff, extent);
+        pipeline::set_full_scissor(**buff, extent);
         let chunks = self.chunks.read().expect("Lock poisoned");
         // TODO: using another data structure may permit to get directly an iterator over the required chunks instead of filtering
-        for (pos, chunk) in chunks
-            .iter()
-            .filter(|&(pos, _)| pos.between(&self.min_pos, &self.max_pos))
-        {
+        let region_chunks = || {
+            chunks
+                .iter()
+                .filter(|&(pos, _)| pos.between(&self.min_pos, &self.max_pos))
+        };
+        let mut is_empty = true;
+        let mut draw_stats = DrawStats::default();
+        bind(**buff, pipeline, descriptor_set);
+        for (pos, chunk) in region_chunks() {
             debug_assert_eq!(pos.region(), self.pos);
             is_empty = false;
-            let Some(ref vertex_buffer) = *chunk.vertex_buffer.lock().expect("Lock poisoned") else { continue; };
+            draw_stats += draw_chunk(**buff, pipeline, pos, chunk);
+        }
+        // After all the faces of the region so they don't hide the lines.
+        if let Some(overlay) = overlay {
+            bind(**buff, &overlay.pipeline, descriptor_set);
             unsafe {
-                DEVICE.cmd_bind_vertex_buffers(**buff, 0, &[vertex_buffer.buffer], &[0]);
                 DEVICE.cmd_push_constants(
                     **buff,
-                    pipeline.layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    pos.as_bytes(),
+                    overlay.pipeline.layout,
+                    vk::ShaderStageFlags::FRAGMENT,
+                    WireframeOverlay::COLOR_OFFSET,
+                    &overlay.color.map(f32::to_ne_bytes).concat(),
                 );
-                let vertices_count = vertex_buffer.size() / size_of::<Vertex>();
-                DEVICE.cmd_draw(**buff, vertices_count as u32, 1, 0, 0);
+            }
+            // Not counted in the stats, they are the same chunks drawn again.
+            for (pos, chunk) in region_chunks() {
+                draw_chunk(**buff, &overlay.pipeline, pos, chunk);
             }
         }
+        self.draw_stats[index] = draw_stats;
 
         buff.end()?;
         Ok(is_empty)
@@ -109,12 +145,21 @@ impl RegionCmdBuff {
         &mut self,
         index: usize,
         pipeline: &Pipeline,
+        overlay: Option<&WireframeOverlay>,
         descriptor_set: vk::DescriptorSet,
         inheritance_info: &vk::CommandBufferInheritanceInfo,
+        extent: vk::Extent2D,
     ) -> Result<Option<vk::CommandBuffer>> {
         if self.dirty_buffs[index] {
             self.dirty_buffs[index] = false;
-            let empty = self.record_commands(index, pipeline, descriptor_set, inheritance_info)?;
+            let empty = self.record_commands(
+                index,
+                pipeline,
+                overlay,
+                descriptor_set,
+                inheritance_info,
+                extent,
+            )?;
             if empty {
                 return Ok(None);
             }
@@ -122,18 +167,68 @@ impl RegionCmdBuff {
         Ok(Some(*self.buffers[index]))
     }
 
+    /// What the buffer at `index` draws. Only valid after [`RegionCmdBuff::fetch_cmd_buff`].
+    #[inline]
+    pub fn draw_stats(&self, index: usize) -> DrawStats {
+        self.draw_stats[index]
+    }
+
     #[inline]
     pub fn set_dirty(&mut self) {
         self.dirty_buffs.fill(true);
     }
 }
 
+fn bind(buff: vk::CommandBuffer, pipeline: &Pipeline, descriptor_set: vk::DescriptorSet) {
+    unsafe {
+        DEVICE.cmd_bind_pipeline(buff, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline);
+        DEVICE.cmd_bind_descriptor_sets(
+            buff,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            0,
+            &[descriptor_set],
+            &[],
+        );
+    }
+}
+
+/// Draw the mesh of the chunk at `pos`, if it has one.
+fn draw_chunk(
+    buff: vk::CommandBuffer,
+    pipeline: &Pipeline,
+    pos: &ChunkPos,
+    chunk: &Chunk,
+) -> DrawStats {
+    let Some(ref mesh) = *chunk.vertex_buffer.lock().expect("Lock poisoned") else {
+        return DrawStats::default();
+    };
+    unsafe {
+        DEVICE.cmd_bind_vertex_buffers(buff, 0, &[mesh.buffer().buffer], &[0]);
+        DEVICE.cmd_push_constants(
+            buff,
+            pipeline.layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            pos.as_bytes(),
+        );
+        DEVICE.cmd_draw(buff, mesh.vertices_count() as u32, 1, 0, 0);
+    }
+    DrawStats {
+        draw_calls: 1,
+        triangles: mesh.vertices_count() / 3,
+    }
+}
+
 #[derive(Debug)]
 pub struct RegionsManager {
     regions: Mutex<HashMap<RegionPos, RegionCmdBuff>>,
     chunks: Arc<RwLock<Chunks>>,
     pool: Mutex<CommandPool>,
     buffers_count: AtomicUsize,
+    /// Buffers of the removed regions, indexed like the buffers of a region. Each one may still be used by the
+    /// last frame of its swapchain image, so it's only freed by [`RegionsManager::free_retired`].
+    retired: Mutex<Vec<Vec<CommandBuffer>>>,
 }
 
 impl RegionsManager {
@@ -145,6 +240,7 @@ impl RegionsManager {
             chunks,
             pool,
             buffers_count: AtomicUsize::new(buffers_count),
+            retired: Mutex::new((0..buffers_count).map(|_| Vec::new()).collect()),
         })
     }
 
@@ -174,13 +270,62 @@ impl RegionsManager {
         self.regions.lock().expect("Mutex poisoned")
     }
 
+    /// Remove a region, its buffers are freed once the GPU is done with them.
+    pub fn retire(&self, region: RegionCmdBuff) {
+        let mut retired = self.retired.lock().expect("Mutex poisoned");
+        for (index, buffer) in region.buffers.into_iter().enumerate() {
+            retired[index].push(buffer);
+        }
+    }
+
+    /// Remove the regions without any loaded chunk left, even the ones out of the frustum which the renderer
+    /// doesn't record. Return the count removed.
+    pub fn remove_empty(&self) -> usize {
+        let mut regions = self.inner();
+        // The renderer locks the regions then the chunks.
+        let occupied = self
+            .chunks
+            .read()
+            .expect("Lock poisoned")
+            .iter()
+            .map(|(pos, _)| pos.region())
+            .collect::<HashSet<_>>();
+        let empty = regions
+            .keys()
+            .filter(|pos| !occupied.contains(pos))
+            .copied()
+            .collect::<Vec<_>>();
+        for pos in &empty {
+            let region = regions.remove(pos).expect("Region should exists");
+            self.retire(region);
+        }
+        empty.len()
+    }
+
+    /// Free the retired buffers at `index`. The GPU must be done with the last frame of this swapchain image.
+    pub fn free_retired(&self, index: usize) {
+        let buffers = mem::take(&mut self.retired.lock().expect("Mutex poisoned")[index]);
+        if !buffers.is_empty() {
+            self.pool
+                .lock()
+                .expect("Mutex poisoned")
+                .free_buffers(buffers);
+        }
+    }
+
+    /// The queue must be idle.
     pub fn pipeline_recreated(&self, new_count: usize) -> Result<()> {
         let mut pool = self.pool.lock().expect("Mutex poisoned");
+        let mut retired = self.retired.lock().expect("Mutex poisoned");
+        pool.free_buffers(retired.drain(..).flatten());
+        retired.resize_with(new_count, Vec::new);
+        drop(retired);
         self.buffers_count.store(new_count, Ordering::Relaxed);
         let mut regions = self.inner();
         for region in regions.values_mut() {
             pool.realloc_buffers(&mut region.buffers, new_count, true)?;
             region.dirty_buffs.resize(new_count, true);
+            region.draw_stats.resize(new_count, DrawStats::default());
             region.set_dirty();
         }
         Ok(())
@@ -192,3 +337,37 @@ impl RegionsManager {
         self.regions.lock().expect("Mutex poisoned").len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_region_removal() {
+        let chunks = Chunks::new();
+        let regions =
+            RegionsManager::new(Arc::clone(&chunks), 2).expect("Region manager creation failed");
+        let pos = ChunkPos::new(1, 0, 1);
+        chunks.write().expect("Lock poisoned").load_sync([pos]);
+        regions
+            .set_dirty(pos.region())
+            .expect("Region creation failed");
+        assert_eq!(regions.remove_empty(), 0);
+        assert_eq!(regions.len(), 1);
+
+        chunks
+            .write()
+            .expect("Lock poisoned")
+            .drain_filter(|_, _| true, &regions);
+        assert_eq!(regions.remove_empty(), 1);
+        assert_eq!(regions.len(), 0);
+        for index in 0..2 {
+            assert_eq!(
+                regions.retired.lock().expect("Mutex poisoned")[index].len(),
+                1
+            );
+            regions.free_retired(index);
+            assert!(regions.retired.lock().expect("Mutex poisoned")[index].is_empty());
+        }
+    }
+}
@@ -11,13 +11,21 @@ use super::{depth::DepthBuffer, swapchain::Swapchain, DEVICE};
 pub struct RenderPassCreationOptions {
     color: vk::AttachmentDescription,
     depth: Option<vk::AttachmentDescription>,
+    /// The color attachment is sampled by a following render pass.
+    sampled: bool,
 }
 
 impl RenderPassCreationOptions {
+    /// Render to the swapchain images.
+    #[inline]
     pub fn default(swapchain: &Swapchain) -> Self {
+        Self::new(swapchain.format.format)
+    }
+
+    pub fn new(format: vk::Format) -> Self {
         Self {
             color: vk::AttachmentDescription::builder()
-                .format(swapchain.format.format)
+                .format(format)
                 .samples(vk::SampleCountFlags::_1)
                 .load_op(vk::AttachmentLoadOp::CLEAR)
                 .store_op(vk::AttachmentStoreOp::STORE)
@@ -27,15 +35,32 @@ impl RenderPassCreationOptions {
                 .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
                 .build(),
             depth: None,
+            sampled: false,
         }
     }
 
-    pub fn with_depth(mut self, physical_device: vk::PhysicalDevice) -> Result<Self> {
+    /// Render to an image sampled by the fragment shaders of the next render pass instead of presenting it.
+    pub fn sampled(mut self) -> Self {
+        self.color.final_layout = vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL;
+        self.sampled = true;
+        self
+    }
+
+    /// Don't clear the color attachment, for passes writing every pixel.
+    pub fn without_clear(mut self) -> Self {
+        self.color.load_op = vk::AttachmentLoadOp::DONT_CARE;
+        self
+    }
+
+    pub fn with_depth(self, physical_device: vk::PhysicalDevice) -> Result<Self> {
+        let format = DepthBuffer::get_format(physical_device)
+            .context("No valid depth buffer format found")?;
+        Ok(self.with_depth_format(format))
+    }
+
+    fn with_depth_format(mut self, format: vk::Format) -> Self {
         let depth = vk::AttachmentDescription::builder()
-            .format(
-                DepthBuffer::get_format(physical_device)
-                    .context("No valid depth buffer format found")?,
-            )
+            .format(format)
             .samples(vk::SampleCountFlags::_1)
             .load_op(vk::AttachmentLoadOp::CLEAR)
             .store_op(vk::AttachmentStoreOp::DONT_CARE)
@@ -45,13 +70,14 @@ impl RenderPassCreationOptions {
             .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
             .build();
         self.depth = Some(depth);
-        Ok(self)
+        self
     }
 }
 
 #[derive(Debug)]
 pub struct RenderPass {
     inner: vk::RenderPass,
+    depth: bool,
 }
 
 impl RenderPass {
@@ -89,27 +115,49 @@ impl RenderPass {
                     | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
             );
 
+        // Make the color writes visible to the next render pass sampling the attachment.
+        let sampled_dependency = vk::SubpassDependency::builder()
+            .src_subpass(0)
+            .dst_subpass(vk::SUBPASS_EXTERNAL)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags::SHADER_READ)
+            .build();
+
         let attachments = if let Some(depth) = options.depth {
             vec![options.color, depth]
         } else {
             vec![options.color]
         };
         let subpasses = &[subpass];
-        let dependencies = &[dependency];
+        let mut dependencies = vec![dependency.build()];
+        if options.sampled {
+            dependencies.push(sampled_dependency);
+        }
         let info = vk::RenderPassCreateInfo::builder()
             .attachments(&attachments)
             .subpasses(subpasses)
-            .dependencies(dependencies);
+            .dependencies(&dependencies);
 
         let render_pass = unsafe { DEVICE.create_render_pass(&info, None)? };
 
-        Ok(Self { inner: render_pass })
+        Ok(Self {
+            inner: render_pass,
+            depth: options.depth.is_some(),
+        })
     }
 
     #[inline]
     pub fn recreate(&mut self, options: &RenderPassCreationOptions) -> Result<()> {
         drop_then_new(self, || Self::new(options))
     }
+
+    /// The pipelines of a render pass without depth attachment don't test nor write depth.
+    #[inline]
+    pub fn has_depth(&self) -> bool {
+        self.depth
+    }
 }
 
 impl Deref for RenderPass {
@@ -124,3 +172,20 @@ impl Drop for RenderPass {
         unsafe { DEVICE.destroy_render_pass(self.inner, None) };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_pass_depth() -> Result<()> {
+        let options = RenderPassCreationOptions::new(vk::Format::R16G16B16A16_SFLOAT).sampled();
+        let render_pass = RenderPass::new(&options)?;
+        assert!(!render_pass.has_depth());
+
+        let options = options.with_depth_format(vk::Format::D32_SFLOAT);
+        let render_pass = RenderPass::new(&options)?;
+        assert!(render_pass.has_depth());
+        Ok(())
+    }
+}
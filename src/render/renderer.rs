@@ -1,11 +1,13 @@
 use std::{
     fmt::Debug,
-    mem::size_of,
+    mem::{self, size_of},
     sync::{atomic::Ordering, Arc, RwLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use nalgebra_glm::Vec3;
 use vulkanalia::{
     loader::{LibloadingLoader, LIBRARY},
     vk::{self, DeviceV1_0, Handle, HasBuilder, KhrSwapchainExtension},
@@ -19,34 +21,55 @@ use crate::{
     options::AppOptions,
     render::{camera::UniformBufferObject, devices::Device, uniform::Uniforms},
     shader_module,
-    world::{chunks::Chunks, ChunkPos, EntityPos},
+    world::{chunks::Chunks, Aabb, BlockId, BlockPos, ChunkPos, EntityPos, Frustum},
 };
 
 use super::{
-    camera::Camera,
+    camera::{Camera, DEFAULT_SPAWN},
     commands::{CommandBuffer, CommandPool},
     depth::DepthBuffer,
     descriptors::DescriptorSetLayout,
     devices::{self, DEVICE},
+    error::{vk_error, RenderError},
     framebuffers::Framebuffers,
     gui_renderer::GuiRenderer,
+    horizon::HorizonRenderer,
     instance::Instance,
-    memory::init_allocator,
-    pipeline::{Pipeline, PipelineCreationOptions},
+    memory::{self, heaps, init_allocator},
+    particles::{ParticleRenderer, Particles},
+    pipeline::{DepthBias, Pipeline, PipelineCreationOptions},
+    post_process::{PostProcess, SCENE_FORMAT},
     queues::QUEUES,
+    regions::{DrawStats, WireframeOverlay},
     render_pass::{RenderPass, RenderPassCreationOptions},
+    sky::DayCycle,
     surface::Surface,
     swapchain::Swapchain,
-    sync::{Fences, Semaphores},
-    vertex::Vertex,
-    RegionsManager,
+    sync::{wait_fences, Fences, Semaphores},
+    RegionsManager, Vertex,
 };
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+#[cfg(feature = "debug_boxes")]
+use super::debug_renderer::DebugRenderer;
 
+/// Upper bound of `AppOptions::frames_in_flight`.
+pub const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// Indexing scheme:
+/// - the per-frame sync objects (`in_flight_fences`, `image_available_semaphores` and `render_finished_semaphores`)
+///   are indexed by `frame`, which cycles through `0..frames_in_flight`.
+/// - everything written while recording (`command_buffers`, `uniforms`, the regions and gui secondary buffers,
+///   the post process targets) is indexed by the acquired `image_index`. `images_in_flight[image_index]` is waited
+///   before reusing them so they are never written while the GPU reads them, whatever the frames in flight count is.
 #[derive(Debug)]
 pub struct Renderer {
     gui_renderer: GuiRenderer,
+    #[cfg(feature = "debug_boxes")]
+    debug_renderer: DebugRenderer,
+    particle_renderer: ParticleRenderer,
+    horizon_renderer: HorizonRenderer,
+    /// Tonemaps the HDR scene to the swapchain images, the GUI is drawn in its composite render pass.
+    post_process: PostProcess,
 
     images_in_flight: Fences,
     in_flight_fences: Fences,
@@ -55,8 +78,11 @@ pub struct Renderer {
     command_buffers: Vec<CommandBuffer>,
     command_pool: CommandPool,
     framebuffers: Framebuffers,
-    depth_buffer: DepthBuffer,
+    /// `None` if disabled in the options.
+    depth_buffer: Option<DepthBuffer>,
     pipeline: Pipeline,
+    /// `None` if disabled in the options.
+    wireframe_overlay: Option<WireframeOverlay>,
     render_pass: RenderPass,
     uniforms: Uniforms<UniformBufferObject>,
     swapchain: Swapchain,
@@ -65,7 +91,21 @@ pub struct Renderer {
     _entry: Entry,
 
     frame: usize,
+    frames_in_flight: usize,
+    /// Set when a frame timed out after acquiring its image, see [`Renderer::recover_from_stall`].
+    stalled: bool,
+    /// The gui textures changes not applied yet, kept over the skipped frames.
+    gui_textures_delta: egui::TexturesDelta,
     camera: Camera,
+    /// Used instead of `camera` to render while `AppOptions::debug_camera` is set. The chunks are still culled and
+    /// loaded for `camera`, which stays where it was.
+    debug_camera: Option<Camera>,
+    day_cycle: DayCycle,
+    particles: Particles,
+    /// Last query of the memory heaps for the GUI, `None` before the first one.
+    heaps_queried: Option<Instant>,
+    regions_cleaned: Instant,
+    chunks: Arc<RwLock<Chunks>>,
     pub regions: Arc<RegionsManager>,
 }
 
@@ -84,32 +124,51 @@ impl Renderer {
             .context("Swapchain creation failed")?;
         let uniforms = Uniforms::<UniformBufferObject>::new(swapchain.images.len())
             .context("Uniforms creation failed")?;
-        let render_pass_options =
-            RenderPassCreationOptions::default(&swapchain).with_depth(physical_device)?;
+        let post_process = PostProcess::new(&swapchain).context("Post process creation failed")?;
+        let render_pass_options = Self::render_pass_options(physical_device)?;
         let render_pass =
             RenderPass::new(&render_pass_options).context("Render pass creation failed")?;
-        let pipeline_options = Self::create_pipeline_options(&uniforms.layout)
+        let pipeline_options = Self::create_pipeline_options(&uniforms.layout, false)
             .context("Pipeline options creation failed")?;
         let pipeline = Pipeline::new::<Vertex>(&swapchain, &render_pass, &pipeline_options)
             .context("Pipeline creation failed")?;
-        let depth_buffer = DepthBuffer::new(physical_device, &swapchain)
-            .context("Depth buffer creation failed")?;
-        let framebuffers = Framebuffers::new(&swapchain, &render_pass, &depth_buffer)?;
+        let wireframe_overlay =
+            Self::create_wireframe_overlay(&swapchain, &render_pass, &uniforms.layout)?;
+        let depth_buffer = Self::create_depth_buffer(physical_device, &swapchain, &render_pass)?;
+        let framebuffers = Framebuffers::new(
+            post_process.scene_views(),
+            swapchain.extent,
+            &render_pass,
+            depth_buffer.as_ref(),
+        )?;
         let mut command_pool = CommandPool::new(QUEUES.get_default_graphics().family)?;
         let command_buffers = command_pool
             .alloc_buffers(framebuffers.count(), false)
             .context("Command buffers allocation failed")?;
-        let gui_renderer = GuiRenderer::new(&swapchain, &render_pass, &mut command_pool)
-            .context("Gui renderer creation failed")?;
-        let render_finished_semaphores = Semaphores::new(MAX_FRAMES_IN_FLIGHT)?;
-        let image_available_semaphores = Semaphores::new(MAX_FRAMES_IN_FLIGHT)?;
-        let in_flight_fences = Fences::new(MAX_FRAMES_IN_FLIGHT, true)?;
+        let gui_renderer = GuiRenderer::new(
+            &swapchain,
+            post_process.composite_render_pass(),
+            &mut command_pool,
+        )
+        .context("Gui renderer creation failed")?;
+        #[cfg(feature = "debug_boxes")]
+        let debug_renderer = DebugRenderer::new(&swapchain, &render_pass, &uniforms.layout)
+            .context("Debug renderer creation failed")?;
+        let particle_renderer = ParticleRenderer::new(&swapchain, &render_pass, &uniforms.layout)
+            .context("Particle renderer creation failed")?;
+        let horizon_renderer = HorizonRenderer::new(&swapchain, &render_pass, &uniforms.layout)
+            .context("Horizon renderer creation failed")?;
+        let frames_in_flight = Self::frames_in_flight(swapchain.images.len());
+        let render_finished_semaphores = Semaphores::new(frames_in_flight)?;
+        let image_available_semaphores = Semaphores::new(frames_in_flight)?;
+        let in_flight_fences = Fences::new(frames_in_flight, true)?;
         let images_in_flight = Fences::from_vec(vec![vk::Fence::null(); swapchain.images.len()]);
 
-        let camera = Camera::new(swapchain.extent);
+        let spawn = AppOptions::get().spawn.unwrap_or(DEFAULT_SPAWN);
+        let camera = Camera::new(swapchain.extent, spawn);
 
         let regions = Arc::new(
-            RegionsManager::new(chunks, swapchain.images.len())
+            RegionsManager::new(Arc::clone(&chunks), swapchain.images.len())
                 .context("Region manager creation failed")?,
         );
 
@@ -121,6 +180,7 @@ impl Renderer {
             uniforms,
             render_pass,
             pipeline,
+            wireframe_overlay,
             depth_buffer,
             framebuffers,
             command_pool,
@@ -131,51 +191,175 @@ impl Renderer {
             images_in_flight,
 
             gui_renderer,
+            #[cfg(feature = "debug_boxes")]
+            debug_renderer,
+            particle_renderer,
+            horizon_renderer,
+            post_process,
 
             frame: 0,
+            frames_in_flight,
+            stalled: false,
+            gui_textures_delta: egui::TexturesDelta::default(),
             camera,
+            debug_camera: None,
+            day_cycle: DayCycle::new(),
+            particles: Particles::new(),
+            heaps_queried: None,
+            regions_cleaned: Instant::now(),
+            chunks,
             regions,
         })
     }
 
-    fn create_pipeline_options(layout: &DescriptorSetLayout) -> Result<PipelineCreationOptions> {
-        let push_constant_range = vk::PushConstantRange::builder()
+    /// Return the frames in flight count from the options, clamped to `1..=MAX_FRAMES_IN_FLIGHT` and to `images_count`.
+    fn frames_in_flight(images_count: usize) -> usize {
+        let wanted = AppOptions::get().frames_in_flight;
+        let count = wanted.clamp(1, MAX_FRAMES_IN_FLIGHT).min(images_count);
+        if count != wanted {
+            warn!(
+                "{} frames in flight requested but only {} can be used ({} swapchain images)",
+                wanted, count, images_count
+            );
+        }
+        debug_assert!(count <= images_count);
+        count
+    }
+
+    /// The scene is rendered in HDR then sampled by the post process.
+    fn render_pass_options(
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<RenderPassCreationOptions> {
+        let options = RenderPassCreationOptions::new(SCENE_FORMAT).sampled();
+        if AppOptions::get().depth_buffer {
+            options.with_depth(physical_device)
+        } else {
+            Ok(options)
+        }
+    }
+
+    /// Return `None` if `render_pass` has no depth attachment.
+    fn create_depth_buffer(
+        physical_device: vk::PhysicalDevice,
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+    ) -> Result<Option<DepthBuffer>> {
+        if !render_pass.has_depth() {
+            return Ok(None);
+        }
+        let depth_buffer =
+            DepthBuffer::new(physical_device, swapchain).context("Depth buffer creation failed")?;
+        Ok(Some(depth_buffer))
+    }
+
+    /// With `wireframe_overlay`, the options of the pipeline drawing the lines of the overlay over the terrain.
+    fn create_pipeline_options(
+        layout: &DescriptorSetLayout,
+        wireframe_overlay: bool,
+    ) -> Result<PipelineCreationOptions> {
+        let mut push_constant_ranges = vec![vk::PushConstantRange::builder()
             .stage_flags(vk::ShaderStageFlags::VERTEX)
             .offset(0)
             .size(size_of::<ChunkPos>() as u32)
-            .build();
+            .build()];
+        #[cfg(not(feature = "debug_vertex"))]
+        let vertex_shader = shader_module!("shader.vert")?;
+        #[cfg(feature = "debug_vertex")]
+        let vertex_shader = shader_module!("debug_chunk.vert")?;
+        let options = AppOptions::get();
+        let (fragment_shader, polygon_mode, depth_bias) = if wireframe_overlay {
+            push_constant_ranges.push(
+                vk::PushConstantRange::builder()
+                    .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+                    .offset(WireframeOverlay::COLOR_OFFSET)
+                    .size(size_of::<[f32; 4]>() as u32)
+                    .build(),
+            );
+            let depth_bias = DepthBias {
+                constant_factor: options.wireframe_overlay.depth_bias_constant,
+                slope_factor: options.wireframe_overlay.depth_bias_slope,
+            };
+            (
+                shader_module!("wireframe.frag")?,
+                vk::PolygonMode::LINE,
+                Some(depth_bias),
+            )
+        } else {
+            (shader_module!("shader.frag")?, options.polygon_mode, None)
+        };
         Ok(PipelineCreationOptions {
             shaders: vec![
-                (shader_module!("shader.vert")?, vk::ShaderStageFlags::VERTEX),
-                (
-                    shader_module!("shader.frag")?,
-                    vk::ShaderStageFlags::FRAGMENT,
-                ),
+                (vertex_shader, vk::ShaderStageFlags::VERTEX),
+                (fragment_shader, vk::ShaderStageFlags::FRAGMENT),
             ],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             cull_mode: vk::CullModeFlags::BACK,
-            polygon_mode: AppOptions::get().polygon_mode,
+            polygon_mode,
+            depth_bias,
             descriptors_layouts: vec![layout],
-            push_constant_ranges: vec![push_constant_range],
+            push_constant_ranges,
             blend_attachment: vk::PipelineColorBlendAttachmentState::builder()
                 .blend_enable(false)
                 .color_write_mask(vk::ColorComponentFlags::all())
                 .build(),
-            dynamic_state: Default::default(),
+            dynamic_state: vk::PipelineDynamicStateCreateInfo::builder()
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(),
         })
     }
 
+    /// Return `None` if the overlay is disabled in the options.
+    fn create_wireframe_overlay(
+        swapchain: &Swapchain,
+        render_pass: &RenderPass,
+        layout: &DescriptorSetLayout,
+    ) -> Result<Option<WireframeOverlay>> {
+        let options = AppOptions::get().wireframe_overlay;
+        if !options.enabled {
+            return Ok(None);
+        }
+        let pipeline_options = Self::create_pipeline_options(layout, true)
+            .context("Wireframe overlay pipeline options creation failed")?;
+        let pipeline = Pipeline::new::<Vertex>(swapchain, render_pass, &pipeline_options)
+            .context("Wireframe overlay pipeline creation failed")?;
+        let [r, g, b] = options.color;
+        Ok(Some(WireframeOverlay {
+            pipeline,
+            color: [r, g, b, 1.],
+        }))
+    }
+
     pub fn render(
         &mut self,
         elapsed: Duration,
         window: &Window,
         inputs: &Inputs,
         gui_primitives: &[egui::ClippedPrimitive],
+        gui_pixels_per_point: f32,
         gui_textures_delta: egui::TexturesDelta,
     ) -> Result<()> {
-        self.camera.tick(inputs, elapsed);
-
-        unsafe { DEVICE.wait_for_fences(&[self.in_flight_fences[self.frame]], true, u64::MAX) }
-            .context("Fence waiting failed")?;
+        let use_debug_camera = AppOptions::get().debug_camera;
+        if use_debug_camera != self.debug_camera.is_some() {
+            self.debug_camera =
+                use_debug_camera.then(|| Camera::new(self.swapchain.extent, self.camera.pos));
+        }
+        self.debug_camera
+            .as_mut()
+            .unwrap_or(&mut self.camera)
+            .tick(inputs, elapsed, &self.chunks);
+        self.day_cycle.tick(elapsed);
+        self.particles.tick(elapsed);
+        // Applied by the first frame actually rendered.
+        self.gui_textures_delta.append(gui_textures_delta);
+
+        let gpu_timeout = AppOptions::get().gpu_timeout;
+        if !wait_fences(&[self.in_flight_fences[self.frame]], gpu_timeout)? {
+            warn!("Frame skipped: the GPU is still busy after {gpu_timeout:?}");
+            return Ok(());
+        }
+        if self.stalled {
+            return self.recover_from_stall(window, gpu_timeout);
+        }
 
         let result = unsafe {
             DEVICE.acquire_next_image_khr(
@@ -188,23 +372,27 @@ impl Renderer {
 
         let image_index = match result {
             Ok((image_index, _)) => image_index,
-            Err(vk::ErrorCode::OUT_OF_DATE_KHR) => {
-                return self
-                    .recreate_swapchain(window)
-                    .context("Swapchain recreation failed")
+            Err(e) => {
+                let e = vk_error(e);
+                if RenderError::find(&e) == Some(RenderError::SwapchainOutOfDate) {
+                    return self
+                        .recreate_swapchain(window)
+                        .context("Swapchain recreation failed");
+                }
+                return Err(e.context("Next image acquiring failed"));
             }
-            Err(e) => return Err(anyhow!(e).context("Next image acquiring failed")),
         };
 
-        if !self.images_in_flight[image_index as usize].is_null() {
-            unsafe {
-                DEVICE.wait_for_fences(
-                    &[self.images_in_flight[image_index as usize]],
-                    true,
-                    u64::MAX,
-                )
-            }
-            .context("Fence waiting failed")?;
+        let image_fence = self.images_in_flight[image_index as usize];
+        if !image_fence.is_null() && !wait_fences(&[image_fence], gpu_timeout)? {
+            warn!("Frame skipped: the GPU is still using the acquired image after {gpu_timeout:?}");
+            self.stalled = true;
+            return Ok(());
+        }
+        self.regions.free_retired(image_index as usize);
+        if self.regions_cleaned.elapsed() >= AppOptions::get().region_cleanup_interval {
+            self.regions.remove_empty();
+            self.regions_cleaned = Instant::now();
         }
 
         // Commands recording
@@ -217,7 +405,7 @@ impl Renderer {
                 .extent(self.swapchain.extent);
             let color_clear_value = vk::ClearValue {
                 color: vk::ClearColorValue {
-                    float32: [0.0, 0.0, 0.0, 0.0],
+                    float32: self.day_cycle.sky_color(),
                 },
             };
             let depth_clear_value = vk::ClearValue {
@@ -226,7 +414,11 @@ impl Renderer {
                     stencil: 0,
                 },
             };
-            let clear_values = &[color_clear_value, depth_clear_value];
+            let clear_values: &[_] = if self.render_pass.has_depth() {
+                &[color_clear_value, depth_clear_value]
+            } else {
+                &[color_clear_value]
+            };
             let info = vk::RenderPassBeginInfo::builder()
                 .render_pass(*self.render_pass)
                 .framebuffer(self.framebuffers[image_index as usize])
@@ -245,20 +437,39 @@ impl Renderer {
                 .subpass(0)
                 .framebuffer(self.framebuffers[image_index as usize]);
 
+            let mut draw_stats = DrawStats::default();
+            // Before the terrain, which covers it without depth test if there is no depth buffer.
+            let horizon_buff = self
+                .horizon_renderer
+                .render(
+                    image_index as usize,
+                    self.camera.pos.chunk(),
+                    *self.debug_camera.as_ref().unwrap_or(&self.camera).pos,
+                    *self.uniforms[image_index as usize].descriptor_set,
+                    &inheritance_info,
+                    self.swapchain.extent,
+                )
+                .context("Horizon rendering failed")?;
+            if let Some(horizon_buff) = horizon_buff {
+                draw_stats.draw_calls += 1;
+                unsafe { DEVICE.cmd_execute_commands(**command_buff, &[horizon_buff]) }
+            }
+
             let mut to_delete = Vec::new();
             let mut regions = self.regions.inner();
-            gui::DATA
-                .read()
-                .expect("Lock poisoned")
-                .loaded_regions
-                .store(regions.len(), Ordering::Relaxed);
+            let frustum = Frustum::from_view_proj(&self.camera.view_proj());
             for region in regions.values_mut() {
+                if !frustum.intersects(&Aabb::from_region(region.pos)) {
+                    continue;
+                }
                 let buff = match region
                     .fetch_cmd_buff(
                         image_index as usize,
                         &self.pipeline,
+                        self.wireframe_overlay.as_ref(),
                         *self.uniforms[image_index as usize].descriptor_set,
                         &inheritance_info,
+                        self.swapchain.extent,
                     )
                     .context("Secondary cmd buff recording failed")?
                 {
@@ -268,23 +479,100 @@ impl Renderer {
                         continue;
                     }
                 };
+                draw_stats += region.draw_stats(image_index as usize);
                 unsafe { DEVICE.cmd_execute_commands(**command_buff, &[buff]) }
             }
 
             for region in to_delete {
-                regions.remove(&region);
+                let region = regions.remove(&region).expect("Region should exists");
+                self.regions.retire(region);
             }
+            gui::DATA
+                .read()
+                .expect("Lock poisoned")
+                .loaded_regions
+                .store(regions.len(), Ordering::Relaxed);
             drop(regions);
 
-            let gui_buff = self
+            let particles = self.particles.instances();
+            let particles_buff = self
+                .particle_renderer
+                .render(
+                    image_index as usize,
+                    &particles,
+                    self.debug_camera
+                        .as_ref()
+                        .unwrap_or(&self.camera)
+                        .pos
+                        .look_dir(),
+                    *self.uniforms[image_index as usize].descriptor_set,
+                    &inheritance_info,
+                    self.swapchain.extent,
+                )
+                .context("Particles rendering failed")?;
+            if let Some(particles_buff) = particles_buff {
+                draw_stats.draw_calls += 1;
+                unsafe { DEVICE.cmd_execute_commands(**command_buff, &[particles_buff]) }
+            }
+
+            #[cfg(feature = "debug_boxes")]
+            {
+                let boxes = self.debug_renderer.collect_boxes(
+                    &self.chunks,
+                    &self.regions,
+                    self.camera.pos.chunk(),
+                    &self.camera.view_proj(),
+                );
+                let debug_buff = self
+                    .debug_renderer
+                    .render(
+                        image_index as usize,
+                        &boxes,
+                        *self.uniforms[image_index as usize].descriptor_set,
+                        &inheritance_info,
+                        self.swapchain.extent,
+                    )
+                    .context("Debug boxes rendering failed")?;
+                if let Some(debug_buff) = debug_buff {
+                    draw_stats.draw_calls += 1;
+                    unsafe { DEVICE.cmd_execute_commands(**command_buff, &[debug_buff]) }
+                }
+            }
+            unsafe { DEVICE.cmd_end_render_pass(**command_buff) };
+
+            self.post_process
+                .cmd_begin_composite(**command_buff, image_index as usize);
+            let (gui_buff, gui_draw_stats) = self
                 .gui_renderer
                 .render(
                     image_index as usize,
                     gui_primitives,
-                    gui_textures_delta,
-                    &inheritance_info,
+                    gui_pixels_per_point,
+                    mem::take(&mut self.gui_textures_delta),
+                    &self.post_process.inheritance_info(image_index as usize),
                 )
                 .context("Gui rendering failed")?;
+            draw_stats += gui_draw_stats;
+            let data = gui::DATA.read().expect("Lock poisoned");
+            data.draw_calls
+                .store(draw_stats.draw_calls, Ordering::Relaxed);
+            data.triangles
+                .store(draw_stats.triangles, Ordering::Relaxed);
+            drop(data);
+            memory::allocator().update_gui_data(
+                &mut gui::DATA
+                    .write()
+                    .expect("Lock poisoned")
+                    .memory_fragmentation,
+            );
+            if self
+                .heaps_queried
+                .map_or(true, |queried| queried.elapsed() >= heaps::REFRESH_INTERVAL)
+            {
+                self.heaps_queried = Some(Instant::now());
+                gui::DATA.write().expect("Lock poisoned").memory_heaps =
+                    heaps::query(self.physical_device);
+            }
 
             unsafe {
                 DEVICE.cmd_execute_commands(**command_buff, &[gui_buff]);
@@ -296,7 +584,11 @@ impl Renderer {
 
         self.images_in_flight[image_index as usize] = self.in_flight_fences[self.frame];
 
-        self.uniforms[image_index as usize].write(self.camera.ubo());
+        let ubo = self.debug_camera.as_ref().unwrap_or(&self.camera).ubo(
+            self.day_cycle.sun_light(),
+            AppOptions::get().terrain_shading,
+        );
+        self.uniforms[image_index as usize].write(ubo);
 
         let wait_semaphores = &[self.image_available_semaphores[self.frame]];
         let wait_stages = &[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
@@ -330,58 +622,169 @@ impl Renderer {
             .image_indices(image_indices);
 
         let result = unsafe { DEVICE.queue_present_khr(*DEVICE.graphics_queue, &present_info) };
-        let changed = result == Ok(vk::SuccessCode::SUBOPTIMAL_KHR)
-            || result == Err(vk::ErrorCode::OUT_OF_DATE_KHR);
+        let changed = match result {
+            Ok(code) => code == vk::SuccessCode::SUBOPTIMAL_KHR,
+            Err(e) => {
+                let e = vk_error(e);
+                if RenderError::find(&e) != Some(RenderError::SwapchainOutOfDate) {
+                    return Err(e.context("Presenting failed"));
+                }
+                true
+            }
+        };
 
         if changed {
             self.recreate_swapchain(window)?;
-        } else if let Err(e) = result {
-            return Err(anyhow!(e).context("Presenting failed"));
         }
 
-        self.frame = (self.frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.frame = (self.frame + 1) % self.frames_in_flight;
 
         Ok(())
     }
 
+    /// A skipped frame acquired an image which is never presented and whose semaphore is never waited.
+    /// Once all the frames are done, start again from new semaphores and a new swapchain.
+    /// A device lost while waiting is still returned as an error.
+    fn recover_from_stall(&mut self, window: &Window, gpu_timeout: Option<Duration>) -> Result<()> {
+        if !wait_fences(&self.in_flight_fences, gpu_timeout)? {
+            warn!("Frame skipped: the GPU is still busy after {gpu_timeout:?}");
+            return Ok(());
+        }
+        // The acquire of the skipped image may still signal its semaphore, which mustn't be destroyed before.
+        unsafe { DEVICE.device_wait_idle() }.context("Device wait idle failed")?;
+        self.image_available_semaphores = Semaphores::new(self.frames_in_flight)?;
+        self.stalled = false;
+        self.recreate_swapchain(window)
+            .context("Swapchain recreation failed")
+    }
+
+    /// Recreate the swapchain and the resources depending on its extent.
+    /// The pipelines use a dynamic viewport and scissor so they are only rebuilt if the surface format changed.
     pub fn recreate_swapchain(&mut self, window: &Window) -> Result<()> {
+        let start = Instant::now();
         unsafe { DEVICE.queue_wait_idle(*DEVICE.graphics_queue) }
             .context("Graphics queue wait idle failed")?;
+        let old_format = self.swapchain.format.format;
         self.swapchain
             .recreate(self.physical_device, window, *self.surface)
             .context("New swapchain creation failed")?;
-        self.depth_buffer
-            .recreate(self.physical_device, &self.swapchain)
-            .context("Depth buffer recreation failed")?;
-        self.recreate_pipeline()?;
+        if let Some(depth_buffer) = &mut self.depth_buffer {
+            depth_buffer
+                .recreate(self.physical_device, &self.swapchain)
+                .context("Depth buffer recreation failed")?;
+        }
+        let images_count = self.swapchain.images.len();
+        let images_count_changed = self.uniforms.len() != images_count;
+        if images_count_changed {
+            self.uniforms = Uniforms::<UniformBufferObject>::new(images_count)
+                .context("Uniforms creation failed")?;
+        }
+        let frames_in_flight = Self::frames_in_flight(images_count);
+        if frames_in_flight != self.frames_in_flight {
+            self.render_finished_semaphores = Semaphores::new(frames_in_flight)?;
+            self.image_available_semaphores = Semaphores::new(frames_in_flight)?;
+            self.in_flight_fences = Fences::new(frames_in_flight, true)?;
+            self.frames_in_flight = frames_in_flight;
+            self.frame = 0;
+        }
+        let rebuild_pipelines = self.swapchain.format.format != old_format || images_count_changed;
+        if rebuild_pipelines {
+            // The render pass depends on the swapchain format and the pipeline on the uniforms layout.
+            self.recreate_pipeline()?;
+        } else {
+            self.post_process
+                .resize(&self.swapchain)
+                .context("Post process resize failed")?;
+            self.framebuffers
+                .recreate(
+                    self.post_process.scene_views(),
+                    self.swapchain.extent,
+                    &self.render_pass,
+                    self.depth_buffer.as_ref(),
+                )
+                .context("Framebuffers recreation failed")?;
+            self.gui_renderer
+                .resize(&self.swapchain)
+                .context("Gui renderer resize failed")?;
+            self.regions
+                .pipeline_recreated(self.swapchain.images.len())
+                .context("Regions pipeline recreation handling failed")?;
+        }
         self.command_pool
             .realloc_buffers(&mut self.command_buffers, self.framebuffers.count(), false)
             .context("Command buffers reallocation failed")?;
+        // The queue is idle so no image is in flight anymore. This also forgets fences that may have been destroyed above.
+        self.images_in_flight.clear();
         self.images_in_flight
             .resize(self.swapchain.images.len(), vk::Fence::null());
-        self.camera.rebuild_proj(self.swapchain.extent);
+        self.rebuild_proj();
+        // The resize hitch, to compare with a pipelines rebuild.
+        debug!(
+            "Swapchain recreated in {:?} (pipelines rebuilt: {})",
+            start.elapsed(),
+            rebuild_pipelines
+        );
 
         Ok(())
     }
 
+    /// Rebuild the projection of the cameras from the swapchain extent and the options.
+    pub fn rebuild_proj(&mut self) {
+        self.camera.rebuild_proj(self.swapchain.extent);
+        if let Some(debug_camera) = &mut self.debug_camera {
+            debug_camera.rebuild_proj(self.swapchain.extent);
+        }
+    }
+
     pub fn recreate_pipeline(&mut self) -> Result<()> {
         unsafe { DEVICE.queue_wait_idle(*DEVICE.graphics_queue) }
             .context("Graphics queue wait idle failed")?;
-        let render_pass_options =
-            RenderPassCreationOptions::default(&self.swapchain).with_depth(self.physical_device)?;
+        self.post_process
+            .recreate(&self.swapchain)
+            .context("Post process recreation failed")?;
+        let render_pass_options = Self::render_pass_options(self.physical_device)?;
         self.render_pass
             .recreate(&render_pass_options)
             .context("Render pass recreation failed")?;
-        let pipeline_options = Self::create_pipeline_options(&self.uniforms.layout)
+        if self.render_pass.has_depth() != self.depth_buffer.is_some() {
+            self.depth_buffer = None;
+            self.depth_buffer = Self::create_depth_buffer(
+                self.physical_device,
+                &self.swapchain,
+                &self.render_pass,
+            )?;
+        }
+        let pipeline_options = Self::create_pipeline_options(&self.uniforms.layout, false)
             .context("Pipeline options creation failed")?;
         self.pipeline
             .recreate::<Vertex>(&self.swapchain, &self.render_pass, &pipeline_options)
             .context("Pipeline recreation failed")?;
+        self.wireframe_overlay = None;
+        self.wireframe_overlay = Self::create_wireframe_overlay(
+            &self.swapchain,
+            &self.render_pass,
+            &self.uniforms.layout,
+        )?;
         self.framebuffers
-            .recreate(&self.swapchain, &self.render_pass, &self.depth_buffer)
+            .recreate(
+                self.post_process.scene_views(),
+                self.swapchain.extent,
+                &self.render_pass,
+                self.depth_buffer.as_ref(),
+            )
             .context("Framebuffers recreation failed")?;
         self.gui_renderer
-            .recreate(&self.swapchain, &self.render_pass)?;
+            .recreate(&self.swapchain, self.post_process.composite_render_pass())?;
+        #[cfg(feature = "debug_boxes")]
+        self.debug_renderer
+            .recreate(&self.swapchain, &self.render_pass, &self.uniforms.layout)
+            .context("Debug renderer recreation failed")?;
+        self.particle_renderer
+            .recreate(&self.swapchain, &self.render_pass, &self.uniforms.layout)
+            .context("Particle renderer recreation failed")?;
+        self.horizon_renderer
+            .recreate(&self.swapchain, &self.render_pass, &self.uniforms.layout)
+            .context("Horizon renderer recreation failed")?;
         self.regions
             .pipeline_recreated(self.swapchain.images.len())
             .context("Regions pipeline recreation handling failed")?;
@@ -392,6 +795,23 @@ impl Renderer {
     pub fn camera_pos(&self) -> EntityPos {
         self.camera.pos
     }
+
+    /// Move the player, the debug camera stays where it is.
+    #[inline]
+    pub fn teleport(&mut self, pos: Vec3) {
+        self.camera.teleport(pos);
+    }
+
+    /// Throw particles out of a block the player broke.
+    #[inline]
+    pub fn block_broken(&mut self, pos: BlockPos, block: BlockId) {
+        self.particles.spawn_break(&Aabb::from_block(pos), block);
+    }
+
+    #[inline]
+    pub fn player_bounding_box(&self) -> Aabb {
+        self.camera.bounding_box()
+    }
 }
 
 impl Drop for Renderer {
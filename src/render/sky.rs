@@ -0,0 +1,69 @@
+use std::{f32::consts::TAU, time::Duration};
+
+use nalgebra_glm::{Vec3, Vec4};
+
+use crate::{gui, options::AppOptions};
+
+/// Real time duration of a whole day at speed 1.
+const DAY_DURATION: Duration = Duration::from_secs(600);
+const DAY_SKY_COLOR: [f32; 3] = [0.45, 0.65, 0.9];
+const NIGHT_SKY_COLOR: [f32; 3] = [0.01, 0.01, 0.03];
+const DAY_AMBIENT: f32 = 0.3;
+const NIGHT_AMBIENT: f32 = 0.05;
+
+/// Time of day driving the sun position and the sky color.
+#[derive(Debug)]
+pub struct DayCycle {
+    /// In `[0; 1[`. 0 is sunrise, 0.25 noon, 0.5 sunset and 0.75 midnight.
+    time: f32,
+}
+
+impl DayCycle {
+    pub const fn new() -> Self {
+        Self { time: 0.25 }
+    }
+
+    pub fn tick(&mut self, elapsed: Duration) {
+        // Keep the lighting constant so bench runs are comparable.
+        if cfg!(feature = "bench") {
+            return;
+        }
+        let options = AppOptions::get();
+        if !options.day_cycle_paused {
+            let advance = elapsed.as_secs_f32() / DAY_DURATION.as_secs_f32();
+            self.time = (self.time + advance * options.day_cycle_speed).rem_euclid(1.);
+        }
+        drop(options);
+
+        gui::DATA.write().expect("Lock poisoned").time_of_day = self.time;
+    }
+
+    /// Normalized direction towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.time * TAU;
+        // Slightly tilted so the sun isn't exactly aligned with the x axis faces.
+        Vec3::new(angle.cos(), angle.sin(), 0.3).normalize()
+    }
+
+    /// How much the sun lights the world, in `[0; 1]`. Fades out around the horizon.
+    pub fn daylight(&self) -> f32 {
+        (self.sun_direction().y * 4.).clamp(0., 1.)
+    }
+
+    /// `xyz` is the direction towards the sun scaled by its intensity and `w` the ambient light.
+    pub fn sun_light(&self) -> Vec4 {
+        let daylight = self.daylight();
+        let sun = self.sun_direction() * daylight;
+        let ambient = NIGHT_AMBIENT + (DAY_AMBIENT - NIGHT_AMBIENT) * daylight;
+        Vec4::new(sun.x, sun.y, sun.z, ambient)
+    }
+
+    pub fn sky_color(&self) -> [f32; 4] {
+        let daylight = self.daylight();
+        let mut color = [0., 0., 0., 1.];
+        for ((c, night), day) in color.iter_mut().zip(NIGHT_SKY_COLOR).zip(DAY_SKY_COLOR) {
+            *c = night + (day - night) * daylight;
+        }
+        color
+    }
+}
@@ -7,7 +7,7 @@ use std::{
 use anyhow::{Context, Result};
 use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
-use super::{commands::CommandBuffer, devices::DEVICE, Buffer};
+use super::{commands::CommandBuffer, devices::DEVICE, memory::MemoryProperties, Buffer};
 
 #[derive(Debug)]
 pub struct StagingBuffer {
@@ -19,7 +19,9 @@ impl StagingBuffer {
         let buff = Buffer::new(
             size,
             vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            // Flushed before each copy.
+            MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE)
+                .prefer(vk::MemoryPropertyFlags::HOST_COHERENT),
             true,
             alignment,
         )?;
@@ -42,6 +44,10 @@ impl StagingBuffer {
         unsafe { slice::from_raw_parts_mut(ptr as *mut _, len) }
     }
 
+    /// Copy the `size` first bytes to `dst`. The copy is followed by a barrier making them visible to
+    /// `dst_access` in `dst_stage`, which must be supported by `queue`.
+    #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(feature = "compute_meshing", allow(dead_code))]
     pub fn copy_into(
         &self,
         queue: vk::Queue,
@@ -49,6 +55,8 @@ impl StagingBuffer {
         fence: vk::Fence,
         dst: &mut Buffer,
         size: usize,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
     ) -> Result<()> {
         self.buff.flush().context("Buffer flush failed")?;
         command_buff.begin()?;
@@ -56,7 +64,26 @@ impl StagingBuffer {
             .size(size as u64)
             .src_offset(0)
             .dst_offset(0);
-        unsafe { DEVICE.cmd_copy_buffer(**command_buff, self.buff.buffer, dst.buffer, &[region]) };
+        let barrier = vk::BufferMemoryBarrier::builder()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(dst_access)
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .buffer(dst.buffer)
+            .offset(0)
+            .size(size as u64);
+        unsafe {
+            DEVICE.cmd_copy_buffer(**command_buff, self.buff.buffer, dst.buffer, &[region]);
+            DEVICE.cmd_pipeline_barrier(
+                **command_buff,
+                vk::PipelineStageFlags::TRANSFER,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[] as &[vk::MemoryBarrier],
+                &[barrier],
+                &[] as &[vk::ImageMemoryBarrier],
+            );
+        }
         command_buff.end()?;
 
         let buffers = &[**command_buff];
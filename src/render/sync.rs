@@ -1,9 +1,12 @@
-use std::ops::{Deref, DerefMut};
+use std::{
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
-use super::devices::DEVICE;
+use super::{devices::DEVICE, error::vk_error};
 
 #[derive(Debug)]
 pub struct Semaphores {
@@ -61,6 +64,18 @@ pub fn create_fence(signaled: bool) -> Result<vk::Fence> {
     unsafe { DEVICE.create_fence(&info, None) }.context("Fence creation failed")
 }
 
+/// Wait for all the `fences` up to `timeout`, or forever if it's `None`. Return `false` if they aren't all
+/// signaled in time. A lost device is an error, found with `RenderError::find`.
+pub fn wait_fences(fences: &[vk::Fence], timeout: Option<Duration>) -> Result<bool> {
+    let timeout = timeout.map_or(u64::MAX, |timeout| {
+        u64::try_from(timeout.as_nanos()).unwrap_or(u64::MAX)
+    });
+    let code = unsafe { DEVICE.wait_for_fences(fences, true, timeout) }
+        .map_err(vk_error)
+        .context("Fence waiting failed")?;
+    Ok(code != vk::SuccessCode::TIMEOUT)
+}
+
 #[derive(Debug)]
 pub struct Fences {
     fences: Vec<vk::Fence>,
@@ -115,3 +130,19 @@ impl DerefMut for Fences {
         &mut self.fences
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wait_fences_timeout() {
+        let fences = Fences::new(2, true).expect("Fences creation failed");
+        assert!(wait_fences(&fences, Some(Duration::ZERO)).expect("Fence waiting failed"));
+
+        let unsignaled = Fences::new(1, false).expect("Fences creation failed");
+        let fences = [fences[0], unsignaled[0]];
+        let timeout = Some(Duration::from_millis(1));
+        assert!(!wait_fences(&fences, timeout).expect("Fence waiting failed"));
+    }
+}
@@ -1,4 +1,7 @@
+use std::ops::Deref;
+
 use anyhow::{Context, Result};
+use log::warn;
 use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
 
 use crate::render::DEVICE;
@@ -6,44 +9,134 @@ use crate::render::DEVICE;
 use super::{descriptors::DescriptorSet, image::Image, Buffer, CommandBuffer};
 
 #[derive(Debug)]
-pub struct TextureCreationOptions {
-    pub format: vk::Format,
+pub struct SamplerOptions {
     pub filter: vk::Filter,
     pub address_mode: vk::SamplerAddressMode,
-    pub anisotropy: bool,
+    /// Max anisotropy of the filtering, 1 disables it. Clamped to `1..=maxSamplerAnisotropy`.
+    pub anisotropy: f32,
+    /// Added to the mip level, negative values sharpen. Clamped to `maxSamplerLodBias`.
+    pub lod_bias: f32,
+    /// Sample between the mip levels. Without it only the base level is used, which keeps
+    /// `NEAREST` textures crisp at a distance.
+    pub mipmaps: bool,
 }
 
-impl Default for TextureCreationOptions {
+impl Default for SamplerOptions {
     fn default() -> Self {
         Self {
-            format: vk::Format::R8G8B8A8_SRGB,
             filter: vk::Filter::LINEAR,
             address_mode: vk::SamplerAddressMode::REPEAT,
-            anisotropy: true,
+            anisotropy: 16.0,
+            lod_bias: 0.0,
+            mipmaps: true,
+        }
+    }
+}
+
+impl SamplerOptions {
+    fn sampler_info(&self, limits: &vk::PhysicalDeviceLimits) -> vk::SamplerCreateInfo {
+        let anisotropy = self.anisotropy.clamp(1.0, limits.max_sampler_anisotropy);
+        if anisotropy != self.anisotropy {
+            warn!(
+                "Anisotropy {} clamped to {} by the device",
+                self.anisotropy, anisotropy
+            );
+        }
+        let max_bias = limits.max_sampler_lod_bias;
+        let lod_bias = self.lod_bias.clamp(-max_bias, max_bias);
+        if lod_bias != self.lod_bias {
+            warn!(
+                "LOD bias {} clamped to {} by the device",
+                self.lod_bias, lod_bias
+            );
         }
+        let (mipmap_mode, max_lod) = if self.mipmaps {
+            (vk::SamplerMipmapMode::LINEAR, vk::LOD_CLAMP_NONE)
+        } else {
+            (vk::SamplerMipmapMode::NEAREST, 0.0)
+        };
+        vk::SamplerCreateInfo::builder()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .anisotropy_enable(anisotropy > 1.0)
+            .max_anisotropy(anisotropy)
+            .mip_lod_bias(lod_bias)
+            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
+            .unnormalized_coordinates(false)
+            .compare_enable(false)
+            .compare_op(vk::CompareOp::ALWAYS)
+            .mipmap_mode(mipmap_mode)
+            .min_lod(0.0)
+            .max_lod(max_lod)
+            .build()
     }
 }
 
+/// Can be shared by any number of textures as an immutable sampler of their descriptor set layout.
+#[derive(Debug)]
+pub struct Sampler {
+    inner: vk::Sampler,
+}
+
+impl Sampler {
+    pub fn new(options: &SamplerOptions) -> Result<Self> {
+        let info = options.sampler_info(&DEVICE.properties.limits);
+        let sampler =
+            unsafe { DEVICE.create_sampler(&info, None) }.context("Sampler creation failed")?;
+        Ok(Self { inner: sampler })
+    }
+
+    /// A binding of `samplers`, they must outlive the layout and the sets allocated with it.
+    pub fn immutable_binding(
+        binding: u32,
+        samplers: &[vk::Sampler],
+    ) -> vk::DescriptorSetLayoutBindingBuilder<'_> {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLER)
+            .immutable_samplers(samplers)
+            .descriptor_count(samplers.len() as u32)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+    }
+}
+
+impl Deref for Sampler {
+    type Target = vk::Sampler;
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe { DEVICE.destroy_sampler(self.inner, None) };
+    }
+}
+
+/// An image read with the samplers of its descriptor set layout, see [`Sampler::immutable_binding`].
 #[derive(Debug)]
 pub struct Texture {
     _image: Image,
-    sampler: vk::Sampler,
     pub descriptor_set: DescriptorSet,
 }
 
 impl Texture {
     /// Create a new texture by copying pixels from `buff`. Buff should have been created with `TRANSFER_SRC`.
+    /// Its view is written at `binding` of `descriptor_set`, see [`Texture::image_binding`].
     pub fn new(
         command_buff: &mut CommandBuffer,
         buff: &Buffer,
         size: vk::Extent3D,
+        format: vk::Format,
         binding: u32,
         mut descriptor_set: DescriptorSet,
-        options: &TextureCreationOptions,
     ) -> Result<Self> {
         let mut image = Image::new(
             size,
-            options.format,
+            format,
             vk::ImageTiling::OPTIMAL,
             vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
             vk::ImageAspectFlags::COLOR,
@@ -74,44 +167,34 @@ impl Texture {
             )
             .context("Image layout transition failed")?;
 
-        let info = vk::SamplerCreateInfo::builder()
-            .mag_filter(options.filter)
-            .min_filter(options.filter)
-            .address_mode_u(options.address_mode)
-            .address_mode_v(options.address_mode)
-            .address_mode_w(options.address_mode)
-            .anisotropy_enable(options.anisotropy)
-            .max_anisotropy(16.0)
-            .border_color(vk::BorderColor::INT_OPAQUE_BLACK)
-            .unnormalized_coordinates(false)
-            .compare_enable(false)
-            .compare_op(vk::CompareOp::ALWAYS)
-            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
-            .min_lod(0.0)
-            .max_lod(vk::LOD_CLAMP_NONE);
-        let sampler =
-            unsafe { DEVICE.create_sampler(&info, None) }.context("Sampler creation failed")?;
-
         let info = vk::DescriptorImageInfo::builder()
             .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
-            .image_view(image.view)
-            .sampler(sampler);
+            .image_view(image.view);
         let image_info = &[info];
-        let sampler_write = vk::WriteDescriptorSet::builder()
+        let image_write = vk::WriteDescriptorSet::builder()
             .dst_set(*descriptor_set)
             .dst_binding(binding)
             .dst_array_element(0)
-            .descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
             .image_info(image_info);
-        descriptor_set.update(&[sampler_write]);
+        descriptor_set.update(&[image_write]);
 
         Ok(Self {
             _image: image,
-            sampler,
             descriptor_set,
         })
     }
 
+    /// The view of a [`Texture`].
+    pub fn image_binding(binding: u32) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding::builder()
+            .binding(binding)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(1)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT)
+            .build()
+    }
+
     pub fn binding(binding: u32) -> vk::DescriptorSetLayoutBinding {
         vk::DescriptorSetLayoutBinding::builder()
             .binding(binding)
@@ -122,8 +205,61 @@ impl Texture {
     }
 }
 
-impl Drop for Texture {
-    fn drop(&mut self) {
-        unsafe { DEVICE.destroy_sampler(self.sampler, None) };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> vk::PhysicalDeviceLimits {
+        vk::PhysicalDeviceLimits {
+            max_sampler_anisotropy: 8.0,
+            max_sampler_lod_bias: 2.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sampler_info_modes() {
+        let pixel_art = SamplerOptions {
+            filter: vk::Filter::NEAREST,
+            mipmaps: false,
+            ..Default::default()
+        }
+        .sampler_info(&limits());
+        assert_eq!(pixel_art.mag_filter, vk::Filter::NEAREST);
+        assert_eq!(pixel_art.min_filter, vk::Filter::NEAREST);
+        assert_eq!(pixel_art.mipmap_mode, vk::SamplerMipmapMode::NEAREST);
+        assert_eq!(pixel_art.max_lod, 0.0);
+
+        let smooth = SamplerOptions::default().sampler_info(&limits());
+        assert_eq!(smooth.mag_filter, vk::Filter::LINEAR);
+        assert_eq!(smooth.min_filter, vk::Filter::LINEAR);
+        assert_eq!(smooth.mipmap_mode, vk::SamplerMipmapMode::LINEAR);
+        assert_eq!(smooth.max_lod, vk::LOD_CLAMP_NONE);
+    }
+
+    #[test]
+    fn sampler_limits_clamped() {
+        let info = |anisotropy, lod_bias| {
+            SamplerOptions {
+                anisotropy,
+                lod_bias,
+                ..Default::default()
+            }
+            .sampler_info(&limits())
+        };
+
+        let high = info(16.0, 5.0);
+        assert_eq!(high.max_anisotropy, 8.0);
+        assert_eq!(high.anisotropy_enable, vk::TRUE);
+        assert_eq!(high.mip_lod_bias, 2.0);
+
+        let low = info(0.0, -5.0);
+        assert_eq!(low.max_anisotropy, 1.0);
+        assert_eq!(low.anisotropy_enable, vk::FALSE);
+        assert_eq!(low.mip_lod_bias, -2.0);
+
+        let within = info(4.0, -0.5);
+        assert_eq!(within.max_anisotropy, 4.0);
+        assert_eq!(within.mip_lod_bias, -0.5);
     }
 }
@@ -11,6 +11,7 @@ use super::{
     buffer::Buffer,
     descriptors::{DescriptorPool, DescriptorSet, DescriptorSetLayout},
     devices::DEVICE,
+    memory::MemoryProperties,
 };
 
 #[derive(Debug)]
@@ -30,7 +31,11 @@ impl<T> Uniforms<T> {
         let mut buff = Buffer::new(
             entry_size * count,
             vk::BufferUsageFlags::UNIFORM_BUFFER,
-            vk::MemoryPropertyFlags::HOST_VISIBLE,
+            // The uniforms are written without flush.
+            MemoryProperties::required(
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )
+            .prefer(vk::MemoryPropertyFlags::DEVICE_LOCAL),
             true,
             entry_align,
         )
@@ -87,7 +92,7 @@ impl<T> Uniforms<T> {
             .binding(binding)
             .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
             .descriptor_count(1)
-            .stage_flags(vk::ShaderStageFlags::VERTEX)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT)
     }
 
     #[inline]
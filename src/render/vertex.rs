@@ -8,12 +8,164 @@ pub trait VertexDescriptor {
     fn attribute_descriptions() -> impl Unsize<[vk::VertexInputAttributeDescription]>;
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Bumped whenever the layout of [`Vertex`] changes, for anything keeping vertices across runs.
+#[allow(dead_code)]
+pub const VERTEX_FORMAT_VERSION: u32 = 3;
+
+/// A chunk vertex packed in a single `u32`.
+///
+/// Bit layout of `data`, from the least significant bit:
+/// - `0..6`: x position in the chunk, [`Vertex::X`]
+/// - `6..12`: y position in the chunk, [`Vertex::Y`]
+/// - `12..18`: z position in the chunk, [`Vertex::Z`]
+/// - `18..20`: baked light modifier of the face, [`Vertex::LIGHT_MODIFIER`]
+/// - `20..23`: face direction, as an index in `+x, -x, +y, -y, +z, -z`, [`Vertex::FACE`]
+/// - `23..27`: block light in front of the face, [`Vertex::BLOCK_LIGHT`]
+/// - `27..31`: sky light in front of the face, [`Vertex::SKY_LIGHT`]
+///
+/// `shader.vert` unpacks it and `mesh.comp` packs it, they must be kept in sync.
+#[derive(Debug, Clone, Copy, Default)]
 #[repr(C)]
 pub struct Vertex {
     pub data: u32,
 }
 
+/// `bits` bits of [`Vertex::data`] starting at `shift`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VertexField {
+    pub shift: u32,
+    pub bits: u32,
+}
+
+impl VertexField {
+    #[inline(always)]
+    pub const fn mask(self) -> u32 {
+        ((1 << self.bits) - 1) << self.shift
+    }
+
+    #[inline(always)]
+    const fn pack(self, value: u32) -> u32 {
+        value << self.shift
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    #[inline(always)]
+    pub const fn unpack(self, data: u32) -> u32 {
+        (data & self.mask()) >> self.shift
+    }
+}
+
+impl Vertex {
+    pub const X: VertexField = VertexField { shift: 0, bits: 6 };
+    pub const Y: VertexField = VertexField { shift: 6, bits: 6 };
+    pub const Z: VertexField = VertexField { shift: 12, bits: 6 };
+    pub const LIGHT_MODIFIER: VertexField = VertexField { shift: 18, bits: 2 };
+    pub const FACE: VertexField = VertexField { shift: 20, bits: 3 };
+    pub const BLOCK_LIGHT: VertexField = VertexField { shift: 23, bits: 4 };
+    pub const SKY_LIGHT: VertexField = VertexField { shift: 27, bits: 4 };
+    /// In the order of their bits.
+    const FIELDS: [VertexField; 7] = [
+        Self::X,
+        Self::Y,
+        Self::Z,
+        Self::LIGHT_MODIFIER,
+        Self::FACE,
+        Self::BLOCK_LIGHT,
+        Self::SKY_LIGHT,
+    ];
+
+    #[inline(always)]
+    pub fn new(
+        pos: (u8, u8, u8),
+        light_modifier: u32,
+        face: u32,
+        block_light: u32,
+        sky_light: u32,
+    ) -> Self {
+        debug_assert!(pos.0 <= 32 && pos.1 <= 32 && pos.2 <= 32);
+        debug_assert!(light_modifier < 4);
+        debug_assert!(face < 6);
+        debug_assert!(block_light < 16 && sky_light < 16);
+        let data = Self::X.pack(pos.0 as u32)
+            | Self::Y.pack(pos.1 as u32)
+            | Self::Z.pack(pos.2 as u32)
+            | Self::LIGHT_MODIFIER.pack(light_modifier)
+            | Self::FACE.pack(face)
+            | Self::BLOCK_LIGHT.pack(block_light)
+            | Self::SKY_LIGHT.pack(sky_light);
+        Self { data }
+    }
+}
+
+// The fields are contiguous and fit in `data`.
+const _: () = {
+    let mut end = 0;
+    let mut i = 0;
+    while i < Vertex::FIELDS.len() {
+        assert!(Vertex::FIELDS[i].shift == end);
+        end += Vertex::FIELDS[i].bits;
+        i += 1;
+    }
+    assert!(end <= u32::BITS);
+};
+
+/// Indexed by [`Vertex::FACE`], same as `NORMALS` in the shaders.
+#[cfg(any(feature = "debug_vertex", test))]
+const NORMALS: [[f32; 3]; 6] = [
+    [1., 0., 0.],
+    [-1., 0., 0.],
+    [0., 1., 0.],
+    [0., -1., 0.],
+    [0., 0., 1.],
+    [0., 0., -1.],
+];
+
+/// A chunk vertex with plain `f32` fields, easier to read in a graphics debugger than [`Vertex`].
+///
+/// With the `debug_vertex` feature it replaces [`Vertex`] as `render::Vertex`, so the meshing writes it
+/// directly, and `debug_chunk.vert` draws it. It is built from the packed vertex, so both formats draw the
+/// same mesh.
+#[cfg(any(feature = "debug_vertex", test))]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[repr(C)]
+pub struct DebugVertex {
+    /// In the chunk.
+    pub pos: [f32; 3],
+    pub normal: [f32; 3],
+    pub color: [f32; 3],
+    /// Block light and sky light, from 0 to 1.
+    pub light: [f32; 2],
+}
+
+#[cfg(any(feature = "debug_vertex", test))]
+impl DebugVertex {
+    /// Same arguments as [`Vertex::new`].
+    #[inline(always)]
+    pub fn new(
+        pos: (u8, u8, u8),
+        light_modifier: u32,
+        face: u32,
+        block_light: u32,
+        sky_light: u32,
+    ) -> Self {
+        Vertex::new(pos, light_modifier, face, block_light, sky_light).into()
+    }
+}
+
+#[cfg(any(feature = "debug_vertex", test))]
+impl From<Vertex> for DebugVertex {
+    /// Unpacks the fields as `shader.vert` does.
+    fn from(vertex: Vertex) -> Self {
+        let field = |field: VertexField| field.unpack(vertex.data);
+        Self {
+            pos: [Vertex::X, Vertex::Y, Vertex::Z].map(|axis| field(axis) as f32),
+            normal: NORMALS[field(Vertex::FACE) as usize],
+            color: [1.; 3],
+            light: [Vertex::BLOCK_LIGHT, Vertex::SKY_LIGHT].map(|light| field(light) as f32 / 15.),
+        }
+    }
+}
+
 impl VertexDescriptor for Vertex {
     fn binding_description() -> vk::VertexInputBindingDescription {
         vk::VertexInputBindingDescription::builder()
@@ -32,3 +184,72 @@ impl VertexDescriptor for Vertex {
             .build()]
     }
 }
+
+#[cfg(feature = "debug_vertex")]
+impl VertexDescriptor for DebugVertex {
+    fn binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription::builder()
+            .binding(0)
+            .stride(size_of::<Self>() as u32)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .build()
+    }
+
+    fn attribute_descriptions() -> impl Unsize<[vk::VertexInputAttributeDescription]> {
+        let attribute = |location, format, offset: usize| {
+            vk::VertexInputAttributeDescription::builder()
+                .binding(0)
+                .location(location)
+                .format(format)
+                .offset(offset as u32)
+                .build()
+        };
+        [
+            attribute(0, vk::Format::R32G32B32_SFLOAT, offset_of!(Self, pos)),
+            attribute(1, vk::Format::R32G32B32_SFLOAT, offset_of!(Self, normal)),
+            attribute(2, vk::Format::R32G32B32_SFLOAT, offset_of!(Self, color)),
+            attribute(3, vk::Format::R32G32_SFLOAT, offset_of!(Self, light)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_layout() {
+        let vertex = Vertex::new((32, 17, 1), 3, 5, 14, 9);
+        assert_eq!(Vertex::X.unpack(vertex.data), 32);
+        assert_eq!(Vertex::Y.unpack(vertex.data), 17);
+        assert_eq!(Vertex::Z.unpack(vertex.data), 1);
+        assert_eq!(Vertex::LIGHT_MODIFIER.unpack(vertex.data), 3);
+        assert_eq!(Vertex::FACE.unpack(vertex.data), 5);
+        assert_eq!(Vertex::BLOCK_LIGHT.unpack(vertex.data), 14);
+        assert_eq!(Vertex::SKY_LIGHT.unpack(vertex.data), 9);
+
+        // Same masks as the documented layout and the shaders.
+        assert_eq!(Vertex::X.mask(), 63);
+        assert_eq!(Vertex::Y.mask(), 63 << 6);
+        assert_eq!(Vertex::Z.mask(), 63 << 12);
+        assert_eq!(Vertex::LIGHT_MODIFIER.mask(), 3 << 18);
+        assert_eq!(Vertex::FACE.mask(), 7 << 20);
+        assert_eq!(Vertex::BLOCK_LIGHT.mask(), 15 << 23);
+        assert_eq!(Vertex::SKY_LIGHT.mask(), 15 << 27);
+        let masks = Vertex::FIELDS.map(VertexField::mask);
+        assert_eq!(masks.iter().fold(0, |all, mask| all | mask), (1 << 31) - 1);
+    }
+
+    #[test]
+    fn debug_vertex_unpacked() {
+        let vertex = DebugVertex::new((32, 17, 1), 3, 3, 15, 0);
+        assert_eq!(vertex.pos, [32., 17., 1.]);
+        assert_eq!(vertex.normal, [0., -1., 0.]);
+        assert_eq!(vertex.color, [1.; 3]);
+        assert_eq!(vertex.light, [1., 0.]);
+        for face in 0..6 {
+            let normal = DebugVertex::new((0, 0, 0), 0, face, 0, 0).normal;
+            assert_eq!(normal.iter().map(|x| x.abs()).sum::<f32>(), 1.);
+        }
+    }
+}
@@ -1,17 +1,23 @@
-use std::ops::Deref;
+use std::{cell::Cell, fmt::Display, ops::Deref};
 
 use anyhow::{Context, Result};
-use log::warn;
+use log::{debug, info, warn};
 use winit::{
+    dpi::PhysicalPosition,
     event_loop::{EventLoop, EventLoopBuilder},
-    window::{CursorGrabMode, WindowBuilder},
+    monitor::MonitorHandle,
+    window::{CursorGrabMode, Fullscreen, WindowBuilder},
 };
 
-use crate::events::MainLoopEvent;
+use crate::{
+    events::MainLoopEvent,
+    options::{AppOptions, FullscreenMode},
+};
 
 #[derive(Debug)]
 pub struct Window {
     window: winit::window::Window,
+    cursor_grab: Cell<CursorGrab>,
 }
 
 impl Deref for Window {
@@ -25,21 +31,212 @@ impl Deref for Window {
 impl Window {
     pub fn new() -> Result<(Self, EventLoop<MainLoopEvent>)> {
         let event_loop = EventLoopBuilder::with_user_event().build();
+        let (fullscreen_mode, start_fullscreen) = {
+            let options = AppOptions::get();
+            (options.fullscreen_mode, options.start_fullscreen)
+        };
+        let fullscreen = start_fullscreen.map(|index| {
+            let monitor = event_loop.available_monitors().nth(index).or_else(|| {
+                warn!("No monitor {}, using the primary one", index);
+                event_loop.primary_monitor()
+            });
+            fullscreen_on(monitor, fullscreen_mode)
+        });
         let window = WindowBuilder::new()
             .with_title("Vulkan Voxels 2")
+            .with_fullscreen(fullscreen)
+            // Shown by the app once the spawn area is ready.
+            .with_visible(AppOptions::get().spawn_pregeneration == 0)
             .build(&event_loop)
             .context("Window creation failed")?;
-        Ok((Self { window }, event_loop))
+        let window = Self {
+            window,
+            cursor_grab: Cell::new(CursorGrab::Released),
+        };
+        Ok((window, event_loop))
+    }
+
+    /// Grab and hide the cursor while the game is focused, release and show it otherwise.
+    pub fn capture_cursor(&self, captured: bool) {
+        let grab = self
+            .cursor_grab
+            .get()
+            .set_captured(captured, |mode| self.set_cursor_grab(mode));
+        self.cursor_grab.set(grab);
+        self.set_cursor_visible(!captured);
+    }
+
+    /// Switch between windowed and fullscreen on the current monitor. The swapchain must be recreated after.
+    pub fn toggle_fullscreen(&self, mode: FullscreenMode) {
+        if self.fullscreen().is_some() {
+            self.set_fullscreen(None);
+            info!("Windowed");
+        } else {
+            self.set_fullscreen(Some(fullscreen_on(self.current_monitor(), mode)));
+        }
+        // Some platforms release the grab when the window changes.
+        if let CursorGrab::Grabbed(grab_mode) = self.cursor_grab.get() {
+            if let Err(e) = self.set_cursor_grab(grab_mode) {
+                warn!("Cursor grab after fullscreen toggle failed: {}", e);
+            }
+        }
+    }
+
+    /// Keep the cursor in the window on platforms without grab support. Should be called each frame.
+    pub fn recenter_cursor(&self) {
+        if self.cursor_grab.get() != CursorGrab::Recentered {
+            return;
+        }
+        let size = self.inner_size();
+        let center = PhysicalPosition::new(size.width / 2, size.height / 2);
+        if let Err(e) = self.set_cursor_position(center) {
+            debug!("Cursor recentering failed: {}", e);
+        }
+    }
+}
+
+/// `None` is the current monitor when it's borderless.
+fn fullscreen_on(monitor: Option<MonitorHandle>, mode: FullscreenMode) -> Fullscreen {
+    if mode == FullscreenMode::Exclusive {
+        let video_mode = monitor.as_ref().and_then(|monitor| {
+            let modes = monitor.video_modes().collect::<Vec<_>>();
+            let best = best_video_mode(modes.iter().map(|mode| {
+                let size = mode.size();
+                (size.width, size.height, mode.refresh_rate_millihertz())
+            }))?;
+            modes.into_iter().nth(best)
+        });
+        match video_mode {
+            Some(video_mode) => {
+                info!("Exclusive fullscreen: {}", video_mode);
+                return Fullscreen::Exclusive(video_mode);
+            }
+            None => warn!("No video mode for exclusive fullscreen, using borderless"),
+        }
+    }
+    info!(
+        "Borderless fullscreen on {}",
+        monitor
+            .as_ref()
+            .and_then(MonitorHandle::name)
+            .unwrap_or_else(|| "the current monitor".to_owned())
+    );
+    Fullscreen::Borderless(monitor)
+}
+
+/// Index of the largest mode, then of highest refresh rate. `modes` are `(width, height, refresh rate)`.
+fn best_video_mode(modes: impl Iterator<Item = (u32, u32, u32)>) -> Option<usize> {
+    modes
+        .enumerate()
+        .max_by_key(|&(_, (width, height, refresh_rate))| {
+            (u64::from(width) * u64::from(height), refresh_rate)
+        })
+        .map(|(i, _)| i)
+}
+
+/// How the cursor is kept in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CursorGrab {
+    Released,
+    Grabbed(CursorGrabMode),
+    /// No grab mode is supported, the cursor is moved back to the center each frame instead.
+    Recentered,
+}
+
+impl CursorGrab {
+    /// Return the new state, `set_grab` applying a grab mode to the window.
+    fn set_captured<E: Display>(
+        self,
+        captured: bool,
+        mut set_grab: impl FnMut(CursorGrabMode) -> Result<(), E>,
+    ) -> Self {
+        match (self, captured) {
+            (Self::Released, true) => {
+                // Confined keeps the cursor usable by the OS, Locked is the only mode of some platforms.
+                for mode in [CursorGrabMode::Confined, CursorGrabMode::Locked] {
+                    match set_grab(mode) {
+                        Ok(()) => return Self::Grabbed(mode),
+                        Err(e) => debug!("Cursor grab mode {:?} failed: {}", mode, e),
+                    }
+                }
+                warn!("Cursor grabbing failed, it will be recentered each frame instead");
+                Self::Recentered
+            }
+            (Self::Grabbed(_), false) => {
+                if let Err(e) = set_grab(CursorGrabMode::None) {
+                    warn!("Cursor release failed: {}", e);
+                }
+                Self::Released
+            }
+            (Self::Recentered, false) => Self::Released,
+            // Already in the wanted state, focus events can be repeated.
+            (state, _) => state,
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
 
-    pub fn grab_cursor(&self) {
-        self.set_cursor_grab(CursorGrabMode::Confined)
-            .or_else(|e| self.set_cursor_grab(CursorGrabMode::Locked).context(e))
-            .unwrap_or_else(|_| warn!("Cursor grabbing failed"))
+    #[test]
+    fn best_video_mode_order() {
+        let modes = [
+            (1920, 1080, 60_000),
+            (2560, 1440, 60_000),
+            (2560, 1440, 144_000),
+            (1920, 1080, 240_000),
+        ];
+        assert_eq!(best_video_mode(modes.into_iter()), Some(2));
+        assert_eq!(best_video_mode(modes[..2].iter().copied()), Some(1));
+        assert_eq!(best_video_mode([].into_iter()), None);
     }
 
-    pub fn release_cursor(&self) {
-        self.set_cursor_grab(CursorGrabMode::None)
-            .unwrap_or_else(|_| warn!("Cursor release failed"))
+    #[test]
+    fn cursor_grab_transitions() {
+        let calls = RefCell::new(Vec::new());
+        // Like macOS, which only supports `Locked`.
+        let locked_only = |mode| {
+            calls.borrow_mut().push(mode);
+            match mode {
+                CursorGrabMode::Confined => Err("unsupported"),
+                _ => Ok(()),
+            }
+        };
+
+        let grab = CursorGrab::Released.set_captured(true, locked_only);
+        assert_eq!(grab, CursorGrab::Grabbed(CursorGrabMode::Locked));
+        // A repeated focus doesn't grab again.
+        let grab = grab.set_captured(true, locked_only);
+        assert_eq!(grab, CursorGrab::Grabbed(CursorGrabMode::Locked));
+        let grab = grab.set_captured(false, locked_only);
+        assert_eq!(grab, CursorGrab::Released);
+        let grab = grab.set_captured(false, locked_only);
+        assert_eq!(grab, CursorGrab::Released);
+        assert_eq!(
+            *calls.borrow(),
+            [
+                CursorGrabMode::Confined,
+                CursorGrabMode::Locked,
+                CursorGrabMode::None
+            ]
+        );
+
+        // Without any grab support, nothing is released.
+        calls.borrow_mut().clear();
+        let unsupported = |mode| {
+            calls.borrow_mut().push(mode);
+            Err("unsupported")
+        };
+        let grab = CursorGrab::Released.set_captured(true, unsupported);
+        assert_eq!(grab, CursorGrab::Recentered);
+        let grab = grab.set_captured(false, unsupported);
+        assert_eq!(grab, CursorGrab::Released);
+        assert_eq!(
+            *calls.borrow(),
+            [CursorGrabMode::Confined, CursorGrabMode::Locked]
+        );
     }
 }
@@ -0,0 +1,296 @@
+//! Recording and replay of the player inputs, to reproduce a session.
+//!
+//! With `VOXELS_RECORD` set to a path, the inputs handled by the app are written to this file. With
+//! `VOXELS_REPLAY`, they are read from it instead and the live inputs are ignored until the end of the
+//! recording, then the player takes over. The GUI isn't recorded.
+//!
+//! The file is a list of lines, see [`Input`] for the inputs:
+//! - `seed <seed>` first, the replay generates the same world.
+//! - the inputs, in the order they were received.
+//! - `frame <nanoseconds>` after the inputs of each frame, with the frame time given to the simulation.
+//!
+//! The replay is locked to the frames rather than to the clock: each frame replays the inputs of one
+//! recorded frame and advances the simulation by its recorded time, whatever the time it really took. A
+//! session is reproduced at any frame rate, only faster or slower. The chunks are still generated by the
+//! worker threads, so which ones are loaded at a given frame may differ.
+
+use std::{
+    collections::VecDeque,
+    env,
+    fmt::{self, Display},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem,
+    time::Duration,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use log::{info, warn};
+use winit::event::{
+    ElementState, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+
+const RECORD_VAR: &str = "VOXELS_RECORD";
+const REPLAY_VAR: &str = "VOXELS_REPLAY";
+
+/// An input handled by the app. Written as `press <key>`, `release <key>`, `click <button>`, `scroll <y>`,
+/// `focus <bool>` and `motion <dx> <dy>`, with the key code as a number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Input {
+    KeyPressed(VirtualKeyCode),
+    KeyReleased(VirtualKeyCode),
+    MousePressed(MouseButton),
+    /// Vertical scroll, only its sign is used.
+    Scroll(f64),
+    Focused(bool),
+    MouseMotion((f64, f64)),
+}
+
+impl Input {
+    /// The input of a live window event, if the app handles it.
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match *event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode,
+                        scancode,
+                        ..
+                    },
+                ..
+            } => {
+                let Some(key) = virtual_keycode else {
+                    warn!("Unknown key: {}", scancode);
+                    return None;
+                };
+                Some(match state {
+                    ElementState::Pressed => Self::KeyPressed(key),
+                    ElementState::Released => Self::KeyReleased(key),
+                })
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button,
+                ..
+            } => Some(Self::MousePressed(button)),
+            WindowEvent::MouseWheel { delta, .. } => Some(Self::Scroll(match delta {
+                MouseScrollDelta::LineDelta(_, y) => y as f64,
+                MouseScrollDelta::PixelDelta(pos) => pos.y,
+            })),
+            WindowEvent::Focused(focused) => Some(Self::Focused(focused)),
+            _ => None,
+        }
+    }
+
+    fn parse(kind: &str, values: &[&str]) -> Result<Self> {
+        Ok(match (kind, values) {
+            ("press", &[key]) => Self::KeyPressed(parse_key(key)?),
+            ("release", &[key]) => Self::KeyReleased(parse_key(key)?),
+            ("click", &[button]) => Self::MousePressed(match button {
+                "left" => MouseButton::Left,
+                "right" => MouseButton::Right,
+                "middle" => MouseButton::Middle,
+                other => MouseButton::Other(other.parse().context("Invalid mouse button")?),
+            }),
+            ("scroll", &[y]) => Self::Scroll(y.parse().context("Invalid scroll")?),
+            ("focus", &[focused]) => Self::Focused(focused.parse().context("Invalid focus")?),
+            ("motion", &[x, y]) => Self::MouseMotion((
+                x.parse().context("Invalid motion")?,
+                y.parse().context("Invalid motion")?,
+            )),
+            _ => bail!("Unknown input `{} {}`", kind, values.join(" ")),
+        })
+    }
+}
+
+impl Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // `{}` writes the shortest representation reading back the same `f64`.
+        match *self {
+            Self::KeyPressed(key) => write!(f, "press {}", key as u32),
+            Self::KeyReleased(key) => write!(f, "release {}", key as u32),
+            Self::MousePressed(button) => match button {
+                MouseButton::Left => write!(f, "click left"),
+                MouseButton::Right => write!(f, "click right"),
+                MouseButton::Middle => write!(f, "click middle"),
+                MouseButton::Other(other) => write!(f, "click {}", other),
+            },
+            Self::Scroll(y) => write!(f, "scroll {}", y),
+            Self::Focused(focused) => write!(f, "focus {}", focused),
+            Self::MouseMotion((x, y)) => write!(f, "motion {} {}", x, y),
+        }
+    }
+}
+
+fn parse_key(code: &str) -> Result<VirtualKeyCode> {
+    let code = code.parse::<u32>().context("Invalid key code")?;
+    ensure!(
+        code <= VirtualKeyCode::Cut as u32,
+        "Unknown key code {}",
+        code
+    );
+    // Safety: the variants have no explicit discriminant so they are numbered from 0 to `Cut`, the last one.
+    Ok(unsafe { mem::transmute::<u32, VirtualKeyCode>(code) })
+}
+
+/// Writes the inputs to the file at `VOXELS_RECORD`.
+#[derive(Debug)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// Return `None` if `VOXELS_RECORD` isn't set.
+    pub fn from_env(seed: u32) -> Result<Option<Self>> {
+        let Some(path) = env::var_os(RECORD_VAR) else {
+            return Ok(None);
+        };
+        if env::var_os(REPLAY_VAR).is_some() {
+            bail!("{} and {} can't be set together", RECORD_VAR, REPLAY_VAR);
+        }
+        let file = File::create(&path)
+            .with_context(|| format!("Failed to create {}", path.to_string_lossy()))?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "seed {}", seed).context("Failed to write the recording")?;
+        info!("Recording the inputs to {}", path.to_string_lossy());
+        Ok(Some(Self { writer }))
+    }
+
+    pub fn input(&mut self, input: Input) -> Result<()> {
+        writeln!(self.writer, "{}", input).context("Failed to write the recording")
+    }
+
+    /// End the frame. The file is flushed so a crash keeps the inputs up to it.
+    pub fn frame(&mut self, elapsed: Duration) -> Result<()> {
+        writeln!(self.writer, "frame {}", elapsed.as_nanos())
+            .context("Failed to write the recording")?;
+        self.writer.flush().context("Failed to write the recording")
+    }
+}
+
+/// The inputs of a recorded frame.
+#[derive(Debug, PartialEq)]
+pub struct Frame {
+    pub inputs: Vec<Input>,
+    pub elapsed: Duration,
+}
+
+/// Reads the frames of the file at `VOXELS_REPLAY`.
+#[derive(Debug)]
+pub struct Replay {
+    pub seed: u32,
+    frames: VecDeque<Frame>,
+}
+
+impl Replay {
+    /// Return `None` if `VOXELS_REPLAY` isn't set.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(path) = env::var_os(REPLAY_VAR) else {
+            return Ok(None);
+        };
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.to_string_lossy()))?;
+        let replay = Self::parse(&content)?;
+        info!(
+            "Replaying {} frames from {}",
+            replay.frames.len(),
+            path.to_string_lossy()
+        );
+        Ok(Some(replay))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let mut lines = content.lines().enumerate();
+        let seed = match lines.next().map(|(_, line)| line.split_once(' ')) {
+            Some(Some(("seed", seed))) => seed.parse().context("Invalid seed at line 1")?,
+            _ => bail!("The recording doesn't start with its seed"),
+        };
+
+        let mut frames = VecDeque::new();
+        let mut inputs = Vec::new();
+        for (i, line) in lines {
+            let mut words = line.split_whitespace();
+            let Some(kind) = words.next() else {
+                continue;
+            };
+            let values = words.collect::<Vec<_>>();
+            if kind == "frame" {
+                let &[nanos] = values.as_slice() else {
+                    bail!("Expected the frame time at line {}", i + 1);
+                };
+                let nanos = nanos
+                    .parse()
+                    .with_context(|| format!("Invalid frame time at line {}", i + 1))?;
+                frames.push_back(Frame {
+                    inputs: mem::take(&mut inputs),
+                    elapsed: Duration::from_nanos(nanos),
+                });
+            } else {
+                inputs.push(
+                    Input::parse(kind, &values).with_context(|| format!("At line {}", i + 1))?,
+                );
+            }
+        }
+        // The inputs after the last frame were received while the recording app was closing.
+        Ok(Self { seed, frames })
+    }
+
+    /// Return `None` once all the frames are replayed.
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        self.frames.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recording_round_trip() {
+        let inputs = [
+            Input::Focused(true),
+            Input::KeyPressed(VirtualKeyCode::Key1),
+            Input::KeyPressed(VirtualKeyCode::Cut),
+            Input::KeyReleased(VirtualKeyCode::Escape),
+            Input::MousePressed(MouseButton::Right),
+            Input::MousePressed(MouseButton::Other(7)),
+            Input::Scroll(-0.1),
+            Input::MouseMotion((1. / 3., -1e-20)),
+        ];
+        let mut content = "seed 42\n".to_owned();
+        for input in &inputs[..3] {
+            content += &format!("{}\n", input);
+        }
+        content += "frame 16666667\n\nframe 1\n";
+        for input in &inputs[3..] {
+            content += &format!("{}\n", input);
+        }
+        content += "frame 5000000\npress 1\n";
+
+        let mut replay = Replay::parse(&content).expect("Valid recording");
+        assert_eq!(replay.seed, 42);
+        let frames = [
+            (&inputs[..3], 16_666_667),
+            (&[][..], 1),
+            (&inputs[3..], 5_000_000),
+        ];
+        for (inputs, nanos) in frames {
+            let frame = replay.next_frame().expect("Missing frame");
+            assert_eq!(frame.inputs, inputs);
+            assert_eq!(frame.elapsed, Duration::from_nanos(nanos));
+        }
+        assert_eq!(replay.next_frame(), None);
+    }
+
+    #[test]
+    fn recording_invalid() {
+        assert!(Replay::parse("").is_err());
+        assert!(Replay::parse("press 1\nframe 1").is_err());
+        assert!(Replay::parse("seed 1\nfly 1\nframe 1").is_err());
+        assert!(Replay::parse("seed 1\nmotion 1\nframe 1").is_err());
+        let past_last = VirtualKeyCode::Cut as u32 + 1;
+        assert!(Replay::parse(&format!("seed 1\npress {}\nframe 1", past_last)).is_err());
+        assert!(Replay::parse("seed 1\nframe -1").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+/// Runs something at a fixed rate whatever the frame rate, from the time elapsed each frame.
+#[derive(Debug)]
+pub struct FixedTimestep {
+    /// Elapsed time not consumed by a tick yet, less than a period.
+    accumulated: Duration,
+}
+
+impl FixedTimestep {
+    pub const fn new() -> Self {
+        Self {
+            accumulated: Duration::ZERO,
+        }
+    }
+
+    /// Add the time elapsed since the last call and return how many ticks of `period` are due, at most
+    /// `max_ticks`. The ones beyond are dropped instead of delaying the next frames.
+    pub fn advance(&mut self, elapsed: Duration, period: Duration, max_ticks: u32) -> u32 {
+        self.accumulated += elapsed;
+        let period = period.as_nanos().max(1);
+        let accumulated = self.accumulated.as_nanos();
+        self.accumulated = Duration::from_nanos((accumulated % period) as u64);
+        (accumulated / period).min(max_ticks as u128) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ticks_count() {
+        let period = Duration::from_millis(50);
+        let mut timestep = FixedTimestep::new();
+        // 60 FPS, a tick every 3 frames with the remainder carried over.
+        let ticks = (0..30)
+            .map(|_| timestep.advance(Duration::from_micros(16_667), period, 1))
+            .collect::<Vec<_>>();
+        assert_eq!(ticks.iter().sum::<u32>(), 10);
+        assert!(ticks.chunks(3).all(|frames| frames == [0, 0, 1]));
+
+        // 10 FPS: two ticks are due each frame.
+        let mut timestep = FixedTimestep::new();
+        for _ in 0..5 {
+            assert_eq!(timestep.advance(Duration::from_millis(100), period, 4), 2);
+        }
+        // A long frame doesn't make the next ones catch up.
+        assert_eq!(timestep.advance(Duration::from_millis(420), period, 4), 4);
+        assert_eq!(timestep.advance(Duration::from_millis(30), period, 4), 1);
+        assert_eq!(timestep.advance(Duration::from_millis(10), period, 4), 0);
+    }
+}
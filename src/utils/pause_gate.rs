@@ -0,0 +1,67 @@
+use std::sync::{Condvar, Mutex};
+
+/// Parks the threads calling [`PauseGate::wait`] while paused.
+#[derive(Debug)]
+pub struct PauseGate {
+    paused: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl PauseGate {
+    pub const fn new() -> Self {
+        Self {
+            paused: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        let mut current = self.paused.lock().expect("Mutex poisoned");
+        if *current != paused {
+            *current = paused;
+            self.condvar.notify_all();
+        }
+    }
+
+    /// Return immediately if not paused, otherwise block until resumed.
+    pub fn wait(&self) {
+        let paused = self.paused.lock().expect("Mutex poisoned");
+        drop(
+            self.condvar
+                .wait_while(paused, |paused| *paused)
+                .expect("Mutex poisoned"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Arc},
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+
+    #[test]
+    fn parks_while_paused() {
+        let gate = Arc::new(PauseGate::new());
+        gate.wait();
+
+        gate.set_paused(true);
+        let (sender, receiver) = mpsc::channel();
+        let handle = {
+            let gate = Arc::clone(&gate);
+            thread::spawn(move || {
+                gate.wait();
+                sender.send(()).expect("Channel disconnected");
+            })
+        };
+        assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+        gate.set_paused(false);
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        handle.join().expect("Thread panicked");
+    }
+}
@@ -3,4 +3,21 @@
 pub enum BlockId {
     Air = 0,
     Block,
+    /// Generated below `GenerationOptions::bedrock_height`, can't be placed.
+    Bedrock,
+    Torch,
+}
+
+impl BlockId {
+    /// The blocks the player can place, in the scroll wheel order.
+    pub const PLACEABLE: [Self; 2] = [Self::Block, Self::Torch];
+
+    /// Block light level of the block itself, spread to the air around it.
+    #[inline]
+    pub fn light_emission(self) -> u8 {
+        match self {
+            Self::Torch => 14,
+            Self::Air | Self::Block | Self::Bedrock => 0,
+        }
+    }
 }
@@ -1,25 +1,96 @@
-use std::sync::{Arc, Mutex, RwLock};
+use std::{
+    mem,
+    sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
+};
 
-use log::trace;
+use log::{trace, warn};
 
 use crate::{
     render::{Buffer, Vertex},
-    world::chunk_mesh::{mesh, ADDENDS},
+    world::chunk_mesh::{light_tolerance, mesh, ADDENDS},
 };
 
-use super::{blocks::BlockId, chunks::Chunks, pos::ChunkPos, BLOCKS_PER_CHUNK};
+#[cfg(feature = "skylight")]
+use super::light::SkyLight;
+use super::{
+    blocks::BlockId,
+    chunks::Chunks,
+    light::LightLevels,
+    pos::{ChunkPos, LocalBlockPos},
+    BLOCKS_PER_CHUNK, MAX_VERTICES_PER_CHUNK,
+};
 
 #[derive(Debug)]
 pub struct Chunk {
     pub(super) pos: ChunkPos,
     pub(super) blocks: RwLock<ChunkBlocks>,
-    pub vertex_buffer: Mutex<Option<Buffer>>,
+    pub vertex_buffer: Mutex<Option<ChunkMesh>>,
+    /// Not meshed because of a neighbour not generated yet, see [`Chunks::ready_to_mesh`].
+    pub(super) waiting_for_neighbours: AtomicBool,
+    /// Sent again to the generator threads by [`Chunks::regenerate`].
+    pub(super) regenerating: AtomicBool,
 }
 
+/// Where a chunk is in its lifecycle, see [`Chunks::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkState {
+    Unloaded,
+    /// Loaded but not generated yet, it only contains air.
+    Loading,
+    /// Its blocks are generated but it has no mesh: it's waiting for the meshing threads or has no visible face.
+    Generated,
+    /// A mesh is uploaded. It may be outdated by an edit until the chunk is meshed again.
+    Meshed,
+}
+
+/// The vertices of a chunk, at the start of a buffer which may be larger.
 #[derive(Debug)]
+pub struct ChunkMesh {
+    buffer: Buffer,
+    vertices_count: usize,
+}
+
+impl ChunkMesh {
+    /// `vertices_count` vertices at the start of `buffer`.
+    pub fn new(buffer: Buffer, vertices_count: usize) -> Self {
+        assert!(
+            vertices_count * mem::size_of::<Vertex>() <= buffer.size(),
+            "{vertices_count} vertices don't fit in a buffer of {}B",
+            buffer.size()
+        );
+        Self {
+            buffer,
+            vertices_count,
+        }
+    }
+
+    #[inline]
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    #[inline]
+    pub fn vertices_count(&self) -> usize {
+        self.vertices_count
+    }
+
+    #[inline]
+    pub fn into_buffer(self) -> Buffer {
+        self.buffer
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct ChunkBlocks {
     pub data: [BlockId; BLOCKS_PER_CHUNK],
     pub solid_blocks_count: u32,
+    /// Set by the generator, chunks not generated yet only contain air.
+    pub generated: bool,
+    /// Block light, see the `light` module. `None` while the whole chunk is dark, like most of them.
+    pub light: Option<Box<LightLevels>>,
+    /// Sky light, set by the generator.
+    #[cfg(feature = "skylight")]
+    pub sky_light: SkyLight,
 }
 
 impl Default for ChunkBlocks {
@@ -27,23 +98,128 @@ impl Default for ChunkBlocks {
         Self {
             data: [BlockId::Air; BLOCKS_PER_CHUNK],
             solid_blocks_count: 0,
+            generated: false,
+            light: None,
+            #[cfg(feature = "skylight")]
+            sky_light: SkyLight::Full,
         }
     }
 }
 
+impl ChunkBlocks {
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.solid_blocks_count as usize == BLOCKS_PER_CHUNK
+    }
+
+    #[inline]
+    pub fn light(&self, pos: LocalBlockPos) -> u8 {
+        self.light
+            .as_ref()
+            .map_or(0, |light| light.get(pos.to_index()))
+    }
+
+    #[inline]
+    pub fn set_light(&mut self, pos: LocalBlockPos, level: u8) {
+        if level == 0 && self.light.is_none() {
+            return;
+        }
+        self.light
+            .get_or_insert_with(|| Box::new(LightLevels::new()))
+            .set(pos.to_index(), level);
+    }
+
+    #[cfg(feature = "skylight")]
+    #[inline]
+    pub fn sky_light(&self, pos: LocalBlockPos) -> u8 {
+        self.sky_light.get(pos.to_index())
+    }
+
+    /// Everything is in the full sky light without the `skylight` feature.
+    #[cfg(not(feature = "skylight"))]
+    #[inline]
+    pub fn sky_light(&self, _pos: LocalBlockPos) -> u8 {
+        super::light::MAX_LIGHT
+    }
+}
+
 impl Chunk {
     pub fn new(pos: ChunkPos) -> Self {
         Self {
             pos,
             blocks: RwLock::new(Default::default()),
             vertex_buffer: Mutex::new(None),
+            waiting_for_neighbours: AtomicBool::new(false),
+            regenerating: AtomicBool::new(false),
+        }
+    }
+
+    /// Replace the mesh of the chunk in one step and return the previous one.
+    ///
+    /// The previous buffer may still be used by in-flight command buffers so it shouldn't be dropped
+    /// right away but retired with [`Chunks::retire_buffer`].
+    pub fn swap_vertex_buffer(&self, mesh: Option<ChunkMesh>) -> Option<ChunkMesh> {
+        let mut vertex_buffer = self.vertex_buffer.lock().expect("Mutex poisoned");
+        mem::replace(&mut *vertex_buffer, mesh)
+    }
+
+    /// The state of the chunk, never [`ChunkState::Unloaded`] since it's alive.
+    pub fn state(&self) -> ChunkState {
+        if self.vertex_buffer.lock().expect("Mutex poisoned").is_some() {
+            ChunkState::Meshed
+        } else if self.blocks.read().expect("Lock poisoned").generated {
+            ChunkState::Generated
+        } else {
+            ChunkState::Loading
         }
     }
 
-    /// Return the count of vertices generated.
+    #[inline]
+    pub fn get_block(&self, pos: LocalBlockPos) -> BlockId {
+        self.blocks.read().expect("Lock poisoned").data[pos.to_index()]
+    }
+
+    /// Return the count of vertices generated. A mesh not fitting in `buff` is truncated with a warning.
+    /// The mesh is simplified if the chunk is far enough from `Chunks::player_chunk`.
     pub fn mesh(&self, chunks: &Arc<RwLock<Chunks>>, buff: &mut [Vertex]) -> usize {
         trace!(target: "meshing", "Mesh chunk {:?}", self.pos);
 
+        let neighbours = self.neighbours(chunks);
+        let player_chunk = chunks.read().expect("Lock poisoned").player_chunk;
+
+        let blocks = self.blocks.read().expect("Lock poisoned");
+
+        if blocks.solid_blocks_count == 0 || is_buried(&blocks, &neighbours) {
+            return 0;
+        }
+
+        let tolerance = light_tolerance(self.pos, player_chunk);
+        let vertices = mesh(
+            &blocks,
+            &neighbours,
+            buff,
+            MAX_VERTICES_PER_CHUNK,
+            tolerance,
+        );
+        if vertices.truncated {
+            warn!(
+                "Mesh of chunk {} truncated to {} vertices",
+                self.pos, vertices.count
+            );
+        }
+        vertices.count
+    }
+
+    /// Return `true` if the chunk has no visible face because it and its six neighbours are full,
+    /// so it doesn't need to be meshed.
+    pub fn is_occluded(&self, chunks: &RwLock<Chunks>) -> bool {
+        let neighbours = self.neighbours(chunks);
+        let blocks = self.blocks.read().expect("Lock poisoned");
+        is_buried(&blocks, &neighbours)
+    }
+
+    /// The loaded neighbours, in the `ADDENDS` order.
+    pub(super) fn neighbours(&self, chunks: &RwLock<Chunks>) -> [Option<Arc<Chunk>>; 6] {
         let mut neighbours: [Option<Arc<Chunk>>; 6] = [None, None, None, None, None, None];
         let chunks = chunks.read().expect("Lock poisoned");
         for i in 0..6 {
@@ -53,14 +229,134 @@ impl Chunk {
             let neighbour = chunks.get(&pos);
             neighbours[i] = neighbour.cloned();
         }
-        drop(chunks);
+        neighbours
+    }
+}
 
-        let blocks = self.blocks.read().expect("Lock poisoned");
+/// Return `true` if the chunk and all its neighbours are full so it has no visible face,
+/// like the chunks below the bedrock level.
+pub(super) fn is_buried(blocks: &ChunkBlocks, neighbours: &[Option<Arc<Chunk>>; 6]) -> bool {
+    blocks.is_full()
+        && neighbours.iter().all(|neighbour| {
+            neighbour
+                .as_ref()
+                .is_some_and(|chunk| chunk.blocks.read().expect("Lock poisoned").is_full())
+        })
+}
 
-        if blocks.solid_blocks_count == 0 {
-            return 0;
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use vulkanalia::vk;
+
+    use crate::render::MemoryProperties;
+
+    use super::*;
+
+    const BUFFER_SIZE: usize = 64;
+
+    fn create_buffer() -> ChunkMesh {
+        let buffer = Buffer::new(
+            BUFFER_SIZE,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            MemoryProperties::required(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            false,
+            4,
+        )
+        .expect("Buffer creation failed");
+        ChunkMesh::new(buffer, BUFFER_SIZE / mem::size_of::<Vertex>())
+    }
+
+    #[test]
+    fn mesh_count_independent_of_size() {
+        let mesh = ChunkMesh::new(create_buffer().into_buffer(), 3);
+        assert_eq!(mesh.vertices_count(), 3);
+        assert!(mesh.buffer().size() > 3 * mem::size_of::<Vertex>());
+    }
+
+    #[test]
+    #[should_panic(expected = "don't fit in a buffer")]
+    fn mesh_count_exceeding_buffer() {
+        let buffer = create_buffer().into_buffer();
+        let too_many = buffer.size() / mem::size_of::<Vertex>() + 1;
+        ChunkMesh::new(buffer, too_many);
+    }
+
+    #[test]
+    fn swap_vertex_buffer_is_atomic() {
+        let chunk = Arc::new(Chunk::new(ChunkPos::new(0, 0, 0)));
+        let first = create_buffer();
+        let mut current = first.buffer().buffer;
+        assert!(chunk.swap_vertex_buffer(Some(first)).is_none());
+
+        let remesher = {
+            let chunk = Arc::clone(&chunk);
+            thread::spawn(move || {
+                // Kept alive so their handles aren't reused.
+                let mut replaced = Vec::new();
+                for _ in 0..500 {
+                    let mesh = create_buffer();
+                    let handle = mesh.buffer().buffer;
+                    // The previous mesh is handed back to be retired, not dropped.
+                    let old = chunk
+                        .swap_vertex_buffer(Some(mesh))
+                        .expect("Mesh should never be missing");
+                    assert_eq!(old.buffer().buffer, current);
+                    current = handle;
+                    replaced.push(old);
+                }
+            })
+        };
+
+        // Simulate the regions recording: a complete mesh should always be visible.
+        while !remesher.is_finished() {
+            let vertex_buffer = chunk.vertex_buffer.lock().expect("Mutex poisoned");
+            let mesh = vertex_buffer
+                .as_ref()
+                .expect("Mesh should never be missing");
+            assert!(mesh.buffer().size() >= BUFFER_SIZE);
+        }
+        remesher.join().expect("Remesher thread panicked");
+    }
+
+    #[test]
+    fn enclosed_chunk_not_meshed() {
+        let chunks = Chunks::new();
+        let center = ChunkPos::new(0, -10, 0);
+        let fill = |pos: ChunkPos, block: BlockId| {
+            let mut chunks_lock = chunks.write().expect("Lock poisoned");
+            chunks_lock.load(pos).expect("Chunk loading failed");
+            let chunk = chunks_lock.get(&pos).expect("Chunk should exists");
+            let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+            blocks.data = [block; BLOCKS_PER_CHUNK];
+            blocks.solid_blocks_count = if block == BlockId::Air {
+                0
+            } else {
+                BLOCKS_PER_CHUNK as u32
+            };
+        };
+        fill(center, BlockId::Block);
+        for addend in ADDENDS {
+            fill(
+                center + ChunkPos::new(addend.0 as _, addend.1 as _, addend.2 as _),
+                BlockId::Bedrock,
+            );
         }
+        let chunk = Arc::clone(
+            chunks
+                .read()
+                .expect("Lock poisoned")
+                .get(&center)
+                .expect("Chunk should exists"),
+        );
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        assert!(chunk.is_occluded(&chunks));
+        assert_eq!(chunk.mesh(&chunks, &mut buff), 0);
 
-        mesh(&blocks.data, &neighbours, buff)
+        // The faces towards the empty neighbour are visible.
+        fill(center + ChunkPos::new(1, 0, 0), BlockId::Air);
+        assert!(!chunk.is_occluded(&chunks));
+        assert_ne!(chunk.mesh(&chunks, &mut buff), 0);
     }
 }
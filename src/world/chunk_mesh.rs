@@ -1,11 +1,16 @@
 use std::{mem, sync::Arc};
 
 use crate::{
+    options::AppOptions,
     render::Vertex,
-    world::{LocalBlockPos, CHUNK_SIZE, MAX_VERTICES_PER_CHUNK},
+    world::{ChunkPos, LocalBlockPos, CHUNK_SIZE, MAX_VERTICES_PER_CHUNK},
 };
 
-use super::{blocks::BlockId, chunk::Chunk, BLOCKS_PER_CHUNK};
+use super::{
+    blocks::BlockId,
+    chunk::{Chunk, ChunkBlocks},
+    light::MAX_LIGHT,
+};
 
 pub const ADDENDS: [(i8, i8, i8); 6] = [
     (1, 0, 0),
@@ -17,13 +22,87 @@ pub const ADDENDS: [(i8, i8, i8); 6] = [
 ];
 pub const LIGHT_MODIFIERS: [u32; 6] = [1, 1, 3, 0, 2, 2];
 
+/// A face of the greedy meshing mask: its direction, block light, ambient occlusion, sky light and block,
+/// packed.
+/// Faces are only merged in a quad if they are entirely equal so the quad keeps the attributes of each face,
+/// unless the mesh is simplified, see [`FaceMask::merges_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FaceMask(u32);
+
+impl FaceMask {
+    const NONE: Self = Self(0);
+    /// Bits of the block and sky light.
+    const LIGHTS: u32 = 0xf << 4 | 0xf << 16;
+
+    /// `dir` is the index in `ADDENDS`, `light` the block and sky light in front of the face and `ao` holds
+    /// 2 bits per corner.
+    #[inline(always)]
+    fn new(dir: usize, block: BlockId, light: (u8, u8), ao: u8) -> Self {
+        Self(
+            (dir as u32 + 1)
+                | (light.0 as u32) << 4
+                | (ao as u32) << 8
+                | (light.1 as u32) << 16
+                | (block as u32) << 20,
+        )
+    }
+
+    #[inline(always)]
+    fn is_none(self) -> bool {
+        self == Self::NONE
+    }
+
+    #[inline(always)]
+    fn dir(self) -> usize {
+        (self.0 & 0x7) as usize - 1
+    }
+
+    /// The block and sky light.
+    #[inline(always)]
+    fn light(self) -> (u8, u8) {
+        ((self.0 >> 4 & 0xf) as u8, (self.0 >> 16 & 0xf) as u8)
+    }
+
+    /// Whether `other` can be merged in a quad starting with `self`: they are equal except for their lights,
+    /// which may differ by up to `light_tolerance` levels. The quad has the lights of `self`.
+    #[inline(always)]
+    fn merges_with(self, other: Self, light_tolerance: u8) -> bool {
+        if light_tolerance == 0 || other.is_none() {
+            return self == other;
+        }
+        let (light, other_light) = (self.light(), other.light());
+        self.0 & !Self::LIGHTS == other.0 & !Self::LIGHTS
+            && light.0.abs_diff(other_light.0) <= light_tolerance
+            && light.1.abs_diff(other_light.1) <= light_tolerance
+    }
+}
+
+/// The light tolerance to mesh the chunk at `pos` with, from `AppOptions::mesh_simplification`.
+pub fn light_tolerance(pos: ChunkPos, player_chunk: ChunkPos) -> u8 {
+    let options = AppOptions::get().mesh_simplification;
+    let distance = [
+        pos.x().abs_diff(player_chunk.x()),
+        pos.y().abs_diff(player_chunk.y()),
+        pos.z().abs_diff(player_chunk.z()),
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(0);
+    match options.distance {
+        Some(min_distance) if distance >= min_distance as u64 => options.light_tolerance,
+        _ => 0,
+    }
+}
+
+/// The block at `block_pos + addend` and its block and sky light, which may be in a neighbour chunk.
+/// Unloaded neighbours are air in the sky.
 #[inline(always)]
-fn block_exist(
-    blocks: &[BlockId; BLOCKS_PER_CHUNK],
+fn block_at(
+    blocks: &ChunkBlocks,
     neighbours: &[Option<Arc<Chunk>>; 6],
     block_pos: [i8; 3],
     addend: [i8; 3],
-) -> bool {
+) -> (BlockId, (u8, u8)) {
     let pos = [
         block_pos[0] + addend[0],
         block_pos[1] + addend[1],
@@ -32,7 +111,8 @@ fn block_exist(
 
     let local_pos = LocalBlockPos::try_new(pos[0], pos[1], pos[2]);
     if let Some(pos) = local_pos {
-        blocks[pos.to_index()] != BlockId::Air
+        let light = (blocks.light(pos), blocks.sky_light(pos));
+        (blocks.data[pos.to_index()], light)
     } else {
         let (neighbour, pos) = if pos[0] >= CHUNK_SIZE as _ {
             (
@@ -71,29 +151,46 @@ fn block_exist(
         let neighbour = &neighbours[neighbour];
         if let Some(chunk) = neighbour {
             let blocks = chunk.blocks.read().expect("Lock poisoned");
-            blocks.data[pos.to_index()] != BlockId::Air
+            let light = (blocks.light(pos), blocks.sky_light(pos));
+            (blocks.data[pos.to_index()], light)
         } else {
-            false
+            (BlockId::Air, (0, MAX_LIGHT))
         }
     }
 }
 
 #[inline(always)]
-fn build_vert(pos: (u8, u8, u8), light_modifier: u32) -> Vertex {
-    let data = pos.0 as u32 | (pos.1 as u32) << 6 | (pos.2 as u32) << 12 | light_modifier << 18;
-    Vertex { data }
+fn build_vert(pos: (u8, u8, u8), light_modifier: u32, dir: usize, light: (u8, u8)) -> Vertex {
+    Vertex::new(
+        pos,
+        light_modifier,
+        dir as u32,
+        light.0 as u32,
+        light.1 as u32,
+    )
 }
 
+/// Return `false` without writing anything if the quad would go past `max_vertices`.
 #[inline(always)]
-fn append_quad(buff: &mut [Vertex], buff_idx: &mut usize, points: [(i8, i8, i8); 4], dir: usize) {
+fn append_quad(
+    buff: &mut [Vertex],
+    buff_idx: &mut usize,
+    max_vertices: usize,
+    points: [(i8, i8, i8); 4],
+    dir: usize,
+    light: (u8, u8),
+) -> bool {
+    if *buff_idx + 6 > max_vertices {
+        return false;
+    }
     debug_assert!(points.iter().all(|&p| p >= (0, 0, 0)));
     let points: [(u8, u8, u8); 4] = unsafe { mem::transmute(points) };
     let light_modifier = LIGHT_MODIFIERS[dir];
     let verts: [Vertex; 4] = [
-        build_vert(points[0], light_modifier),
-        build_vert(points[1], light_modifier),
-        build_vert(points[2], light_modifier),
-        build_vert(points[3], light_modifier),
+        build_vert(points[0], light_modifier, dir, light),
+        build_vert(points[1], light_modifier, dir, light),
+        build_vert(points[2], light_modifier, dir, light),
+        build_vert(points[3], light_modifier, dir, light),
     ];
 
     let idx = *buff_idx;
@@ -115,16 +212,35 @@ fn append_quad(buff: &mut [Vertex], buff_idx: &mut usize, points: [(i8, i8, i8);
         buff[idx + 5] = verts[2];
     }
     *buff_idx += 6;
+    true
+}
+
+/// The vertices written by [`mesh`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshedVertices {
+    pub count: usize,
+    /// `max_vertices` was reached, the faces past it are missing.
+    pub truncated: bool,
 }
 
+/// Mesh the chunk in `buff`, stopping at the first quad going past `max_vertices`.
+///
+/// `MAX_VERTICES_PER_CHUNK` vertices are enough for the worst case, a checkerboard of blocks without any merged
+/// face, but the check keeps a tighter merging from writing past the buffer.
+///
+/// With a `light_tolerance`, faces whose lights differ by up to that many levels are merged too, see
+/// `MeshSimplification`.
 #[inline]
 pub fn mesh(
-    blocks: &[BlockId; BLOCKS_PER_CHUNK],
+    blocks: &ChunkBlocks,
     neighbours: &[Option<Arc<Chunk>>; 6],
     buff: &mut [Vertex],
-) -> usize {
+    max_vertices: usize,
+    light_tolerance: u8,
+) -> MeshedVertices {
     // here to gain ~5us/iter
     assert!(buff.len() == MAX_VERTICES_PER_CHUNK);
+    debug_assert!(max_vertices <= MAX_VERTICES_PER_CHUNK);
     let mut buff_idx = 0;
     for d in 0..3 {
         let u = (d + 1) % 3;
@@ -133,7 +249,7 @@ pub fn mesh(
         let mut x = [0; 3];
         let mut q = [0; 3];
 
-        let mut mask = [0_u8; CHUNK_SIZE * CHUNK_SIZE];
+        let mut mask = [FaceMask::NONE; CHUNK_SIZE * CHUNK_SIZE];
 
         q[d] = 1;
         x[d] = -1;
@@ -143,13 +259,13 @@ pub fn mesh(
             while x[v] < CHUNK_SIZE as i8 {
                 x[u] = 0;
                 while x[u] < CHUNK_SIZE as i8 {
-                    let block_current_exists = block_exist(blocks, neighbours, x, [0, 0, 0]);
-                    let block_compare_exists = block_exist(blocks, neighbours, x, q);
-                    mask[n] = match (block_current_exists, block_compare_exists) {
-                        (true, true) => 0,
-                        (false, false) => 0,
-                        (true, false) => 1,
-                        (false, true) => 2,
+                    // A face is lit by the air block in front of it.
+                    let (block_current, light_current) = block_at(blocks, neighbours, x, [0, 0, 0]);
+                    let (block_compare, light_compare) = block_at(blocks, neighbours, x, q);
+                    mask[n] = match (block_current != BlockId::Air, block_compare != BlockId::Air) {
+                        (true, false) => FaceMask::new(d * 2, block_current, light_compare, 0),
+                        (false, true) => FaceMask::new(d * 2 + 1, block_compare, light_current, 0),
+                        _ => FaceMask::NONE,
                     };
                     n += 1;
                     x[u] += 1;
@@ -163,23 +279,20 @@ pub fn mesh(
             for j in 0..CHUNK_SIZE {
                 let mut i = 0;
                 while i < CHUNK_SIZE {
-                    if mask[n] != 0 {
+                    let face = mask[n];
+                    if !face.is_none() {
                         let mut w = 1;
-                        let mut last_mask = mask[n];
-                        while i + w < CHUNK_SIZE && mask[n + w] != 0 && mask[n + w] == last_mask {
-                            last_mask = mask[n + w];
+                        while i + w < CHUNK_SIZE && face.merges_with(mask[n + w], light_tolerance) {
                             w += 1;
                         }
 
                         let mut h = 1;
-                        last_mask = mask[n];
                         'a: while j + h < CHUNK_SIZE {
                             for k in 0..w {
-                                let m = mask[n + k + h * CHUNK_SIZE];
-                                if m == 0 || m != last_mask {
+                                let next = mask[n + k + h * CHUNK_SIZE];
+                                if !face.merges_with(next, light_tolerance) {
                                     break 'a;
                                 }
-                                last_mask = m;
                             }
 
                             h += 1;
@@ -194,9 +307,10 @@ pub fn mesh(
                         let mut dv = [0; 3];
                         dv[v] = h as _;
 
-                        append_quad(
+                        let appended = append_quad(
                             buff,
                             &mut buff_idx,
+                            max_vertices,
                             [
                                 (x[0], x[1], x[2]),
                                 (x[0] + du[0], x[1] + du[1], x[2] + du[2]),
@@ -207,12 +321,19 @@ pub fn mesh(
                                     x[2] + dv[2] + du[2],
                                 ),
                             ],
-                            d * 2 + mask[n] as usize - 1,
+                            face.dir(),
+                            face.light(),
                         );
+                        if !appended {
+                            return MeshedVertices {
+                                count: buff_idx,
+                                truncated: true,
+                            };
+                        }
 
                         for l in 0..h {
                             for k in 0..w {
-                                mask[n + k + l * CHUNK_SIZE] = 0;
+                                mask[n + k + l * CHUNK_SIZE] = FaceMask::NONE;
                             }
                         }
 
@@ -227,29 +348,136 @@ pub fn mesh(
         }
     }
 
-    buff_idx
+    MeshedVertices {
+        count: buff_idx,
+        truncated: false,
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::world::MAX_VERTICES_PER_CHUNK;
-
     use super::*;
     use test::Bencher;
 
     #[bench]
     fn mesh(b: &mut Bencher) {
-        let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
-        for (i, block) in blocks.iter_mut().enumerate() {
+        let mut blocks = ChunkBlocks::default();
+        for (i, block) in blocks.data.iter_mut().enumerate() {
             if i % 4 == 0 {
                 *block = BlockId::Block;
             }
         }
-        let mut buff = vec![Vertex { data: 0 }; MAX_VERTICES_PER_CHUNK];
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
         let neighbours = [None, None, None, None, None, None];
 
         b.iter(|| {
-            super::mesh(&blocks, &neighbours, &mut buff);
+            super::mesh(&blocks, &neighbours, &mut buff, MAX_VERTICES_PER_CHUNK, 0);
         })
     }
+
+    fn vertices_count(placed: &[(u8, u8, u8, BlockId)]) -> usize {
+        let mut blocks = ChunkBlocks::default();
+        for &(x, y, z, block) in placed {
+            blocks.data[LocalBlockPos::new(x, y, z).to_index()] = block;
+        }
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        let neighbours = [None, None, None, None, None, None];
+        super::mesh(&blocks, &neighbours, &mut buff, MAX_VERTICES_PER_CHUNK, 0).count
+    }
+
+    #[test]
+    fn different_faces_not_merged() {
+        // Two blocks side by side along x: the 4 faces along the pair are merged, the 2 ends aren't.
+        let same = vertices_count(&[(4, 4, 4, BlockId::Block), (5, 4, 4, BlockId::Block)]);
+        assert_eq!(same, 6 * 6);
+        let different = vertices_count(&[(4, 4, 4, BlockId::Block), (5, 4, 4, BlockId::Bedrock)]);
+        assert_eq!(different, 10 * 6);
+
+        let face = FaceMask::new(3, BlockId::Block, (7, 15), 0);
+        assert_eq!((face.dir(), face.light()), (3, (7, 15)));
+        assert_ne!(face, FaceMask::new(2, BlockId::Block, (7, 15), 0));
+        assert_ne!(face, FaceMask::new(3, BlockId::Bedrock, (7, 15), 0));
+        assert_ne!(face, FaceMask::new(3, BlockId::Block, (8, 15), 0));
+        assert_ne!(face, FaceMask::new(3, BlockId::Block, (7, 14), 0));
+        assert_ne!(face, FaceMask::new(3, BlockId::Block, (7, 15), 0b01));
+        assert!(FaceMask::NONE.is_none() && !face.is_none());
+    }
+
+    #[test]
+    fn simplified_light_gradient() {
+        // A floor lit by a gradient along x: 0, 1, 2, 3, 0, 1...
+        let mut blocks = ChunkBlocks::default();
+        for x in 0..CHUNK_SIZE as u8 {
+            for z in 0..CHUNK_SIZE as u8 {
+                blocks.data[LocalBlockPos::new(x, 0, z).to_index()] = BlockId::Block;
+                blocks.set_light(LocalBlockPos::new(x, 1, z), x % 4);
+            }
+        }
+        blocks.solid_blocks_count = (CHUNK_SIZE * CHUNK_SIZE) as u32;
+        let neighbours = [None, None, None, None, None, None];
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        let mut quads = |light_tolerance| {
+            super::mesh(
+                &blocks,
+                &neighbours,
+                &mut buff,
+                MAX_VERTICES_PER_CHUNK,
+                light_tolerance,
+            )
+            .count
+                / 6
+        };
+        // The top is a quad per row, the bottom and the 4 sides a quad each.
+        assert_eq!(quads(0), CHUNK_SIZE + 5);
+        // 0, 1 and 2 are merged, then 3 alone.
+        assert_eq!(quads(2), CHUNK_SIZE / 2 + 5);
+        assert_eq!(quads(3), 6);
+
+        let face = FaceMask::new(2, BlockId::Block, (3, 15), 0);
+        assert!(face.merges_with(FaceMask::new(2, BlockId::Block, (1, 14), 0), 2));
+        assert!(!face.merges_with(FaceMask::new(2, BlockId::Block, (0, 15), 0), 2));
+        assert!(!face.merges_with(FaceMask::new(2, BlockId::Bedrock, (3, 15), 0), 2));
+        assert!(!face.merges_with(FaceMask::NONE, 15));
+    }
+
+    #[test]
+    fn worst_case_truncated() {
+        // Nothing can be merged in a checkerboard, each block has its 6 faces.
+        let mut blocks = ChunkBlocks::default();
+        for x in 0..CHUNK_SIZE as u8 {
+            for y in 0..CHUNK_SIZE as u8 {
+                for z in 0..CHUNK_SIZE as u8 {
+                    if (x + y + z) % 2 == 0 {
+                        blocks.data[LocalBlockPos::new(x, y, z).to_index()] = BlockId::Block;
+                    }
+                }
+            }
+        }
+        let neighbours = [None, None, None, None, None, None];
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        let full = super::mesh(&blocks, &neighbours, &mut buff, MAX_VERTICES_PER_CHUNK, 0);
+        assert_eq!(
+            full,
+            MeshedVertices {
+                count: MAX_VERTICES_PER_CHUNK,
+                truncated: false
+            }
+        );
+
+        // One vertex short, the last quad is dropped.
+        let short = super::mesh(
+            &blocks,
+            &neighbours,
+            &mut buff,
+            MAX_VERTICES_PER_CHUNK - 1,
+            0,
+        );
+        assert_eq!(
+            short,
+            MeshedVertices {
+                count: MAX_VERTICES_PER_CHUNK - 6,
+                truncated: true
+            }
+        );
+    }
 }
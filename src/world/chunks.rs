@@ -1,18 +1,50 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
-    sync::{atomic::Ordering, Arc, RwLock},
-    time::SystemTime,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    env, iter, mem,
+    sync::{atomic::Ordering, Arc, Mutex, RwLock},
+    thread::{self, JoinHandle},
+    time::{Instant, SystemTime},
 };
 
 use anyhow::{Context, Result};
 use crossbeam_channel::{Receiver, Sender};
+use log::{trace, warn};
+use mini_moka::sync::Cache;
 
 use crate::{
     gui,
+    options::{AppOptions, BufferRounding},
     render::{Buffer, RegionsManager, MAX_FRAMES_IN_FLIGHT},
 };
 
-use super::{chunk::Chunk, generator, meshing, ChunkPos};
+#[cfg(any(test, feature = "bench"))]
+use crate::render::Vertex;
+
+use super::{
+    blocks::BlockId,
+    chunk::{Chunk, ChunkBlocks, ChunkMesh, ChunkState},
+    generator, light, meshing,
+    observers::{ChunkEvent, ChunkObservers},
+    BlockPos, ChunkPos, CHUNK_SIZE,
+};
+#[cfg(any(test, feature = "bench"))]
+use super::{chunk_mesh::ADDENDS, MAX_VERTICES_PER_CHUNK};
+
+/// Overrides `AppOptions::shared_workers` with `0` or `1`.
+const SHARED_WORKERS_VAR: &str = "VOXELS_SHARED_WORKERS";
+
+/// Whether the meshing threads also generate the chunks, from the options and the environment.
+pub fn shared_workers() -> bool {
+    match env::var(SHARED_WORKERS_VAR).as_deref() {
+        Ok("1") => true,
+        Ok("0") => false,
+        Ok(value) => {
+            warn!("Unknown {SHARED_WORKERS_VAR} value: {value:?}");
+            AppOptions::get().shared_workers
+        }
+        Err(_) => AppOptions::get().shared_workers,
+    }
+}
 
 #[derive(Debug)]
 pub struct Chunks {
@@ -21,57 +53,143 @@ pub struct Chunks {
     generator_receiver: Receiver<generator::Message>,
     meshing_sender: Sender<meshing::Message>,
     meshing_receiver: Receiver<meshing::Message>,
+    seed: u32,
 
     waiting_for_delete_buffers: WaitingForDeleteBuffers,
+    /// Buffers replaced by a remesh, waiting to go through `waiting_for_delete_buffers`.
+    retired_buffers: Mutex<Vec<Buffer>>,
+    /// Vertex buffers out of `waiting_for_delete_buffers`, reused instead of destroyed.
+    recycled_buffers: RecycledBuffers,
+    /// Chunks edited since the last [`Chunks::flush_dirty`].
+    dirty: Mutex<HashSet<ChunkPos>>,
+    /// Blocks of the generated chunks recently unloaded, see `AppOptions::unloaded_chunks_cache_capacity`.
+    /// `None` if it's disabled.
+    unloaded_cache: Option<Cache<ChunkPos, Arc<ChunkBlocks>>>,
+    pub observers: Arc<ChunkObservers>,
+    /// Updated by each world tick, the distance of the chunks to it decides whether their mesh is simplified.
+    pub player_chunk: ChunkPos,
 }
 
 impl Chunks {
     pub fn new() -> Arc<RwLock<Self>> {
         let (generator_sender, generator_receiver) = generator::create_sender();
         let (meshing_sender, meshing_receiver) = meshing::create_sender();
+        let seed = AppOptions::get().generation.seed.unwrap_or_else(|| {
+            if cfg!(feature = "bench") {
+                0
+            } else {
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_secs() as u32
+            }
+        });
+        let cache_capacity = AppOptions::get().unloaded_chunks_cache_capacity;
         Arc::new(RwLock::new(Self {
             data: HashMap::new(),
             generator_sender,
             generator_receiver,
             meshing_sender,
             meshing_receiver,
-            waiting_for_delete_buffers: Default::default(),
+            seed,
+            waiting_for_delete_buffers: WaitingForDeleteBuffers::new(),
+            retired_buffers: Mutex::new(Vec::new()),
+            recycled_buffers: RecycledBuffers::default(),
+            dirty: Mutex::new(HashSet::new()),
+            unloaded_cache: (cache_capacity > 0).then(|| Cache::new(cache_capacity)),
+            observers: Arc::new(ChunkObservers::default()),
+            player_chunk: ChunkPos::new(0, 0, 0),
         }))
     }
 
     pub fn init(s: &Arc<RwLock<Self>>, regions: &Arc<RegionsManager>) {
         let chunks = s.read().expect("Lock poisoned");
+        let shared = shared_workers();
 
-        let seed = if cfg!(feature = "bench") {
-            0
-        } else {
-            SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .expect("Time went backwards")
-                .as_secs() as u32
-        };
-        generator::start_threads(seed, chunks.generator_receiver.clone(), s);
-        meshing::start_threads(chunks.meshing_receiver.clone(), s, regions);
+        generator::start_threads(
+            chunks.seed,
+            if shared {
+                0
+            } else {
+                generator::threads_count()
+            },
+            chunks.generator_receiver.clone(),
+            s,
+        );
+        meshing::start_threads(
+            meshing::threads_count(),
+            chunks.meshing_receiver.clone(),
+            shared.then(|| (chunks.seed, chunks.generator_receiver.clone())),
+            s,
+            regions,
+        );
     }
 
     /// Return `true` if the chunk has been successfully loaded.
+    /// Its blocks come from the unloaded chunks cache if they're there, else it's sent to the generator threads.
     #[inline]
     pub fn load(&mut self, pos: ChunkPos) -> Result<bool> {
-        if let Entry::Vacant(entry) = self.data.entry(pos) {
-            let chunk = Chunk::new(pos);
-            let data = gui::DATA.read().expect("Lock poisoned");
-            data.created_chunks_total.fetch_add(1, Ordering::Relaxed);
-            data.created_chunks.fetch_add(1, Ordering::Relaxed);
-            let arc = Arc::new(chunk);
-            let weak = Arc::downgrade(&arc);
-            entry.insert(arc);
+        let Entry::Vacant(entry) = self.data.entry(pos) else {
+            return Ok(false);
+        };
+        let mut chunk = Chunk::new(pos);
+        let data = gui::DATA.read().expect("Lock poisoned");
+        data.created_chunks_total.fetch_add(1, Ordering::Relaxed);
+        data.created_chunks.fetch_add(1, Ordering::Relaxed);
+        drop(data);
+        let cached = self.unloaded_cache.as_ref().and_then(|cache| {
+            let blocks = cache.get(&pos)?;
+            cache.invalidate(&pos);
+            Some(Arc::try_unwrap(blocks).unwrap_or_else(|blocks| ChunkBlocks::clone(&blocks)))
+        });
+        let from_cache = cached.is_some();
+        if let Some(blocks) = cached {
+            *chunk.blocks.get_mut().expect("Lock poisoned") = blocks;
+        }
+        let arc = Arc::clone(entry.insert(Arc::new(chunk)));
+        self.observers.notify(ChunkEvent::Loaded(pos));
+
+        if !from_cache {
             self.generator_sender
-                .send(weak)
+                .send(Arc::downgrade(&arc))
                 .context("Sender disconnected")?;
-            Ok(true)
-        } else {
-            Ok(false)
+            return Ok(true);
+        }
+        trace!("Chunk {} loaded from the cache", pos);
+        self.observers.notify(ChunkEvent::Generated(pos));
+        self.release_waiting_neighbours(pos);
+        if arc.blocks.read().expect("Lock poisoned").solid_blocks_count != 0 {
+            self.chunk_generated(&arc);
+        }
+        Ok(true)
+    }
+
+    /// Load the chunks at `positions` not loaded yet and generate them on the calling thread, then return them.
+    /// Unlike [`Chunks::load`], they aren't sent to the generator threads nor to the meshing threads.
+    pub fn load_sync(&mut self, positions: impl IntoIterator<Item = ChunkPos>) -> Vec<Arc<Chunk>> {
+        let new_chunks: Vec<Arc<Chunk>> = positions
+            .into_iter()
+            .filter_map(|pos| match self.data.entry(pos) {
+                Entry::Vacant(entry) => Some(Arc::clone(entry.insert(Arc::new(Chunk::new(pos))))),
+                Entry::Occupied(_) => None,
+            })
+            .collect();
+        let data = gui::DATA.read().expect("Lock poisoned");
+        data.created_chunks_total
+            .fetch_add(new_chunks.len(), Ordering::Relaxed);
+        data.created_chunks
+            .fetch_add(new_chunks.len(), Ordering::Relaxed);
+        drop(data);
+
+        for chunk in &new_chunks {
+            self.observers.notify(ChunkEvent::Loaded(chunk.pos));
+        }
+        generator::generate_sync(self.seed, &new_chunks);
+        for chunk in &new_chunks {
+            self.observers.notify(ChunkEvent::Generated(chunk.pos));
+            self.release_waiting_neighbours(chunk.pos);
         }
+        new_chunks
     }
 
     #[inline]
@@ -80,13 +198,130 @@ impl Chunks {
         C: FnMut(&ChunkPos, &mut Arc<Chunk>) -> bool,
     {
         let drained = self.data.drain_filter(closure);
+        let mut unloaded = Vec::new();
         self.waiting_for_delete_buffers
-            .tick(drained.filter_map(|(_, chunk)| {
+            .push(drained.filter_map(|(_, chunk)| {
+                self.observers.notify(ChunkEvent::Unloaded(chunk.pos));
+                if let Some(cache) = &self.unloaded_cache {
+                    let blocks = chunk.blocks.read().expect("Lock poisoned");
+                    if blocks.generated {
+                        cache.insert(chunk.pos, Arc::new(blocks.clone()));
+                    }
+                }
+                unloaded.push(chunk.pos);
                 regions
                     .set_dirty(chunk.pos.region())
                     .expect("Region should exists");
-                chunk.vertex_buffer.lock().expect("Mutex poisoned").take()
+                let mesh = chunk.vertex_buffer.lock().expect("Mutex poisoned").take();
+                mesh.map(ChunkMesh::into_buffer)
             }));
+        for pos in unloaded {
+            self.release_waiting_neighbours(pos);
+        }
+    }
+
+    /// Discard the chunks farthest from `center` until at most `max` are loaded, except the ones `keep` returns
+    /// `true` for. Their buffers go through the delete queue like with [`Chunks::drain_filter`].
+    pub fn evict_farthest(
+        &mut self,
+        max: usize,
+        center: ChunkPos,
+        keep: impl Fn(&ChunkPos) -> bool,
+        regions: &RegionsManager,
+    ) {
+        let excess = self.data.len().saturating_sub(max);
+        if excess == 0 {
+            return;
+        }
+        let (cx, cy, cz) = center.xyz();
+        let mut candidates = self
+            .data
+            .keys()
+            .filter(|pos| !keep(pos))
+            .map(|&pos| {
+                let (x, y, z) = pos.xyz();
+                ((x - cx).pow(2) + (y - cy).pow(2) + (z - cz).pow(2), pos)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by_key(|&(distance, _)| std::cmp::Reverse(distance));
+        let evicted = candidates
+            .into_iter()
+            .take(excess)
+            .map(|(_, pos)| pos)
+            .collect::<HashSet<_>>();
+        trace!("Evicting {} chunks", evicted.len());
+        self.drain_filter(|pos, _| evicted.contains(pos), regions);
+    }
+
+    /// Clear the chunk and send it to the generator threads again. Its meshed neighbours are meshed again once
+    /// it's generated, like the ones waiting for it. Return `false` if the chunk isn't loaded.
+    pub fn regenerate(&self, pos: ChunkPos, regions: &RegionsManager) -> Result<bool> {
+        let Some(chunk) = self.data.get(&pos) else {
+            return Ok(false);
+        };
+
+        *chunk.blocks.write().expect("Lock poisoned") = Default::default();
+        if let Some(mesh) = chunk.swap_vertex_buffer(None) {
+            self.retire_buffer(mesh.into_buffer());
+        }
+        regions.set_dirty(pos.region())?;
+
+        for pos in neighbour_positions(pos) {
+            if let Some(neighbour) = self.data.get(&pos)
+                && neighbour.state() == ChunkState::Meshed
+            {
+                neighbour
+                    .waiting_for_neighbours
+                    .store(true, Ordering::SeqCst);
+            }
+        }
+        chunk.regenerating.store(true, Ordering::Relaxed);
+        self.generator_sender
+            .send(Arc::downgrade(chunk))
+            .context("Sender disconnected")?;
+        Ok(true)
+    }
+
+    /// Generate the chunk at `pos` and its missing neighbours then mesh it, all on the calling thread.
+    /// Return the count of vertices generated.
+    ///
+    /// Bypasses the generator and meshing threads so the result only depends on `seed`.
+    /// Nothing is uploaded to the GPU.
+    #[cfg(any(test, feature = "bench"))]
+    pub fn generate_and_mesh_sync(s: &Arc<RwLock<Self>>, seed: u32, pos: ChunkPos) -> usize {
+        let mut chunks = s.write().expect("Lock poisoned");
+        let new_chunks: Vec<Arc<Chunk>> = ADDENDS
+            .iter()
+            .map(|addend| pos + ChunkPos::new(addend.0 as _, addend.1 as _, addend.2 as _))
+            .chain([pos])
+            .filter_map(|pos| match chunks.data.entry(pos) {
+                Entry::Vacant(entry) => Some(Arc::clone(entry.insert(Arc::new(Chunk::new(pos))))),
+                Entry::Occupied(_) => None,
+            })
+            .collect();
+        let chunk = Arc::clone(chunks.data.get(&pos).expect("Chunk should exists"));
+        drop(chunks);
+
+        generator::generate_sync(seed, &new_chunks);
+
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        chunk.mesh(s, &mut buff)
+    }
+
+    /// A retired vertex buffer of at least `size` bytes, no longer used by any frame. Mapped and device local
+    /// buffers are filled differently so only one created with the same `mapped` is returned.
+    #[inline]
+    pub fn take_recycled_buffer(&self, size: usize, mapped: bool) -> Option<Buffer> {
+        self.recycled_buffers.take(size, mapped)
+    }
+
+    /// Delay the destruction of a buffer that may still be used by in-flight frames.
+    #[inline]
+    pub fn retire_buffer(&self, buffer: Buffer) {
+        self.retired_buffers
+            .lock()
+            .expect("Mutex poisoned")
+            .push(buffer);
     }
 
     pub fn update_gui_data(&self) {
@@ -97,16 +332,133 @@ impl Chunks {
             .store(self.meshing_sender.len(), Ordering::Relaxed);
     }
 
+    /// Count of chunks waiting for generation.
+    #[inline]
+    pub fn generation_backlog(&self) -> usize {
+        self.generator_sender.len()
+    }
+
     #[inline]
     pub fn get(&self, pos: &ChunkPos) -> Option<&Arc<Chunk>> {
         self.data.get(pos)
     }
 
+    #[inline]
+    pub fn state(&self, pos: &ChunkPos) -> ChunkState {
+        self.data
+            .get(pos)
+            .map_or(ChunkState::Unloaded, |chunk| chunk.state())
+    }
+
+    /// Return `None` if the chunk isn't loaded. Chunks not generated yet only contain air.
+    #[inline]
+    pub fn get_block(&self, pos: BlockPos) -> Option<BlockId> {
+        self.data
+            .get(&pos.chunk())
+            .map(|chunk| chunk.get_block(pos.local()))
+    }
+
+    /// Replace a block and mark its chunk dirty, with the neighbours sharing the face of the block and the chunks
+    /// whose light changed.
+    /// The remesh is deferred to [`Chunks::flush_dirty`] so a chunk is meshed once whatever the count of edits.
+    /// Return `false` if the chunk isn't loaded.
+    pub fn set_block(&self, pos: BlockPos, block: BlockId) -> bool {
+        let chunk_pos = pos.chunk();
+        let Some(chunk) = self.data.get(&chunk_pos) else {
+            return false;
+        };
+        let local = pos.local();
+        let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+        let previous = mem::replace(&mut blocks.data[local.to_index()], block);
+        if previous == block {
+            return true;
+        }
+        if previous == BlockId::Air {
+            blocks.solid_blocks_count += 1;
+        } else if block == BlockId::Air {
+            blocks.solid_blocks_count -= 1;
+        }
+        drop(blocks);
+
+        let lit = light::block_changed(self, pos);
+        let mut dirty = self.dirty.lock().expect("Mutex poisoned");
+        dirty.extend(touching_chunks(pos));
+        dirty.extend(lit);
+        true
+    }
+
+    /// Send the chunk to the meshing threads with the edits of the frame, see [`Chunks::flush_dirty`].
+    pub fn remesh_later(&self, pos: ChunkPos) {
+        self.dirty.lock().expect("Mutex poisoned").insert(pos);
+    }
+
+    /// Called once per rendered frame. The buffers queued `MAX_FRAMES_IN_FLIGHT` frames ago aren't used by the GPU
+    /// anymore and are destroyed or recycled.
+    pub fn end_frame(&mut self) {
+        let retired = mem::take(&mut *self.retired_buffers.lock().expect("Mutex poisoned"));
+        self.waiting_for_delete_buffers.push(retired.into_iter());
+        let recycled = (AppOptions::get().vertex_buffer_rounding != BufferRounding::Exact)
+            .then_some(&self.recycled_buffers);
+        self.waiting_for_delete_buffers.tick(recycled);
+    }
+
+    /// Send the chunks edited since the last flush to the meshing threads, once each.
+    pub fn flush_dirty(&self) {
+        let dirty = mem::take(&mut *self.dirty.lock().expect("Mutex poisoned"));
+        for pos in dirty {
+            if let Some(chunk) = self.data.get(&pos) {
+                self.meshing_sender
+                    .send(Arc::downgrade(chunk))
+                    .expect("Sender disconnected");
+            }
+        }
+    }
+
     #[inline]
     pub fn iter(&self) -> impl Iterator<Item = (&ChunkPos, &Arc<Chunk>)> {
         self.data.iter()
     }
 
+    /// With `AppOptions::wait_for_neighbours`, return `false` if a loaded neighbour of `chunk` isn't generated
+    /// yet. The chunk then shouldn't be meshed: the neighbour would be air in its mesh, showing the faces at
+    /// their border until it's meshed again. It's sent to the meshing threads again by
+    /// [`Chunks::release_waiting_neighbours`] once the neighbour is generated or unloaded.
+    ///
+    /// The unloaded neighbours don't delay it, the faces towards them are expected. The chunks are only locked
+    /// with the option.
+    pub fn ready_to_mesh(s: &RwLock<Self>, chunk: &Chunk) -> bool {
+        !AppOptions::get().wait_for_neighbours
+            || s.read().expect("Lock poisoned").neighbours_generated(chunk)
+    }
+
+    /// Return `true` if all the loaded neighbours of `chunk` are generated, else mark it as waiting for them.
+    fn neighbours_generated(&self, chunk: &Chunk) -> bool {
+        // Marked before the check so a neighbour generated meanwhile is either seen generated or sees the mark.
+        chunk.waiting_for_neighbours.store(true, Ordering::SeqCst);
+        let generated = neighbour_positions(chunk.pos).all(|pos| {
+            self.data.get(&pos).map_or(true, |neighbour| {
+                neighbour.blocks.read().expect("Lock poisoned").generated
+            })
+        });
+        if generated {
+            chunk.waiting_for_neighbours.store(false, Ordering::SeqCst);
+        }
+        generated
+    }
+
+    /// Send the neighbours waiting for the chunk at `pos` to the meshing threads, once it's generated or unloaded.
+    pub fn release_waiting_neighbours(&self, pos: ChunkPos) {
+        for pos in neighbour_positions(pos) {
+            if let Some(neighbour) = self.data.get(&pos)
+                && neighbour
+                    .waiting_for_neighbours
+                    .swap(false, Ordering::SeqCst)
+            {
+                self.chunk_generated(neighbour);
+            }
+        }
+    }
+
     #[inline]
     pub fn chunk_generated(&self, chunk: &Arc<Chunk>) {
         self.meshing_sender
@@ -119,23 +471,409 @@ impl Chunks {
         self.data.len()
     }
 
+    #[inline]
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
     pub fn stop_threads(&self) {
+        // Parked threads wouldn't see the exit message.
+        super::WORKERS_PAUSE.set_paused(false);
         generator::stop_threads(&self.generator_sender);
         meshing::stop_threads(&self.meshing_sender);
     }
 }
 
-#[derive(Debug, Default)]
+/// The six chunks sharing a face with the chunk at `pos`.
+fn neighbour_positions(pos: ChunkPos) -> impl Iterator<Item = ChunkPos> {
+    ADDENDS
+        .into_iter()
+        .map(move |(x, y, z)| pos + ChunkPos::new(x as _, y as _, z as _))
+}
+
+/// The chunk of `pos` and the neighbours sharing a face of the block, whose meshes depend on it.
+pub(super) fn touching_chunks(pos: BlockPos) -> impl Iterator<Item = ChunkPos> {
+    const LAST: u8 = CHUNK_SIZE as u8 - 1;
+    let chunk_pos = pos.chunk();
+    let (x, y, z) = pos.local().xyz();
+    let neighbours = [x, y, z]
+        .into_iter()
+        .enumerate()
+        .filter_map(move |(axis, coord)| {
+            let offset = match coord {
+                0 => -1,
+                LAST => 1,
+                _ => return None,
+            };
+            let mut addend = [0; 3];
+            addend[axis] = offset;
+            Some(chunk_pos + ChunkPos::new(addend[0], addend[1], addend[2]))
+        });
+    iter::once(chunk_pos).chain(neighbours)
+}
+
+/// Buffers are kept `MAX_FRAMES_IN_FLIGHT` frames then destroyed on a dedicated thread,
+/// so freeing many of them at once (e.g. when teleporting) doesn't stall the main loop.
+#[derive(Debug)]
 struct WaitingForDeleteBuffers {
     buffers: [Vec<Buffer>; MAX_FRAMES_IN_FLIGHT],
     index: usize,
+    /// `None` only while dropping.
+    cleanup_sender: Option<Sender<Vec<Buffer>>>,
+    cleanup_thread: Option<JoinHandle<()>>,
 }
 
 impl WaitingForDeleteBuffers {
+    fn new() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<Vec<Buffer>>();
+        let cleanup_thread = thread::Builder::new()
+            .name("Buffers cleanup".to_string())
+            .spawn(move || {
+                for buffers in receiver {
+                    let count = buffers.len();
+                    let start = Instant::now();
+                    drop(buffers);
+                    trace!(target: "allocator", "Destroyed {} buffers in {:?}", count, start.elapsed());
+                }
+            })
+            .expect("Thread spawn failed");
+        Self {
+            buffers: Default::default(),
+            index: 0,
+            cleanup_sender: Some(sender),
+            cleanup_thread: Some(cleanup_thread),
+        }
+    }
+
+    /// Queue buffers the frames in flight may still use.
     #[inline]
-    fn tick<I: Iterator<Item = Buffer>>(&mut self, new_buffs: I) {
-        self.buffers[self.index].clear();
+    fn push<I: Iterator<Item = Buffer>>(&mut self, new_buffs: I) {
         self.buffers[self.index].extend(new_buffs);
+    }
+
+    /// Called once per frame. The expired buffers go to `recycled` first if it's set.
+    fn tick(&mut self, recycled: Option<&RecycledBuffers>) {
         self.index = (self.index + 1) % MAX_FRAMES_IN_FLIGHT;
+        let mut expired = mem::take(&mut self.buffers[self.index]);
+        if let Some(recycled) = recycled {
+            expired = recycled.put(expired);
+        }
+        if !expired.is_empty() {
+            self.cleanup_sender
+                .as_ref()
+                .expect("Cleanup thread stopped")
+                .send(expired)
+                .expect("Cleanup thread stopped");
+        }
+    }
+}
+
+impl Drop for WaitingForDeleteBuffers {
+    /// Wait for the buffers sent to the cleanup thread so none outlives the device.
+    fn drop(&mut self) {
+        self.cleanup_sender = None;
+        if let Some(handle) = self.cleanup_thread.take()
+            && let Err(e) = handle.join()
+        {
+            warn!("Failed to join the cleanup thread: {:?}", e);
+        }
+    }
+}
+
+/// Kept buffers beyond this count are destroyed.
+const MAX_RECYCLED_BUFFERS: usize = 256;
+
+#[derive(Debug, Default)]
+struct RecycledBuffers(Mutex<Vec<Buffer>>);
+
+impl RecycledBuffers {
+    /// Keep `buffers` up to `MAX_RECYCLED_BUFFERS` and return the others.
+    fn put(&self, mut buffers: Vec<Buffer>) -> Vec<Buffer> {
+        let mut recycled = self.0.lock().expect("Mutex poisoned");
+        let kept = buffers.len().min(MAX_RECYCLED_BUFFERS - recycled.len());
+        recycled.extend(buffers.drain(..kept));
+        buffers
+    }
+
+    /// The smallest buffer of at least `size` bytes created with the same `mapped`.
+    fn take(&self, size: usize, mapped: bool) -> Option<Buffer> {
+        let mut recycled = self.0.lock().expect("Mutex poisoned");
+        let (i, _) = recycled
+            .iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.size() >= size && buffer.is_mapped() == mapped)
+            .min_by_key(|(_, buffer)| buffer.size())?;
+        Some(recycled.swap_remove(i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+
+    use crate::world::{LocalBlockPos, BLOCKS_PER_CHUNK};
+
+    use super::*;
+
+    const SEED: u32 = 42;
+
+    fn vertex_buffer(size: usize) -> Buffer {
+        use vulkanalia::vk;
+
+        use crate::render::MemoryProperties;
+
+        Buffer::new(
+            size,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            MemoryProperties::required(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            false,
+            4,
+        )
+        .expect("Buffer creation failed")
+    }
+
+    /// Mesh the chunks from y = 1 to 4. The surface is always between 50 and 150 blocks high so it's in one of them.
+    fn mesh_surface_column(x: i64, z: i64) -> usize {
+        let chunks = Chunks::new();
+        (1..=4)
+            .map(|y| Chunks::generate_and_mesh_sync(&chunks, SEED, ChunkPos::new(x, y, z)))
+            .sum()
+    }
+
+    #[test]
+    fn sync_generation_is_deterministic() {
+        let first = mesh_surface_column(3, -7);
+        let second = mesh_surface_column(3, -7);
+        assert_ne!(first, 0);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn sync_generation_hidden_chunks() {
+        let chunks = Chunks::new();
+
+        let buried = ChunkPos::new(0, 0, 0);
+        assert_eq!(Chunks::generate_and_mesh_sync(&chunks, SEED, buried), 0);
+        let chunk = Arc::clone(
+            chunks
+                .read()
+                .expect("Lock poisoned")
+                .get(&buried)
+                .expect("Chunk should exists"),
+        );
+        assert_eq!(
+            chunk
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .solid_blocks_count as usize,
+            BLOCKS_PER_CHUNK
+        );
+
+        let sky = ChunkPos::new(0, 10, 0);
+        assert_eq!(Chunks::generate_and_mesh_sync(&chunks, SEED, sky), 0);
+    }
+
+    #[test]
+    fn edits_coalesced() {
+        let chunks = Chunks::new();
+        let mut chunks = chunks.write().expect("Lock poisoned");
+        let pos = ChunkPos::new(0, 0, 0);
+        assert!(chunks.load(pos).expect("Chunk loading failed"));
+
+        // Away from the borders so no neighbour is dirty.
+        for i in 0..100 {
+            let local = LocalBlockPos::new(1 + i % 10, 1 + i / 10, 5);
+            assert!(chunks.set_block(BlockPos::new(pos, local), BlockId::Block));
+        }
+        assert!(chunks.meshing_receiver.is_empty());
+
+        chunks.flush_dirty();
+        assert_eq!(chunks.meshing_receiver.len(), 1);
+        let chunk = chunks
+            .meshing_receiver
+            .recv()
+            .expect("Channel disconnected");
+        assert_eq!(chunk.upgrade().expect("Chunk dropped").pos, pos);
+        assert_eq!(
+            chunks
+                .get(&pos)
+                .expect("Chunk should exists")
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .solid_blocks_count,
+            100
+        );
+
+        chunks.flush_dirty();
+        assert!(chunks.meshing_receiver.is_empty());
+    }
+
+    #[test]
+    fn recycled_buffers_reuse() {
+        let buffer = vertex_buffer;
+        let recycled = RecycledBuffers::default();
+        assert!(recycled.put(vec![buffer(1024), buffer(4096)]).is_empty());
+
+        // A smaller mesh takes the smallest buffer fitting it, a bigger one the other.
+        // A mapped buffer is never handed out for a copy destination, nor the opposite.
+        assert!(recycled.take(800, true).is_none());
+        let small = recycled.take(800, false).expect("No buffer fitting");
+        assert!((1024..4096).contains(&small.size()));
+        let big = recycled.take(2000, false).expect("No buffer fitting");
+        assert!(big.size() >= 4096);
+        assert!(recycled.take(100, false).is_none());
+
+        let buffers = (0..MAX_RECYCLED_BUFFERS + 2).map(|_| buffer(64)).collect();
+        assert_eq!(recycled.put(buffers).len(), 2);
+    }
+
+    #[test]
+    fn retired_buffer_kept_while_in_flight() {
+        let chunks = Chunks::new();
+        let mut chunks = chunks.write().expect("Lock poisoned");
+        let buffer = vertex_buffer(64);
+        let handle = buffer.buffer;
+        chunks.retire_buffer(buffer);
+
+        let waiting = |chunks: &Chunks| {
+            chunks
+                .waiting_for_delete_buffers
+                .buffers
+                .iter()
+                .flatten()
+                .any(|buffer| buffer.buffer == handle)
+        };
+        // Still usable by the frames recorded before the remesh.
+        for _ in 1..MAX_FRAMES_IN_FLIGHT {
+            chunks.end_frame();
+            assert!(waiting(&chunks));
+        }
+        chunks.end_frame();
+        assert!(!waiting(&chunks));
+    }
+
+    #[test]
+    fn chunk_states() {
+        let chunks = Chunks::new();
+        let regions =
+            RegionsManager::new(Arc::clone(&chunks), 1).expect("Region manager creation failed");
+        let state = |pos| chunks.read().expect("Lock poisoned").state(&pos);
+        // High above the surface, only air.
+        let pos = ChunkPos::new(0, 20, 0);
+        assert_eq!(state(pos), ChunkState::Unloaded);
+
+        assert!(chunks
+            .write()
+            .expect("Lock poisoned")
+            .load(pos)
+            .expect("Loading failed"));
+        assert_eq!(state(pos), ChunkState::Loading);
+        let chunk = Arc::clone(
+            chunks
+                .read()
+                .expect("Lock poisoned")
+                .get(&pos)
+                .expect("Loaded chunk"),
+        );
+        generator::generate_sync(SEED, slice::from_ref(&chunk));
+        assert_eq!(state(pos), ChunkState::Generated);
+
+        // Without any visible face, it has no mesh.
+        let mut vertices = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        meshing::mesh_sync(&chunk, &chunks, &regions, &mut vertices).expect("Meshing failed");
+        assert_eq!(state(pos), ChunkState::Generated);
+        let block_pos = BlockPos::new(pos, LocalBlockPos::new(1, 2, 3));
+        assert!(chunks
+            .read()
+            .expect("Lock poisoned")
+            .set_block(block_pos, BlockId::Block));
+        meshing::mesh_sync(&chunk, &chunks, &regions, &mut vertices).expect("Meshing failed");
+        assert_eq!(state(pos), ChunkState::Meshed);
+
+        chunks
+            .write()
+            .expect("Lock poisoned")
+            .drain_filter(|_, _| true, &regions);
+        assert_eq!(state(pos), ChunkState::Unloaded);
+    }
+
+    #[test]
+    fn adjacent_chunks_readiness() {
+        let chunks = Chunks::new();
+        let (first, second) = (ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0));
+        let [first, second] = [first, second].map(|pos| {
+            let mut chunks = chunks.write().expect("Lock poisoned");
+            assert!(chunks.load(pos).expect("Loading failed"));
+            Arc::clone(chunks.get(&pos).expect("Loaded chunk"))
+        });
+        let chunks = chunks.read().expect("Lock poisoned");
+
+        // The second one is loaded but still air.
+        generator::generate_sync(SEED, slice::from_ref(&first));
+        assert!(!chunks.neighbours_generated(&first));
+        assert!(first.waiting_for_neighbours.load(Ordering::SeqCst));
+        assert!(chunks.meshing_receiver.is_empty());
+
+        // Its generation sends the first one to the meshing threads again, once.
+        generator::generate_sync(SEED, slice::from_ref(&second));
+        chunks.release_waiting_neighbours(second.pos);
+        let mess = chunks.meshing_receiver.try_recv().expect("Chunk not sent");
+        assert!(mess.ptr_eq(&Arc::downgrade(&first)));
+        chunks.release_waiting_neighbours(second.pos);
+        assert!(chunks.meshing_receiver.is_empty());
+
+        // Both are ready, their other neighbours aren't loaded.
+        assert!(chunks.neighbours_generated(&first));
+        assert!(chunks.neighbours_generated(&second));
+        assert!(!first.waiting_for_neighbours.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn unloaded_chunk_from_cache() {
+        let chunks = Chunks::new();
+        let regions =
+            RegionsManager::new(Arc::clone(&chunks), 1).expect("Region manager creation failed");
+        let mut chunks = chunks.write().expect("Lock poisoned");
+        // Buried, full of blocks.
+        let pos = ChunkPos::new(0, 0, 0);
+        assert!(chunks.load(pos).expect("Loading failed"));
+        let chunk = chunks
+            .generator_receiver
+            .try_recv()
+            .expect("Chunk not sent to generation")
+            .upgrade()
+            .expect("Chunk dropped");
+        generator::generate_sync(SEED, slice::from_ref(&chunk));
+        drop(chunk);
+        let block_pos = BlockPos::new(pos, LocalBlockPos::new(1, 2, 3));
+        assert!(chunks.set_block(block_pos, BlockId::Air));
+
+        chunks.drain_filter(|_, _| true, &regions);
+        assert_eq!(chunks.state(&pos), ChunkState::Unloaded);
+        assert!(chunks.load(pos).expect("Loading failed"));
+        assert!(chunks.generator_receiver.is_empty());
+        assert_eq!(chunks.state(&pos), ChunkState::Generated);
+        assert_eq!(chunks.get_block(block_pos), Some(BlockId::Air));
+        assert_eq!(
+            chunks
+                .get(&pos)
+                .expect("Loaded chunk")
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .solid_blocks_count as usize,
+            BLOCKS_PER_CHUNK - 1
+        );
+        // Sent to the meshing threads like after its generation.
+        assert_eq!(chunks.meshing_receiver.len(), 1);
+
+        // Not cached if it's unloaded before its generation.
+        let pos = ChunkPos::new(5, 0, 0);
+        assert!(chunks.load(pos).expect("Loading failed"));
+        chunks.drain_filter(|chunk_pos, _| *chunk_pos == pos, &regions);
+        assert!(chunks.load(pos).expect("Loading failed"));
+        assert_eq!(chunks.generator_receiver.len(), 2);
     }
 }
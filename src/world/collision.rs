@@ -0,0 +1,210 @@
+use nalgebra_glm::Vec3;
+
+use super::{blocks::BlockId, chunks::Chunks, Aabb, BlockPos};
+
+/// Move `aabb` by `motion`, stopping each axis at the first solid block, and return the motion applied.
+///
+/// Axes are resolved one after the other, `y` first so landing happens before sliding along walls.
+/// Blocks of unloaded chunks are treated as air: there is no collision until the chunk is loaded and generated.
+pub fn move_aabb(chunks: &Chunks, mut aabb: Aabb, motion: Vec3) -> Vec3 {
+    let mut applied = Vec3::zeros();
+    for axis in [1, 0, 2] {
+        let delta = motion[axis];
+        if delta == 0. {
+            continue;
+        }
+
+        let mut swept = aabb;
+        if delta > 0. {
+            swept.max[axis] += delta;
+        } else {
+            swept.min[axis] += delta;
+        }
+
+        let mut allowed = delta;
+        for block in solid_blocks(chunks, &swept) {
+            // Blocks already overlapping the box are ignored so an entity stuck in a block can get out.
+            if delta > 0. && block.min[axis] >= aabb.max[axis] {
+                allowed = allowed.min(block.min[axis] - aabb.max[axis]);
+            } else if delta < 0. && block.max[axis] <= aabb.min[axis] {
+                allowed = allowed.max(block.max[axis] - aabb.min[axis]);
+            }
+        }
+
+        aabb.min[axis] += allowed;
+        aabb.max[axis] += allowed;
+        applied[axis] = allowed;
+    }
+    applied
+}
+
+/// Return the boxes of the solid blocks intersecting `aabb`.
+fn solid_blocks(chunks: &Chunks, aabb: &Aabb) -> Vec<Aabb> {
+    let min = aabb.min.map(|v| v.floor() as i64);
+    let max = aabb.max.map(|v| v.ceil() as i64);
+
+    let mut blocks = Vec::new();
+    for x in min.x..max.x {
+        for y in min.y..max.y {
+            for z in min.z..max.z {
+                let pos = BlockPos::from_world(x, y, z);
+                if let Some(block) = chunks.get_block(pos) && block != BlockId::Air {
+                    blocks.push(Aabb::from_block(pos));
+                }
+            }
+        }
+    }
+    blocks
+}
+
+/// The first solid block on a ray.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RaycastHit {
+    pub block: BlockPos,
+    /// The block in front of the face hit, where a block placed against it goes.
+    pub adjacent: BlockPos,
+}
+
+/// Walk the blocks crossed by the ray from `origin` towards `dir` and return the first solid one
+/// closer than `max_distance`. Like for collisions, unloaded chunks are treated as air.
+pub fn raycast(chunks: &Chunks, origin: Vec3, dir: Vec3, max_distance: f32) -> Option<RaycastHit> {
+    if dir == Vec3::zeros() {
+        return None;
+    }
+    let dir = dir.normalize();
+    let mut cell = origin.map(|v| v.floor() as i64);
+    let step = dir.map(|v| {
+        if v > 0. {
+            1
+        } else if v < 0. {
+            -1
+        } else {
+            0
+        }
+    });
+    // Distance along the ray to cross a whole block on each axis.
+    let delta = dir.map(|v| 1. / v.abs());
+    // Distance along the ray to the next block boundary on each axis.
+    let mut next = Vec3::zeros();
+    for axis in 0..3 {
+        next[axis] = match step[axis] {
+            1 => (cell[axis] as f32 + 1. - origin[axis]) * delta[axis],
+            -1 => (origin[axis] - cell[axis] as f32) * delta[axis],
+            _ => f32::INFINITY,
+        };
+    }
+
+    let mut previous = cell;
+    let mut distance = 0.;
+    while distance <= max_distance {
+        let pos = BlockPos::from_world(cell.x, cell.y, cell.z);
+        if let Some(block) = chunks.get_block(pos) && block != BlockId::Air {
+            return Some(RaycastHit {
+                block: pos,
+                adjacent: BlockPos::from_world(previous.x, previous.y, previous.z),
+            });
+        }
+        let axis = next.imin();
+        previous = cell;
+        cell[axis] += step[axis];
+        distance = next[axis];
+        next[axis] += delta[axis];
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::world::{ChunkPos, LocalBlockPos, CHUNK_SIZE};
+
+    use super::*;
+
+    /// A loaded chunk at the origin whose lower half is solid, surrounded by unloaded chunks.
+    fn half_filled_chunks() -> Arc<RwLock<Chunks>> {
+        let chunks = Chunks::new();
+        let mut lock = chunks.write().expect("Lock poisoned");
+        let pos = ChunkPos::new(0, 0, 0);
+        lock.load(pos).expect("Chunk loading failed");
+        let chunk = lock.get(&pos).expect("Chunk should exists");
+        let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+        for x in 0..CHUNK_SIZE as u8 {
+            for y in 0..CHUNK_SIZE as u8 / 2 {
+                for z in 0..CHUNK_SIZE as u8 {
+                    blocks.data[LocalBlockPos::new(x, y, z).to_index()] = BlockId::Block;
+                }
+            }
+        }
+        drop(blocks);
+        drop(lock);
+        chunks
+    }
+
+    fn player_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(Vec3::new(x, y, z), Vec3::new(x + 0.5, y + 1.75, z + 0.5))
+    }
+
+    #[test]
+    fn lands_on_ground() {
+        let chunks = half_filled_chunks();
+        let chunks = chunks.read().expect("Lock poisoned");
+
+        let applied = move_aabb(&chunks, player_box(5., 17.5, 5.), Vec3::new(0., -5., 0.));
+        assert_eq!(applied, Vec3::new(0., -1.5, 0.));
+        // Already standing on the ground
+        let applied = move_aabb(&chunks, player_box(5., 16., 5.), Vec3::new(1., -1., 0.));
+        assert_eq!(applied, Vec3::new(1., 0., 0.));
+    }
+
+    #[test]
+    fn stops_against_walls() {
+        let chunks = half_filled_chunks();
+        let chunks = chunks.read().expect("Lock poisoned");
+
+        // Sunk in the ground: the overlapped blocks are ignored but the ones ahead stop the box
+        let applied = move_aabb(&chunks, player_box(5.25, 15.5, 5.), Vec3::new(2., 0., 0.));
+        assert_eq!(applied, Vec3::new(0.25, 0., 0.));
+        let applied = move_aabb(&chunks, player_box(5., 15.5, 5.25), Vec3::new(0., 0., -2.));
+        assert_eq!(applied, Vec3::new(0., 0., -0.25));
+        // Above the ground
+        let applied = move_aabb(&chunks, player_box(5., 17., 5.), Vec3::new(0., 0., -3.));
+        assert_eq!(applied, Vec3::new(0., 0., -3.));
+    }
+
+    #[test]
+    fn no_collision_in_unloaded_chunks() {
+        let chunks = half_filled_chunks();
+        let chunks = chunks.read().expect("Lock poisoned");
+
+        let applied = move_aabb(&chunks, player_box(-5., 10., 5.), Vec3::new(0., -4., 0.));
+        assert_eq!(applied, Vec3::new(0., -4., 0.));
+    }
+
+    #[test]
+    fn raycast_hits_ground() {
+        let chunks = half_filled_chunks();
+        let chunks = chunks.read().expect("Lock poisoned");
+
+        let hit = raycast(&chunks, Vec3::new(5.5, 20.5, 5.5), -Vec3::y(), 10.).expect("No hit");
+        assert_eq!(hit.block, BlockPos::from_world(5, 15, 5));
+        assert_eq!(hit.adjacent, BlockPos::from_world(5, 16, 5));
+
+        // Going down and towards +x, the ground is entered by the top face.
+        let dir = Vec3::new(1., -2., 0.);
+        let hit = raycast(&chunks, Vec3::new(5.5, 20.5, 5.5), dir, 10.).expect("No hit");
+        assert_eq!(hit.block, BlockPos::from_world(7, 15, 5));
+        assert_eq!(hit.adjacent, BlockPos::from_world(7, 16, 5));
+
+        // Too far
+        assert_eq!(
+            raycast(&chunks, Vec3::new(5.5, 20.5, 5.5), -Vec3::y(), 4.),
+            None
+        );
+        // Unloaded chunk
+        assert_eq!(
+            raycast(&chunks, Vec3::new(-5.5, 20.5, 5.5), -Vec3::y(), 30.),
+            None
+        );
+    }
+}
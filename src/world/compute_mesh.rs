@@ -0,0 +1,377 @@
+use std::{
+    mem::{size_of, size_of_val},
+    slice,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{Context, Result};
+use log::trace;
+use vulkanalia::vk::{self, DeviceV1_0, HasBuilder};
+
+use crate::{
+    render::{
+        create_fence, Buffer, CommandBuffer, CommandPool, ComputePipeline, MemoryProperties, Queue,
+        Vertex, DEVICE,
+    },
+    shader_module,
+};
+
+use super::{
+    blocks::BlockId,
+    chunk::{is_buried, Chunk},
+    chunks::Chunks,
+    LocalBlockPos, BLOCKS_PER_CHUNK, CHUNK_SIZE, MAX_VERTICES_PER_CHUNK,
+};
+
+const LAYER_SIZE: usize = CHUNK_SIZE * CHUNK_SIZE;
+/// The chunk blocks followed by the layer touching the chunk of each neighbour.
+const INPUT_SIZE: usize = (BLOCKS_PER_CHUNK + 6 * LAYER_SIZE) * size_of::<BlockId>();
+
+/// Mesh chunks with `mesh.comp` instead of the CPU.
+/// Experimental, only used by the meshing threads with the `compute_meshing` feature.
+///
+/// The vertices are written to a device local buffer then the vertex count is read back from an atomic counter
+/// in host visible memory. They are then copied to a buffer of the exact size.
+#[derive(Debug)]
+pub struct ComputeMesher {
+    pipeline: ComputePipeline,
+    input: Buffer,
+    vertices: Buffer,
+    counter: Buffer,
+    queue: Queue,
+    command_buff: CommandBuffer,
+    _command_pool: CommandPool,
+    fence: vk::Fence,
+}
+
+impl ComputeMesher {
+    pub fn new(queue: Queue) -> Result<Self> {
+        let mut pipeline = ComputePipeline::new(shader_module!("mesh.comp")?, 3)
+            .context("Compute pipeline creation failed")?;
+        let input = Buffer::new(
+            INPUT_SIZE,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE),
+            true,
+            size_of::<u32>(),
+        )
+        .context("Input buffer creation failed")?;
+        let vertices = Buffer::new(
+            MAX_VERTICES_PER_CHUNK * size_of::<Vertex>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_SRC,
+            MemoryProperties::required(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+            false,
+            size_of::<u32>(),
+        )
+        .context("Vertices buffer creation failed")?;
+        let counter = Buffer::new(
+            size_of::<u32>(),
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE),
+            true,
+            size_of::<u32>(),
+        )
+        .context("Counter buffer creation failed")?;
+        pipeline.set_buffers(&[&input, &vertices, &counter]);
+
+        let mut command_pool = CommandPool::new(queue.family)?;
+        let command_buff = command_pool
+            .alloc_buffers(1, false)
+            .context("Command buffer alloc failed")?
+            .pop()
+            .expect("One command buffer should be allocated");
+        let fence = create_fence(false)?;
+
+        Ok(Self {
+            pipeline,
+            input,
+            vertices,
+            counter,
+            queue,
+            command_buff,
+            _command_pool: command_pool,
+            fence,
+        })
+    }
+
+    /// Return the count of vertices generated. They stay in the mesher until [`ComputeMesher::copy_into`].
+    pub fn mesh(&mut self, chunk: &Chunk, chunks: &RwLock<Chunks>) -> Result<usize> {
+        trace!(target: "meshing", "Mesh chunk {:?} on the GPU", chunk.pos);
+
+        let neighbours = chunk.neighbours(chunks);
+        let blocks = chunk.blocks.read().expect("Lock poisoned");
+        if blocks.solid_blocks_count == 0 || is_buried(&blocks, &neighbours) {
+            return Ok(0);
+        }
+        self.mesh_blocks(&blocks.data, &neighbours)
+    }
+
+    pub fn mesh_blocks(
+        &mut self,
+        blocks: &[BlockId; BLOCKS_PER_CHUNK],
+        neighbours: &[Option<Arc<Chunk>>; 6],
+    ) -> Result<usize> {
+        self.write_input(blocks, neighbours)?;
+        self.counter.data()?[..size_of::<u32>()].copy_from_slice(&0_u32.to_ne_bytes());
+        self.counter.flush().context("Counter flush failed")?;
+
+        let pipeline = &self.pipeline;
+        submit_and_wait(
+            &mut self.command_buff,
+            &self.queue,
+            self.fence,
+            |command_buff| unsafe {
+                pipeline.cmd_dispatch(command_buff, (1, 1, 1));
+                let barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::HOST_READ);
+                DEVICE.cmd_pipeline_barrier(
+                    command_buff,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::HOST,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[] as &[vk::BufferMemoryBarrier],
+                    &[] as &[vk::ImageMemoryBarrier],
+                );
+            },
+        )
+        .context("Mesh dispatch failed")?;
+
+        self.counter
+            .invalidate()
+            .context("Counter invalidation failed")?;
+        let mut count = [0; size_of::<u32>()];
+        count.copy_from_slice(&self.counter.data()?[..size_of::<u32>()]);
+        Ok(u32::from_ne_bytes(count) as usize)
+    }
+
+    /// Copy the `vertices_count` first vertices of the last mesh into `dst`.
+    pub fn copy_into(&mut self, dst: &Buffer, vertices_count: usize) -> Result<()> {
+        let src = self.vertices.buffer;
+        // The vertex input stage can only be waited for on a graphics queue, see the staging meshing threads.
+        let (dst_stage, dst_access) = if self.queue.flags.contains(vk::QueueFlags::GRAPHICS) {
+            (
+                vk::PipelineStageFlags::VERTEX_INPUT,
+                vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            )
+        } else {
+            (
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_READ,
+            )
+        };
+        submit_and_wait(
+            &mut self.command_buff,
+            &self.queue,
+            self.fence,
+            |command_buff| unsafe {
+                let barrier = vk::MemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::SHADER_WRITE)
+                    .dst_access_mask(vk::AccessFlags::TRANSFER_READ);
+                DEVICE.cmd_pipeline_barrier(
+                    command_buff,
+                    vk::PipelineStageFlags::COMPUTE_SHADER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[barrier],
+                    &[] as &[vk::BufferMemoryBarrier],
+                    &[] as &[vk::ImageMemoryBarrier],
+                );
+                let size = (vertices_count * size_of::<Vertex>()) as u64;
+                let region = vk::BufferCopy::builder()
+                    .size(size)
+                    .src_offset(0)
+                    .dst_offset(0);
+                DEVICE.cmd_copy_buffer(command_buff, src, dst.buffer, &[region]);
+                // The vertices are read by the draws of the next frames.
+                let barrier = vk::BufferMemoryBarrier::builder()
+                    .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                    .dst_access_mask(dst_access)
+                    .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+                    .buffer(dst.buffer)
+                    .offset(0)
+                    .size(size);
+                DEVICE.cmd_pipeline_barrier(
+                    command_buff,
+                    vk::PipelineStageFlags::TRANSFER,
+                    dst_stage,
+                    vk::DependencyFlags::empty(),
+                    &[] as &[vk::MemoryBarrier],
+                    &[barrier],
+                    &[] as &[vk::ImageMemoryBarrier],
+                );
+            },
+        )
+        .context("Vertices copy failed")
+    }
+
+    fn write_input(
+        &mut self,
+        blocks: &[BlockId; BLOCKS_PER_CHUNK],
+        neighbours: &[Option<Arc<Chunk>>; 6],
+    ) -> Result<()> {
+        let data = self.input.data()?;
+        let (chunk_data, mut layers_data) = data.split_at_mut(size_of_val(blocks));
+        chunk_data.copy_from_slice(as_bytes(blocks));
+
+        let mut layer = [BlockId::Air; LAYER_SIZE];
+        for (face, neighbour) in neighbours.iter().enumerate() {
+            layer.fill(BlockId::Air);
+            if let Some(neighbour) = neighbour {
+                let blocks = neighbour.blocks.read().expect("Lock poisoned");
+                for a in 0..CHUNK_SIZE {
+                    for b in 0..CHUNK_SIZE {
+                        let pos = layer_pos(face, a as u8, b as u8);
+                        layer[a * CHUNK_SIZE + b] = blocks.data[pos.to_index()];
+                    }
+                }
+            }
+            let (layer_data, rest) = layers_data.split_at_mut(size_of_val(&layer));
+            layer_data.copy_from_slice(as_bytes(&layer));
+            layers_data = rest;
+        }
+
+        self.input.flush().context("Input flush failed")
+    }
+}
+
+impl Drop for ComputeMesher {
+    fn drop(&mut self) {
+        unsafe { DEVICE.destroy_fence(self.fence, None) };
+    }
+}
+
+fn submit_and_wait<C>(
+    command_buff: &mut CommandBuffer,
+    queue: &Queue,
+    fence: vk::Fence,
+    closure: C,
+) -> Result<()>
+where
+    C: FnOnce(vk::CommandBuffer),
+{
+    command_buff.begin()?;
+    closure(**command_buff);
+    command_buff.end()?;
+
+    let buffers = &[**command_buff];
+    let submit_info = vk::SubmitInfo::builder().command_buffers(buffers);
+    unsafe {
+        DEVICE
+            .queue_submit(**queue, &[submit_info], fence)
+            .context("Queue submit failed")?;
+        DEVICE
+            .wait_for_fences(&[fence], true, u64::MAX)
+            .context("Failed waiting for fence")?;
+        DEVICE
+            .reset_fences(&[fence])
+            .context("Failed to reset fence")?;
+    }
+    Ok(())
+}
+
+/// Position in the neighbour at `face` of the block touching the chunk.
+/// `a` and `b` are the two other coordinates, in the `x, y, z` order.
+#[inline(always)]
+fn layer_pos(face: usize, a: u8, b: u8) -> LocalBlockPos {
+    const LAST: u8 = CHUNK_SIZE as u8 - 1;
+    match face {
+        0 => LocalBlockPos::new(0, a, b),
+        1 => LocalBlockPos::new(LAST, a, b),
+        2 => LocalBlockPos::new(a, 0, b),
+        3 => LocalBlockPos::new(a, LAST, b),
+        4 => LocalBlockPos::new(a, b, 0),
+        5 => LocalBlockPos::new(a, b, LAST),
+        _ => unreachable!(),
+    }
+}
+
+#[inline(always)]
+fn as_bytes(blocks: &[BlockId]) -> &[u8] {
+    unsafe { slice::from_raw_parts(blocks.as_ptr() as *const u8, size_of_val(blocks)) }
+}
+
+#[cfg(test)]
+mod tests {
+    use test::Bencher;
+
+    use crate::{
+        render::QUEUES,
+        world::{chunk::ChunkBlocks, chunk_mesh},
+    };
+
+    use super::*;
+
+    fn create_mesher() -> ComputeMesher {
+        let queue = QUEUES
+            .fetch_queue(vk::QueueFlags::COMPUTE)
+            .expect("No queue left");
+        ComputeMesher::new(queue).expect("Compute mesher creation failed")
+    }
+
+    /// Same pattern as the `mesh` bench.
+    fn bench_blocks() -> Box<[BlockId; BLOCKS_PER_CHUNK]> {
+        let mut blocks = Box::new([BlockId::Air; BLOCKS_PER_CHUNK]);
+        for (i, block) in blocks.iter_mut().enumerate() {
+            if i % 4 == 0 {
+                *block = BlockId::Block;
+            }
+        }
+        blocks
+    }
+
+    #[test]
+    fn same_vertices_count_as_cpu() {
+        let mut mesher = create_mesher();
+        let neighbours = [None, None, None, None, None, None];
+        let mut buff = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+
+        let mut full = Box::new([BlockId::Block; BLOCKS_PER_CHUNK]);
+        let mut sparse = Box::new([BlockId::Air; BLOCKS_PER_CHUNK]);
+        let mut random = 0x1234_5678_u32;
+        for block in sparse.iter_mut() {
+            random ^= random << 13;
+            random ^= random >> 17;
+            random ^= random << 5;
+            if random % 3 == 0 {
+                *block = BlockId::Block;
+            } else if random % 7 == 0 {
+                // Faces of different blocks aren't merged.
+                *block = BlockId::Bedrock;
+            }
+        }
+        full[0] = BlockId::Air;
+
+        for blocks in [bench_blocks(), full, sparse] {
+            let mut chunk_blocks = Box::<ChunkBlocks>::default();
+            chunk_blocks.data = *blocks;
+            let expected = chunk_mesh::mesh(
+                &chunk_blocks,
+                &neighbours,
+                &mut buff,
+                MAX_VERTICES_PER_CHUNK,
+                0,
+            )
+            .count;
+            let count = mesher
+                .mesh_blocks(&blocks, &neighbours)
+                .expect("Compute meshing failed");
+            assert_eq!(count, expected);
+        }
+    }
+
+    #[bench]
+    fn compute_mesh(b: &mut Bencher) {
+        let mut mesher = create_mesher();
+        let blocks = bench_blocks();
+        let neighbours = [None, None, None, None, None, None];
+
+        b.iter(|| {
+            mesher
+                .mesh_blocks(&blocks, &neighbours)
+                .expect("Compute meshing failed")
+        })
+    }
+}
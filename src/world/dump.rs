@@ -0,0 +1,187 @@
+//! Text dumps of the blocks of chunks, to share the data reproducing a generation or meshing bug.
+//!
+//! A dump is a list of chunks, each one written as:
+//! - `chunk <x> <y> <z>`
+//! - `solid_blocks_count <count>`
+//! - for each layer from the bottom, `y <y>` then a line of `x` for each `z`, with a character per block:
+//!   `.` for air, `#` for a block, `=` for bedrock and `i` for a torch.
+
+use std::fmt::{self, Display};
+
+#[cfg(test)]
+use anyhow::{bail, ensure, Context, Result};
+
+use super::{blocks::BlockId, chunk::Chunk, ChunkPos, LocalBlockPos, BLOCKS_PER_CHUNK, CHUNK_SIZE};
+
+/// The blocks of a chunk, in the layout of `ChunkBlocks::data`.
+#[derive(Debug, PartialEq)]
+pub struct ChunkDump {
+    pub pos: ChunkPos,
+    pub solid_blocks_count: u32,
+    pub blocks: Box<[BlockId; BLOCKS_PER_CHUNK]>,
+}
+
+impl ChunkDump {
+    /// Return `None` if the chunk isn't generated yet.
+    pub fn new(chunk: &Chunk) -> Option<Self> {
+        let blocks = chunk.blocks.read().expect("Lock poisoned");
+        blocks.generated.then(|| Self {
+            pos: chunk.pos,
+            solid_blocks_count: blocks.solid_blocks_count,
+            blocks: Box::new(blocks.data),
+        })
+    }
+
+    /// Parse the chunks of a dump.
+    #[cfg(test)]
+    pub fn parse_all(content: &str) -> Result<Vec<Self>> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let mut dumps = Vec::new();
+        while let Some((i, line)) = lines.next() {
+            let mut next = |expected: &str| {
+                lines
+                    .next()
+                    .with_context(|| format!("Expected {} after line {}", expected, i + 1))
+            };
+            let pos = match parse_values(line, "chunk")?[..] {
+                [x, y, z] => ChunkPos::new(x, y, z),
+                _ => bail!("Expected the chunk position at line {}", i + 1),
+            };
+            let (count_line, line) = next("the solid blocks count")?;
+            let solid_blocks_count = match parse_values(line, "solid_blocks_count")?[..] {
+                [count] => u32::try_from(count).context("Invalid solid blocks count")?,
+                _ => bail!("Expected the solid blocks count at line {}", count_line + 1),
+            };
+
+            let mut blocks = Box::new([BlockId::Air; BLOCKS_PER_CHUNK]);
+            for y in 0..CHUNK_SIZE as u8 {
+                let (layer_line, line) = next("a layer")?;
+                ensure!(
+                    parse_values(line, "y")? == [y as i64],
+                    "Expected layer {} at line {}",
+                    y,
+                    layer_line + 1
+                );
+                for z in 0..CHUNK_SIZE as u8 {
+                    let (row_line, row) = next("a row")?;
+                    let row = row.trim().as_bytes();
+                    ensure!(
+                        row.len() == CHUNK_SIZE,
+                        "Expected {} blocks at line {}",
+                        CHUNK_SIZE,
+                        row_line + 1
+                    );
+                    for (x, &c) in row.iter().enumerate() {
+                        blocks[LocalBlockPos::new(x as u8, y, z).to_index()] =
+                            block_from_char(c)
+                                .with_context(|| format!("At line {}", row_line + 1))?;
+                    }
+                }
+            }
+            dumps.push(Self {
+                pos,
+                solid_blocks_count,
+                blocks,
+            });
+        }
+        Ok(dumps)
+    }
+}
+
+impl Display for ChunkDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "chunk {}", self.pos)?;
+        writeln!(f, "solid_blocks_count {}", self.solid_blocks_count)?;
+        for y in 0..CHUNK_SIZE as u8 {
+            writeln!(f, "y {}", y)?;
+            for z in 0..CHUNK_SIZE as u8 {
+                let row = (0..CHUNK_SIZE as u8)
+                    .map(|x| block_char(self.blocks[LocalBlockPos::new(x, y, z).to_index()]))
+                    .collect::<String>();
+                writeln!(f, "{}", row)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The integers after `keyword` on `line`.
+#[cfg(test)]
+fn parse_values(line: &str, keyword: &str) -> Result<Vec<i64>> {
+    let mut words = line.split_whitespace();
+    ensure!(
+        words.next() == Some(keyword),
+        "Expected `{}`, found `{}`",
+        keyword,
+        line
+    );
+    words
+        .map(|word| {
+            word.parse()
+                .with_context(|| format!("Invalid number `{}`", word))
+        })
+        .collect()
+}
+
+fn block_char(block: BlockId) -> char {
+    match block {
+        BlockId::Air => '.',
+        BlockId::Block => '#',
+        BlockId::Bedrock => '=',
+        BlockId::Torch => 'i',
+    }
+}
+
+#[cfg(test)]
+fn block_from_char(c: u8) -> Result<BlockId> {
+    Ok(match c {
+        b'.' => BlockId::Air,
+        b'#' => BlockId::Block,
+        b'=' => BlockId::Bedrock,
+        b'i' => BlockId::Torch,
+        _ => bail!("Unknown block `{}`", c.escape_ascii()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dump_round_trip() {
+        let chunk = Chunk::new(ChunkPos::new(-3, 0, 7));
+        assert_eq!(ChunkDump::new(&chunk), None);
+
+        {
+            let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+            blocks.generated = true;
+            for (i, block) in [BlockId::Block, BlockId::Bedrock, BlockId::Torch]
+                .into_iter()
+                .enumerate()
+            {
+                blocks.data[LocalBlockPos::new(i as u8, 31, 5).to_index()] = block;
+            }
+            blocks.solid_blocks_count = 3;
+        }
+        let dump = ChunkDump::new(&chunk).expect("Generated chunk");
+        let other = ChunkDump {
+            pos: ChunkPos::new(0, 1, 0),
+            solid_blocks_count: 0,
+            blocks: Box::new([BlockId::Air; BLOCKS_PER_CHUNK]),
+        };
+        let content = format!("{}\n{}", dump, other);
+        assert!(content.starts_with("chunk -3 0 7\nsolid_blocks_count 3\ny 0\n"));
+        assert_eq!(
+            ChunkDump::parse_all(&content).expect("Valid dump"),
+            [dump, other]
+        );
+
+        assert!(ChunkDump::parse_all("chunk 0 0\nsolid_blocks_count 0").is_err());
+        let truncated = &content[..content.len() / 3];
+        assert!(ChunkDump::parse_all(truncated).is_err());
+        assert!(ChunkDump::parse_all(&content.replacen('#', "?", 1)).is_err());
+    }
+}
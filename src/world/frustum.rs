@@ -0,0 +1,50 @@
+use nalgebra_glm::{Mat4, Vec4};
+
+use super::Aabb;
+
+/// The volume seen by a camera, as 6 planes facing inward.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    /// `(a, b, c, d)` where a point `p` is in front of the plane if `a*p.x + b*p.y + c*p.z + d >= 0`.
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extract the planes of a view projection matrix with a Vulkan depth range (`0..=1`).
+    pub fn from_view_proj(view_proj: &Mat4) -> Self {
+        let row = |i: usize| view_proj.row(i).transpose();
+        Self {
+            planes: [
+                row(3) + row(0),
+                row(3) - row(0),
+                row(3) + row(1),
+                row(3) - row(1),
+                row(2),
+                row(3) - row(2),
+            ],
+        }
+    }
+
+    /// Conservative: a box near a corner of the frustum can be reported as intersecting.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner the most in front of the plane.
+            let x = if plane.x >= 0. {
+                aabb.max.x
+            } else {
+                aabb.min.x
+            };
+            let y = if plane.y >= 0. {
+                aabb.max.y
+            } else {
+                aabb.min.y
+            };
+            let z = if plane.z >= 0. {
+                aabb.max.z
+            } else {
+                aabb.min.z
+            };
+            plane.x * x + plane.y * y + plane.z * z + plane.w >= 0.
+        })
+    }
+}
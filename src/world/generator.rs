@@ -9,36 +9,58 @@ use std::{
 
 use anyhow::{Context, Result};
 use crossbeam_channel::{Receiver, Sender};
-use log::warn;
+use log::{info, warn};
 use mini_moka::sync::Cache;
 use noise::{Fbm, MultiFractal, NoiseFn, Perlin};
 
-use crate::{gui, world::LocalBlockPos};
+use crate::{
+    gui,
+    options::{AppOptions, GenerationOptions, WorldGenMode},
+    utils::clamp_threads_count,
+    world::LocalBlockPos,
+};
 
+#[cfg(feature = "skylight")]
+use super::light::{self, SkyLight};
 use super::{
-    blocks::BlockId, chunk::Chunk, chunks::Chunks, ChunkPos, FlatChunkPos, BLOCKS_PER_CHUNK,
-    CHUNK_SIZE,
+    blocks::BlockId,
+    chunk::{Chunk, ChunkBlocks},
+    chunks::Chunks,
+    observers::ChunkEvent,
+    ChunkPos, FlatChunkPos, BLOCKS_PER_CHUNK, CHUNK_SIZE, WORKERS_PAUSE,
 };
 
-pub const THREADS_COUNT: usize = 2;
-const MAX_HEIGHT_MAPS_CACHE: usize = 4096;
-
 pub type Message = Weak<Chunk>;
 
 static EXIT: AtomicBool = AtomicBool::new(false);
 static HANDLES: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+/// The cache of the running threads, also read by the minimap.
+static HEIGHT_MAPS: RwLock<Option<Cache<FlatChunkPos, HeightMap>>> = RwLock::new(None);
 
 pub fn create_sender() -> (Sender<Message>, Receiver<Message>) {
     crossbeam_channel::unbounded()
 }
 
-pub fn start_threads(seed: u32, receiver: Receiver<Message>, chunks: &Arc<RwLock<Chunks>>) {
+/// The threads count from the options.
+pub fn threads_count() -> usize {
+    clamp_threads_count(AppOptions::get().generator_threads, "generator")
+}
+
+pub fn start_threads(
+    seed: u32,
+    threads_count: usize,
+    receiver: Receiver<Message>,
+    chunks: &Arc<RwLock<Chunks>>,
+) {
     let mut handles = HANDLES.lock().expect("Mutex poisoned");
-    handles.reserve(THREADS_COUNT);
+    handles.reserve(threads_count);
+    EXIT.store(false, Ordering::Relaxed);
 
-    let cache = Cache::new(MAX_HEIGHT_MAPS_CACHE as u64);
+    let options = AppOptions::get().generation;
+    let cache = Cache::new(options.height_maps_cache_capacity);
+    *HEIGHT_MAPS.write().expect("Lock poisoned") = Some(cache.clone());
 
-    for i in 0..THREADS_COUNT {
+    for i in 0..threads_count {
         let receiver = receiver.clone();
         let chunks = Arc::clone(chunks);
         let cache = cache.clone();
@@ -46,15 +68,28 @@ pub fn start_threads(seed: u32, receiver: Receiver<Message>, chunks: &Arc<RwLock
             .name(format!("Generator {}", i))
             .spawn(move || {
                 #[allow(clippy::unwrap_used)]
-                thread_main(seed, receiver, chunks, cache).unwrap()
+                thread_main(seed, options, receiver, chunks, cache).unwrap()
             })
             .expect("Thread spawn failed");
         handles.push(handle);
     }
 }
 
+/// A generator for a thread other than the generator ones, sharing their height maps cache.
+/// [`start_threads`] must have been called, with any threads count.
+pub(super) fn shared_generator(seed: u32) -> Generator {
+    let options = AppOptions::get().generation;
+    let cache = HEIGHT_MAPS
+        .read()
+        .expect("Lock poisoned")
+        .clone()
+        .expect("Generator threads not started");
+    Generator::new(seed, options, cache)
+}
+
 pub fn stop_threads(sender: &Sender<Message>) {
     EXIT.store(true, Ordering::Relaxed);
+    *HEIGHT_MAPS.write().expect("Lock poisoned") = None;
     let mut handles = HANDLES.lock().expect("Mutex poisoned");
     for _ in 0..handles.len() {
         let _ = sender.send(Weak::new());
@@ -69,85 +104,207 @@ pub fn stop_threads(sender: &Sender<Message>) {
 
 fn thread_main(
     seed: u32,
+    options: GenerationOptions,
     receiver: Receiver<Message>,
     chunks: Arc<RwLock<Chunks>>,
     height_maps_cache: Cache<FlatChunkPos, HeightMap>,
 ) -> Result<()> {
-    let generator = Generator::new(seed, height_maps_cache);
+    let generator = Generator::new(seed, options, height_maps_cache);
 
     while !EXIT.load(Ordering::Relaxed) {
         let chunk = receiver.recv().context("Channel disconnected")?;
+        WORKERS_PAUSE.wait();
         if let Some(chunk) = chunk.upgrade() {
-            let mut blocks_lock = chunk.blocks.write().expect("Lock poisoned");
-            let solid_blocks_count = generator.generate(&chunk.pos, &mut blocks_lock.data);
-            blocks_lock.solid_blocks_count = solid_blocks_count;
-            drop(blocks_lock);
-            if solid_blocks_count == 0 {
-                continue;
-            }
-            chunks
-                .read()
-                .expect("Lock poisoned")
-                .chunk_generated(&chunk);
-            let data = gui::DATA.read().expect("Lock poisoned");
-            data.generated_chunks_total.fetch_add(1, Ordering::Relaxed);
-            data.generated_chunks.fetch_add(1, Ordering::Relaxed);
+            generator.process(&chunk, &chunks);
         }
     }
 
     Ok(())
 }
 
-type HeightMap = [u32; CHUNK_SIZE * CHUNK_SIZE];
+/// Generate the chunks on the calling thread, without going through the generator threads.
+/// Unlike the threads, the current generation options are used.
+pub fn generate_sync(seed: u32, chunks: &[Arc<Chunk>]) {
+    let options = AppOptions::get().generation;
+    let generator = Generator::new(
+        seed,
+        options,
+        Cache::new(options.height_maps_cache_capacity),
+    );
+    for chunk in chunks {
+        let mut blocks_lock = chunk.blocks.write().expect("Lock poisoned");
+        generator.fill(&chunk.pos, &mut blocks_lock);
+    }
+}
+
+/// Surface height of each column, in blocks, indexed by `x * CHUNK_SIZE + z`.
+pub type HeightMap = [i64; CHUNK_SIZE * CHUNK_SIZE];
+
+/// The height map of a column if the generator threads have it cached.
+/// Only the `WorldGenMode::Noise` mode uses height maps.
+pub fn cached_height_map(pos: FlatChunkPos) -> Option<HeightMap> {
+    HEIGHT_MAPS
+        .read()
+        .expect("Lock poisoned")
+        .as_ref()?
+        .get(&pos)
+}
 
 #[derive(Debug)]
-struct Generator {
+pub(super) struct Generator {
     noise: Fbm<Perlin>,
+    options: GenerationOptions,
     height_maps_cache: Cache<FlatChunkPos, HeightMap>,
 }
 
 impl Generator {
-    fn new(seed: u32, height_maps_cache: Cache<FlatChunkPos, HeightMap>) -> Self {
+    pub(super) fn new(
+        seed: u32,
+        options: GenerationOptions,
+        height_maps_cache: Cache<FlatChunkPos, HeightMap>,
+    ) -> Self {
         Self {
             noise: Fbm::new(seed).set_frequency(0.001),
+            options,
             height_maps_cache,
         }
     }
 
-    /// Return the solid blocks count.
-    fn generate(&self, pos: &ChunkPos, blocks: &mut [BlockId; BLOCKS_PER_CHUNK]) -> u32 {
-        let map = self.get_height_map(&pos.flat());
+    /// Generate the blocks of `chunk` then send it to the meshing threads if it has visible faces.
+    pub(super) fn process(&self, chunk: &Arc<Chunk>, chunks: &RwLock<Chunks>) {
+        let mut blocks_lock = chunk.blocks.write().expect("Lock poisoned");
+        self.fill(&chunk.pos, &mut blocks_lock);
+        let solid_blocks_count = blocks_lock.solid_blocks_count;
+        drop(blocks_lock);
+        if chunk.regenerating.swap(false, Ordering::Relaxed) {
+            info!(
+                "Chunk {} regenerated: {} solid blocks",
+                chunk.pos, solid_blocks_count
+            );
+        }
+        let hidden = solid_blocks_count == 0 || chunk.is_occluded(chunks);
+        // Everything under one read of the chunks.
+        let chunks = chunks.read().expect("Lock poisoned");
+        chunks.observers.notify(ChunkEvent::Generated(chunk.pos));
+        chunks.release_waiting_neighbours(chunk.pos);
+        if hidden {
+            return;
+        }
+        chunks.chunk_generated(chunk);
+        drop(chunks);
+        let data = gui::DATA.read().expect("Lock poisoned");
+        data.generated_chunks_total.fetch_add(1, Ordering::Relaxed);
+        data.generated_chunks.fetch_add(1, Ordering::Relaxed);
+    }
 
-        let chunk_floor = pos.y() * CHUNK_SIZE as i64;
+    /// Generate the blocks of the chunk at `pos`, with their sky light.
+    fn fill(&self, pos: &ChunkPos, blocks: &mut ChunkBlocks) {
+        blocks.solid_blocks_count = self.generate(pos, &mut blocks.data);
+        blocks.generated = true;
+        #[cfg(feature = "skylight")]
+        {
+            blocks.sky_light = self.sky_light(pos, &blocks.data);
+        }
+    }
 
-        let mut solid_blocks = 0;
+    /// Return the solid blocks count.
+    fn generate(&self, pos: &ChunkPos, blocks: &mut [BlockId; BLOCKS_PER_CHUNK]) -> u32 {
+        let Some(bedrock_height) = self.options.bedrock_height else {
+            return self.generate_terrain(pos, blocks);
+        };
+        let chunk_floor = pos.y().saturating_mul(CHUNK_SIZE as i64);
+        let bedrock_layers = bedrock_height
+            .saturating_sub(chunk_floor)
+            .clamp(0, CHUNK_SIZE as i64) as usize;
+        // Entirely below the bedrock level, the terrain isn't generated.
+        if bedrock_layers == CHUNK_SIZE {
+            blocks.fill(BlockId::Bedrock);
+            return BLOCKS_PER_CHUNK as u32;
+        }
 
+        let mut solid_blocks = self.generate_terrain(pos, blocks);
         for x in 0..CHUNK_SIZE {
-            for z in 0..CHUNK_SIZE {
-                let mut y = 0;
-                while y < CHUNK_SIZE && (chunk_floor + y as i64) < map[x * CHUNK_SIZE + z] as i64 {
-                    let pos = LocalBlockPos::new(x as u8, y as u8, z as u8);
-                    blocks[pos.to_index()] = BlockId::Block;
+            for y in 0..bedrock_layers {
+                for z in 0..CHUNK_SIZE {
+                    let block =
+                        &mut blocks[LocalBlockPos::new(x as u8, y as u8, z as u8).to_index()];
+                    if *block == BlockId::Air {
+                        solid_blocks += 1;
+                    }
+                    *block = BlockId::Bedrock;
+                }
+            }
+        }
+        solid_blocks
+    }
 
-                    solid_blocks += 1;
-                    y += 1;
+    /// Return the solid blocks count.
+    fn generate_terrain(&self, pos: &ChunkPos, blocks: &mut [BlockId; BLOCKS_PER_CHUNK]) -> u32 {
+        match self.options.mode {
+            WorldGenMode::Noise => {
+                let map = self.get_height_map(&pos.flat());
+                fill_columns(pos, blocks, |x, z| map[x * CHUNK_SIZE + z])
+            }
+            WorldGenMode::Flat { height } => fill_columns(pos, blocks, |_, _| height),
+            WorldGenMode::Checkerboard => {
+                // Same pattern as the `mesh` bench.
+                let mut solid_blocks = 0;
+                for (i, block) in blocks.iter_mut().enumerate() {
+                    if i % 4 == 0 {
+                        *block = BlockId::Block;
+                        solid_blocks += 1;
+                    }
                 }
+                solid_blocks
             }
+            WorldGenMode::Empty => 0,
         }
+    }
 
-        solid_blocks
+    /// The sky light of the generated blocks, from the same heights as the terrain.
+    #[cfg(feature = "skylight")]
+    fn sky_light(&self, pos: &ChunkPos, blocks: &[BlockId; BLOCKS_PER_CHUNK]) -> SkyLight {
+        match self.options.mode {
+            WorldGenMode::Noise => {
+                // Looked up by the terrain just before, not counted again in the cache statistics.
+                let map = self
+                    .height_maps_cache
+                    .get(&pos.flat())
+                    .unwrap_or_else(|| self.create_height_map(&pos.flat()));
+                light::column_sky_light(pos, blocks, |x, z| map[x * CHUNK_SIZE + z])
+            }
+            WorldGenMode::Flat { height } => light::column_sky_light(pos, blocks, |_, _| height),
+            // Not made of columns, the air is lit as if nothing was above it.
+            WorldGenMode::Checkerboard | WorldGenMode::Empty => {
+                light::column_sky_light(pos, blocks, |_, _| i64::MIN)
+            }
+        }
     }
 
+    /// The cache is keyed by the flat position so all the chunks of a column share the same entry
+    /// and moving vertically doesn't create new height maps.
     fn get_height_map(&self, pos: &FlatChunkPos) -> HeightMap {
-        self.height_maps_cache.get(pos).unwrap_or_else(|| {
-            let map = self.create_height_map(pos);
-            self.height_maps_cache.insert(*pos, map);
-            map
-        })
+        if let Some(map) = self.height_maps_cache.get(pos) {
+            let data = gui::DATA.read().expect("Lock poisoned");
+            data.height_maps_cache_hits.fetch_add(1, Ordering::Relaxed);
+            return map;
+        }
+
+        let map = self.create_height_map(pos);
+        self.height_maps_cache.insert(*pos, map);
+        // Counted once generated, the noise isn't computed under the lock.
+        let data = gui::DATA.read().expect("Lock poisoned");
+        data.height_maps_cache_misses
+            .fetch_add(1, Ordering::Relaxed);
+        data.height_maps_cache_size.store(
+            self.height_maps_cache.entry_count() as usize,
+            Ordering::Relaxed,
+        );
+        map
     }
 
     fn create_height_map(&self, pos: &FlatChunkPos) -> HeightMap {
-        let mut map: [MaybeUninit<u32>; CHUNK_SIZE * CHUNK_SIZE] = MaybeUninit::uninit_array();
+        let mut map: [MaybeUninit<i64>; CHUNK_SIZE * CHUNK_SIZE] = MaybeUninit::uninit_array();
         let off = (
             (pos.x() * CHUNK_SIZE as i64) as f64,
             (pos.z() * CHUNK_SIZE as i64) as f64,
@@ -157,7 +314,7 @@ impl Generator {
                 let val = self.noise.get([off.0 + x as f64, off.1 + z as f64]);
                 // scale from [-1; 1] to [0; 1]
                 let val = (val + 1.) / 2.;
-                let val = (val * 100.) as u32 + 50;
+                let val = (val * self.options.amplitude as f64) as i64 + self.options.base_height;
                 map[x * CHUNK_SIZE + z].write(val);
             }
         }
@@ -166,6 +323,34 @@ impl Generator {
     }
 }
 
+/// Fill each column of the chunk with solid blocks up to the height (in blocks) given by `height_at(x, z)`.
+/// Return the solid blocks count.
+fn fill_columns(
+    pos: &ChunkPos,
+    blocks: &mut [BlockId; BLOCKS_PER_CHUNK],
+    height_at: impl Fn(usize, usize) -> i64,
+) -> u32 {
+    let chunk_floor = pos.y().saturating_mul(CHUNK_SIZE as i64);
+
+    let mut solid_blocks = 0;
+
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            // The surface may be far above or below the chunk.
+            let filled = height_at(x, z)
+                .saturating_sub(chunk_floor)
+                .clamp(0, CHUNK_SIZE as i64) as usize;
+            for y in 0..filled {
+                let pos = LocalBlockPos::new(x as u8, y as u8, z as u8);
+                blocks[pos.to_index()] = BlockId::Block;
+            }
+            solid_blocks += filled as u32;
+        }
+    }
+
+    solid_blocks
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::SystemTime;
@@ -173,16 +358,19 @@ mod tests {
     use test::Bencher;
 
     use super::*;
+    use crate::world::WORKERS_TEST_LOCK;
 
     #[bench]
     fn generate(b: &mut Bencher) {
         let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
-        let cache = Cache::new(MAX_HEIGHT_MAPS_CACHE as u64);
+        let options = GenerationOptions::new();
+        let cache = Cache::new(options.height_maps_cache_capacity);
         let generator = Generator::new(
             SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .expect("Time went backwards")
                 .as_secs() as u32,
+            options,
             cache,
         );
         let mut x = (generator.noise.get([0., 0.]) * 100.) as i64;
@@ -195,4 +383,168 @@ mod tests {
             z += 1;
         })
     }
+
+    fn generate_with(options: GenerationOptions, pos: ChunkPos) -> u32 {
+        let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
+        let generator = Generator::new(0, options, Cache::new(options.height_maps_cache_capacity));
+        generator.generate(&pos, &mut blocks)
+    }
+
+    #[test]
+    fn extreme_amplitudes() {
+        let options = GenerationOptions {
+            base_height: -1_000_000,
+            amplitude: 2_000_000,
+            ..GenerationOptions::new()
+        };
+        // Whatever the noise, the surface is between -1M and 1M.
+        assert_eq!(generate_with(options, ChunkPos::new(0, 100_000, 0)), 0);
+        assert_eq!(
+            generate_with(options, ChunkPos::new(0, -100_000, 0)),
+            BLOCKS_PER_CHUNK as u32
+        );
+        assert_eq!(generate_with(options, ChunkPos::new(0, i64::MAX, 0)), 0);
+        assert_eq!(
+            generate_with(options, ChunkPos::new(0, i64::MIN, 0)),
+            BLOCKS_PER_CHUNK as u32
+        );
+    }
+
+    #[test]
+    fn far_surface_crossing_chunk() {
+        let columns = (CHUNK_SIZE * CHUNK_SIZE) as u32;
+
+        let high = GenerationOptions {
+            base_height: 5000,
+            amplitude: 0,
+            ..GenerationOptions::new()
+        };
+        // 5000 = 156 * 32 + 8
+        assert_eq!(generate_with(high, ChunkPos::new(3, 156, -2)), 8 * columns);
+        assert_eq!(generate_with(high, ChunkPos::new(3, 155, -2)), 32 * columns);
+        assert_eq!(generate_with(high, ChunkPos::new(3, 157, -2)), 0);
+
+        let low = GenerationOptions {
+            base_height: -5000,
+            amplitude: 0,
+            ..GenerationOptions::new()
+        };
+        // -5000 = -157 * 32 + 24
+        assert_eq!(generate_with(low, ChunkPos::new(-1, -157, 7)), 24 * columns);
+        assert_eq!(generate_with(low, ChunkPos::new(-1, -156, 7)), 0);
+    }
+
+    #[test]
+    fn height_maps_cache_counters() {
+        let options = GenerationOptions::new();
+        let generator = Generator::new(0, options, Cache::new(options.height_maps_cache_capacity));
+        let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
+        let mut generate = |x, y, z| generator.generate(&ChunkPos::new(x, y, z), &mut blocks);
+        // The other tests generate chunks too, the counters can only grow more.
+        let counters = || {
+            let data = gui::DATA.read().expect("Lock poisoned");
+            (
+                data.height_maps_cache_hits.load(Ordering::Relaxed),
+                data.height_maps_cache_misses.load(Ordering::Relaxed),
+            )
+        };
+        let (hits, misses) = counters();
+
+        generate(0, 0, 0);
+        // Same column
+        generate(0, 3, 0);
+        generate(0, -8, 0);
+        generate(1, 0, 0);
+        generate(1, 1, 0);
+
+        let (new_hits, new_misses) = counters();
+        assert!(new_misses - misses >= 2);
+        assert!(new_hits - hits >= 3);
+        // One entry per column.
+        let cached = |x| {
+            let pos = ChunkPos::new(x, 0, 0).flat();
+            generator.height_maps_cache.contains_key(&pos)
+        };
+        assert!(cached(0) && cached(1) && !cached(2));
+    }
+
+    #[test]
+    fn debug_modes() {
+        let columns = (CHUNK_SIZE * CHUNK_SIZE) as u32;
+        let with_mode = |mode| GenerationOptions {
+            mode,
+            ..GenerationOptions::new()
+        };
+
+        let flat = with_mode(WorldGenMode::Flat { height: 40 });
+        assert_eq!(generate_with(flat, ChunkPos::new(5, 0, -3)), 32 * columns);
+        assert_eq!(generate_with(flat, ChunkPos::new(5, 1, -3)), 8 * columns);
+        assert_eq!(generate_with(flat, ChunkPos::new(5, 2, -3)), 0);
+
+        let checkerboard = with_mode(WorldGenMode::Checkerboard);
+        for y in [-100, 0, 100] {
+            assert_eq!(
+                generate_with(checkerboard, ChunkPos::new(0, y, 0)),
+                BLOCKS_PER_CHUNK as u32 / 4
+            );
+        }
+
+        let empty = with_mode(WorldGenMode::Empty);
+        assert_eq!(generate_with(empty, ChunkPos::new(0, -100, 0)), 0);
+    }
+
+    #[test]
+    fn bedrock_floor() {
+        let options = GenerationOptions {
+            mode: WorldGenMode::Flat { height: 40 },
+            bedrock_height: Some(36),
+            ..GenerationOptions::new()
+        };
+        let generator = Generator::new(0, options, Cache::new(options.height_maps_cache_capacity));
+        let columns = (CHUNK_SIZE * CHUNK_SIZE) as u32;
+
+        // 36 = 32 + 4: bedrock then the flat terrain up to 40.
+        let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
+        assert_eq!(
+            generator.generate(&ChunkPos::new(2, 1, -4), &mut blocks),
+            8 * columns
+        );
+        let block_at = |y| blocks[LocalBlockPos::new(7, y, 12).to_index()];
+        assert_eq!(block_at(0), BlockId::Bedrock);
+        assert_eq!(block_at(3), BlockId::Bedrock);
+        assert_eq!(block_at(4), BlockId::Block);
+        assert_eq!(block_at(7), BlockId::Block);
+        assert_eq!(block_at(8), BlockId::Air);
+
+        let mut blocks = [BlockId::Air; BLOCKS_PER_CHUNK];
+        assert_eq!(
+            generator.generate(&ChunkPos::new(2, -3, -4), &mut blocks),
+            BLOCKS_PER_CHUNK as u32
+        );
+        assert!(blocks.iter().all(|&block| block == BlockId::Bedrock));
+
+        // Air below the bedrock level is filled too.
+        let empty = GenerationOptions {
+            mode: WorldGenMode::Empty,
+            ..options
+        };
+        assert_eq!(generate_with(empty, ChunkPos::new(2, 1, -4)), 4 * columns);
+    }
+
+    #[test]
+    fn start_stop_threads() {
+        let _workers = WORKERS_TEST_LOCK.lock().expect("Mutex poisoned");
+        let chunks = Chunks::new();
+        let (sender, receiver) = create_sender();
+        start_threads(0, 3, receiver, &chunks);
+        assert_eq!(HANDLES.lock().expect("Mutex poisoned").len(), 3);
+
+        let chunk = Arc::new(Chunk::new(ChunkPos::new(0, 0, 0)));
+        sender
+            .send(Arc::downgrade(&chunk))
+            .expect("Sender disconnected");
+
+        stop_threads(&sender);
+        assert!(HANDLES.lock().expect("Mutex poisoned").is_empty());
+    }
 }
@@ -0,0 +1,407 @@
+//! Block light: emitted by some blocks (see [`BlockId::light_emission`]) and spread through the air,
+//! losing one level per block.
+//!
+//! With the `skylight` feature, sky light too: the air above the surface is fully lit and the sky light
+//! spreads like the block light, except that the full level goes down without loss. The generator sets it
+//! from the height map, see [`column_sky_light`].
+//!
+//! Only edits change the light, incrementally from the edited block with two BFS queues per kind of light:
+//! - removal: starting from the edited block, the blocks lit by the one they were reached from (darker, or
+//!   full below a full sky block) are cleared since their light may have come from it. The other lit blocks
+//!   met on the way are queued for spreading.
+//! - spreading: each queued block lights its air neighbours if they are darker than what it gives them.
+//!
+//! For the block light, a level decreases at each step so an update stays within `MAX_LIGHT` blocks of the
+//! edit: each queue visits at most the ~4k blocks of this diamond, whatever the count of sources around.
+//! The sky light isn't bounded downwards: placing a block above a shaft darkens the whole shaft, which is
+//! why it is behind a feature. Unloaded chunks stop the light, and the chunks generated after an edit
+//! ignore it.
+
+use std::collections::{HashSet, VecDeque};
+
+use super::{
+    blocks::BlockId, chunk_mesh::ADDENDS, chunks::Chunks, BlockPos, ChunkPos, BLOCKS_PER_CHUNK,
+};
+#[cfg(feature = "skylight")]
+use super::{LocalBlockPos, CHUNK_SIZE};
+
+pub const MAX_LIGHT: u8 = 15;
+
+/// One level per block, 4 bits each, indexed like the blocks.
+#[derive(Debug, Clone)]
+pub struct LightLevels([u8; BLOCKS_PER_CHUNK / 2]);
+
+impl LightLevels {
+    #[inline]
+    pub fn new() -> Self {
+        Self([0; BLOCKS_PER_CHUNK / 2])
+    }
+
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> u8 {
+        (self.0[index / 2] >> (index % 2 * 4)) & 0xf
+    }
+
+    #[inline(always)]
+    pub fn set(&mut self, index: usize, level: u8) {
+        debug_assert!(level <= MAX_LIGHT);
+        let shift = index % 2 * 4;
+        let byte = &mut self.0[index / 2];
+        *byte = (*byte & !(0xf << shift)) | level << shift;
+    }
+}
+
+/// The sky light of a chunk. The levels are only stored for the chunks crossed by the surface or by the
+/// shade of an edit, the others are entirely lit or dark.
+#[cfg(feature = "skylight")]
+#[derive(Debug, Clone)]
+pub enum SkyLight {
+    Dark,
+    Full,
+    Levels(Box<LightLevels>),
+}
+
+#[cfg(feature = "skylight")]
+impl SkyLight {
+    #[inline(always)]
+    pub fn get(&self, index: usize) -> u8 {
+        match self {
+            Self::Dark => 0,
+            Self::Full => MAX_LIGHT,
+            Self::Levels(levels) => levels.get(index),
+        }
+    }
+
+    pub fn set(&mut self, index: usize, level: u8) {
+        let uniform = match self {
+            Self::Dark => 0,
+            Self::Full => MAX_LIGHT,
+            Self::Levels(levels) => return levels.set(index, level),
+        };
+        if level == uniform {
+            return;
+        }
+        let mut levels = Box::new(LightLevels::new());
+        if uniform != 0 {
+            levels.0.fill(uniform | uniform << 4);
+        }
+        levels.set(index, level);
+        *self = Self::Levels(levels);
+    }
+}
+
+/// The generated sky light of a chunk whose blocks are solid up to the height (in blocks) given by
+/// `height_at(x, z)`, like the generator fills them: the air is fully lit above the surface and dark below.
+#[cfg(feature = "skylight")]
+pub fn column_sky_light(
+    pos: &ChunkPos,
+    blocks: &[BlockId; BLOCKS_PER_CHUNK],
+    height_at: impl Fn(usize, usize) -> i64,
+) -> SkyLight {
+    let chunk_floor = pos.y().saturating_mul(CHUNK_SIZE as i64);
+
+    let mut levels = Box::new(LightLevels::new());
+    let mut lit_blocks = 0;
+    for x in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            let shaded = height_at(x, z)
+                .saturating_sub(chunk_floor)
+                .clamp(0, CHUNK_SIZE as i64) as usize;
+            for y in shaded..CHUNK_SIZE {
+                let index = LocalBlockPos::new(x as u8, y as u8, z as u8).to_index();
+                if blocks[index] == BlockId::Air {
+                    levels.set(index, MAX_LIGHT);
+                    lit_blocks += 1;
+                }
+            }
+        }
+    }
+
+    match lit_blocks {
+        0 => SkyLight::Dark,
+        BLOCKS_PER_CHUNK => SkyLight::Full,
+        _ => SkyLight::Levels(levels),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LightKind {
+    Block,
+    #[cfg(feature = "skylight")]
+    Sky,
+}
+
+impl LightKind {
+    #[inline]
+    fn emission(self, block: BlockId) -> u8 {
+        match self {
+            Self::Block => block.light_emission(),
+            #[cfg(feature = "skylight")]
+            Self::Sky => 0,
+        }
+    }
+
+    /// Whether the full level goes down without loss, like the sunlight.
+    #[inline]
+    fn falls(self) -> bool {
+        match self {
+            Self::Block => false,
+            #[cfg(feature = "skylight")]
+            Self::Sky => true,
+        }
+    }
+
+    /// The level given by a block of `level` to its neighbour at `addend`.
+    #[inline]
+    fn spread(self, level: u8, addend: (i8, i8, i8)) -> u8 {
+        if self.falls() && level == MAX_LIGHT && addend == (0, -1, 0) {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+}
+
+/// Update the light after the block at `pos` has been replaced.
+/// Return the chunks whose light changed, with their neighbours sharing a changed border block.
+pub fn block_changed(chunks: &Chunks, pos: BlockPos) -> HashSet<ChunkPos> {
+    #[allow(unused_mut)]
+    let mut changed = update(chunks, pos, LightKind::Block);
+    #[cfg(feature = "skylight")]
+    changed.extend(update(chunks, pos, LightKind::Sky));
+    changed
+}
+
+fn update(chunks: &Chunks, pos: BlockPos, kind: LightKind) -> HashSet<ChunkPos> {
+    let mut light = LightAccess {
+        chunks,
+        kind,
+        changed: HashSet::new(),
+    };
+    let Some((block, level)) = light.get(pos) else {
+        return HashSet::new();
+    };
+
+    let mut spread = VecDeque::new();
+    let mut removal = VecDeque::from([(pos, level)]);
+    light.set(pos, 0);
+    while let Some((pos, level)) = removal.pop_front() {
+        for addend in ADDENDS {
+            let neighbour = pos.offset(addend);
+            match light.get(neighbour) {
+                Some((_, 0)) | None => {}
+                Some((_, neighbour_level))
+                    if neighbour_level < level || neighbour_level == kind.spread(level, addend) =>
+                {
+                    light.set(neighbour, 0);
+                    removal.push_back((neighbour, neighbour_level));
+                }
+                Some(_) => spread.push_back(neighbour),
+            }
+        }
+    }
+
+    let emission = kind.emission(block);
+    if emission != 0 {
+        light.set(pos, emission);
+        spread.push_back(pos);
+    }
+    while let Some(pos) = spread.pop_front() {
+        let Some((_, level)) = light.get(pos) else {
+            continue;
+        };
+        for addend in ADDENDS {
+            let neighbour = pos.offset(addend);
+            let neighbour_level = kind.spread(level, addend);
+            if let Some((BlockId::Air, current)) = light.get(neighbour)
+                && current < neighbour_level
+            {
+                light.set(neighbour, neighbour_level);
+                spread.push_back(neighbour);
+            }
+        }
+    }
+
+    light.changed
+}
+
+/// The blocks and one kind of light of the loaded chunks, by world position.
+struct LightAccess<'a> {
+    chunks: &'a Chunks,
+    kind: LightKind,
+    changed: HashSet<ChunkPos>,
+}
+
+impl LightAccess<'_> {
+    /// Return `None` if the chunk isn't loaded.
+    fn get(&self, pos: BlockPos) -> Option<(BlockId, u8)> {
+        let chunk = self.chunks.get(&pos.chunk())?;
+        let local = pos.local();
+        let blocks = chunk.blocks.read().expect("Lock poisoned");
+        let level = match self.kind {
+            LightKind::Block => blocks.light(local),
+            #[cfg(feature = "skylight")]
+            LightKind::Sky => blocks.sky_light(local),
+        };
+        Some((blocks.data[local.to_index()], level))
+    }
+
+    fn set(&mut self, pos: BlockPos, level: u8) {
+        let Some(chunk) = self.chunks.get(&pos.chunk()) else {
+            return;
+        };
+        let local = pos.local();
+        let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+        match self.kind {
+            LightKind::Block => {
+                if blocks.light(local) == level {
+                    return;
+                }
+                blocks.set_light(local, level);
+            }
+            #[cfg(feature = "skylight")]
+            LightKind::Sky => {
+                if blocks.sky_light(local) == level {
+                    return;
+                }
+                blocks.sky_light.set(local.to_index(), level);
+            }
+        }
+        drop(blocks);
+        self.changed.extend(super::chunks::touching_chunks(pos));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn light_at(chunks: &Chunks, pos: BlockPos) -> u8 {
+        chunks
+            .get(&pos.chunk())
+            .expect("Chunk should be loaded")
+            .blocks
+            .read()
+            .expect("Lock poisoned")
+            .light(pos.local())
+    }
+
+    fn replace(chunks: &Chunks, pos: BlockPos, block: BlockId) -> HashSet<ChunkPos> {
+        let chunk = chunks.get(&pos.chunk()).expect("Chunk should be loaded");
+        chunk.blocks.write().expect("Lock poisoned").data[pos.local().to_index()] = block;
+        block_changed(chunks, pos)
+    }
+
+    #[test]
+    fn light_cave() {
+        let chunks = Chunks::new();
+        let mut chunks = chunks.write().expect("Lock poisoned");
+        for pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0)] {
+            chunks.load(pos).expect("Chunk loading failed");
+            let chunk = chunks.get(&pos).expect("Chunk should be loaded");
+            let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+            blocks.data = [BlockId::Block; BLOCKS_PER_CHUNK];
+            blocks.solid_blocks_count = BLOCKS_PER_CHUNK as u32;
+            #[cfg(feature = "skylight")]
+            {
+                blocks.sky_light = SkyLight::Dark;
+            }
+        }
+        // A 6x3x5 cave across the border of the two chunks, lit from one side.
+        let cave = |x: i64, y: i64, z: i64| {
+            (28..34).contains(&x) && (1..4).contains(&y) && (1..6).contains(&z)
+        };
+        for x in 28..34 {
+            for y in 1..4 {
+                for z in 1..6 {
+                    replace(&chunks, BlockPos::from_world(x, y, z), BlockId::Air);
+                }
+            }
+        }
+        let torch = BlockPos::from_world(28, 2, 3);
+        let changed = replace(&chunks, torch, BlockId::Torch);
+        assert_eq!(
+            changed,
+            HashSet::from([ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0)])
+        );
+
+        for x in 27..35 {
+            for y in 0..5 {
+                for z in 0..7 {
+                    let pos = BlockPos::from_world(x, y, z);
+                    let expected = if pos == torch {
+                        14
+                    } else if cave(x, y, z) {
+                        14 - ((x - 28).abs() + (y - 2).abs() + (z - 3).abs()) as u8
+                    } else {
+                        0
+                    };
+                    assert_eq!(light_at(&chunks, pos), expected, "{pos:?}");
+                }
+            }
+        }
+
+        // Removing the torch darkens the whole cave.
+        replace(&chunks, torch, BlockId::Air);
+        for x in 28..34 {
+            for z in 1..6 {
+                assert_eq!(light_at(&chunks, BlockPos::from_world(x, 2, z)), 0);
+            }
+        }
+    }
+
+    #[cfg(feature = "skylight")]
+    #[test]
+    fn overhang_shade() {
+        let chunks = Chunks::new();
+        let mut chunks = chunks.write().expect("Lock poisoned");
+        let pos = ChunkPos::new(0, 0, 0);
+        chunks.load(pos).expect("Chunk loading failed");
+        {
+            let chunk = chunks.get(&pos).expect("Chunk should be loaded");
+            let mut blocks = chunk.blocks.write().expect("Lock poisoned");
+            for x in 0..CHUNK_SIZE as u8 {
+                for z in 0..CHUNK_SIZE as u8 {
+                    blocks.data[LocalBlockPos::new(x, 0, z).to_index()] = BlockId::Block;
+                }
+            }
+            blocks.sky_light = column_sky_light(&pos, &blocks.data, |_, _| 1);
+        }
+        let sky_light_at = |chunks: &Chunks, x, y, z| {
+            chunks
+                .get(&pos)
+                .expect("Chunk should be loaded")
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .sky_light(LocalBlockPos::new(x, y, z))
+        };
+        assert_eq!(sky_light_at(&chunks, 12, 0, 12), 0);
+        assert_eq!(sky_light_at(&chunks, 12, 1, 12), MAX_LIGHT);
+
+        // A 5x5 roof at y 4, the light comes from under its sides.
+        for x in 10..15 {
+            for z in 10..15 {
+                replace(&chunks, BlockPos::from_world(x, 4, z), BlockId::Block);
+            }
+        }
+        for x in 10..15u8 {
+            for z in 10..15u8 {
+                let to_side = (x - 9).min(15 - x).min(z - 9).min(15 - z);
+                for y in 1..4 {
+                    assert_eq!(sky_light_at(&chunks, x, y, z), MAX_LIGHT - to_side);
+                }
+                assert_eq!(sky_light_at(&chunks, x, 4, z), 0);
+                assert_eq!(sky_light_at(&chunks, x, 5, z), MAX_LIGHT);
+            }
+        }
+        assert_eq!(sky_light_at(&chunks, 9, 1, 12), MAX_LIGHT);
+
+        // A hole in the roof lets the full light down again.
+        replace(&chunks, BlockPos::from_world(12, 4, 12), BlockId::Air);
+        for y in 1..5 {
+            assert_eq!(sky_light_at(&chunks, 12, y, 12), MAX_LIGHT);
+        }
+        assert_eq!(sky_light_at(&chunks, 11, 2, 12), MAX_LIGHT - 1);
+    }
+}
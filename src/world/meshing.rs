@@ -1,3 +1,5 @@
+#[cfg(not(feature = "compute_meshing"))]
+use std::time::Duration;
 use std::{
     mem::{align_of, size_of},
     sync::{
@@ -5,26 +7,46 @@ use std::{
         Arc, Mutex, RwLock, Weak,
     },
     thread::{self, JoinHandle},
-    time::Duration,
+    time::Instant,
 };
 
 use anyhow::{Context, Result};
-use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Select, Sender};
 use log::warn;
-use vulkanalia::vk::{self, DeviceV1_0, SuccessCode};
+use vulkanalia::vk;
+#[cfg(not(feature = "compute_meshing"))]
+use vulkanalia::vk::{DeviceV1_0, SuccessCode};
 
 use crate::{
     gui,
-    render::{
-        create_fence, Buffer, CommandPool, RegionsManager, StagingBuffer, Vertex, DEVICE, QUEUES,
-    },
+    options::AppOptions,
+    render::{Buffer, MemoryProperties, RegionsManager, RenderError, Vertex, QUEUES},
+    utils::clamp_threads_count,
+};
+#[cfg(not(feature = "compute_meshing"))]
+use crate::{
+    render::{create_fence, unified_memory, CommandPool, StagingBuffer, DEVICE},
     utils::try_init_array,
 };
 
-use super::{chunk::Chunk, chunks::Chunks, MAX_VERTICES_PER_CHUNK};
+#[cfg(feature = "compute_meshing")]
+use super::compute_mesh::ComputeMesher;
+use super::{
+    chunk::{Chunk, ChunkMesh},
+    chunks::Chunks,
+    generator::{self, Generator},
+    observers::{ChunkEvent, ChunkObservers},
+    MAX_VERTICES_PER_CHUNK, WORKERS_PAUSE,
+};
 
-pub const THREADS_COUNT: usize = 10;
+#[cfg(not(feature = "compute_meshing"))]
 const IN_FLIGHT_COPIES: usize = 4;
+/// Capabilities of the queue used by each meshing thread.
+pub const QUEUE_FLAGS: vk::QueueFlags = if cfg!(feature = "compute_meshing") {
+    vk::QueueFlags::COMPUTE
+} else {
+    vk::QueueFlags::TRANSFER
+};
 pub type Message = Weak<Chunk>;
 
 static EXIT: AtomicBool = AtomicBool::new(false);
@@ -34,22 +56,37 @@ pub fn create_sender() -> (Sender<Message>, Receiver<Message>) {
     crossbeam_channel::unbounded()
 }
 
+/// The threads count from the options.
+pub fn threads_count() -> usize {
+    clamp_threads_count(AppOptions::get().meshing_threads, "meshing")
+}
+
+/// With `generation`, the seed and the generator receiver, the threads also generate the chunks
+/// (see `AppOptions::shared_workers`). The generator threads must have been started first.
 pub fn start_threads(
+    threads_count: usize,
     receiver: Receiver<Message>,
+    generation: Option<(u32, Receiver<generator::Message>)>,
     chunks: &Arc<RwLock<Chunks>>,
     regions: &Arc<RegionsManager>,
 ) {
     let mut handles = HANDLES.lock().expect("Mutex poisoned");
-    handles.reserve(THREADS_COUNT);
-    for i in 0..THREADS_COUNT {
-        let receiver = receiver.clone();
+    handles.reserve(threads_count);
+    EXIT.store(false, Ordering::Relaxed);
+    for i in 0..threads_count {
+        let tasks = Tasks {
+            meshing: receiver.clone(),
+            generation: generation
+                .as_ref()
+                .map(|(seed, receiver)| (receiver.clone(), generator::shared_generator(*seed))),
+        };
         let chunks = Arc::clone(chunks);
         let regions = Arc::clone(regions);
         let handle = thread::Builder::new()
             .name(format!("Meshing {}", i))
             .spawn(|| {
                 #[allow(clippy::unwrap_used)]
-                thread_main(receiver, chunks, regions).unwrap()
+                thread_main(tasks, chunks, regions).unwrap()
             })
             .expect("Thread spawn failed");
         handles.push(handle);
@@ -70,10 +107,140 @@ pub fn stop_threads(sender: &Sender<Message>) {
     }
 }
 
+enum Task {
+    Generate(generator::Message),
+    Mesh(Message),
+}
+
+/// The work of a meshing thread: the chunks to mesh and, with the shared workers, the ones to generate.
+struct Tasks {
+    meshing: Receiver<Message>,
+    generation: Option<(Receiver<generator::Message>, Generator)>,
+}
+
+impl Tasks {
+    fn next(&self, deadline: Option<Instant>) -> Result<Task, RecvTimeoutError> {
+        let Some((generation, _)) = &self.generation else {
+            let mess = match deadline {
+                Some(deadline) => self.meshing.recv_deadline(deadline),
+                None => self.meshing.recv().map_err(RecvTimeoutError::from),
+            };
+            return mess.map(Task::Mesh);
+        };
+        let mut select = Select::new();
+        let meshing_index = select.recv(&self.meshing);
+        select.recv(generation);
+        let oper = match deadline {
+            Some(deadline) => select
+                .select_deadline(deadline)
+                .map_err(|_| RecvTimeoutError::Timeout)?,
+            None => select.select(),
+        };
+        let task = if oper.index() == meshing_index {
+            oper.recv(&self.meshing).map(Task::Mesh)
+        } else {
+            oper.recv(generation).map(Task::Generate)
+        };
+        task.map_err(RecvTimeoutError::from)
+    }
+
+    /// Generate the chunks received until one to mesh is, or until `deadline`.
+    fn recv_mesh(
+        &self,
+        chunks: &RwLock<Chunks>,
+        deadline: Option<Instant>,
+    ) -> Result<Message, RecvTimeoutError> {
+        loop {
+            let task = self.next(deadline)?;
+            WORKERS_PAUSE.wait();
+            match task {
+                Task::Mesh(mess) => {
+                    let ready = mess
+                        .upgrade()
+                        .map_or(true, |chunk| Chunks::ready_to_mesh(chunks, &chunk));
+                    if ready {
+                        return Ok(mess);
+                    }
+                }
+                Task::Generate(chunk) => {
+                    if let (Some(chunk), Some((_, generator))) = (chunk.upgrade(), &self.generation)
+                    {
+                        generator.process(&chunk, chunks);
+                    }
+                }
+            }
+            if EXIT.load(Ordering::Relaxed) {
+                return Ok(Weak::new());
+            }
+        }
+    }
+}
+
 fn thread_main(
-    receiver: Receiver<Message>,
+    tasks: Tasks,
     chunks: Arc<RwLock<Chunks>>,
     regions: Arc<RegionsManager>,
+) -> Result<()> {
+    let observers = Arc::clone(&chunks.read().expect("Lock poisoned").observers);
+    #[cfg(feature = "compute_meshing")]
+    {
+        compute_thread_main(tasks, chunks, regions, observers)
+    }
+    #[cfg(not(feature = "compute_meshing"))]
+    {
+        if unified_memory() {
+            direct_thread_main(tasks, chunks, regions, observers)
+        } else {
+            staging_thread_main(tasks, chunks, regions, observers)
+        }
+    }
+}
+
+/// Experimental: mesh the chunks with a compute shader then copy the vertices to a buffer of the exact size.
+#[cfg(feature = "compute_meshing")]
+fn compute_thread_main(
+    tasks: Tasks,
+    chunks: Arc<RwLock<Chunks>>,
+    regions: Arc<RegionsManager>,
+    observers: Arc<ChunkObservers>,
+) -> Result<()> {
+    let queue = QUEUES.fetch_queue(QUEUE_FLAGS)?;
+    let mut mesher = ComputeMesher::new(queue).context("Compute mesher creation failed")?;
+
+    while !EXIT.load(Ordering::Relaxed) {
+        let mess = tasks
+            .recv_mesh(&chunks, None)
+            .context("Channel disconnected")?;
+        let Some(chunk) = mess.upgrade() else {
+            continue;
+        };
+
+        let vertices_count = timed_mesh(|| mesher.mesh(&chunk, &chunks))?;
+        if vertices_count == 0 {
+            clear_mesh(&chunk, &chunks, &regions)?;
+            continue;
+        }
+
+        let Some(vertex_buff) = create_vertex_buffer(&chunk, &chunks, vertices_count, false)?
+        else {
+            continue;
+        };
+        mesher.copy_into(&vertex_buff, vertices_count)?;
+
+        let mesh = ChunkMesh::new(vertex_buff, vertices_count);
+        mesh_uploaded(&chunk, mesh, &chunks, &regions, &observers)?;
+    }
+
+    Ok(())
+}
+
+/// Used on discrete GPUs: the vertices are written to a staging buffer then copied to device local memory.
+#[cfg(not(feature = "compute_meshing"))]
+fn staging_thread_main(
+    tasks: Tasks,
+    chunks: Arc<RwLock<Chunks>>,
+    regions: Arc<RegionsManager>,
+    observers: Arc<ChunkObservers>,
 ) -> Result<()> {
     let fences: [vk::Fence; IN_FLIGHT_COPIES] = try_init_array(|| create_fence(true))?;
     let mut staging_buffs: [StagingBuffer; IN_FLIGHT_COPIES] = try_init_array(|| {
@@ -83,13 +250,26 @@ fn thread_main(
         )
     })
     .context("Staging buffer creation failed")?;
-    let queue = QUEUES.fetch_queue(vk::QueueFlags::TRANSFER)?;
+    let queue = QUEUES.fetch_queue(QUEUE_FLAGS)?;
+    // The vertex input stage can only be waited for on a graphics queue. Otherwise the copy is made visible to
+    // any later command and the graphics queue only reads the vertices after the fence is signaled.
+    let (dst_stage, dst_access) = if queue.flags.contains(vk::QueueFlags::GRAPHICS) {
+        (
+            vk::PipelineStageFlags::VERTEX_INPUT,
+            vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+        )
+    } else {
+        (
+            vk::PipelineStageFlags::ALL_COMMANDS,
+            vk::AccessFlags::MEMORY_READ,
+        )
+    };
     let mut command_pool = CommandPool::new(queue.family)?;
     let mut command_buffs = command_pool
         .alloc_buffers(IN_FLIGHT_COPIES, false)
         .context("Command buffers alloc failed")?;
-    const NONE_INIT: Option<(Arc<Chunk>, Buffer)> = None;
-    let mut in_copy_chunks: [Option<(Arc<Chunk>, Buffer)>; IN_FLIGHT_COPIES] =
+    const NONE_INIT: Option<(Arc<Chunk>, ChunkMesh)> = None;
+    let mut in_copy_chunks: [Option<(Arc<Chunk>, ChunkMesh)>; IN_FLIGHT_COPIES] =
         [NONE_INIT; IN_FLIGHT_COPIES];
 
     let mut buff_idx = 0;
@@ -97,9 +277,11 @@ fn thread_main(
 
     while !EXIT.load(Ordering::Relaxed) {
         let mess = if current_copies_count == 0 {
-            receiver.recv().context("Channel disconnected")?
+            tasks
+                .recv_mesh(&chunks, None)
+                .context("Channel disconnected")?
         } else {
-            let r = receiver.recv_timeout(Duration::from_millis(100));
+            let r = tasks.recv_mesh(&chunks, Some(Instant::now() + Duration::from_millis(100)));
             match r {
                 Ok(mess) => mess,
                 Err(RecvTimeoutError::Timeout) => Weak::new(),
@@ -120,16 +302,8 @@ fn thread_main(
             };
 
             buff_idx = signaled_fence;
-            if let Some((finished_copy_chunk, vertex_buffer)) = in_copy_chunks[buff_idx].take() {
-                *finished_copy_chunk
-                    .vertex_buffer
-                    .lock()
-                    .expect("Mutex poisoned") = Some(vertex_buffer);
-                let region_pos = finished_copy_chunk.pos.region();
-                regions.set_dirty(region_pos)?;
-                let data = gui::DATA.read().expect("Lock poisoned");
-                data.meshed_chunks_total.fetch_add(1, Ordering::Relaxed);
-                data.meshed_chunks.fetch_add(1, Ordering::Relaxed);
+            if let Some((finished_copy_chunk, mesh)) = in_copy_chunks[buff_idx].take() {
+                mesh_uploaded(&finished_copy_chunk, mesh, &chunks, &regions, &observers)?;
                 current_copies_count -= 1;
             }
 
@@ -142,27 +316,34 @@ fn thread_main(
 
         if let Some(chunk) = mess.upgrade() {
             let vertices = unsafe { staging_buff.data::<Vertex>() };
-            let vertices_count = chunk.mesh(&chunks, vertices);
+            let vertices_count = timed_mesh(|| chunk.mesh(&chunks, vertices));
             if vertices_count == 0 {
+                clear_mesh(&chunk, &chunks, &regions)?;
                 continue;
             }
             let vertices_size = vertices_count * size_of::<Vertex>();
 
-            let mut vertex_buff = Buffer::new(
-                vertices_size,
-                vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-                vk::MemoryPropertyFlags::DEVICE_LOCAL,
-                false,
-                align_of::<Vertex>(),
-            )
-            .context("Vertex buffer creation failed")?;
+            let Some(mut vertex_buff) =
+                create_vertex_buffer(&chunk, &chunks, vertices_count, false)?
+            else {
+                continue;
+            };
 
             unsafe { DEVICE.reset_fences(&[fences[buff_idx]]) }.context("Failed to reset fence")?;
             staging_buff
-                .copy_into(*queue, command_buff, fence, &mut vertex_buff, vertices_size)
+                .copy_into(
+                    *queue,
+                    command_buff,
+                    fence,
+                    &mut vertex_buff,
+                    vertices_size,
+                    dst_stage,
+                    dst_access,
+                )
                 .context("Buffer copy failed")?;
 
-            in_copy_chunks[buff_idx] = Some((chunk, vertex_buff));
+            let mesh = ChunkMesh::new(vertex_buff, vertices_count);
+            in_copy_chunks[buff_idx] = Some((chunk, mesh));
 
             current_copies_count += 1;
         }
@@ -174,7 +355,186 @@ fn thread_main(
     Ok(())
 }
 
+/// Used when the device local memory is host visible (integrated GPUs): the vertices are written directly
+/// to the vertex buffer, without staging buffer nor transfer.
+#[cfg(not(feature = "compute_meshing"))]
+fn direct_thread_main(
+    tasks: Tasks,
+    chunks: Arc<RwLock<Chunks>>,
+    regions: Arc<RegionsManager>,
+    observers: Arc<ChunkObservers>,
+) -> Result<()> {
+    let mut vertices = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+
+    while !EXIT.load(Ordering::Relaxed) {
+        let mess = tasks
+            .recv_mesh(&chunks, None)
+            .context("Channel disconnected")?;
+        let Some(chunk) = mess.upgrade() else {
+            continue;
+        };
+
+        let vertices_count = timed_mesh(|| chunk.mesh(&chunks, &mut vertices));
+        if vertices_count == 0 {
+            clear_mesh(&chunk, &chunks, &regions)?;
+            continue;
+        }
+
+        let Some(mut vertex_buff) = create_vertex_buffer(&chunk, &chunks, vertices_count, true)?
+        else {
+            continue;
+        };
+        vertex_buff.write(&vertices[..vertices_count])?;
+
+        let mesh = ChunkMesh::new(vertex_buff, vertices_count);
+        mesh_uploaded(&chunk, mesh, &chunks, &regions, &observers)?;
+    }
+
+    Ok(())
+}
+
+/// Mesh the chunk and upload it on the calling thread, with `vertices` as scratch space.
+///
+/// Like on the direct threads, the vertex buffer is host visible. On a discrete GPU it may not be device local,
+/// it is replaced if the chunk is remeshed. Once retired it is only recycled for other host visible buffers.
+pub fn mesh_sync(
+    chunk: &Arc<Chunk>,
+    chunks: &Arc<RwLock<Chunks>>,
+    regions: &RegionsManager,
+    vertices: &mut [Vertex],
+) -> Result<()> {
+    if !Chunks::ready_to_mesh(chunks, chunk) {
+        return Ok(());
+    }
+    let vertices_count = chunk.mesh(chunks, vertices);
+    if vertices_count == 0 {
+        return Ok(());
+    }
+    let Some(mut vertex_buff) = create_vertex_buffer(chunk, chunks, vertices_count, true)? else {
+        return Ok(());
+    };
+    vertex_buff.write(&vertices[..vertices_count])?;
+    let mesh = ChunkMesh::new(vertex_buff, vertices_count);
+    let observers = Arc::clone(&chunks.read().expect("Lock poisoned").observers);
+    mesh_uploaded(chunk, mesh, chunks, regions, &observers)
+}
+
+/// Record how long `mesh` takes in the bench results. Nothing is measured without the `bench` feature.
+#[inline(always)]
+fn timed_mesh<R>(mesh: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "bench")]
+    let start = std::time::Instant::now();
+    let r = mesh();
+    #[cfg(feature = "bench")]
+    crate::bench::MESH_TIMES.record(start.elapsed());
+    r
+}
+
+/// Device local vertex buffer filled by a transfer.
+fn device_local_vertex_buffer(size: usize) -> Result<Buffer> {
+    Buffer::new(
+        size,
+        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+        MemoryProperties::required(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+        false,
+        align_of::<Vertex>(),
+    )
+}
+
+/// Host visible vertex buffer, in device local memory if possible.
+fn mapped_vertex_buffer(size: usize) -> Result<Buffer> {
+    Buffer::new(
+        size,
+        vk::BufferUsageFlags::VERTEX_BUFFER,
+        MemoryProperties::required(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            .prefer(vk::MemoryPropertyFlags::DEVICE_LOCAL),
+        true,
+        align_of::<Vertex>(),
+    )
+}
+
+/// A buffer for at least `vertices_count` vertices, host visible if `mapped` else device local and filled by a
+/// transfer: a recycled one of the same kind if there is one fitting, else a new one with the size rounded by
+/// `AppOptions::vertex_buffer_rounding`.
+/// Return `None` if the device is out of memory. The chunk is then meshed again from the next frame.
+fn create_vertex_buffer(
+    chunk: &Arc<Chunk>,
+    chunks: &RwLock<Chunks>,
+    vertices_count: usize,
+    mapped: bool,
+) -> Result<Option<Buffer>> {
+    let size = vertices_count * size_of::<Vertex>();
+    let recycled = chunks
+        .read()
+        .expect("Lock poisoned")
+        .take_recycled_buffer(size, mapped);
+    if let Some(buffer) = recycled {
+        return Ok(Some(buffer));
+    }
+    let capacity = AppOptions::get()
+        .vertex_buffer_rounding
+        .round(vertices_count)
+        .min(MAX_VERTICES_PER_CHUNK);
+    let create = if mapped {
+        mapped_vertex_buffer
+    } else {
+        device_local_vertex_buffer
+    };
+    match create(capacity * size_of::<Vertex>()) {
+        Ok(buffer) => Ok(Some(buffer)),
+        // Memory may be freed by discarded chunks so try again later. Sent back right away, it would be
+        // retried in a loop until then.
+        Err(e) if RenderError::find(&e).map_or(false, RenderError::is_out_of_memory) => {
+            warn!("Chunk {} meshing delayed: {}", chunk.pos, e);
+            chunks
+                .read()
+                .expect("Lock poisoned")
+                .remesh_later(chunk.pos);
+            Ok(None)
+        }
+        Err(e) => Err(e.context("Vertex buffer creation failed")),
+    }
+}
+
+/// Replace the chunk mesh by `mesh`, which must be complete.
+fn mesh_uploaded(
+    chunk: &Chunk,
+    mesh: ChunkMesh,
+    chunks: &RwLock<Chunks>,
+    regions: &RegionsManager,
+    observers: &ChunkObservers,
+) -> Result<()> {
+    // The new buffer is complete here so a frame sees either the whole old mesh or the whole new one.
+    // The region can't be set dirty while holding the chunk lock: the renderer locks the regions then the chunks.
+    let old_mesh = chunk.swap_vertex_buffer(Some(mesh));
+    regions.set_dirty(chunk.pos.region())?;
+    if let Some(old_mesh) = old_mesh {
+        chunks
+            .read()
+            .expect("Lock poisoned")
+            .retire_buffer(old_mesh.into_buffer());
+    }
+    observers.notify(ChunkEvent::Meshed(chunk.pos));
+    let data = gui::DATA.read().expect("Lock poisoned");
+    data.meshed_chunks_total.fetch_add(1, Ordering::Relaxed);
+    data.meshed_chunks.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Drop the mesh of a chunk left without any visible face, by an edit for example.
+fn clear_mesh(chunk: &Chunk, chunks: &RwLock<Chunks>, regions: &RegionsManager) -> Result<()> {
+    if let Some(old_mesh) = chunk.swap_vertex_buffer(None) {
+        regions.set_dirty(chunk.pos.region())?;
+        chunks
+            .read()
+            .expect("Lock poisoned")
+            .retire_buffer(old_mesh.into_buffer());
+    }
+    Ok(())
+}
+
 /// Return the index of the first signaled fence (starting to check from `start_at` and looping through in `fences`) or `None` if no fence is signaled.
+#[cfg(not(feature = "compute_meshing"))]
 fn get_first_signaled_fence(fences: &[vk::Fence], start_at: usize) -> Result<Option<usize>> {
     let mut checked_count = 0;
     let mut i = start_at;
@@ -191,3 +551,93 @@ fn get_first_signaled_fence(fences: &[vk::Fence], start_at: usize) -> Result<Opt
 
     Ok(None)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mini_moka::sync::Cache;
+
+    use crate::{
+        options::{GenerationOptions, WorldGenMode},
+        world::{BlockId, BlockPos, ChunkPos, LocalBlockPos},
+    };
+
+    use super::*;
+
+    #[test]
+    fn shared_tasks() {
+        let options = GenerationOptions {
+            mode: WorldGenMode::Flat { height: 8 },
+            ..GenerationOptions::new()
+        };
+        let generator = Generator::new(0, options, Cache::new(16));
+        let (meshing_sender, meshing_receiver) = create_sender();
+        let (generation_sender, generation_receiver) = generator::create_sender();
+        let tasks = Tasks {
+            meshing: meshing_receiver,
+            generation: Some((generation_receiver, generator)),
+        };
+        let chunks = Chunks::new();
+
+        // The chunks to generate are processed while waiting for one to mesh.
+        let chunk = Arc::new(Chunk::new(ChunkPos::new(0, 0, 0)));
+        generation_sender
+            .send(Arc::downgrade(&chunk))
+            .expect("Sender disconnected");
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert!(matches!(
+            tasks.recv_mesh(&chunks, Some(deadline)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+        assert!(
+            chunk
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .solid_blocks_count
+                > 0
+        );
+
+        meshing_sender
+            .send(Arc::downgrade(&chunk))
+            .expect("Sender disconnected");
+        let mess = tasks
+            .recv_mesh(&chunks, None)
+            .expect("Channel disconnected");
+        assert!(mess.ptr_eq(&Arc::downgrade(&chunk)));
+    }
+
+    #[test]
+    fn meshed_chunks_counters() {
+        let chunks = Chunks::new();
+        let regions =
+            RegionsManager::new(Arc::clone(&chunks), 1).expect("Region manager creation failed");
+        let counters = || {
+            let data = gui::DATA.read().expect("Lock poisoned");
+            (
+                data.meshed_chunks_total.load(Ordering::Relaxed),
+                data.meshed_chunks.load(Ordering::Relaxed),
+            )
+        };
+        // High above the surface, only air but the placed block.
+        let pos = ChunkPos::new(0, 20, 0);
+        let chunk = chunks
+            .write()
+            .expect("Lock poisoned")
+            .load_sync([pos])
+            .remove(0);
+        assert!(chunks.read().expect("Lock poisoned").set_block(
+            BlockPos::new(pos, LocalBlockPos::new(1, 2, 3)),
+            BlockId::Block
+        ));
+
+        // Other tests may mesh chunks concurrently, the counters only have to advance.
+        let (total, meshed) = counters();
+        let mut vertices = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        mesh_sync(&chunk, &chunks, &regions, &mut vertices).expect("Meshing failed");
+        let (new_total, new_meshed) = counters();
+        assert!(new_total > total);
+        assert!(new_meshed > meshed);
+    }
+}
@@ -2,37 +2,96 @@ mod blocks;
 pub mod chunk;
 mod chunk_mesh;
 pub mod chunks;
+pub mod collision;
+#[cfg(feature = "compute_meshing")]
+mod compute_mesh;
+mod dump;
+mod frustum;
 mod generator;
+mod light;
 pub mod meshing;
+pub mod observers;
 mod pos;
 
+pub use blocks::BlockId;
+pub use collision::RaycastHit;
+pub use frustum::Frustum;
+pub use generator::{cached_height_map, HeightMap};
 pub use pos::*;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use log::{info, warn};
+use nalgebra_glm::Vec2;
 
-use std::sync::{atomic::Ordering, Arc, RwLock};
+use std::{
+    fs, iter,
+    sync::{atomic::Ordering, Arc, RwLock},
+    time::Instant,
+};
 
-use crate::{gui, render::RegionsManager};
+use crate::{
+    gui,
+    options::AppOptions,
+    render::{RegionsManager, Vertex},
+    utils::PauseGate,
+};
 
-use self::chunks::Chunks;
+use self::{
+    chunk::{Chunk, ChunkState},
+    chunks::Chunks,
+    dump::ChunkDump,
+};
 
 pub const CHUNK_SIZE: usize = 32;
 pub const BLOCKS_PER_CHUNK: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
 pub const MAX_VERTICES_PER_CHUNK: usize = BLOCKS_PER_CHUNK * 18;
-pub const RENDER_DISTANCE: usize = 10;
-pub const DISCARD_DISTANCE: usize = RENDER_DISTANCE + 2;
 pub const REGION_SIZE: usize = 8;
+/// Upper bound of `AppOptions::spawn_pregeneration`, up to 9x9x9 chunks are meshed on the main thread.
+pub const MAX_SPAWN_PREGENERATION: usize = 4;
+
+/// Parks the generator and meshing threads before they process their next chunk.
+static WORKERS_PAUSE: PauseGate = PauseGate::new();
+/// Serializes the tests that start or stop the generator and meshing threads, whose handles are global.
+#[cfg(test)]
+static WORKERS_TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
 
 #[derive(Debug)]
 pub struct World {
     chunks: Arc<RwLock<Chunks>>,
     regions: Arc<RegionsManager>,
+    render_distance: RenderDistance,
+    /// Offsets of the chunks to load around the player, sorted from the nearest to the furthest.
+    load_order: Vec<(i32, i32, i32)>,
+    /// Whether this world started the generator and meshing threads, and so stops them on drop.
+    owns_threads: bool,
 }
 
 impl World {
     pub fn new(chunks: Arc<RwLock<Chunks>>, regions: Arc<RegionsManager>) -> Result<Self> {
         Chunks::init(&chunks, &regions);
-        Ok(Self { chunks, regions })
+        let mut world = Self::with_render_distance(chunks, regions, RenderDistance::from_options());
+        world.owns_threads = true;
+        Ok(world)
+    }
+
+    fn with_render_distance(
+        chunks: Arc<RwLock<Chunks>>,
+        regions: Arc<RegionsManager>,
+        render_distance: RenderDistance,
+    ) -> Self {
+        Self {
+            chunks,
+            regions,
+            render_distance,
+            load_order: render_distance.load_order(),
+            owns_threads: false,
+        }
+    }
+
+    /// Apply a new render distance. The chunks out of it are discarded by the next tick.
+    pub fn set_render_distance(&mut self, render_distance: RenderDistance) {
+        self.render_distance = render_distance;
+        self.load_order = render_distance.load_order();
     }
 
     pub fn create_chunks() -> Arc<RwLock<Chunks>> {
@@ -40,82 +99,681 @@ impl World {
     }
 
     pub fn tick(&self, player_pos: EntityPos) -> Result<()> {
-        let player_chunk_pos = player_pos.chunk();
-        let (px, py, pz) = player_chunk_pos.xyz();
         let mut chunks = self.chunks.write().expect("Lock poisoned");
-
         chunks.update_gui_data();
+        chunks.player_chunk = player_pos.chunk();
+        let (discard_margin, look_ahead, max_loaded) = {
+            let options = AppOptions::get();
+            (
+                options.discard_margin,
+                options.look_ahead,
+                options.max_loaded_chunks,
+            )
+        };
+        let look_ahead = self.look_ahead(player_pos, look_ahead);
+        self.update_chunks(
+            &mut chunks,
+            player_pos.chunk(),
+            look_ahead,
+            discard_margin,
+            max_loaded,
+        )?;
+        Ok(())
+    }
+
+    /// Called once per rendered frame, whether the world ticked or not.
+    pub fn end_frame(&self) {
+        let mut chunks = self.chunks.write().expect("Lock poisoned");
+        // The edits of the whole frame are meshed at once.
+        chunks.flush_dirty();
+        chunks.end_frame();
+    }
+
+    /// Generate and mesh the chunks up to `radius` chunks around `center` on the calling thread, before the first
+    /// frame. The rest is loaded by the worker threads from the first tick.
+    ///
+    /// `radius` is clamped to [`MAX_SPAWN_PREGENERATION`] and the area to the render distance so the startup stays
+    /// short.
+    /// The chunks one step further are generated too, so the meshed ones have their neighbours, and are
+    /// meshed by the meshing threads.
+    pub fn pregenerate_spawn(&self, center: ChunkPos, radius: usize) -> Result<()> {
+        let radius = radius.min(MAX_SPAWN_PREGENERATION);
+        if radius == 0 {
+            return Ok(());
+        }
+        let start = Instant::now();
+        // With a margin of 1 for the neighbours of the meshed chunks.
+        let in_area = |pos: ChunkPos, margin: usize| {
+            let offsets = [
+                pos.x().abs_diff(center.x()),
+                pos.y().abs_diff(center.y()),
+                pos.z().abs_diff(center.z()),
+            ];
+            offsets
+                .iter()
+                .all(|&offset| offset <= (radius + margin) as u64)
+                && self.render_distance.contains(center, pos, margin)
+        };
+        let outer = radius as i64 + 1;
+        let positions = (-outer..=outer).flat_map(|x| {
+            (-outer..=outer)
+                .flat_map(move |y| (-outer..=outer).map(move |z| center + ChunkPos::new(x, y, z)))
+        });
+        let generated = {
+            let mut chunks = self.chunks.write().expect("Lock poisoned");
+            chunks.player_chunk = center;
+            chunks.load_sync(positions.filter(|&pos| in_area(pos, 1)))
+        };
+
+        let mut vertices = vec![Vertex::default(); MAX_VERTICES_PER_CHUNK];
+        let mut meshed = 0;
+        for chunk in &generated {
+            if in_area(chunk.pos, 0) {
+                meshing::mesh_sync(chunk, &self.chunks, &self.regions, &mut vertices)?;
+                meshed += 1;
+                continue;
+            }
+            // Sent to the meshing threads like the generator threads do.
+            let empty = chunk
+                .blocks
+                .read()
+                .expect("Lock poisoned")
+                .solid_blocks_count
+                == 0;
+            if !empty && !chunk.is_occluded(&self.chunks) {
+                self.chunks
+                    .read()
+                    .expect("Lock poisoned")
+                    .chunk_generated(chunk);
+            }
+        }
+        info!(
+            "Spawn area pregenerated in {:.2?}: {} chunks generated, {} meshed",
+            start.elapsed(),
+            generated.len(),
+            meshed
+        );
+        Ok(())
+    }
 
+    /// Discard at once all the chunks out of the render distance of `pos` and start loading around it.
+    ///
+    /// Unlike [`World::tick`] which keeps chunks up to `AppOptions::discard_margin` beyond the render distance,
+    /// nothing of the previous area stays loaded so the memory is bounded during a teleport.
+    pub fn recenter(&self, pos: EntityPos) -> Result<()> {
+        let mut chunks = self.chunks.write().expect("Lock poisoned");
+        let max_loaded = AppOptions::get().max_loaded_chunks;
+        self.update_chunks(&mut chunks, pos.chunk(), None, 0, max_loaded)
+    }
+
+    /// The center of the chunks prefetched `distance` chunks ahead of `pos`, horizontally.
+    /// `distance` is clamped to the horizontal render distance so at most twice as many chunks are loaded.
+    /// `None` if there is nothing to prefetch.
+    fn look_ahead(&self, pos: EntityPos, distance: usize) -> Option<ChunkPos> {
+        let distance = distance.min(self.render_distance.horizontal()) as f32;
+        let look_dir = pos.look_dir();
+        // Looking straight up or down gives no horizontal direction.
+        let forward = Vec2::new(look_dir.x, look_dir.z).try_normalize(1e-3)? * distance;
+        let (x, z) = (forward.x.round() as i64, forward.y.round() as i64);
+        if x == 0 && z == 0 {
+            return None;
+        }
+        Some(pos.chunk() + ChunkPos::new(x, 0, z))
+    }
+
+    /// Discard the chunks further than the render distance plus `discard_margin` from `center` and `look_ahead`
+    /// then load the ones around them. Beyond `max_loaded` chunks, nothing is prefetched and the farthest ones
+    /// out of the render distance of `center` are discarded.
+    fn update_chunks(
+        &self,
+        chunks: &mut Chunks,
+        center: ChunkPos,
+        look_ahead: Option<ChunkPos>,
+        discard_margin: usize,
+        max_loaded: usize,
+    ) -> Result<()> {
         chunks.drain_filter(
             |pos, _| {
-                let dx = (px - pos.x()).abs();
-                let dy = (py - pos.y()).abs();
-                let dz = (pz - pos.z()).abs();
-                dx > DISCARD_DISTANCE as _
-                    || dy > DISCARD_DISTANCE as _
-                    || dz > DISCARD_DISTANCE as _
+                let in_range = |around| self.render_distance.contains(around, *pos, discard_margin);
+                !in_range(center) && !look_ahead.is_some_and(in_range)
             },
             &self.regions,
         );
 
-        let mut load = |x: i32, y: i32, z: i32| -> Result<()> {
-            let pos = ChunkPos::new(
-                player_chunk_pos.x() + x as i64,
-                player_chunk_pos.y() + y as i64,
-                player_chunk_pos.z() + z as i64,
-            );
-            chunks.load(pos)?;
-            Ok(())
-        };
-
-        let n = RENDER_DISTANCE as i32 * 3;
-        let m = RENDER_DISTANCE as i32;
-        for distance in 0..n - 1 {
-            for i in 0..=distance {
-                let x = i;
-                for j in 0..=distance - x {
-                    let y = j;
-                    let z = distance - (x + y);
-                    if x <= m && y <= m && z <= m {
-                        load(x, y, z)?;
-                        if x != 0 {
-                            load(-x, y, z)?;
-                        }
-                        if y != 0 {
-                            load(x, -y, z)?;
-                        }
-                        if z != 0 {
-                            load(x, y, -z)?;
-                        }
-                        if x != 0 && y != 0 {
-                            load(-x, -y, z)?;
-                        }
-                        if x != 0 && z != 0 {
-                            load(-x, y, -z)?;
-                        }
-                        if y != 0 && z != 0 {
-                            load(x, -y, -z)?;
-                        }
-                        if x != 0 && y != 0 && z != 0 {
-                            load(-x, -y, -z)?;
-                        }
+        // Chunks are loaded from the nearest so stopping while the generator is behind prioritizes them.
+        // The generator channel is FIFO so the chunk the player is in is the first generated.
+        // The prefetched ones come after all the chunks around the player.
+        let max_backlog = AppOptions::get().max_generation_backlog;
+        let mut throttled = false;
+        'load: for load_center in iter::once(center).chain(look_ahead) {
+            for &(x, y, z) in &self.load_order {
+                let pos = load_center + ChunkPos::new(x as i64, y as i64, z as i64);
+                if load_center != center {
+                    if chunks.len() >= max_loaded {
+                        break 'load;
+                    }
+                    if self.render_distance.contains(center, pos, 0) {
+                        continue;
                     }
                 }
+                if chunks.generation_backlog() >= max_backlog {
+                    throttled = true;
+                    break 'load;
+                }
+                chunks.load(pos)?;
             }
         }
+        chunks.evict_farthest(
+            max_loaded,
+            center,
+            |pos| self.render_distance.contains(center, *pos, 0),
+            &self.regions,
+        );
+
+        let data = gui::DATA.read().expect("Lock poisoned");
+        data.loaded_chunks.store(chunks.len(), Ordering::Relaxed);
+        data.loading_throttled.store(throttled, Ordering::Relaxed);
 
-        gui::DATA
+        Ok(())
+    }
+
+    /// Park the generator and meshing threads once they finish their current chunk. The queued chunks stay queued
+    /// so [`World::tick`] must not be called while paused, then the first tick after resuming discards the ones
+    /// out of range and loads around the player's current position.
+    pub fn set_paused(&self, paused: bool) {
+        WORKERS_PAUSE.set_paused(paused);
+    }
+
+    /// The first solid block `pos` looks at, up to `max_distance` blocks away.
+    pub fn raycast(&self, pos: EntityPos, max_distance: f32) -> Option<RaycastHit> {
+        let chunks = self.chunks.read().expect("Lock poisoned");
+        collision::raycast(&chunks, *pos, pos.look_dir(), max_distance)
+    }
+
+    /// The loaded chunks intersecting `frustum`, for tools needing what a camera may see (a minimap, stats...).
+    /// The chunks lock is only held while collecting them.
+    #[allow(dead_code)]
+    pub fn visible_chunks(
+        &self,
+        frustum: &Frustum,
+    ) -> impl Iterator<Item = (ChunkPos, Arc<Chunk>)> {
+        let chunks = self.chunks.read().expect("Lock poisoned");
+        chunks
+            .iter()
+            .filter(|(&pos, _)| frustum.intersects(&Aabb::from_chunk(pos)))
+            .map(|(&pos, chunk)| (pos, Arc::clone(chunk)))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[inline]
+    pub fn chunk_state(&self, pos: ChunkPos) -> ChunkState {
+        self.chunks.read().expect("Lock poisoned").state(&pos)
+    }
+
+    /// Return `None` if the chunk isn't loaded.
+    pub fn get_block(&self, pos: BlockPos) -> Option<BlockId> {
+        self.chunks.read().expect("Lock poisoned").get_block(pos)
+    }
+
+    /// The chunk is remeshed at the end of the frame. Return `false` if it isn't loaded.
+    pub fn set_block(&self, pos: BlockPos, block: BlockId) -> bool {
+        self.chunks
             .read()
             .expect("Lock poisoned")
-            .loaded_chunks
-            .store(chunks.len(), Ordering::Relaxed);
+            .set_block(pos, block)
+    }
+
+    /// Debug command clearing the chunk and generating it again, see [`Chunks::regenerate`].
+    pub fn regenerate_chunk(&self, pos: ChunkPos) -> Result<()> {
+        let chunks = self.chunks.read().expect("Lock poisoned");
+        if !chunks.regenerate(pos, &self.regions)? {
+            warn!("Chunk {} isn't loaded", pos);
+        }
+        Ok(())
+    }
+
+    /// Debug command writing the blocks of the chunk, and of its 6 neighbours with `neighbours`, to
+    /// `chunk_<x>_<y>_<z>.txt` in the working directory. See the `dump` module for the format.
+    /// The chunks not loaded or not generated yet are skipped.
+    pub fn dump_chunk(&self, pos: ChunkPos, neighbours: bool) -> Result<()> {
+        let chunks = self.chunks.read().expect("Lock poisoned");
+        let mut positions = vec![pos];
+        if neighbours {
+            positions.extend(
+                chunk_mesh::ADDENDS
+                    .iter()
+                    .map(|&(x, y, z)| pos + ChunkPos::new(x as i64, y as i64, z as i64)),
+            );
+        }
+        let mut content = String::new();
+        for pos in positions {
+            match chunks.get(&pos).map(|chunk| ChunkDump::new(chunk)) {
+                Some(Some(dump)) => content += &dump.to_string(),
+                Some(None) => warn!("Chunk {} isn't generated yet", pos),
+                None => warn!("Chunk {} isn't loaded", pos),
+            }
+        }
+        if content.is_empty() {
+            return Ok(());
+        }
 
+        let (x, y, z) = pos.xyz();
+        let path = format!("chunk_{}_{}_{}.txt", x, y, z);
+        fs::write(&path, content).with_context(|| format!("Failed to write {}", path))?;
+        info!("Chunk {} dumped to {}", pos, path);
         Ok(())
     }
 }
 
+/// How far chunks are loaded around the player: the radii of an ellipsoid on each axis, in chunks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderDistance {
+    pub x: usize,
+    pub y: usize,
+    pub z: usize,
+}
+
+impl RenderDistance {
+    #[inline]
+    pub fn from_options() -> Self {
+        AppOptions::get().render_distance
+    }
+
+    /// The smallest horizontal radius.
+    #[inline]
+    pub fn horizontal(self) -> usize {
+        self.x.min(self.z)
+    }
+
+    /// Count of chunks in range of a center.
+    pub fn chunks_count(self) -> usize {
+        self.offsets().count()
+    }
+
+    /// Return `true` if `pos` is in range of `center`, with `margin` added to the radii.
+    #[inline]
+    pub fn contains(self, center: ChunkPos, pos: ChunkPos, margin: usize) -> bool {
+        let offsets = [
+            pos.x().abs_diff(center.x()),
+            pos.y().abs_diff(center.y()),
+            pos.z().abs_diff(center.z()),
+        ];
+        let radii = [self.x, self.y, self.z].map(|radius| (radius + margin) as u64);
+        if offsets
+            .iter()
+            .zip(&radii)
+            .any(|(offset, radius)| offset > radius)
+        {
+            return false;
+        }
+        // `sum((offset / radius)²) <= 1` without divisions. The offsets are 0 on the axes of radius 0.
+        let radii = radii.map(|radius| radius.max(1) as u128);
+        let product = radii.iter().map(|radius| radius * radius).product::<u128>();
+        let sum = offsets
+            .iter()
+            .zip(radii)
+            .map(|(&offset, radius)| (offset as u128).pow(2) * product / (radius * radius))
+            .sum::<u128>();
+        sum <= product
+    }
+
+    /// Return `true` if at least one chunk of `region` is in range of `center`.
+    #[cfg_attr(not(feature = "debug_boxes"), allow(dead_code))]
+    pub fn contains_region(self, center: ChunkPos, region: RegionPos) -> bool {
+        // The nearest chunk of the region on each axis is the nearest of the ellipsoid.
+        let nearest = |center: i64, region: i64| {
+            let min = region * REGION_SIZE as i64;
+            center.clamp(min, min + REGION_SIZE as i64 - 1)
+        };
+        let nearest = ChunkPos::new(
+            nearest(center.x(), region.x()),
+            nearest(center.y(), region.y()),
+            nearest(center.z(), region.z()),
+        );
+        self.contains(center, nearest, 0)
+    }
+
+    /// The offsets of the chunks in range of a center, unordered.
+    fn offsets(self) -> impl Iterator<Item = (i32, i32, i32)> {
+        let (rx, ry, rz) = (self.x as i32, self.y as i32, self.z as i32);
+        (-rx..=rx)
+            .flat_map(move |x| (-ry..=ry).flat_map(move |y| (-rz..=rz).map(move |z| (x, y, z))))
+            .filter(move |&(x, y, z)| {
+                let pos = ChunkPos::new(x as i64, y as i64, z as i64);
+                self.contains(ChunkPos::new(0, 0, 0), pos, 0)
+            })
+    }
+
+    /// Return the offsets of the chunks in range, sorted from the nearest to the furthest.
+    fn load_order(self) -> Vec<(i32, i32, i32)> {
+        let mut order = self.offsets().collect::<Vec<_>>();
+        // Stable sort so the order is the same for each run.
+        order.sort_by_key(|&(x, y, z)| x * x + y * y + z * z);
+        order
+    }
+}
+
 impl Drop for World {
     fn drop(&mut self) {
-        self.chunks.read().expect("Lock poisoned").stop_threads();
+        if self.owns_threads {
+            self.chunks.read().expect("Lock poisoned").stop_threads();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Mutex};
+
+    use nalgebra_glm::{self as glm, Vec3};
+
+    use super::{observers::ChunkEvent, *};
+
+    /// A world with its own chunks and regions, without the generator and meshing threads.
+    fn test_world(render_distance: RenderDistance) -> World {
+        let chunks = Chunks::new();
+        let regions = Arc::new(
+            RegionsManager::new(Arc::clone(&chunks), 1).expect("Region manager creation failed"),
+        );
+        World::with_render_distance(chunks, regions, render_distance)
+    }
+
+    #[test]
+    fn recenter_discards_far_chunks() {
+        let render_distance = RenderDistance { x: 10, y: 3, z: 10 };
+        let world = test_world(render_distance);
+
+        world
+            .recenter(EntityPos::new(0., 0., 0., 0., 0.))
+            .expect("Recenter failed");
+        assert_ne!(world.chunks.read().expect("Lock poisoned").len(), 0);
+
+        let pos = EntityPos::new(100_000., -3000., 50_000., 0., 0.);
+        world.recenter(pos).expect("Recenter failed");
+        let chunks = world.chunks.read().expect("Lock poisoned");
+        for (chunk_pos, _) in chunks.iter() {
+            assert!(render_distance.contains(pos.chunk(), *chunk_pos, 0));
+        }
+    }
+
+    #[test]
+    fn teleport_unloads_old_area() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 2 };
+        let world = test_world(render_distance);
+        let start = EntityPos::new(0., 0., 0., 0., 0.);
+        world.tick(start).expect("Tick failed");
+        let old_area: Vec<_> = world
+            .chunks
+            .read()
+            .expect("Lock poisoned")
+            .iter()
+            .map(|(pos, _)| *pos)
+            .collect();
+        assert!(!old_area.is_empty());
+
+        // Same as `MainLoopEvent::Teleport`, then the next frames tick around the destination.
+        let target = EntityPos::new(10_000., 0., -5_000., 0., 0.);
+        world.recenter(target).expect("Recenter failed");
+        world.tick(target).expect("Tick failed");
+        world.tick(target).expect("Tick failed");
+        let chunks = world.chunks.read().expect("Lock poisoned");
+        for pos in &old_area {
+            assert!(chunks.get(pos).is_none(), "Chunk {} still loaded", pos);
+        }
+        assert!(chunks.get(&target.chunk()).is_some());
+    }
+
+    #[test]
+    fn no_reload_when_oscillating() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 2 };
+        let world = test_world(render_distance);
+        assert!(AppOptions::get().discard_margin >= 1);
+
+        // On each side of the boundary between the chunks 0 and 1.
+        let a = EntityPos::new(31.5, 0., 0., 0., 0.);
+        let b = EntityPos::new(32.5, 0., 0., 0., 0.);
+        world.tick(a).expect("Tick failed");
+        world.tick(b).expect("Tick failed");
+        let loaded: Vec<_> = world
+            .chunks
+            .read()
+            .expect("Lock poisoned")
+            .iter()
+            .map(|(pos, chunk)| (*pos, Arc::clone(chunk)))
+            .collect();
+
+        for _ in 0..10 {
+            world.tick(a).expect("Tick failed");
+            world.tick(b).expect("Tick failed");
+        }
+        let chunks = world.chunks.read().expect("Lock poisoned");
+        assert_eq!(chunks.len(), loaded.len());
+        for (pos, chunk) in &loaded {
+            let current = chunks.get(pos).expect("Chunk discarded");
+            assert!(Arc::ptr_eq(chunk, current), "Chunk {} reloaded", pos);
+        }
+    }
+
+    #[test]
+    fn visible_chunks_in_frustum() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 2 };
+        let world = test_world(render_distance);
+        world
+            .recenter(EntityPos::new(16., 16., 16., 0., 0.))
+            .expect("Recenter failed");
+
+        // Looking toward +x from the chunk (0, 0, 0): y and z in 8..24, x in 1.5..71.
+        let eye = Vec3::new(1., 16., 16.);
+        let view = glm::look_at(&eye, &(eye + Vec3::x()), &Vec3::y());
+        let proj = glm::ortho_rh_zo(-8., 8., -8., 8., 0.5, 70.);
+        let frustum = Frustum::from_view_proj(&(proj * view));
+
+        let visible: HashSet<_> = world.visible_chunks(&frustum).map(|(pos, _)| pos).collect();
+        let expected = HashSet::from([
+            ChunkPos::new(0, 0, 0),
+            ChunkPos::new(1, 0, 0),
+            ChunkPos::new(2, 0, 0),
+        ]);
+        assert_eq!(visible, expected);
+    }
+
+    #[test]
+    fn spawn_pregeneration() {
+        let render_distance = RenderDistance { x: 3, y: 1, z: 3 };
+        let world = test_world(render_distance);
+        let center = ChunkPos::new(2, 0, -5);
+        world
+            .pregenerate_spawn(center, 0)
+            .expect("Pregeneration failed");
+        assert_eq!(world.chunks.read().expect("Lock poisoned").len(), 0);
+
+        world
+            .pregenerate_spawn(center, 1)
+            .expect("Pregeneration failed");
+        let chunks = world.chunks.read().expect("Lock poisoned");
+        let mut count = 0;
+        for x in -4..=4 {
+            for y in -4..=4 {
+                for z in -4..=4 {
+                    // The cube of radius 2 cut by the render distance with a margin of 1.
+                    let pos = center + ChunkPos::new(x, y, z);
+                    let expected = [x, y, z].iter().all(|c| c.abs() <= 2)
+                        && render_distance.contains(center, pos, 1);
+                    let state = chunks.state(&pos);
+                    assert_eq!(state != ChunkState::Unloaded, expected, "{pos}");
+                    if expected {
+                        assert_ne!(state, ChunkState::Loading, "{pos}");
+                        count += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(chunks.len(), count);
+    }
+
+    #[test]
+    fn load_order_nearest_first() {
+        let render_distance = RenderDistance { x: 3, y: 1, z: 3 };
+        let world = test_world(render_distance);
+        let loaded = Arc::new(Mutex::new(Vec::new()));
+        {
+            let loaded = Arc::clone(&loaded);
+            world
+                .chunks
+                .read()
+                .expect("Lock poisoned")
+                .observers
+                .register(move |event| {
+                    if let ChunkEvent::Loaded(pos) = event {
+                        loaded.lock().expect("Mutex poisoned").push(pos);
+                    }
+                });
+        }
+
+        // Looking straight up so nothing is prefetched.
+        let player = EntityPos::new(100., -40., 70., 90., 0.);
+        world.tick(player).expect("Tick failed");
+        let loaded = loaded.lock().expect("Mutex poisoned");
+        let center = player.chunk();
+        assert_eq!(loaded[0], center);
+        // The disk of radius 3 at y = 0 and the center of the layers above and below.
+        assert_eq!(loaded.len(), 29 + 2);
+        assert_eq!(loaded.len(), render_distance.chunks_count());
+
+        let distance = |pos: &ChunkPos| {
+            pos.x().abs_diff(center.x()).pow(2)
+                + pos.y().abs_diff(center.y()).pow(2)
+                + pos.z().abs_diff(center.z()).pow(2)
+        };
+        for pair in loaded.windows(2) {
+            assert!(distance(&pair[0]) <= distance(&pair[1]));
+        }
+        assert_eq!(loaded.iter().collect::<HashSet<_>>().len(), loaded.len());
+        for pos in loaded.iter() {
+            assert!(render_distance.contains(center, *pos, 0));
+        }
+    }
+
+    #[test]
+    fn ellipsoid_bounds() {
+        // Wide and flat, for surface building.
+        let render_distance = RenderDistance { x: 10, y: 2, z: 10 };
+        let center = ChunkPos::new(5, -3, 100);
+        let offset = |x, y, z| center + ChunkPos::new(x, y, z);
+
+        assert!(render_distance.contains(center, offset(10, 0, 0), 0));
+        assert!(render_distance.contains(center, offset(0, -2, 0), 0));
+        assert!(render_distance.contains(center, offset(0, 0, -10), 0));
+        assert!(render_distance.contains(center, offset(6, 1, 6), 0));
+        assert!(!render_distance.contains(center, offset(7, 1, 7), 0));
+        assert!(!render_distance.contains(center, offset(10, 2, -10), 0));
+        assert!(!render_distance.contains(center, offset(0, 3, 0), 0));
+        assert!(!render_distance.contains(center, offset(11, 0, 0), 0));
+
+        // The margin applies on every axis.
+        assert!(render_distance.contains(center, offset(12, 0, 0), 2));
+        assert!(render_distance.contains(center, offset(0, 4, 0), 2));
+        assert!(!render_distance.contains(center, offset(0, 5, 0), 2));
+
+        // A radius of 0 only keeps the center on its axis.
+        let render_distance = RenderDistance { x: 0, y: 5, z: 3 };
+        assert!(render_distance.contains(center, offset(0, 5, 0), 0));
+        assert!(!render_distance.contains(center, offset(1, 0, 0), 0));
+        // 11, 9, 7 then 1 chunks along y for each z.
+        assert_eq!(render_distance.chunks_count(), 11 + 2 * (9 + 7 + 1));
+    }
+
+    #[test]
+    fn ellipsoid_loaded_set() {
+        // Tall and narrow, for spelunking.
+        let render_distance = RenderDistance { x: 1, y: 3, z: 1 };
+        let world = test_world(render_distance);
+        let center = ChunkPos::new(4, -7, 2);
+        let mut chunks = world.chunks.write().expect("Lock poisoned");
+        world
+            .update_chunks(&mut chunks, center, None, 0, usize::MAX)
+            .expect("Update failed");
+
+        let loaded: HashSet<_> = chunks.iter().map(|(&pos, _)| pos).collect();
+        let mut expected: HashSet<_> = (-3..=3).map(|y| center + ChunkPos::new(0, y, 0)).collect();
+        for (x, z) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            expected.insert(center + ChunkPos::new(x, 0, z));
+        }
+        assert_eq!(loaded, expected);
+    }
+
+    #[test]
+    fn look_ahead_prefetch() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 2 };
+        let world = test_world(render_distance);
+
+        // Yaw 0 looks toward +x, the distance is clamped to the render distance.
+        let pos = EntityPos::new(16., 16., 16., -20., 0.);
+        let ahead = world.look_ahead(pos, 10).expect("Nothing to prefetch");
+        assert_eq!(ahead, ChunkPos::new(2, 0, 0));
+        assert_eq!(world.look_ahead(pos, 0), None);
+        assert_eq!(
+            world.look_ahead(EntityPos::new(16., 16., 16., 90., 0.), 2),
+            None
+        );
+
+        let mut chunks = world.chunks.write().expect("Lock poisoned");
+        world
+            .update_chunks(&mut chunks, pos.chunk(), Some(ahead), 0, usize::MAX)
+            .expect("Update failed");
+        // The 15 chunks around the player and the 10 around the point ahead not among them.
+        assert_eq!(chunks.len(), 15 + 10);
+        assert!(chunks.get(&ChunkPos::new(4, 0, 0)).is_some());
+        assert!(chunks.get(&ChunkPos::new(2, 1, 0)).is_some());
+        assert!(chunks.get(&ChunkPos::new(1, 1, 0)).is_none());
+        assert!(chunks.get(&ChunkPos::new(-3, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn max_loaded_evicts_farthest() {
+        let render_distance = RenderDistance { x: 1, y: 1, z: 1 };
+        let world = test_world(render_distance);
+        let mut chunks = world.chunks.write().expect("Lock poisoned");
+        let update = |chunks: &mut Chunks, x, max_loaded| {
+            world
+                .update_chunks(chunks, ChunkPos::new(x, 0, 0), None, 10, max_loaded)
+                .expect("Update failed");
+            chunks.len()
+        };
+
+        assert_eq!(update(&mut chunks, 0, usize::MAX), 7);
+        assert_eq!(update(&mut chunks, 3, usize::MAX), 14);
+        // 21 chunks: the 7 around x = 0 and the one at x = 2 are evicted.
+        assert_eq!(update(&mut chunks, 6, 13), 13);
+        assert!(chunks.iter().all(|(pos, _)| pos.x() >= 3));
+        assert_eq!(chunks.iter().filter(|(pos, _)| pos.x() == 3).count(), 5);
+
+        // The chunks in the render distance are kept whatever the cap.
+        assert_eq!(update(&mut chunks, 9, 5), 7);
+        assert!(chunks.iter().all(|(pos, _)| pos.x() >= 8));
+    }
+
+    #[test]
+    fn region_in_range() {
+        let render_distance = RenderDistance { x: 2, y: 1, z: 2 };
+        let center = ChunkPos::new(0, 0, 0);
+        let region = |x, y, z| RegionPos::new(x, y, z);
+
+        assert!(render_distance.contains_region(center, region(0, 0, 0)));
+        assert!(render_distance.contains_region(center, region(-1, 0, -1)));
+        assert!(render_distance.contains_region(center, region(0, -1, 0)));
+        assert!(!render_distance.contains_region(center, region(-1, -1, -1)));
+        assert!(!render_distance.contains_region(center, region(1, 0, 0)));
+        assert!(!render_distance.contains_region(center, region(0, 0, -2)));
+
+        // Only the last chunks of the region are in range.
+        let center = ChunkPos::new(REGION_SIZE as i64 + 1, 0, 0);
+        assert!(render_distance.contains_region(center, region(0, 0, 0)));
+        let center = ChunkPos::new(REGION_SIZE as i64 + 2, 0, 0);
+        assert!(!render_distance.contains_region(center, region(0, 0, 0)));
+        // The corner of the region is out of the ellipse.
+        let center = ChunkPos::new(REGION_SIZE as i64 + 1, 0, REGION_SIZE as i64 + 1);
+        assert!(!render_distance.contains_region(center, region(0, 0, 0)));
     }
 }
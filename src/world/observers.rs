@@ -0,0 +1,148 @@
+//! Callbacks for the transitions of the chunks, for tools and tests reacting to them. Only the tests register
+//! observers for now.
+
+use std::{
+    fmt::{self, Debug},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        RwLock,
+    },
+};
+
+use super::ChunkPos;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkEvent {
+    /// Added to the loaded chunks, still empty.
+    Loaded(ChunkPos),
+    /// Its blocks are generated. Sent again when it's regenerated.
+    Generated(ChunkPos),
+    /// A new mesh is uploaded, after the generation or an edit.
+    Meshed(ChunkPos),
+    /// Discarded from the loaded chunks.
+    Unloaded(ChunkPos),
+}
+
+/// Returned by [`ChunkObservers::register`] to unregister the observer.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ObserverId(u64);
+
+type Observer = Box<dyn Fn(ChunkEvent) + Send + Sync>;
+
+/// The observers are called on the thread making the transition (the main thread, a generator or a meshing
+/// thread), possibly while the chunks are locked: they must be quick and must not lock the chunks.
+/// Nothing is locked to notify while none is registered. Shared by the chunks and the meshing threads, which
+/// notify without locking the chunks.
+#[derive(Default)]
+pub struct ChunkObservers {
+    /// `observers` isn't empty.
+    any: AtomicBool,
+    /// With their id.
+    observers: RwLock<Vec<(u64, Observer)>>,
+    #[cfg(test)]
+    next_id: std::sync::atomic::AtomicU64,
+}
+
+impl ChunkObservers {
+    #[cfg(test)]
+    pub fn register(&self, observer: impl Fn(ChunkEvent) + Send + Sync + 'static) -> ObserverId {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut observers = self.observers.write().expect("Lock poisoned");
+        observers.push((id, Box::new(observer)));
+        self.any.store(true, Ordering::Release);
+        ObserverId(id)
+    }
+
+    /// Return `false` if the observer wasn't registered.
+    #[cfg(test)]
+    pub fn unregister(&self, id: ObserverId) -> bool {
+        let mut observers = self.observers.write().expect("Lock poisoned");
+        let len = observers.len();
+        observers.retain(|(observer_id, _)| *observer_id != id.0);
+        self.any.store(!observers.is_empty(), Ordering::Release);
+        observers.len() != len
+    }
+
+    #[inline]
+    pub fn notify(&self, event: ChunkEvent) {
+        if !self.any.load(Ordering::Acquire) {
+            return;
+        }
+        for (_, observer) in self.observers.read().expect("Lock poisoned").iter() {
+            observer(event);
+        }
+    }
+}
+
+impl Debug for ChunkObservers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkObservers")
+            .field(
+                "count",
+                &self.observers.read().expect("Lock poisoned").len(),
+            )
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        options::{GenerationOptions, WorldGenMode},
+        world::{chunk::Chunk, chunks::Chunks, generator::Generator},
+    };
+
+    use super::*;
+
+    #[test]
+    fn counter_observer() {
+        let chunks = Chunks::new();
+        let generated = Arc::new(AtomicUsize::new(0));
+        let loaded = Arc::new(AtomicUsize::new(0));
+        let id = {
+            let (generated, loaded) = (Arc::clone(&generated), Arc::clone(&loaded));
+            chunks
+                .read()
+                .expect("Lock poisoned")
+                .observers
+                .register(move |event| {
+                    let counter = match event {
+                        ChunkEvent::Generated(_) => &generated,
+                        ChunkEvent::Loaded(_) => &loaded,
+                        _ => return,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                })
+        };
+
+        // Generated by the generator threads.
+        let options = GenerationOptions {
+            mode: WorldGenMode::Flat { height: 8 },
+            ..GenerationOptions::new()
+        };
+        let generator = Generator::new(0, options, mini_moka::sync::Cache::new(16));
+        let chunk = Arc::new(Chunk::new(ChunkPos::new(0, 0, 0)));
+        generator.process(&chunk, &chunks);
+        assert_eq!(generated.load(Ordering::Relaxed), 1);
+
+        // Generated on the calling thread.
+        let positions = [ChunkPos::new(5, 0, 0), ChunkPos::new(6, 0, 0)];
+        chunks.write().expect("Lock poisoned").load_sync(positions);
+        assert_eq!(loaded.load(Ordering::Relaxed), 2);
+        assert_eq!(generated.load(Ordering::Relaxed), 3);
+
+        let chunks = chunks.read().expect("Lock poisoned");
+        assert!(chunks.observers.unregister(id));
+        assert!(!chunks.observers.unregister(id));
+        chunks
+            .observers
+            .notify(ChunkEvent::Generated(ChunkPos::new(0, 0, 0)));
+        assert_eq!(generated.load(Ordering::Relaxed), 3);
+    }
+}
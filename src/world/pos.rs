@@ -40,6 +40,11 @@ impl LocalBlockPos {
         ))
     }
 
+    #[inline(always)]
+    pub fn xyz(self) -> (u8, u8, u8) {
+        (self.x, self.y, self.z)
+    }
+
     #[inline(always)]
     pub fn to_index(self) -> usize {
         (self.x as usize * CHUNK_SIZE + self.y as usize) * CHUNK_SIZE + self.z as usize
@@ -140,6 +145,58 @@ pub struct BlockPos {
 }
 
 impl BlockPos {
+    #[inline(always)]
+    pub fn new(chunk_pos: ChunkPos, local_pos: LocalBlockPos) -> Self {
+        Self {
+            chunk_pos,
+            local_pos,
+        }
+    }
+
+    /// Create the position from world block coordinates.
+    #[inline(always)]
+    pub fn from_world(x: i64, y: i64, z: i64) -> Self {
+        let size = CHUNK_SIZE as i64;
+        Self {
+            chunk_pos: ChunkPos::new(x.div_euclid(size), y.div_euclid(size), z.div_euclid(size)),
+            local_pos: LocalBlockPos::new(
+                x.rem_euclid(size) as u8,
+                y.rem_euclid(size) as u8,
+                z.rem_euclid(size) as u8,
+            ),
+        }
+    }
+
+    #[inline(always)]
+    pub fn chunk(&self) -> ChunkPos {
+        self.chunk_pos
+    }
+    #[inline(always)]
+    pub fn local(&self) -> LocalBlockPos {
+        self.local_pos
+    }
+
+    /// The position moved by `addend` blocks, which may be in another chunk.
+    #[inline]
+    pub fn offset(self, addend: (i8, i8, i8)) -> Self {
+        let size = CHUNK_SIZE as i64;
+        let (x, y, z) = self.local_pos.xyz();
+        let (x, y, z) = (
+            x as i64 + addend.0 as i64,
+            y as i64 + addend.1 as i64,
+            z as i64 + addend.2 as i64,
+        );
+        let carry = ChunkPos::new(x.div_euclid(size), y.div_euclid(size), z.div_euclid(size));
+        Self {
+            chunk_pos: self.chunk_pos + carry,
+            local_pos: LocalBlockPos::new(
+                x.rem_euclid(size) as u8,
+                y.rem_euclid(size) as u8,
+                z.rem_euclid(size) as u8,
+            ),
+        }
+    }
+
     pub fn to_vec(self) -> TVec3<i128> {
         let (x, y, z) = self.chunk_pos.xyz();
         let (x, y, z) = (
@@ -160,6 +217,97 @@ impl Debug for BlockPos {
     }
 }
 
+/// An axis-aligned bounding box in world coordinates.
+///
+/// Boxes are half-open: a point on the `max` faces isn't contained and two boxes sharing a face don't intersect,
+/// so each point of the world belongs to exactly one block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    #[inline(always)]
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        debug_assert!(
+            min.x <= max.x && min.y <= max.y && min.z <= max.z,
+            "min {min} should be lower than max {max}"
+        );
+        Self { min, max }
+    }
+
+    /// The cube covered by a chunk.
+    #[inline]
+    pub fn from_chunk(pos: ChunkPos) -> Self {
+        let size = CHUNK_SIZE as f32;
+        let min = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32) * size;
+        Self::new(min, min.add_scalar(size))
+    }
+
+    /// The cube covered by a region.
+    #[inline]
+    pub fn from_region(pos: RegionPos) -> Self {
+        let size = (REGION_SIZE * CHUNK_SIZE) as f32;
+        let min = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32) * size;
+        Self::new(min, min.add_scalar(size))
+    }
+
+    /// The unit cube covered by a block.
+    #[inline]
+    pub fn from_block(pos: BlockPos) -> Self {
+        let pos = pos.to_vec();
+        let min = Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32);
+        Self::new(min, min.add_scalar(1.))
+    }
+
+    #[inline]
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min.x < other.max.x
+            && self.max.x > other.min.x
+            && self.min.y < other.max.y
+            && self.max.y > other.min.y
+            && self.min.z < other.max.z
+            && self.max.z > other.min.z
+    }
+
+    #[inline]
+    pub fn contains_point(&self, point: &Vec3) -> bool {
+        point.x >= self.min.x
+            && point.x < self.max.x
+            && point.y >= self.min.y
+            && point.y < self.max.y
+            && point.z >= self.min.z
+            && point.z < self.max.z
+    }
+
+    /// Return the distance along `dir` at which the ray enters the box, 0 if `origin` is inside or `None` if it misses.
+    ///
+    /// The distance is in units of `dir` so it is in blocks if `dir` is normalized.
+    pub fn ray_intersects(&self, origin: &Vec3, dir: &Vec3) -> Option<f32> {
+        let mut t_min = 0_f32;
+        let mut t_max = f32::INFINITY;
+        for axis in 0..3 {
+            if dir[axis] == 0. {
+                // Parallel to the slab: either always or never between the planes.
+                if origin[axis] < self.min[axis] || origin[axis] >= self.max[axis] {
+                    return None;
+                }
+                continue;
+            }
+            let inv = 1. / dir[axis];
+            let t1 = (self.min[axis] - origin[axis]) * inv;
+            let t2 = (self.max[axis] - origin[axis]) * inv;
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
 /// The position and the look direction of an entity.
 #[derive(Clone, Copy, PartialEq, PartialOrd)]
 pub struct EntityPos {
@@ -183,6 +331,17 @@ impl EntityPos {
     pub fn yaw(&self) -> f32 {
         self.look.y
     }
+    /// Normalized direction the entity looks at.
+    pub fn look_dir(&self) -> Vec3 {
+        let pitch = self.pitch().to_radians();
+        let yaw = self.yaw().to_radians();
+        Vec3::new(
+            yaw.cos() * pitch.cos(),
+            pitch.sin(),
+            yaw.sin() * pitch.cos(),
+        )
+        .normalize()
+    }
     #[inline(always)]
     pub fn chunk(&self) -> ChunkPos {
         let mut x = (self.pos.x / CHUNK_SIZE as f32) as i64;
@@ -304,3 +463,81 @@ impl Display for RegionPos {
         write!(f, "{} {} {}", self.x, self.y, self.z)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Vec3::new(0., 0., 0.), Vec3::new(1., 1., 1.))
+    }
+
+    #[test]
+    fn aabb_from_positions() {
+        let chunk = Aabb::from_chunk(ChunkPos::new(-1, 0, 2));
+        assert_eq!(chunk.min, Vec3::new(-32., 0., 64.));
+        assert_eq!(chunk.max, Vec3::new(0., 32., 96.));
+
+        let block_pos = BlockPos::from_world(-1, 33, 5);
+        assert_eq!(block_pos.chunk(), ChunkPos::new(-1, 1, 0));
+        assert_eq!(block_pos.local(), LocalBlockPos::new(31, 1, 5));
+        let block = Aabb::from_block(block_pos);
+        assert_eq!(block.min, Vec3::new(-1., 33., 5.));
+        assert_eq!(block.max, Vec3::new(0., 34., 6.));
+        assert!(Aabb::from_chunk(block_pos.chunk()).intersects(&block));
+        assert!(!chunk.intersects(&block));
+    }
+
+    #[test]
+    fn aabb_intersects() {
+        let a = unit_box();
+        assert!(a.intersects(&a));
+        assert!(a.intersects(&Aabb::new(Vec3::new(0.5, 0.5, 0.5), Vec3::new(2., 2., 2.))));
+        // Contained
+        assert!(a.intersects(&Aabb::new(
+            Vec3::new(0.2, 0.2, 0.2),
+            Vec3::new(0.8, 0.8, 0.8)
+        )));
+        // Sharing a face, an edge or a corner
+        assert!(!a.intersects(&Aabb::new(Vec3::new(1., 0., 0.), Vec3::new(2., 1., 1.))));
+        assert!(!a.intersects(&Aabb::new(Vec3::new(1., 1., 0.), Vec3::new(2., 2., 1.))));
+        assert!(!a.intersects(&Aabb::new(Vec3::new(-1., -1., -1.), Vec3::new(0., 0., 0.))));
+        // Overlapping on two axes only
+        assert!(!a.intersects(&Aabb::new(Vec3::new(0., 0., 3.), Vec3::new(1., 1., 4.))));
+    }
+
+    #[test]
+    fn aabb_contains_point() {
+        let a = unit_box();
+        assert!(a.contains_point(&Vec3::new(0.5, 0.5, 0.5)));
+        assert!(a.contains_point(&Vec3::new(0., 0., 0.)));
+        assert!(a.contains_point(&Vec3::new(0., 0.999, 0.)));
+        assert!(!a.contains_point(&Vec3::new(1., 0.5, 0.5)));
+        assert!(!a.contains_point(&Vec3::new(1., 1., 1.)));
+        assert!(!a.contains_point(&Vec3::new(-0.001, 0.5, 0.5)));
+    }
+
+    #[test]
+    fn aabb_ray_intersects() {
+        let a = unit_box();
+        let x = Vec3::new(1., 0., 0.);
+
+        assert_eq!(a.ray_intersects(&Vec3::new(-2., 0.5, 0.5), &x), Some(2.));
+        assert_eq!(a.ray_intersects(&Vec3::new(0.5, 0.5, 0.5), &x), Some(0.));
+        // Behind the origin
+        assert_eq!(a.ray_intersects(&Vec3::new(2., 0.5, 0.5), &x), None);
+        // Parallel, outside then along the min face
+        assert_eq!(a.ray_intersects(&Vec3::new(-2., 2., 0.5), &x), None);
+        assert_eq!(a.ray_intersects(&Vec3::new(-2., 0., 0.5), &x), Some(2.));
+        // Diagonal through the corner region
+        let diag = Vec3::new(1., 1., 1.).normalize();
+        let t = a
+            .ray_intersects(&Vec3::new(-1., -1., -1.), &diag)
+            .expect("Should hit");
+        assert!((t - 3_f32.sqrt()).abs() < 1e-5);
+        assert_eq!(
+            a.ray_intersects(&Vec3::new(-1., 0.5, -1.), &Vec3::new(0., 0., 1.)),
+            None
+        );
+    }
+}